@@ -0,0 +1,291 @@
+//! Which pieces attack which squares. Rather than generating the moves
+//! of every piece on the board, these look outwards from the square in
+//! question, which is a lot cheaper.
+use super::legal_moves::{AXES, DIAGONALS};
+use super::{Board, SquareDiff, SquareSpec};
+use crate::piece::{Color, Piece, PieceType};
+
+const KNIGHT_JUMPS: [(i32, i32); 8] = [
+    (2, 1),
+    (2, -1),
+    (-2, 1),
+    (-2, -1),
+    (1, 2),
+    (1, -2),
+    (-1, 2),
+    (-1, -2),
+];
+
+/// A piece that can't move off the line between its king and the piece
+/// pinning it, see [`Board::pinned_pieces`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    /// The pinned piece
+    pub pinned: SquareSpec,
+    /// The piece that pins it
+    pub pinner: SquareSpec,
+    /// The squares between the king and the pinner, and the pinner
+    /// itself, apart from the pinned piece. Those are the only squares
+    /// the pinned piece can still move to.
+    pub ray: Vec<SquareSpec>,
+}
+
+/// All the directions a queen can move in, with whether they're
+/// diagonal
+fn directions() -> impl Iterator<Item = (SquareDiff, bool)> {
+    let axes = AXES.iter().map(|&(r, f)| (SquareDiff::new(r, f), false));
+    let diagonals = DIAGONALS
+        .iter()
+        .map(|&(r, f)| (SquareDiff::new(r, f), true));
+    axes.chain(diagonals)
+}
+
+/// Whether `piece` slides along diagonals, or along ranks and files
+fn slides(piece: PieceType, diagonal: bool) -> bool {
+    match piece {
+        PieceType::Queen => true,
+        PieceType::Bishop => diagonal,
+        PieceType::Rook => !diagonal,
+        _ => false,
+    }
+}
+
+impl Board {
+    /// The squares of the pieces of `color` that attack `sq`, no matter
+    /// what's on it. Pawns attack diagonally, and a piece defending its
+    /// own side also counts.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, SquareSpec};
+    /// # use chess_engine::piece::Color;
+    /// let board = Board::load_fen("4k3/8/8/3p4/8/2N5/8/3RK3 w - - 0 1").unwrap();
+    /// let d5 = "d5".parse::<SquareSpec>().unwrap();
+    ///
+    /// let mut attackers = board.attackers_of(d5, Color::White);
+    /// attackers.sort_by_key(|sq| sq.to_string());
+    /// assert_eq!(attackers, ["c3".parse::<SquareSpec>().unwrap(), "d1".parse().unwrap()]);
+    /// ```
+    pub fn attackers_of(&self, sq: SquareSpec, color: Color) -> Vec<SquareSpec> {
+        let mut attackers = Vec::new();
+        let is = |sq: SquareSpec, piece: PieceType| self[sq] == Some(Piece::new(piece, color));
+
+        // a pawn attacks from one rank behind, seen from its side
+        let behind = match color {
+            Color::White => -1,
+            Color::Black => 1,
+        };
+        for d_file in [-1, 1] {
+            if let Some(from) = sq.checked_add(SquareDiff::new(behind, d_file)) {
+                if is(from, PieceType::Pawn) {
+                    attackers.push(from);
+                }
+            }
+        }
+
+        for &(d_rank, d_file) in &KNIGHT_JUMPS {
+            if let Some(from) = sq.checked_add(SquareDiff::new(d_rank, d_file)) {
+                if is(from, PieceType::Knight) {
+                    attackers.push(from);
+                }
+            }
+        }
+
+        for (dir, diagonal) in directions() {
+            if let Some(from) = sq.checked_add(dir) {
+                if is(from, PieceType::King) {
+                    attackers.push(from);
+                }
+            }
+            if let Some((from, piece)) = self.first_piece(sq, dir) {
+                if piece.color == color && slides(piece.piece, diagonal) {
+                    attackers.push(from);
+                }
+            }
+        }
+
+        attackers
+    }
+
+    /// Every square that a piece of `color` attacks
+    pub fn attacked_squares(&self, color: Color) -> Vec<SquareSpec> {
+        (0..8)
+            .flat_map(|rank| (0..8).map(move |file| SquareSpec::new(rank, file)))
+            .filter(|&sq| !self.attackers_of(sq, color).is_empty())
+            .collect()
+    }
+
+    /// The pieces checking the king of the player to move
+    pub fn checkers(&self) -> Vec<SquareSpec> {
+        match self.king(self.turn) {
+            Some(king) => self.attackers_of(king, self.turn.opposite()),
+            None => vec![],
+        }
+    }
+
+    /// The pieces of `color` that are pinned to their king, so that
+    /// moving them off the pin's ray would leave the king in check
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, SquareSpec};
+    /// # use chess_engine::piece::Color;
+    /// let board = Board::load_fen("4k3/8/8/8/8/8/2B5/r2NK3 w - - 0 1").unwrap();
+    /// let pins = board.pinned_pieces(Color::White);
+    ///
+    /// assert_eq!(pins.len(), 1);
+    /// assert_eq!(pins[0].pinned, "d1".parse::<SquareSpec>().unwrap());
+    /// assert_eq!(pins[0].ray.len(), 3);
+    /// ```
+    pub fn pinned_pieces(&self, color: Color) -> Vec<Pin> {
+        let king = match self.king(color) {
+            Some(king) => king,
+            None => return vec![],
+        };
+
+        let mut pins = Vec::new();
+        for (dir, diagonal) in directions() {
+            let pinned = match self.first_piece(king, dir) {
+                Some((sq, piece)) if piece.color == color => sq,
+                _ => continue,
+            };
+            match self.first_piece(pinned, dir) {
+                Some((pinner, piece)) if piece.color != color && slides(piece.piece, diagonal) => {
+                    let mut ray = Vec::new();
+                    let mut sq = king + dir;
+                    while sq != pinner {
+                        if sq != pinned {
+                            ray.push(sq);
+                        }
+                        sq += dir;
+                    }
+                    ray.push(pinner);
+                    pins.push(Pin {
+                        pinned,
+                        pinner,
+                        ray,
+                    });
+                }
+                _ => (),
+            }
+        }
+        pins
+    }
+
+    /// The sliding pieces of `color` that attack `sq` through exactly
+    /// one other piece of either color, e.g. a rook behind a queen, or
+    /// a bishop behind the piece it pins
+    pub fn x_ray_attackers(&self, sq: SquareSpec, color: Color) -> Vec<SquareSpec> {
+        let mut attackers = Vec::new();
+        for (dir, diagonal) in directions() {
+            let (blocker, _) = match self.first_piece(sq, dir) {
+                Some(first) => first,
+                None => continue,
+            };
+            if let Some((from, piece)) = self.first_piece(blocker, dir) {
+                if piece.color == color && slides(piece.piece, diagonal) {
+                    attackers.push(from);
+                }
+            }
+        }
+        attackers
+    }
+
+    /// The first piece seen from `sq` going in `dir`, not counting the
+    /// one on `sq`
+    fn first_piece(&self, mut sq: SquareSpec, dir: SquareDiff) -> Option<(SquareSpec, Piece)> {
+        while let Some(next) = sq.checked_add(dir) {
+            sq = next;
+            if let Some(piece) = self[sq] {
+                return Some((sq, piece));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn squares(names: &[&str]) -> Vec<SquareSpec> {
+        let mut squares = names
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect::<Vec<SquareSpec>>();
+        squares.sort_by_key(ToString::to_string);
+        squares
+    }
+
+    fn sorted(mut squares: Vec<SquareSpec>) -> Vec<SquareSpec> {
+        squares.sort_by_key(ToString::to_string);
+        squares
+    }
+
+    #[test]
+    fn finds_attackers() {
+        let board = Board::load_fen("4k3/2n5/8/3p4/4P3/1B6/8/3QK3 w - - 0 1").unwrap();
+        let d5 = "d5".parse().unwrap();
+
+        assert_eq!(
+            sorted(board.attackers_of(d5, Color::White)),
+            squares(&["b3", "d1", "e4"])
+        );
+        // the pawn on d5 blocks the queen, and pawns don't attack
+        // forwards
+        assert_eq!(
+            sorted(board.attackers_of("d6".parse().unwrap(), Color::White)),
+            squares(&[])
+        );
+        assert_eq!(
+            sorted(board.attackers_of("e6".parse().unwrap(), Color::Black)),
+            squares(&["c7"])
+        );
+        assert_eq!(
+            sorted(board.attackers_of("e4".parse().unwrap(), Color::Black)),
+            squares(&["d5"])
+        );
+    }
+
+    #[test]
+    fn finds_attacked_squares() {
+        let board = Board::load_fen("k7/8/8/8/8/8/8/N6K w - - 0 1").unwrap();
+        assert_eq!(
+            sorted(board.attacked_squares(Color::White)),
+            squares(&["b3", "c2", "g1", "g2", "h2"])
+        );
+    }
+
+    #[test]
+    fn finds_checkers() {
+        let board = Board::load_fen("4k3/8/3N4/8/8/8/8/4RK2 b - - 0 1").unwrap();
+        assert_eq!(sorted(board.checkers()), squares(&["d6", "e1"]));
+        assert!(board.in_check());
+        assert!(Board::default_board().checkers().is_empty());
+    }
+
+    #[test]
+    fn finds_pins_and_x_rays() {
+        let board = Board::load_fen("4k3/4r3/8/8/1b6/8/3N4/4K2R w K - 0 1").unwrap();
+
+        let pins = board.pinned_pieces(Color::White);
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].pinned, "d2".parse().unwrap());
+        assert_eq!(pins[0].pinner, "b4".parse().unwrap());
+        assert_eq!(sorted(pins[0].ray.clone()), squares(&["b4", "c3"]));
+        // the rook on e7 is in front of its king, but nothing pins it
+        assert!(board.pinned_pieces(Color::Black).is_empty());
+
+        let e1 = "e1".parse().unwrap();
+        assert_eq!(board.x_ray_attackers(e1, Color::Black), squares(&["b4"]));
+        // the rook attacks g1 directly, which isn't an x-ray
+        assert_eq!(
+            board.x_ray_attackers("g1".parse().unwrap(), Color::White),
+            squares(&[])
+        );
+        assert_eq!(
+            board.x_ray_attackers("d1".parse().unwrap(), Color::White),
+            squares(&["h1"])
+        );
+    }
+}
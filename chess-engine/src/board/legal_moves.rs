@@ -3,18 +3,60 @@
 use super::{Board, Castling, Move, SquareDiff, SquareSpec};
 use crate::piece::{Color, Piece, PieceType};
 
-const DIAGONALS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
-const AXES: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+pub(super) const DIAGONALS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+pub(super) const AXES: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
 
-// Enumerate all possible legal moves for a certain pieces. We use a
-// boolean flag for whether this function should filter out moves that
-// result in the king being threatened, and it has to be done this way
-// as we will call this function recursively to see if the king is threatened
+// Enumerate all the legal moves of a piece, which are the pseudo-legal
+// ones that don't leave the king in check
 pub(crate) fn enumerate_legal_moves(
     piece: Piece,
     location: SquareSpec,
     board: &Board,
-    account_for_check: bool,
+) -> Vec<Move> {
+    let mut moves = pseudo_legal_moves(piece, location, board);
+    moves.retain(|m| board.is_legal_after_pseudo(*m));
+    moves
+}
+
+/// Which of a piece's moves to generate
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Targets {
+    /// Every move
+    All,
+    /// Moves that take a piece, en passant included
+    Captures,
+    /// Moves that don't take anything, castling and promotions without
+    /// a capture included
+    Quiets,
+}
+
+impl Targets {
+    /// Whether a move onto a square with `target` on it is wanted, the
+    /// square can't hold a piece of the side moving
+    fn allow(self, target: Option<Piece>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Captures => target.is_some(),
+            Self::Quiets => target.is_none(),
+        }
+    }
+}
+
+// Enumerate the moves of a piece following how it moves, without
+// checking whether they leave the king in check. Castling is the
+// exception, it's only generated if the king doesn't pass through
+// check, and the check for where it ends up is left to the caller.
+pub(crate) fn pseudo_legal_moves(piece: Piece, location: SquareSpec, board: &Board) -> Vec<Move> {
+    pseudo_legal_targets(piece, location, board, Targets::All)
+}
+
+// The same as `pseudo_legal_moves`, but only the moves of `targets`,
+// the others aren't generated at all
+pub(crate) fn pseudo_legal_targets(
+    piece: Piece,
+    location: SquareSpec,
+    board: &Board,
+    targets: Targets,
 ) -> Vec<Move> {
     let diagonals = DIAGONALS
         .iter()
@@ -24,10 +66,10 @@ pub(crate) fn enumerate_legal_moves(
         .iter()
         .map(|&(d_rank, d_file)| SquareDiff { d_rank, d_file });
 
-    let mut moves = match piece.piece {
+    match piece.piece {
         PieceType::Pawn => {
             let mut moves = Vec::new();
-            for to in get_moves_pawn(piece.color, board, location) {
+            for to in get_moves_pawn(piece.color, board, location, targets) {
                 match to {
                     PawnMove::EnPassant(to) => {
                         moves.push(Move::Normal { from: location, to });
@@ -51,77 +93,49 @@ pub(crate) fn enumerate_legal_moves(
             }
             moves
         }
-        PieceType::King => get_moves_king(piece.color, board, location, account_for_check),
-        PieceType::Knight => get_moves_knight(piece.color, board, location)
+        PieceType::King => get_moves_king(piece.color, board, location, targets),
+        PieceType::Knight => get_moves_knight(piece.color, board, location, targets)
             .into_iter()
             .map(|to| Move::Normal { from: location, to })
             .collect(),
-        PieceType::Rook => {
-            get_moves_directions(piece.color, board, location, &axes.collect::<Vec<_>>())
-                .into_iter()
-                .map(|to| Move::Normal { from: location, to })
-                .collect()
-        }
-        PieceType::Bishop => {
-            get_moves_directions(piece.color, board, location, &diagonals.collect::<Vec<_>>())
-                .into_iter()
-                .map(|to| Move::Normal { from: location, to })
-                .collect()
-        }
+        PieceType::Rook => get_moves_directions(
+            piece.color,
+            board,
+            location,
+            &axes.collect::<Vec<_>>(),
+            targets,
+        )
+        .into_iter()
+        .map(|to| Move::Normal { from: location, to })
+        .collect(),
+        PieceType::Bishop => get_moves_directions(
+            piece.color,
+            board,
+            location,
+            &diagonals.collect::<Vec<_>>(),
+            targets,
+        )
+        .into_iter()
+        .map(|to| Move::Normal { from: location, to })
+        .collect(),
         PieceType::Queen => get_moves_directions(
             piece.color,
             board,
             location,
             &axes.chain(diagonals).collect::<Vec<_>>(),
+            targets,
         )
         .into_iter()
         .map(|to| Move::Normal { from: location, to })
         .collect(),
-    };
-
-    if account_for_check {
-        moves.retain(|m| {
-            let new_board = board.unchecked_perform_move(*m);
-            let king = match new_board.king(board.turn()) {
-                Some(k) => k,
-                _ => return true,
-            };
-
-            for (rank, row) in new_board.board.iter().enumerate() {
-                for (file, p) in row.iter().enumerate() {
-                    if let Some(p) = p {
-                        if p.color != piece.color {
-                            for m_other in enumerate_legal_moves(
-                                *p,
-                                SquareSpec {
-                                    rank: rank as u32,
-                                    file: file as u32,
-                                },
-                                &new_board,
-                                false,
-                            ) {
-                                if let Move::Normal { to, .. } = m_other {
-                                    if to == king {
-                                        return false;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            true
-        });
     }
-
-    moves
 }
 
 pub(crate) fn get_moves_king(
     k_col: Color,
     board: &Board,
     orig_sq: SquareSpec,
-    check_castling: bool,
+    targets: Targets,
 ) -> Vec<Move> {
     let mut moves = Vec::new();
 
@@ -137,76 +151,72 @@ pub(crate) fn get_moves_king(
         if let Some(sq) = orig_sq.checked_add(dir) {
             match board[sq] {
                 Some(Piece { color, .. }) if color == k_col => (),
-                _ => moves.push(Move::Normal {
+                target if targets.allow(target) => moves.push(Move::Normal {
                     from: orig_sq,
                     to: sq,
                 }),
+                _ => (),
             }
         }
     }
-    if check_castling {
-        'castle: {
-            if board.is_threatened(k_col, orig_sq) {
-                break 'castle;
-            }
-            'long: {
-                if board.can_castle(Castling::Long, k_col) {
-                    let (bn, cn, dn) = {
-                        let rank = k_col.home_rank();
-                        (
-                            SquareSpec { rank, file: 1 },
-                            SquareSpec { rank, file: 2 },
-                            SquareSpec { rank, file: 3 },
-                        )
-                    };
-                    match (board[bn], board[cn], board[dn]) {
-                        (None, None, None) => (),
-                        _ => break 'long,
-                    };
-
-                    // we only need to check the intermediate square as the
-                    // other check is handled by enumerate_legal_moves
-                    if board.is_threatened(
-                        k_col,
-                        orig_sq
-                            + SquareDiff {
-                                d_rank: 0,
-                                d_file: -1,
-                            },
-                    ) {
-                        break 'long;
-                    }
-
-                    moves.push(Move::Castling(Castling::Long));
+    'castle: {
+        // the rights can be wrong on a board set up by hand, so check
+        // that the king and rooks are where they should be too
+        if targets == Targets::Captures
+            || orig_sq != SquareSpec::new(k_col.home_rank(), 4)
+            || !board.attackers_of(orig_sq, k_col.opposite()).is_empty()
+        {
+            break 'castle;
+        }
+        let has_rook = |file| {
+            board[SquareSpec::new(k_col.home_rank(), file)]
+                == Some(Piece::new(PieceType::Rook, k_col))
+        };
+        'long: {
+            if board.can_castle(Castling::Long, k_col) && has_rook(0) {
+                let (bn, cn, dn) = {
+                    let rank = k_col.home_rank();
+                    (
+                        SquareSpec { rank, file: 1 },
+                        SquareSpec { rank, file: 2 },
+                        SquareSpec { rank, file: 3 },
+                    )
+                };
+                match (board[bn], board[cn], board[dn]) {
+                    (None, None, None) => (),
+                    _ => break 'long,
+                };
+
+                // we only need to check the intermediate square as the
+                // other check is handled by enumerate_legal_moves
+                let passed = orig_sq + SquareDiff::new(0, -1);
+                if !board.attackers_of(passed, k_col.opposite()).is_empty() {
+                    break 'long;
                 }
-            }
-            'short: {
-                if board.can_castle(Castling::Short, k_col) {
-                    let (r#fn, gn) = {
-                        let rank = k_col.home_rank();
-                        (SquareSpec { rank, file: 5 }, SquareSpec { rank, file: 6 })
-                    };
-                    match (board[r#fn], board[gn]) {
-                        (None, None) => (),
-                        _ => break 'short,
-                    };
-
-                    // once again, we only need to check the intermediate
-                    // square as the other check is handled by
-                    // enumerate_legal_moves
-                    if board.is_threatened(
-                        k_col,
-                        orig_sq
-                            + SquareDiff {
-                                d_rank: 0,
-                                d_file: 1,
-                            },
-                    ) {
-                        break 'short;
-                    }
 
-                    moves.push(Move::Castling(Castling::Short));
+                moves.push(Move::Castling(Castling::Long));
+            }
+        }
+        'short: {
+            if board.can_castle(Castling::Short, k_col) && has_rook(7) {
+                let (r#fn, gn) = {
+                    let rank = k_col.home_rank();
+                    (SquareSpec { rank, file: 5 }, SquareSpec { rank, file: 6 })
+                };
+                match (board[r#fn], board[gn]) {
+                    (None, None) => (),
+                    _ => break 'short,
+                };
+
+                // once again, we only need to check the intermediate
+                // square as the other check is handled by
+                // enumerate_legal_moves
+                let passed = orig_sq + SquareDiff::new(0, 1);
+                if !board.attackers_of(passed, k_col.opposite()).is_empty() {
+                    break 'short;
                 }
+
+                moves.push(Move::Castling(Castling::Short));
             }
         }
     }
@@ -220,7 +230,12 @@ enum PawnMove {
     Promotion(SquareSpec),
 }
 
-fn get_moves_pawn(p_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<PawnMove> {
+fn get_moves_pawn(
+    p_col: Color,
+    board: &Board,
+    orig_sq: SquareSpec,
+    targets: Targets,
+) -> Vec<PawnMove> {
     use PawnMove::*;
 
     let mut moves = Vec::new();
@@ -236,6 +251,7 @@ fn get_moves_pawn(p_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<PawnM
     // whether we can move forward once
     if let Some((sq, None)) = orig_sq
         .checked_add(pawn_direction)
+        .filter(|_| targets != Targets::Captures)
         .map(|sq| (sq, board[sq]))
     {
         // check for promotion
@@ -265,9 +281,11 @@ fn get_moves_pawn(p_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<PawnM
 
     let left_diag = orig_sq
         .checked_add(pawn_direction + LEFT)
+        .filter(|_| targets != Targets::Quiets)
         .map(|sq| (sq, board[sq]));
     let right_diag = orig_sq
         .checked_add(pawn_direction + RIGHT)
+        .filter(|_| targets != Targets::Quiets)
         .map(|sq| (sq, board[sq]));
 
     // check en passants
@@ -288,24 +306,38 @@ fn get_moves_pawn(p_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<PawnM
     // impossible for the en passant square to contain a takeable
     // piece
 
+    // taking on to the last rank promotes too
+    let capture = |sq: SquareSpec| {
+        if sq.rank == p_col.opposite().home_rank() {
+            Promotion(sq)
+        } else {
+            Normal(sq)
+        }
+    };
+
     // check left diagonal
     if let Some((sq, Some(Piece { color, .. }))) = left_diag {
         if p_col != color {
-            moves.push(Normal(sq));
+            moves.push(capture(sq));
         }
     }
 
     // check right diagonal
     if let Some((sq, Some(Piece { color, .. }))) = right_diag {
         if p_col != color {
-            moves.push(Normal(sq));
+            moves.push(capture(sq));
         }
     }
 
     moves
 }
 
-fn get_moves_knight(k_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<SquareSpec> {
+fn get_moves_knight(
+    k_col: Color,
+    board: &Board,
+    orig_sq: SquareSpec,
+    targets: Targets,
+) -> Vec<SquareSpec> {
     let mut moves = [
         (2, 1),
         (2, -1),
@@ -321,7 +353,10 @@ fn get_moves_knight(k_col: Color, board: &Board, orig_sq: SquareSpec) -> Vec<Squ
     .filter_map(|sd| orig_sq.checked_add(sd))
     .collect::<Vec<_>>();
 
-    moves.retain(|x| !matches!(board[*x], Some(Piece { color, .. }) if k_col == color));
+    moves.retain(|x| match board[*x] {
+        Some(Piece { color, .. }) if k_col == color => false,
+        target => targets.allow(target),
+    });
 
     moves
 }
@@ -331,6 +366,7 @@ fn get_moves_directions(
     board: &Board,
     orig_sq: SquareSpec,
     directions: &[SquareDiff],
+    targets: Targets,
 ) -> Vec<SquareSpec> {
     // assumes all of the directions are unit vectors
 
@@ -343,11 +379,15 @@ fn get_moves_directions(
             match board[sq_i] {
                 Some(Piece { color, .. }) if color == piece_col => continue 'dir,
                 Some(Piece { .. }) => {
-                    moves.push(sq_i);
+                    if targets != Targets::Quiets {
+                        moves.push(sq_i);
+                    }
                     continue 'dir;
                 }
                 None => {
-                    moves.push(sq_i);
+                    if targets != Targets::Captures {
+                        moves.push(sq_i);
+                    }
                 }
             }
         }
@@ -375,7 +415,7 @@ mod tests {
                 let $spot = stringify!($spot).parse::<SquareSpec>().unwrap();
                 let piece = board[$spot].unwrap();
                 let legal_moves = move_list![$spot; $($token)*].iter().map(|x|*x).collect::<Vec<_>>();
-                let moves = super::enumerate_legal_moves(piece, $spot, &board, true);
+                let moves = super::enumerate_legal_moves(piece, $spot, &board);
 
                 compare_moves(moves, legal_moves);
             }
@@ -529,6 +569,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pawn_take_promotion() {
+        basic_test! {
+            fen: "3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1",
+            piece: e7,
+            legal_moves: [
+                [d8=B],
+                [d8=R],
+                [d8=N],
+                [d8=Q]
+            ],
+        }
+    }
+
     #[test]
     fn pawn_all_at_once() {
         basic_test! {
@@ -631,4 +685,87 @@ mod tests {
             legal_moves: [a3],
         }
     }
+
+    #[test]
+    fn en_passant_uncovering_check() {
+        // taking en passant takes both pawns off the king's rank
+        basic_test! {
+            fen: "8/8/8/KPp4r/8/8/8/7k w - c6 0 1",
+            piece: b5,
+            legal_moves: [b6],
+        }
+    }
+
+    /// Count the positions `depth` moves deep
+    fn perft(board: &mut Board, depth: u32) -> u64 {
+        let moves = board.get_all_legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for m in moves {
+            let undo = board.make_move(m);
+            nodes += perft(board, depth - 1);
+            board.unmake_move(m, undo);
+        }
+        nodes
+    }
+
+    fn check_perft(fen: &str, counts: &[u64]) {
+        let mut board = Board::load_fen(fen).unwrap();
+        for (depth, &count) in (1..).zip(counts) {
+            assert_eq!(
+                perft(&mut board, depth),
+                count,
+                "{} at depth {}",
+                fen,
+                depth
+            );
+        }
+    }
+
+    // the counts of the standard perft positions, from
+    // https://www.chessprogramming.org/Perft_Results
+
+    #[test]
+    fn perft_start_position() {
+        check_perft(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8_902, 197_281],
+        );
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        check_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2_039, 97_862],
+        );
+    }
+
+    #[test]
+    fn perft_position_3() {
+        // en passant captures that would expose the king along the rank
+        check_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2_812, 43_238],
+        );
+    }
+
+    #[test]
+    fn perft_position_4() {
+        // pawns capturing on to the last rank promote
+        check_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9_467],
+        );
+    }
+
+    #[test]
+    fn perft_position_5() {
+        check_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1_486, 62_379],
+        );
+    }
 }
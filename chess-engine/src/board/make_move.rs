@@ -0,0 +1,280 @@
+//! Making and unmaking moves in place, which is what the search does
+//! for every node instead of copying the board
+use super::{Board, Castling, CastlingFlags, Move, SquareDiff, SquareSpec};
+use crate::piece::{Color, Piece, PieceType};
+
+/// Everything about a board that [`Board::make_move`] can't work out
+/// backwards, to undo the move with [`Board::unmake_move`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UndoInfo {
+    captured: Option<Piece>,
+    castling: CastlingFlags,
+    en_passant: Option<SquareSpec>,
+    halfmove: u32,
+}
+
+impl UndoInfo {
+    /// The piece that was captured by the move, if any
+    pub fn captured(&self) -> Option<Piece> {
+        self.captured
+    }
+}
+
+/// The direction from where a pawn of `color` captures en passant to
+/// the pawn it captures
+pub(super) fn en_passant_direction(color: Color) -> SquareDiff {
+    match color {
+        Color::White => SquareDiff::new(-1, 0),
+        Color::Black => SquareDiff::new(1, 0),
+    }
+}
+
+/// The rook and king squares of castling, as `(king_from, king_to,
+/// rook_from, rook_to)`
+fn castling_squares(
+    castling: Castling,
+    color: Color,
+) -> (SquareSpec, SquareSpec, SquareSpec, SquareSpec) {
+    let rank = color.home_rank();
+    let (king_to, rook_from, rook_to) = match castling {
+        Castling::Short => (6, 7, 5),
+        Castling::Long => (2, 0, 3),
+    };
+    (
+        SquareSpec::new(rank, 4),
+        SquareSpec::new(rank, king_to),
+        SquareSpec::new(rank, rook_from),
+        SquareSpec::new(rank, rook_to),
+    )
+}
+
+/// Remove the castling rights that a rook on `sq` gives, which is done
+/// when it moves or is taken
+fn remove_rook_castling(flags: &mut CastlingFlags, sq: SquareSpec, color: Color) {
+    if sq.rank != color.home_rank() {
+        return;
+    }
+    if sq.file == 0 {
+        *flags &= !match color {
+            Color::White => CastlingFlags::WHITE_LONG,
+            Color::Black => CastlingFlags::BLACK_LONG,
+        };
+    } else if sq.file == 7 {
+        *flags &= !match color {
+            Color::White => CastlingFlags::WHITE_SHORT,
+            Color::Black => CastlingFlags::BLACK_SHORT,
+        };
+    }
+}
+
+impl Board {
+    /// Make a move on this board. The move isn't checked, it has to be
+    /// legal, e.g. one from [`Board::get_all_legal_moves`]. Returns
+    /// what's needed to take the move back with
+    /// [`Board::unmake_move`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no piece to move.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Move};
+    /// let mut board = Board::load_fen("r3k3/8/8/8/8/8/8/R3K3 w Qq - 0 1").unwrap();
+    /// let before = board;
+    /// let m = Move::from_uci("a1a8", &board).unwrap();
+    ///
+    /// let undo = board.make_move(m);
+    /// assert_eq!(Some(board), before.perform_move(m));
+    /// board.unmake_move(m, undo);
+    /// assert_eq!(board, before);
+    /// ```
+    pub fn make_move(&mut self, m: Move) -> UndoInfo {
+        let mut undo = UndoInfo {
+            captured: None,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+        };
+        let color = self.turn;
+        let mut new_en_passant = None;
+        let mut reset_halfmove = false;
+
+        match m {
+            Move::Normal { from, to } | Move::Promotion { from, to, .. } => {
+                let piece = self[from].expect("There is a piece to move");
+                match piece.piece {
+                    PieceType::Rook => remove_rook_castling(&mut self.castling, from, color),
+                    PieceType::King => {
+                        self.castling &= !match color {
+                            Color::White => CastlingFlags::WHITE,
+                            Color::Black => CastlingFlags::BLACK,
+                        };
+                    }
+                    PieceType::Pawn => {
+                        reset_halfmove = true;
+                        let dir = en_passant_direction(color);
+                        if self.en_passant == Some(to) {
+                            undo.captured = self[to + dir].take();
+                            debug_assert!(
+                                undo.captured == Some(Piece::new(PieceType::Pawn, color.opposite())),
+                                "The piece taken by en passant wasn't a pawn, this is most likely a bug"
+                            );
+                        } else if (to - from).abs().d_rank == 2 {
+                            // the square the pawn passed over
+                            new_en_passant = Some(to + dir);
+                        }
+                    }
+                    _ => (),
+                }
+
+                if let Some(captured) = self[to] {
+                    reset_halfmove = true;
+                    undo.captured = Some(captured);
+                    if captured.piece == PieceType::Rook {
+                        remove_rook_castling(&mut self.castling, to, captured.color);
+                    }
+                }
+
+                self[to] = match m {
+                    Move::Promotion { target, .. } => Some(Piece::new(target, color)),
+                    _ => Some(piece),
+                };
+                self[from] = None;
+            }
+            Move::Castling(c) => {
+                let (king_from, king_to, rook_from, rook_to) = castling_squares(c, color);
+                self.castling &= !match color {
+                    Color::White => CastlingFlags::WHITE,
+                    Color::Black => CastlingFlags::BLACK,
+                };
+
+                self[king_to] = self[king_from].take();
+                self[rook_to] = self[rook_from].take();
+            }
+        }
+
+        self.en_passant = new_en_passant;
+        self.turn = color.opposite();
+        if color == Color::Black {
+            self.fullmove += 1;
+        }
+        if reset_halfmove {
+            self.halfmove = 0;
+        } else {
+            self.halfmove += 1;
+        }
+
+        undo
+    }
+
+    /// Take back `m`, which has to be the last move made with
+    /// [`Board::make_move`], with the [`UndoInfo`] it returned. The
+    /// board is then exactly as it was before the move.
+    pub fn unmake_move(&mut self, m: Move, undo: UndoInfo) {
+        let color = self.turn.opposite();
+        self.turn = color;
+        if color == Color::Black {
+            self.fullmove -= 1;
+        }
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove = undo.halfmove;
+
+        match m {
+            Move::Normal { from, to } => {
+                let piece = self[to].take();
+                self[from] = piece;
+                let is_pawn = piece.map(|p| p.piece) == Some(PieceType::Pawn);
+                if is_pawn && undo.en_passant == Some(to) {
+                    self[to + en_passant_direction(color)] = undo.captured;
+                } else {
+                    self[to] = undo.captured;
+                }
+            }
+            Move::Promotion { from, to, .. } => {
+                self[from] = Some(Piece::new(PieceType::Pawn, color));
+                self[to] = undo.captured;
+            }
+            Move::Castling(c) => {
+                let (king_from, king_to, rook_from, rook_to) = castling_squares(c, color);
+                self[king_from] = self[king_to].take();
+                self[rook_from] = self[rook_to].take();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tiny xorshift generator, so that the random games are the
+    /// same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Play random games from `fen`, checking every move on the way
+    fn check_random_games(fen: &str, games: usize, plies: usize, rng: &mut Rng) {
+        let start = Board::load_fen(fen).unwrap();
+        for _ in 0..games {
+            let mut board = start;
+            for _ in 0..plies {
+                let moves = board.get_all_legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                for &m in &moves {
+                    let before = board;
+                    let undo = board.make_move(m);
+                    let after = board;
+                    board.unmake_move(m, undo);
+
+                    assert_eq!(board, before, "{} didn't undo {}", m, before);
+                    assert_eq!(before.perform_move(m), Some(after), "{} on {}", m, before);
+                }
+                let _ = board.make_move(moves[rng.below(moves.len())]);
+            }
+        }
+    }
+
+    #[test]
+    fn unmake_restores_the_board() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // castling, promotions and en passant all over the place
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+        for fen in fens {
+            check_random_games(fen, 4, 40, &mut rng);
+        }
+    }
+
+    #[test]
+    fn undo_info_knows_the_capture() {
+        // en passant takes a pawn that isn't on the target square
+        let mut board = Board::load_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        let m = Move::from_uci("e5d6", &board).unwrap();
+
+        let undo = board.make_move(m);
+        assert_eq!(
+            undo.captured(),
+            Some(Piece::new(PieceType::Pawn, Color::Black))
+        );
+        assert_eq!(
+            board,
+            Board::load_fen("4k3/8/3P4/8/8/8/8/4K3 b - - 0 2").unwrap()
+        );
+    }
+}
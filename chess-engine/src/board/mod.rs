@@ -4,13 +4,26 @@ use crate::piece::{Color, Piece, PieceType};
 use bitflags::bitflags;
 use std::fmt;
 
+mod attacks;
 mod fen_parser;
 mod legal_moves;
+mod make_move;
+mod move_info;
 mod move_types;
+mod movegen;
+mod polyglot;
+mod san;
 mod squarespec;
+mod validate;
+mod zobrist;
 
+pub use attacks::Pin;
+pub use make_move::UndoInfo;
+pub use move_info::{MoveFlags, MoveInfo};
 pub use move_types::{Castling, Move};
+pub use movegen::MoveGen;
 pub use squarespec::{SquareDiff, SquareSpec};
+pub use validate::IllegalReason;
 
 bitflags! {
     /// [bitflags] struct
@@ -128,11 +141,22 @@ impl Board {
         match m {
             Move::Normal { from, .. } | Move::Promotion { from, .. } => {
                 self[from].map_or(false, |piece| {
-                    let legal_moves = legal_moves::enumerate_legal_moves(piece, from, self, true);
+                    if piece.color != side {
+                        return false;
+                    }
+                    let legal_moves = legal_moves::enumerate_legal_moves(piece, from, self);
                     legal_moves.into_iter().any(|x| x == m)
                 })
             }
-            Move::Castling(c) => self.can_castle(c, side),
+            Move::Castling(_) => {
+                // castling doesn't change which pieces attack where the
+                // king ends up, so that can be checked on this board
+                let king = SquareSpec::new(side.home_rank(), 4);
+                self[king] == Some(Piece::new(PieceType::King, side))
+                    && legal_moves::get_moves_king(side, self, king, legal_moves::Targets::All)
+                        .contains(&m)
+                    && self.attackers_of(m.to(side), side.opposite()).is_empty()
+            }
         }
     }
 
@@ -142,167 +166,22 @@ impl Board {
     }
 
     /// Perform a move and return the next board. Returns [None] if
-    /// the move was illegal.
-    #[allow(clippy::missing_panics_doc)]
+    /// the move was illegal. See [`Board::make_move`] for making a
+    /// move without copying the board.
     pub fn perform_move(&self, m: Move) -> Option<Board> {
-        // local function because this snippet occurs 3 times
-        fn rook_taken_castling(flags: &mut CastlingFlags, file: u32, color: Color) {
-            if file == 0 {
-                *flags &= !match color {
-                    Color::White => CastlingFlags::WHITE_LONG,
-                    Color::Black => CastlingFlags::BLACK_LONG,
-                };
-            } else if file == 7 {
-                *flags &= !match color {
-                    Color::White => CastlingFlags::WHITE_SHORT,
-                    Color::Black => CastlingFlags::BLACK_SHORT,
-                };
-            }
-        }
-
         if !self.is_legal(m, self.turn) {
             return None;
         }
 
         let mut new_board = *self;
-        let mut new_en_passant = None;
-        let mut reset_halfmove = false;
-
-        match m {
-            Move::Normal { from, to } => {
-                // the move has already been found to be legal, so we can unwrap
-                match self[from].unwrap() {
-                    Piece {
-                        piece: PieceType::Rook,
-                        color,
-                    } => {
-                        // disable castling in one direction
-                        rook_taken_castling(&mut new_board.castling, from.file, color);
-                    }
-                    Piece {
-                        piece: PieceType::King,
-                        color,
-                    } => {
-                        // disable castling in both directions
-                        new_board.castling &= !match color {
-                            Color::White => CastlingFlags::WHITE,
-                            Color::Black => CastlingFlags::BLACK,
-                        }
-                    }
-                    Piece {
-                        piece: PieceType::Pawn,
-                        color,
-                    } => {
-                        reset_halfmove = true;
-                        let dir = match color.opposite() {
-                            Color::White => SquareDiff::new(1, 0),
-                            Color::Black => SquareDiff::new(-1, 0),
-                        };
-                        if let Some(en_passant) = self.en_passant {
-                            if en_passant == to {
-                                debug_assert!(
-                                    new_board[to + dir] == Some(Piece::new(PieceType::Pawn, color.opposite())),
-                                    "The piece taken by en passant wasn't a pawn, this is most likely a bug"
-                                );
-                                new_board[to + dir] = None;
-                            }
-                        } else if (to - from).abs().d_rank == 2 {
-                            // if a pawn moved two squares, we need to
-                            // set the new en passant square
-                            new_en_passant = Some(from + dir);
-                        }
-                    }
-                    _ => (),
-                };
-
-                if self[to].is_some() {
-                    reset_halfmove = true;
-                }
-
-                // disable castling in that direction if the rook was taken
-                if let Some(Piece {
-                    piece: PieceType::Rook,
-                    color,
-                }) = self[to]
-                {
-                    rook_taken_castling(&mut new_board.castling, to.file, color);
-                }
-
-                new_board[to] = self[from];
-                new_board[from] = None;
-            }
-            Move::Castling(c) => {
-                use Castling::{Long, Short};
-
-                let color = self.turn;
-                let rank = color.home_rank();
-                let king_from = SquareSpec::new(rank, 4);
-
-                let (rf, kt, rt) = match c {
-                    Short => (7, 6, 5),
-                    Long => (0, 2, 3),
-                };
-
-                let rook_from = SquareSpec::new(rank, rf);
-                let king_to = SquareSpec::new(rank, kt);
-                let rook_to = SquareSpec::new(rank, rt);
-
-                new_board.castling &= !match color {
-                    Color::White => CastlingFlags::WHITE,
-                    Color::Black => CastlingFlags::BLACK,
-                };
-
-                new_board[king_to] = self[king_from];
-                new_board[king_from] = None;
-                new_board[rook_to] = self[rook_from];
-                new_board[rook_from] = None;
-            }
-            Move::Promotion { from, to, target } => {
-                // since promotions are always pawn moves, this must
-                // result in resetting the halfmove counter
-                reset_halfmove = true;
-
-                // yet again have to double check if either of the
-                // rooks were taken
-                if let Some(Piece {
-                    piece: PieceType::Rook,
-                    color,
-                }) = self[to]
-                {
-                    rook_taken_castling(&mut new_board.castling, to.file, color);
-                }
-
-                // again, the move is guaranteed to be valid, so this
-                // unwrap can't panic
-                new_board[to] = Some(Piece::new(target, self[from].unwrap().color));
-                new_board[from] = None;
-            }
-        }
-
-        new_board.en_passant = new_en_passant;
-        new_board.turn = self.turn.opposite();
-        if self.turn == Color::Black {
-            new_board.fullmove += 1;
-        }
-        if reset_halfmove {
-            new_board.halfmove = 0;
-        } else {
-            new_board.halfmove += 1;
-        }
-
+        let _ = new_board.make_move(m);
         Some(new_board)
     }
 
     /// Returns whether the current player is in check
     pub fn in_check(&self) -> bool {
-        self.is_threatened(
-            self.turn,
-            match self.king(self.turn) {
-                Some(king) => king,
-                // we can't be checked if there's no king to check
-                _ => return false,
-            },
-        )
+        // we can't be checked if there's no king to check
+        !self.checkers().is_empty()
     }
 
     /// Get the current halfmove
@@ -310,6 +189,11 @@ impl Board {
         self.halfmove
     }
 
+    /// Get the castling rights that are left
+    pub fn castling(&self) -> CastlingFlags {
+        self.castling
+    }
+
     /// Performs a move with wanton abandon for the rules, effectively
     /// taking any piece on the resulting squares regardless of color.
     /// Moving an empty piece will also result in a phantom take.
@@ -368,21 +252,33 @@ impl Board {
         new_board
     }
 
-    /// Get all the legal moves for the piece on this square. If the
-    /// square is empty, or if the selected piece is unavailable this
-    /// turn, this will return an empty vector.
-    pub fn get_legal_moves(&self, piece_location: SquareSpec) -> Vec<Move> {
+    /// Get all the legal moves for the piece on this square, with what
+    /// they do on the board. If the square is empty, or if the
+    /// selected piece is unavailable this turn, this will return an
+    /// empty vector.
+    pub fn get_legal_moves(&self, piece_location: SquareSpec) -> Vec<MoveInfo> {
+        self.legal_moves_from(piece_location)
+            .into_iter()
+            .map(|m| self.move_info(m))
+            .collect()
+    }
+
+    /// The legal moves of the piece on this square, without working
+    /// out what they do
+    fn legal_moves_from(&self, piece_location: SquareSpec) -> Vec<Move> {
         if let Some(piece) = self[piece_location] {
             if piece.color != self.turn {
                 return vec![];
             }
-            legal_moves::enumerate_legal_moves(piece, piece_location, self, true)
+            legal_moves::enumerate_legal_moves(piece, piece_location, self)
         } else {
             vec![]
         }
     }
 
-    /// Like [`get_legal_moves`], but for getting all the legal moves possible on this turn
+    /// Like [`get_legal_moves`], but for getting all the legal moves
+    /// possible on this turn. Use [`Board::move_info`] for what they
+    /// do.
     pub fn get_all_legal_moves(&self) -> Vec<Move> {
         let mut all_moves = Vec::new();
 
@@ -391,7 +287,7 @@ impl Board {
                 let sq = SquareSpec::new(rank as u32, file as u32);
                 if let Some(Piece { color, .. }) = piece {
                     if *color == self.turn {
-                        all_moves.append(&mut self.get_legal_moves(sq));
+                        all_moves.append(&mut self.legal_moves_from(sq));
                     }
                 }
             }
@@ -430,31 +326,11 @@ impl Board {
         None
     }
 
-    /// Check if a certain square on the board is threatened
+    /// Check if a certain square on the board is threatened by the
+    /// pieces of the other side than `color`. See
+    /// [`Board::attackers_of`] for which pieces those are.
     pub fn is_threatened(&self, color: Color, sq: SquareSpec) -> bool {
-        for (rank, row) in self.board.iter().enumerate().map(|(c, i)| (c as u32, i)) {
-            for (file, piece) in row
-                .iter()
-                .enumerate()
-                .filter_map(|(c, p)| p.map(|x| (c as u32, x)))
-            {
-                if piece.color == color.opposite() {
-                    let legal_moves = legal_moves::enumerate_legal_moves(
-                        piece,
-                        SquareSpec { rank, file },
-                        self,
-                        false,
-                    );
-                    if legal_moves.into_iter().any(|m| match m {
-                        Move::Normal { to, .. } => to == sq,
-                        _ => false,
-                    }) {
-                        return true;
-                    }
-                }
-            }
-        }
-        false
+        !self.attackers_of(sq, color.opposite()).is_empty()
     }
 }
 
@@ -492,9 +368,12 @@ impl fmt::Display for CastlingFlags {
         if self.contains(CastlingFlags::BLACK_SHORT) {
             s.push('k');
         }
-        if self.contains(CastlingFlags::BLACK_SHORT) {
+        if self.contains(CastlingFlags::BLACK_LONG) {
             s.push('q');
         }
+        if s.is_empty() {
+            s.push('-');
+        }
         write!(f, "{}", s)
     }
 }
@@ -562,6 +441,18 @@ mod tests {
         assert_eq!(&s, DEFAULT_BOARD);
     }
 
+    #[test]
+    fn display_round_trips() {
+        let fens = [
+            "r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2",
+            "4k3/8/8/8/8/8/8/4K3 b - - 12 40",
+        ];
+        for fen in fens {
+            assert_eq!(Board::load_fen(fen).unwrap().to_string(), fen);
+        }
+    }
+
     #[test]
     fn parsing_fen_of_default() {
         let parsed = Board::load_fen(DEFAULT_BOARD).unwrap();
@@ -581,6 +472,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn double_push_sets_en_passant() {
+        let board = Board::default_board()
+            .perform_move(Move::Normal {
+                from: "e2".parse().unwrap(),
+                to: "e4".parse().unwrap(),
+            })
+            .unwrap()
+            .perform_move(Move::Normal {
+                from: "a7".parse().unwrap(),
+                to: "a5".parse().unwrap(),
+            })
+            .unwrap();
+
+        assert_eq!(board.en_passant, Some("a6".parse().unwrap()));
+    }
+
     #[test]
     fn en_passant_works() {
         let board = Board::load_fen("8/8/8/4pP2/8/8/8/8 w - e6 0 1").unwrap();
@@ -611,4 +519,74 @@ mod tests {
     //   violated
     // - the king shouldn't be possible to take
     // - fullmove is updated correctly and according to spec
+
+    /// Whether the player to move can castle, checking that every way
+    /// of asking agrees
+    fn can_castle_on(fen: &str, castling: Castling) -> bool {
+        let board = Board::load_fen(fen).unwrap();
+        let m = Move::Castling(castling);
+        let legal = board.is_legal(m, board.turn);
+
+        assert_eq!(board.perform_move(m).is_some(), legal, "{}", fen);
+        assert_eq!(board.get_all_legal_moves().contains(&m), legal, "{}", fen);
+        legal
+    }
+
+    #[test]
+    fn castling_legality() {
+        use Castling::{Long, Short};
+
+        assert!(can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", Short));
+        assert!(can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", Long));
+        assert!(can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", Short));
+        assert!(can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", Long));
+        // no rights
+        assert!(!can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1", Short));
+        assert!(!can_castle_on("r3k2r/8/8/8/8/8/8/R3K2R b KQk - 0 1", Long));
+    }
+
+    #[test]
+    fn castling_through_pieces() {
+        use Castling::{Long, Short};
+
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/4KB1R w K - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/4K1nR w K - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/RN2K3 w Q - 0 1", Long));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/R1b1K3 w Q - 0 1", Long));
+        assert!(!can_castle_on("r2qk3/8/8/8/8/8/8/4K3 b q - 0 1", Long));
+    }
+
+    #[test]
+    fn castling_with_missing_pieces() {
+        use Castling::{Long, Short};
+
+        // the rights say we can, but the rook isn't there
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/4K3 w KQ - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/R3K3 w KQ - 0 1", Short));
+        // an enemy piece on the corner
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/4K2b w K - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/n3K3 w Q - 0 1", Long));
+        // which is all that stops it
+        assert!(can_castle_on("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", Long));
+        // or the king isn't where it started
+        assert!(!can_castle_on("4k3/8/8/8/8/8/8/R2K3R w KQ - 0 1", Long));
+    }
+
+    #[test]
+    fn castling_through_check() {
+        use Castling::{Long, Short};
+
+        // the king is in check
+        assert!(!can_castle_on("4r1k1/8/8/8/8/8/8/R3K2R w KQ - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/3n4/8/R3K2R w KQ - 0 1", Long));
+        // the square it passes through is attacked
+        assert!(!can_castle_on("5rk1/8/8/8/8/8/8/R3K2R w KQ - 0 1", Short));
+        assert!(!can_castle_on("4k3/8/8/8/8/8/4p3/R3K2R w KQ - 0 1", Long));
+        // the square it ends up on is attacked
+        assert!(!can_castle_on("2r1k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", Long));
+        assert!(!can_castle_on("r3k2r/8/8/8/8/8/8/6R1 b kq - 0 1", Short));
+        // the rook passing an attacked square is fine
+        assert!(can_castle_on("1r2k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", Long));
+        assert!(can_castle_on("r3k2r/8/8/8/8/8/8/1R2K3 b kq - 0 1", Long));
+    }
 }
@@ -0,0 +1,208 @@
+//! Moves together with what they do on the board, so that nobody has
+//! to look at the board again to find out whether a move takes
+//! something or gives check
+use super::make_move::en_passant_direction;
+use super::{Board, Castling, Move, SquareSpec};
+use crate::piece::{Piece, PieceType};
+use bitflags::bitflags;
+
+bitflags! {
+    /// The special things a move can do
+    pub struct MoveFlags: u8 {
+        /// The move takes a piece, en passant included
+        const CAPTURE     = 0b0000_0001;
+        /// A pawn takes en passant
+        const EN_PASSANT  = 0b0000_0010;
+        /// A pawn moves two squares
+        const DOUBLE_PUSH = 0b0000_0100;
+        /// Castling king-side
+        const SHORT_CASTLE = 0b0000_1000;
+        /// Castling queen-side
+        const LONG_CASTLE = 0b0001_0000;
+        /// A pawn promotes
+        const PROMOTION   = 0b0010_0000;
+        /// The move checks the other king
+        const CHECK       = 0b0100_0000;
+    }
+}
+
+/// A legal move and everything it does, see [`Board::move_info`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MoveInfo {
+    m: Move,
+    piece: Piece,
+    from: SquareSpec,
+    to: SquareSpec,
+    captured: Option<(Piece, SquareSpec)>,
+    flags: MoveFlags,
+}
+
+impl MoveInfo {
+    /// The move itself
+    pub fn get_move(&self) -> Move {
+        self.m
+    }
+
+    /// The piece that moves, which is the king for castling and the
+    /// pawn for promotions
+    pub fn piece(&self) -> Piece {
+        self.piece
+    }
+
+    /// The square the piece moves from
+    pub fn from(&self) -> SquareSpec {
+        self.from
+    }
+
+    /// The square the piece moves to, which for castling is the
+    /// king's square
+    pub fn to(&self) -> SquareSpec {
+        self.to
+    }
+
+    /// The piece that is taken and where it was. That's not the `to`
+    /// square when taking en passant.
+    pub fn captured(&self) -> Option<(Piece, SquareSpec)> {
+        self.captured
+    }
+
+    /// What kind of move it is
+    pub fn flags(&self) -> MoveFlags {
+        self.flags
+    }
+
+    /// Whether the move takes a piece
+    pub fn is_capture(&self) -> bool {
+        self.flags.contains(MoveFlags::CAPTURE)
+    }
+
+    /// Whether the move checks the other king
+    pub fn is_check(&self) -> bool {
+        self.flags.contains(MoveFlags::CHECK)
+    }
+}
+
+impl From<MoveInfo> for Move {
+    fn from(info: MoveInfo) -> Move {
+        info.m
+    }
+}
+
+impl Board {
+    /// Describe a legal move of the player to move.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no piece to move.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Move, MoveFlags};
+    /// # use chess_engine::piece::{Color, Piece, PieceType};
+    /// let board = Board::load_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+    /// let info = board.move_info(Move::from_uci("e5d6", &board).unwrap());
+    ///
+    /// assert!(info.flags().contains(MoveFlags::EN_PASSANT));
+    /// assert_eq!(
+    ///     info.captured(),
+    ///     Some((Piece::new(PieceType::Pawn, Color::Black), "d5".parse().unwrap()))
+    /// );
+    /// ```
+    pub fn move_info(&self, m: Move) -> MoveInfo {
+        let color = self.turn;
+        let (from, to) = (m.from(color), m.to(color));
+        let piece = self[from].expect("There is a piece to move");
+        let mut captured = None;
+        let mut flags = MoveFlags::empty();
+
+        match m {
+            Move::Castling(Castling::Short) => flags |= MoveFlags::SHORT_CASTLE,
+            Move::Castling(Castling::Long) => flags |= MoveFlags::LONG_CASTLE,
+            Move::Normal { .. } | Move::Promotion { .. } => {
+                captured = self[to].map(|p| (p, to));
+                if piece.piece == PieceType::Pawn {
+                    if self.en_passant == Some(to) {
+                        let sq = to + en_passant_direction(color);
+                        captured = self[sq].map(|p| (p, sq));
+                        flags |= MoveFlags::EN_PASSANT;
+                    } else if (to - from).abs().d_rank == 2 {
+                        flags |= MoveFlags::DOUBLE_PUSH;
+                    }
+                }
+                if let Move::Promotion { .. } = m {
+                    flags |= MoveFlags::PROMOTION;
+                }
+            }
+        }
+        if captured.is_some() {
+            flags |= MoveFlags::CAPTURE;
+        }
+
+        let mut next = *self;
+        let _ = next.make_move(m);
+        if next.in_check() {
+            flags |= MoveFlags::CHECK;
+        }
+
+        MoveInfo {
+            m,
+            piece,
+            from,
+            to,
+            captured,
+            flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Color;
+
+    fn info(fen: &str, uci: &str) -> MoveInfo {
+        let board = Board::load_fen(fen).unwrap();
+        board.move_info(Move::from_uci(uci, &board).unwrap())
+    }
+
+    #[test]
+    fn describes_moves() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let push = info(start, "e2e4");
+        assert_eq!(push.piece(), Piece::new(PieceType::Pawn, Color::White));
+        assert_eq!(push.flags(), MoveFlags::DOUBLE_PUSH);
+        assert_eq!(info(start, "g1f3").flags(), MoveFlags::empty());
+
+        let castle = info("r3k3/8/8/8/8/8/8/4K3 b q - 0 1", "e8c8");
+        assert_eq!(castle.flags(), MoveFlags::LONG_CASTLE);
+        assert_eq!(castle.to(), "c8".parse().unwrap());
+        assert_eq!(castle.piece(), Piece::new(PieceType::King, Color::Black));
+    }
+
+    #[test]
+    fn knows_captures_promotions_and_checks() {
+        let promotion = info("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q");
+        assert_eq!(
+            promotion.flags(),
+            MoveFlags::PROMOTION | MoveFlags::CAPTURE | MoveFlags::CHECK
+        );
+        assert_eq!(
+            promotion.captured(),
+            Some((
+                Piece::new(PieceType::Knight, Color::Black),
+                "b8".parse().unwrap()
+            ))
+        );
+        let m: Move = promotion.into();
+        assert_eq!(m, promotion.get_move());
+
+        let board = Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let checks = board
+            .get_legal_moves("a1".parse().unwrap())
+            .into_iter()
+            .filter(MoveInfo::is_check)
+            .map(|m| m.to().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(checks, ["a8"]);
+    }
+}
@@ -1,4 +1,5 @@
-use super::SquareSpec;
+use super::{Board, SquareSpec};
+use crate::error::Error;
 use crate::{Color, Piece, PieceType};
 use std::fmt;
 
@@ -59,6 +60,66 @@ impl Move {
             }
         }
     }
+
+    /// Parse a move written in the long algebraic notation used by
+    /// UCI, e.g. `e2e4`, `e7e8q` or `e1g1` for castling. The board is
+    /// needed to know which piece is moving, but the move isn't
+    /// checked for legality.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the string isn't a move, or if there is
+    /// no piece to move.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Castling, Move};
+    /// let board = Board::load_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+    ///
+    /// assert_eq!(Move::from_uci("e1g1", &board).unwrap(), Move::Castling(Castling::Short));
+    /// assert!(Move::from_uci("e2e4", &board).is_err());
+    /// ```
+    pub fn from_uci(s: &str, board: &Board) -> Result<Move, Error> {
+        let invalid = || Error::InvalidMove(s.to_string());
+
+        if !s.is_ascii() || !(s.len() == 4 || s.len() == 5) {
+            return Err(invalid());
+        }
+        let from = s[0..2].parse::<SquareSpec>().map_err(|_| invalid())?;
+        let to = s[2..4].parse::<SquareSpec>().map_err(|_| invalid())?;
+        let piece = board[from].ok_or_else(invalid)?;
+
+        if s.len() == 5 {
+            let target = s[4..5]
+                .to_ascii_uppercase()
+                .parse::<PieceType>()
+                .map_err(|_| invalid())?;
+            Ok(Move::Promotion { from, to, target })
+        } else {
+            Move::new(piece, from, to).ok_or_else(invalid)
+        }
+    }
+
+    /// Write the move in the long algebraic notation used by UCI. The
+    /// color of the player making the move is needed to know where
+    /// castling moves go.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Castling, Move};
+    /// # use chess_engine::piece::Color;
+    /// let castling = Move::Castling(Castling::Long);
+    ///
+    /// assert_eq!(castling.to_uci(Color::Black), "e8c8");
+    /// ```
+    pub fn to_uci(&self, color: Color) -> String {
+        match self {
+            Move::Promotion { from, to, target } => {
+                format!("{}{}{}", from, to, target.to_string().to_lowercase())
+            }
+            _ => format!("{}{}", self.from(color), self.to(color)),
+        }
+    }
 }
 
 impl fmt::Display for Move {
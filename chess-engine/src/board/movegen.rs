@@ -0,0 +1,270 @@
+//! Move generation in stages, for engines that want to try some moves
+//! before generating the rest, and checking moves that didn't come
+//! from the generator, like moves from a transposition table. Each
+//! stage only generates its own moves, so the quiet moves cost nothing
+//! if the captures are enough for a cutoff.
+//!
+//! The stages generate pseudo-legal moves: the pieces move like they
+//! should, but the moves might leave the king in check, which
+//! [`Board::is_legal_after_pseudo`] checks afterwards. Castling is only
+//! generated when the king doesn't castle out of or through check.
+use super::legal_moves::{get_moves_king, pseudo_legal_moves, pseudo_legal_targets, Targets};
+use super::{Board, Move, SquareSpec};
+use crate::piece::{Piece, PieceType};
+
+/// Generates the moves of the player to move on a board
+///
+/// # Examples
+/// ```
+/// # use chess_engine::board::{Board, MoveGen};
+/// let board = Board::default_board();
+/// let gen = MoveGen::new(&board);
+///
+/// assert_eq!(gen.pseudo_legal().len(), 20);
+/// assert!(gen.captures().is_empty());
+/// assert_eq!(gen.legal(), board.get_all_legal_moves());
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct MoveGen<'a> {
+    board: &'a Board,
+}
+
+impl<'a> MoveGen<'a> {
+    /// Create a generator for the player to move on `board`
+    pub fn new(board: &'a Board) -> Self {
+        MoveGen { board }
+    }
+}
+
+impl MoveGen<'_> {
+    /// Every pseudo-legal move
+    pub fn pseudo_legal(&self) -> Vec<Move> {
+        self.generate(Targets::All)
+    }
+
+    /// The pseudo-legal moves that take a piece, en passant included
+    pub fn captures(&self) -> Vec<Move> {
+        self.generate(Targets::Captures)
+    }
+
+    /// The pseudo-legal moves that don't take anything. Promotions
+    /// without a capture and castling are quiet moves.
+    pub fn quiets(&self) -> Vec<Move> {
+        self.generate(Targets::Quiets)
+    }
+
+    /// The pseudo-legal moves that could get the king out of check:
+    /// king moves, and taking or blocking the piece giving check if
+    /// there's only one. If the king isn't in check there's nothing to
+    /// evade, and this is empty.
+    pub fn evasions(&self) -> Vec<Move> {
+        let board = self.board;
+        let checkers = board.checkers();
+        let king = match board.king(board.turn) {
+            Some(king) if !checkers.is_empty() => king,
+            _ => return vec![],
+        };
+
+        let mut moves = get_moves_king(board.turn, board, king, Targets::All);
+        if let [checker] = checkers[..] {
+            // the checker and the squares between it and the king, a
+            // knight or pawn checks from next to the king or jumps so
+            // there's nothing in between
+            let mut targets = vec![checker];
+            if let Some(dir) = (checker - king).as_unit() {
+                let mut sq = king + dir;
+                while sq != checker {
+                    targets.push(sq);
+                    sq += dir;
+                }
+            }
+
+            for (sq, piece) in self.pieces() {
+                if piece.piece == PieceType::King {
+                    continue;
+                }
+                let mut piece_moves = pseudo_legal_moves(piece, sq, board);
+                piece_moves.retain(|m| {
+                    let to = m.to(board.turn);
+                    // the pawn taken en passant is next to the one
+                    // taking it, not on the square it moves to
+                    let en_passant_checker =
+                        self.is_en_passant(*m) && checker == SquareSpec::new(sq.rank, to.file);
+                    targets.contains(&to) || en_passant_checker
+                });
+                moves.append(&mut piece_moves);
+            }
+        }
+        moves
+    }
+
+    /// Every legal move, the same as [`Board::get_all_legal_moves`]
+    pub fn legal(&self) -> Vec<Move> {
+        let mut moves = self.pseudo_legal();
+        moves.retain(|m| self.board.is_legal_after_pseudo(*m));
+        moves
+    }
+
+    /// The pseudo-legal moves of `targets`
+    fn generate(&self, targets: Targets) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for (sq, piece) in self.pieces() {
+            moves.append(&mut pseudo_legal_targets(piece, sq, self.board, targets));
+        }
+        moves
+    }
+
+    /// The pieces of the player to move
+    fn pieces(&self) -> impl Iterator<Item = (SquareSpec, Piece)> + '_ {
+        let turn = self.board.turn;
+        self.board
+            .board
+            .iter()
+            .enumerate()
+            .flat_map(|(rank, row)| {
+                row.iter().enumerate().filter_map(move |(file, piece)| {
+                    piece.map(|p| (SquareSpec::new(rank as u32, file as u32), p))
+                })
+            })
+            .filter(move |(_, piece)| piece.color == turn)
+    }
+
+    fn is_en_passant(&self, m: Move) -> bool {
+        match m {
+            Move::Normal { from, to } => {
+                self.board[from].map(|p| p.piece) == Some(PieceType::Pawn)
+                    && self.board.en_passant == Some(to)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Board {
+    /// Whether `m` is a pseudo-legal move for the player to move, i.e.
+    /// one that [`MoveGen::pseudo_legal`] would generate. It's much
+    /// cheaper than generating every move, to check moves that come
+    /// from somewhere else, like a transposition table.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Move};
+    /// let board = Board::load_fen("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1").unwrap();
+    /// let m = Move::from_uci("e1f2", &board).unwrap();
+    ///
+    /// assert!(board.is_pseudo_legal(m));
+    /// assert!(!board.is_legal_after_pseudo(m));
+    /// assert!(!board.is_pseudo_legal(Move::from_uci("e1e3", &board).unwrap()));
+    /// ```
+    pub fn is_pseudo_legal(&self, m: Move) -> bool {
+        let from = m.from(self.turn);
+        match self[from] {
+            Some(piece) if piece.color == self.turn => match m {
+                Move::Castling(_) if piece.piece != PieceType::King => false,
+                Move::Castling(_) => {
+                    get_moves_king(self.turn, self, from, Targets::Quiets).contains(&m)
+                }
+                _ => pseudo_legal_moves(piece, from, self).contains(&m),
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether a pseudo-legal move `m` is legal, which is whether it
+    /// doesn't leave the king in check. The move has to be
+    /// pseudo-legal, see [`Board::is_pseudo_legal`].
+    pub fn is_legal_after_pseudo(&self, m: Move) -> bool {
+        let mut next = *self;
+        let _ = next.make_move(m);
+        match next.king(self.turn) {
+            Some(king) => next.attackers_of(king, self.turn.opposite()).is_empty(),
+            // we can't be checked if there's no king to check
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(moves: Vec<Move>) -> Vec<String> {
+        let mut moves = moves.iter().map(Move::to_string).collect::<Vec<_>>();
+        moves.sort();
+        moves
+    }
+
+    /// Check the stages against each other on `board` and every
+    /// position up to `depth` moves from it
+    fn check_stages(board: &Board, depth: u32) {
+        let gen = MoveGen::new(board);
+        let pseudo = gen.pseudo_legal();
+        let legal = board.get_all_legal_moves();
+
+        let captures = gen.captures();
+        let quiets = gen.quiets();
+        for &m in &captures {
+            assert!(board.move_info(m).is_capture(), "{} on {}", m, board);
+        }
+        for &m in &quiets {
+            assert!(!board.move_info(m).is_capture(), "{} on {}", m, board);
+        }
+        let stages = captures.into_iter().chain(quiets).collect();
+        assert_eq!(sorted(stages), sorted(pseudo.clone()), "{}", board);
+        assert_eq!(sorted(gen.legal()), sorted(legal.clone()), "{}", board);
+        for &m in &pseudo {
+            assert!(board.is_pseudo_legal(m), "{} on {}", m, board);
+        }
+
+        if board.in_check() {
+            let mut evasions = gen.evasions();
+            evasions.retain(|m| board.is_legal_after_pseudo(*m));
+            assert_eq!(sorted(evasions), sorted(legal.clone()), "{}", board);
+        } else {
+            assert!(gen.evasions().is_empty());
+        }
+
+        if depth > 0 {
+            for m in legal {
+                check_stages(&board.perform_move(m).unwrap(), depth - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn stages_agree() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            // en passant to get out of check from the pawn
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        ];
+        for fen in fens {
+            check_stages(&Board::load_fen(fen).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects_moves_that_cant_be_made() {
+        let board = Board::load_fen("r3k2r/8/8/8/8/8/7P/R3K1NR w KQkq - 0 1").unwrap();
+        let uci = |s| Move::from_uci(s, &board).unwrap();
+
+        assert!(board.is_pseudo_legal(uci("e1c1")));
+        assert!(board.is_pseudo_legal(uci("a1a8")));
+        // the knight is in the way of castling
+        assert!(!board.is_pseudo_legal(uci("e1g1")));
+        // knights jump, rooks and pawns don't
+        assert!(!board.is_pseudo_legal(uci("g1g3")));
+        assert!(!board.is_pseudo_legal(uci("h1h3")));
+        assert!(board.is_pseudo_legal(uci("g1h3")));
+        // it isn't black's move
+        assert!(!board.is_pseudo_legal(uci("a8a1")));
+        // a pawn only promotes on the last rank
+        assert!(!board.is_pseudo_legal(Move::Promotion {
+            from: "h2".parse().unwrap(),
+            to: "h3".parse().unwrap(),
+            target: PieceType::Queen,
+        }));
+    }
+}
@@ -0,0 +1,237 @@
+//! Standard algebraic notation (SAN), the notation used by humans and
+//! in PGN files, e.g. `Nf3`, `exd5`, `O-O` or `e8=Q+`
+use super::{Board, Castling, Move, SquareSpec};
+use crate::error::Error;
+use crate::piece::PieceType;
+
+impl Move {
+    /// Parse a move written in standard algebraic notation. Check and
+    /// annotation marks like `+`, `#` or `!?` are ignored, as is a
+    /// missing `x` for captures. The move has to be legal on `board`.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the string isn't a move, or if it
+    /// doesn't describe exactly one legal move.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Castling, Move};
+    /// let board = Board::default_board();
+    ///
+    /// assert_eq!(Move::from_san("Nf3", &board).unwrap(), Move::from_uci("g1f3", &board).unwrap());
+    /// assert!(Move::from_san("O-O", &board).is_err());
+    /// ```
+    pub fn from_san(s: &str, board: &Board) -> Result<Move, Error> {
+        let invalid = || Error::InvalidMove(s.to_string());
+
+        let san = s.trim_end_matches(|c| matches!(c, '+' | '#' | '!' | '?'));
+        let castling = match san {
+            "O-O" | "0-0" => Some(Castling::Short),
+            "O-O-O" | "0-0-0" => Some(Castling::Long),
+            _ => None,
+        };
+        if let Some(castling) = castling {
+            let m = Move::Castling(castling);
+            return if board.is_legal(m, board.turn()) {
+                Ok(m)
+            } else {
+                Err(invalid())
+            };
+        }
+
+        if !san.is_ascii() || san.len() < 2 {
+            return Err(invalid());
+        }
+        let (piece, rest) = match san[0..1].parse::<PieceType>() {
+            Ok(PieceType::Pawn) => return Err(invalid()),
+            Ok(piece) => (piece, &san[1..]),
+            Err(_) => (PieceType::Pawn, san),
+        };
+        // the promotion is written `e8=Q`, or sometimes `e8Q`
+        let (rest, target) = match rest.rfind(|c: char| c.is_ascii_uppercase()) {
+            Some(i) => {
+                let target = rest[i..].parse::<PieceType>().map_err(|_| invalid())?;
+                (rest[..i].trim_end_matches('='), Some(target))
+            }
+            None => (rest, None),
+        };
+        if rest.len() < 2 {
+            return Err(invalid());
+        }
+        let to = rest[rest.len() - 2..]
+            .parse::<SquareSpec>()
+            .map_err(|_| invalid())?;
+        // whatever is left is the disambiguation, e.g. the `b` of `Nbd2`
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in rest[..rest.len() - 2].chars().filter(|&c| c != 'x') {
+            match c {
+                'a'..='h' => from_file = Some(c as u32 - 'a' as u32),
+                '1'..='8' => from_rank = Some(c as u32 - '1' as u32),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut candidates = board.get_all_legal_moves().into_iter().filter(|m| {
+            let from = m.from(board.turn());
+            let promotion = match m {
+                Move::Promotion { target, .. } => Some(*target),
+                _ => None,
+            };
+            !matches!(m, Move::Castling(_))
+                && board[from].map(|p| p.piece) == Some(piece)
+                && m.to(board.turn()) == to
+                && promotion == target
+                && from_file.map_or(true, |file| from.file == file)
+                && from_rank.map_or(true, |rank| from.rank == rank)
+        });
+
+        match (candidates.next(), candidates.next()) {
+            (Some(m), None) => Ok(m),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Write a move that is legal on `board` in standard algebraic
+    /// notation, including the `+` or `#` for checks and checkmates
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, Move};
+    /// let board = Board::load_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+    ///
+    /// assert_eq!(Move::from_uci("a1a8", &board).unwrap().to_san(&board), "Ra8+");
+    /// assert_eq!(Move::from_uci("h1h8", &board).unwrap().to_san(&board), "Rh8+");
+    /// assert_eq!(Move::from_uci("e1c1", &board).unwrap().to_san(&board), "O-O-O");
+    /// ```
+    pub fn to_san(&self, board: &Board) -> String {
+        let mut san = match self {
+            Move::Castling(Castling::Short) => "O-O".to_string(),
+            Move::Castling(Castling::Long) => "O-O-O".to_string(),
+            Move::Normal { from, to } | Move::Promotion { from, to, .. } => {
+                san_without_check(*self, *from, *to, board)
+            }
+        };
+
+        if let Some(next) = board.perform_move(*self) {
+            if next.in_check() {
+                san.push(if next.get_all_legal_moves().is_empty() {
+                    '#'
+                } else {
+                    '+'
+                });
+            }
+        }
+        san
+    }
+}
+
+fn san_without_check(m: Move, from: SquareSpec, to: SquareSpec, board: &Board) -> String {
+    let piece = match board[from] {
+        Some(piece) => piece.piece,
+        None => return m.to_string(),
+    };
+    let file = |sq: SquareSpec| char::from(b'a' + sq.file as u8);
+    let rank = |sq: SquareSpec| char::from(b'1' + sq.rank as u8);
+    let is_capture = board[to].is_some() || (piece == PieceType::Pawn && from.file != to.file);
+
+    let mut san = String::new();
+    if piece == PieceType::Pawn {
+        if is_capture {
+            san.push(file(from));
+        }
+    } else {
+        san.push_str(&piece.to_string());
+
+        // the other pieces of the same kind that can go to the square
+        let others = board
+            .get_all_legal_moves()
+            .into_iter()
+            .map(|m| m.from(board.turn()))
+            .filter(|&sq| {
+                sq != from
+                    && board[sq].map(|p| p.piece) == Some(piece)
+                    && board.is_legal(Move::Normal { from: sq, to }, board.turn())
+            })
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            if others.iter().all(|sq| sq.file != from.file) {
+                san.push(file(from));
+            } else if others.iter().all(|sq| sq.rank != from.rank) {
+                san.push(rank(from));
+            } else {
+                san.push(file(from));
+                san.push(rank(from));
+            }
+        }
+    }
+    if is_capture {
+        san.push('x');
+    }
+    san.push_str(&to.to_string());
+    if let Move::Promotion { target, .. } = m {
+        san.push('=');
+        san.push_str(&target.to_string());
+    }
+    san
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_all_moves() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 2",
+        ];
+        for fen in fens {
+            let board = Board::load_fen(fen).unwrap();
+            for m in board.get_all_legal_moves() {
+                let san = m.to_san(&board);
+                assert_eq!(
+                    Move::from_san(&san, &board).unwrap(),
+                    m,
+                    "{} in {}",
+                    san,
+                    fen
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn disambiguates_pieces() {
+        // knights on b1 and f3 can go to d2, rooks on a1 and a5 to a3
+        let board = Board::load_fen("4k3/8/8/R7/8/5N2/8/RN2K3 w - - 0 1").unwrap();
+        let san = |uci| Move::from_uci(uci, &board).unwrap().to_san(&board);
+
+        assert_eq!(san("b1d2"), "Nbd2");
+        assert_eq!(san("f3d2"), "Nfd2");
+        assert_eq!(san("a1a3"), "R1a3");
+        assert_eq!(san("f3e5"), "Ne5");
+
+        assert!(Move::from_san("Nd2", &board).is_err());
+        assert!(Move::from_san("R5a3", &board).is_ok());
+    }
+
+    #[test]
+    fn parses_promotions_and_annotations() {
+        let board = Board::load_fen("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let promotion = Move::Promotion {
+            from: "b7".parse().unwrap(),
+            to: "b8".parse().unwrap(),
+            target: PieceType::Queen,
+        };
+
+        assert_eq!(promotion.to_san(&board), "b8=Q+");
+        assert_eq!(Move::from_san("b8=Q+!?", &board).unwrap(), promotion);
+        assert_eq!(Move::from_san("b8Q", &board).unwrap(), promotion);
+        assert!(Move::from_san("b8", &board).is_err());
+        assert!(Move::from_san("Pb8=Q", &board).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Working out why a move is illegal, to explain it to whoever tried
+//! to make it
+use super::{Board, Castling, Move, SquareDiff, SquareSpec};
+use crate::piece::{Piece, PieceType};
+use thiserror::Error;
+
+/// Why a move is illegal, see [`Board::validate_move`]
+#[derive(Error, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IllegalReason {
+    /// There's no piece to move
+    #[error("there is no piece on {0}")]
+    NoPiece(SquareSpec),
+    /// The piece belongs to the other player
+    #[error("the piece on {0} isn't yours to move")]
+    NotYourPiece(SquareSpec),
+    /// The piece doesn't move like that, or something is in the way
+    #[error("the piece on {1} can't move to {2}")]
+    CantMoveThere(PieceType, SquareSpec, SquareSpec),
+    /// A pawn reaching the last rank has to promote
+    #[error("the pawn has to promote on {0}")]
+    MustPromote(SquareSpec),
+    /// Only pawns reaching the last rank promote, and only to a queen,
+    /// rook, bishop or knight
+    #[error("that isn't a valid promotion")]
+    InvalidPromotion,
+    /// The king or the rook has moved, or the rook has been taken
+    #[error("you can't castle on that side any more")]
+    NoCastlingRights,
+    /// There are pieces between the king and the rook
+    #[error("there are pieces between the king and the rook")]
+    CastlingBlocked,
+    /// Castling isn't allowed while in check
+    #[error("you can't castle out of check")]
+    CastlingOutOfCheck,
+    /// The king can't pass through an attacked square when castling
+    #[error("you can't castle through check")]
+    CastlingThroughCheck,
+    /// The piece can't move off the line between the king and the
+    /// piece pinning it
+    #[error("the piece on {0} is pinned to your king")]
+    Pinned(SquareSpec),
+    /// The king is in check and the move doesn't get it out
+    #[error("your king is in check")]
+    InCheck,
+    /// The move would put or leave the king in check
+    #[error("your king would be in check")]
+    KingInCheck,
+}
+
+impl Board {
+    /// Check whether `m` is legal for the player to move, and if it
+    /// isn't, work out why
+    ///
+    /// # Errors
+    ///
+    /// Will return the reason if the move is illegal.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::{Board, IllegalReason, Move};
+    /// let board = Board::load_fen("4k3/8/8/8/8/8/3r4/R3K3 w Q - 0 1").unwrap();
+    ///
+    /// let castle = Move::from_uci("e1c1", &board).unwrap();
+    /// assert_eq!(board.validate_move(castle), Err(IllegalReason::CastlingThroughCheck));
+    /// let king = Move::from_uci("e1f2", &board).unwrap();
+    /// assert_eq!(board.validate_move(king), Err(IllegalReason::KingInCheck));
+    /// ```
+    pub fn validate_move(&self, m: Move) -> Result<(), IllegalReason> {
+        let from = m.from(self.turn);
+        let piece = match self[from] {
+            Some(piece) if piece.color == self.turn => piece,
+            Some(_) => return Err(IllegalReason::NotYourPiece(from)),
+            None => return Err(IllegalReason::NoPiece(from)),
+        };
+
+        if let Move::Castling(castling) = m {
+            return self.validate_castling(castling, piece);
+        }
+
+        if !self.is_pseudo_legal(m) {
+            let to = m.to(self.turn);
+            let last_rank = self.turn.opposite().home_rank();
+            let promotes = piece.piece == PieceType::Pawn && to.rank == last_rank;
+            return Err(match m {
+                Move::Promotion { target, .. }
+                    if !promotes || matches!(target, PieceType::Pawn | PieceType::King) =>
+                {
+                    IllegalReason::InvalidPromotion
+                }
+                Move::Normal { .. } if promotes && self.is_pseudo_legal(promotion(m)) => {
+                    IllegalReason::MustPromote(to)
+                }
+                _ => IllegalReason::CantMoveThere(piece.piece, from, to),
+            });
+        }
+
+        if self.is_legal_after_pseudo(m) {
+            return Ok(());
+        }
+        let to = m.to(self.turn);
+        if piece.piece == PieceType::King {
+            return Err(IllegalReason::KingInCheck);
+        }
+        let pinned = self
+            .pinned_pieces(self.turn)
+            .into_iter()
+            .any(|pin| pin.pinned == from && !pin.ray.contains(&to));
+        Err(if pinned {
+            IllegalReason::Pinned(from)
+        } else if self.in_check() {
+            IllegalReason::InCheck
+        } else {
+            // e.g. taking en passant, which takes two pieces off the
+            // king's rank at once
+            IllegalReason::KingInCheck
+        })
+    }
+
+    fn validate_castling(&self, castling: Castling, king: Piece) -> Result<(), IllegalReason> {
+        let color = self.turn;
+        let rank = color.home_rank();
+        let (rook_file, direction) = match castling {
+            Castling::Short => (7, 1),
+            Castling::Long => (0, -1),
+        };
+        let rook = SquareSpec::new(rank, rook_file);
+        let king_sq = SquareSpec::new(rank, 4);
+        if !self.can_castle(castling, color)
+            || king.piece != PieceType::King
+            || self[rook] != Some(Piece::new(PieceType::Rook, color))
+        {
+            return Err(IllegalReason::NoCastlingRights);
+        }
+
+        let mut sq = king_sq + SquareDiff::new(0, direction);
+        while sq != rook {
+            if self[sq].is_some() {
+                return Err(IllegalReason::CastlingBlocked);
+            }
+            sq += SquareDiff::new(0, direction);
+        }
+
+        let attacked = |sq: SquareSpec| !self.attackers_of(sq, color.opposite()).is_empty();
+        if attacked(king_sq) {
+            return Err(IllegalReason::CastlingOutOfCheck);
+        }
+        if attacked(king_sq + SquareDiff::new(0, direction)) {
+            return Err(IllegalReason::CastlingThroughCheck);
+        }
+        if attacked(king_sq + SquareDiff::new(0, 2 * direction)) {
+            return Err(IllegalReason::KingInCheck);
+        }
+        Ok(())
+    }
+}
+
+/// The same move as a promotion to a queen
+fn promotion(m: Move) -> Move {
+    match m {
+        Move::Normal { from, to } => Move::Promotion {
+            from,
+            to,
+            target: PieceType::Queen,
+        },
+        m => m,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(fen: &str, uci: &str) -> Result<(), IllegalReason> {
+        let board = Board::load_fen(fen).unwrap();
+        board.validate_move(Move::from_uci(uci, &board).unwrap())
+    }
+
+    fn square(s: &str) -> SquareSpec {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn explains_illegal_moves() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(reason(start, "e2e4"), Ok(()));
+        assert_eq!(
+            reason(start, "e2e5"),
+            Err(IllegalReason::CantMoveThere(
+                PieceType::Pawn,
+                square("e2"),
+                square("e5")
+            ))
+        );
+        assert_eq!(
+            reason(start, "e7e5"),
+            Err(IllegalReason::NotYourPiece(square("e7")))
+        );
+        let empty = Move::Normal {
+            from: square("e4"),
+            to: square("e5"),
+        };
+        assert_eq!(
+            Board::default_board().validate_move(empty),
+            Err(IllegalReason::NoPiece(square("e4")))
+        );
+
+        let pinned = "4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1";
+        assert_eq!(
+            reason(pinned, "e2c3"),
+            Err(IllegalReason::Pinned(square("e2")))
+        );
+        let check = "4k3/4r3/8/8/8/8/3N4/4K3 w - - 0 1";
+        assert_eq!(reason(check, "d2b3"), Err(IllegalReason::InCheck));
+        assert_eq!(reason(check, "d2e4"), Ok(()));
+        assert_eq!(reason(check, "e1e2"), Err(IllegalReason::KingInCheck));
+    }
+
+    #[test]
+    fn explains_promotions() {
+        let fen = "4k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        let board = Board::load_fen(fen).unwrap();
+        let promote = |to: &str, target| Move::Promotion {
+            from: square("b7"),
+            to: square(to),
+            target,
+        };
+
+        assert_eq!(
+            board.validate_move(promote("b8", PieceType::Knight)),
+            Ok(())
+        );
+        assert_eq!(
+            board.validate_move(promote("b8", PieceType::King)),
+            Err(IllegalReason::InvalidPromotion)
+        );
+        let normal = Move::Normal {
+            from: square("b7"),
+            to: square("b8"),
+        };
+        assert_eq!(
+            board.validate_move(normal),
+            Err(IllegalReason::MustPromote(square("b8")))
+        );
+        assert_eq!(
+            reason("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1", "b6b7q"),
+            Err(IllegalReason::InvalidPromotion)
+        );
+    }
+
+    #[test]
+    fn explains_castling() {
+        assert_eq!(reason("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"), Ok(()));
+        assert_eq!(
+            reason("4k3/8/8/8/8/8/8/R3K2R w Q - 0 1", "e1g1"),
+            Err(IllegalReason::NoCastlingRights)
+        );
+        assert_eq!(
+            reason("4k3/8/8/8/8/8/8/R3K3 w KQ - 0 1", "e1g1"),
+            Err(IllegalReason::NoCastlingRights)
+        );
+        assert_eq!(
+            reason("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1", "e1c1"),
+            Err(IllegalReason::CastlingBlocked)
+        );
+        assert_eq!(
+            reason("4r1k1/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"),
+            Err(IllegalReason::CastlingOutOfCheck)
+        );
+        assert_eq!(
+            reason("5rk1/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"),
+            Err(IllegalReason::CastlingThroughCheck)
+        );
+        assert_eq!(
+            reason("2r1k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"),
+            Err(IllegalReason::KingInCheck)
+        );
+    }
+
+    #[test]
+    fn agrees_with_is_legal() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ];
+        for fen in fens {
+            let board = Board::load_fen(fen).unwrap();
+            // every move between any two squares, and both castlings
+            let squares = (0..64).map(|i| SquareSpec::new(i / 8, i % 8));
+            let mut moves = vec![
+                Move::Castling(Castling::Short),
+                Move::Castling(Castling::Long),
+            ];
+            for from in squares.clone() {
+                for to in squares.clone() {
+                    moves.push(Move::Normal { from, to });
+                    moves.push(promotion(Move::Normal { from, to }));
+                }
+            }
+
+            for m in moves {
+                assert_eq!(
+                    board.validate_move(m).is_ok(),
+                    board.is_legal(m, board.turn()),
+                    "{} on {}",
+                    m,
+                    board
+                );
+            }
+        }
+    }
+}
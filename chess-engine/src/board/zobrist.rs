@@ -0,0 +1,159 @@
+//! Zobrist hashing of boards, used to identify positions in e.g. the
+//! transposition table
+use super::{Board, CastlingFlags};
+use crate::piece::{Color, PieceType};
+
+// splitmix64, which is good enough to generate the keys at compile
+// time without pulling in a dependency for random numbers
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+// 12 piece kinds * 64 squares, then 4 castling flags, 8 en passant
+// files and finally the side to move
+const N_KEYS: usize = 12 * 64 + 4 + 8 + 1;
+
+const KEYS: [u64; N_KEYS] = {
+    let mut keys = [0; N_KEYS];
+    let mut state = 0x7468_7374_726F_0000;
+    let mut i = 0;
+    while i < N_KEYS {
+        let (next_state, key) = splitmix64(state);
+        state = next_state;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+};
+
+const CASTLING_OFFSET: usize = 12 * 64;
+const EN_PASSANT_OFFSET: usize = CASTLING_OFFSET + 4;
+const TURN_OFFSET: usize = EN_PASSANT_OFFSET + 8;
+
+fn piece_index(piece: PieceType, color: Color) -> usize {
+    let piece = match piece {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    };
+    match color {
+        Color::White => piece,
+        Color::Black => piece + 6,
+    }
+}
+
+impl Board {
+    /// Get the zobrist key of this board. Two boards with the same
+    /// pieces, side to move, castling rights and en passant square
+    /// will always get the same key, the move counters are ignored.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::Board;
+    /// let default = Board::default_board();
+    /// let loaded = Board::load_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 5 20").unwrap();
+    ///
+    /// assert_eq!(default.zobrist_key(), loaded.zobrist_key());
+    /// ```
+    pub fn zobrist_key(&self) -> u64 {
+        let mut key = 0;
+
+        for (rank, row) in self.board.iter().enumerate() {
+            for (file, piece) in row.iter().enumerate() {
+                if let Some(piece) = piece {
+                    key ^= KEYS[piece_index(piece.piece, piece.color) * 64 + rank * 8 + file];
+                }
+            }
+        }
+
+        for (i, flag) in [
+            CastlingFlags::WHITE_SHORT,
+            CastlingFlags::WHITE_LONG,
+            CastlingFlags::BLACK_SHORT,
+            CastlingFlags::BLACK_LONG,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if self.castling.contains(*flag) {
+                key ^= KEYS[CASTLING_OFFSET + i];
+            }
+        }
+
+        if let Some(sq) = self.en_passant {
+            key ^= KEYS[EN_PASSANT_OFFSET + sq.file as usize];
+        }
+
+        if self.turn == Color::Black {
+            key ^= KEYS[TURN_OFFSET];
+        }
+
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Board, Move, SquareSpec};
+
+    #[test]
+    fn transposition_gives_same_key() {
+        let sq = |s: &str| s.parse::<SquareSpec>().unwrap();
+        let board = Board::default_board();
+
+        let a = board
+            .perform_move(Move::Normal {
+                from: sq("g1"),
+                to: sq("f3"),
+            })
+            .and_then(|b| {
+                b.perform_move(Move::Normal {
+                    from: sq("g8"),
+                    to: sq("f6"),
+                })
+            })
+            .and_then(|b| {
+                b.perform_move(Move::Normal {
+                    from: sq("b1"),
+                    to: sq("c3"),
+                })
+            })
+            .unwrap();
+        let b = board
+            .perform_move(Move::Normal {
+                from: sq("b1"),
+                to: sq("c3"),
+            })
+            .and_then(|b| {
+                b.perform_move(Move::Normal {
+                    from: sq("g8"),
+                    to: sq("f6"),
+                })
+            })
+            .and_then(|b| {
+                b.perform_move(Move::Normal {
+                    from: sq("g1"),
+                    to: sq("f3"),
+                })
+            })
+            .unwrap();
+
+        assert_eq!(a.zobrist_key(), b.zobrist_key());
+        assert_ne!(a.zobrist_key(), board.zobrist_key());
+    }
+
+    #[test]
+    fn side_to_move_changes_key() {
+        let white = Board::load_fen("8/8/8/8/8/8/8/K6k w - - 0 1").unwrap();
+        let black = Board::load_fen("8/8/8/8/8/8/8/K6k b - - 0 1").unwrap();
+
+        assert_ne!(white.zobrist_key(), black.zobrist_key());
+    }
+}
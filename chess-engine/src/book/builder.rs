@@ -0,0 +1,359 @@
+//! Building opening books from collections of games
+use super::{encode_move, Book, BookEntry};
+use crate::board::{Board, Move};
+use crate::error::Error;
+use crate::pgn::{GameResult, PgnGame};
+use crate::piece::Color;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// How a move has done in the games it was played in, from the
+/// perspective of the player making it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MoveStats {
+    /// The number of games the move was played in, including those
+    /// without a result
+    pub games: u32,
+    /// The number of those games that were won
+    pub wins: u32,
+    /// The number of those games that were drawn
+    pub draws: u32,
+    /// The number of those games that were lost
+    pub losses: u32,
+}
+
+impl MoveStats {
+    /// The weight of the move in the book, which is its score in half
+    /// points like Polyglot does. Moves that have only lost get a
+    /// weight of zero.
+    pub fn weight(&self) -> u32 {
+        2 * self.wins + self.draws
+    }
+
+    fn add(&mut self, result: GameResult, color: Color) {
+        self.games += 1;
+        match (result, color) {
+            (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => {
+                self.wins += 1;
+            }
+            (GameResult::WhiteWins, Color::Black) | (GameResult::BlackWins, Color::White) => {
+                self.losses += 1;
+            }
+            (GameResult::Draw, _) => self.draws += 1,
+            (GameResult::Unknown, _) => (),
+        }
+    }
+}
+
+/// A position that was seen while building a book, with the moves
+/// played in it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookPosition {
+    /// The first board the position was reached with, transpositions
+    /// into it are counted for it as well
+    pub board: Board,
+    /// The moves played and how they did
+    pub moves: HashMap<Move, MoveStats>,
+}
+
+impl BookPosition {
+    /// The number of games that reached the position
+    pub fn games(&self) -> u32 {
+        self.moves.values().map(|stats| stats.games).sum()
+    }
+
+    /// The moves played often enough, the most played first
+    fn moves_played(&self, min_games: u32) -> Vec<(Move, MoveStats)> {
+        let mut moves = self
+            .moves
+            .iter()
+            .filter(|(_, stats)| stats.games >= min_games)
+            .map(|(&m, &stats)| (m, stats))
+            .collect::<Vec<_>>();
+        // the moves are sorted by name too, so that the order is the
+        // same every time
+        moves.sort_by_cached_key(|&(m, stats)| {
+            (std::cmp::Reverse(stats.games), m.to_uci(self.board.turn()))
+        });
+        moves
+    }
+}
+
+/// Collects the moves of many games to make an opening book of them.
+/// Only the first plies of every game are used, and positions are
+/// identified by their [`Board::polyglot_key`] so that transpositions
+/// are counted together.
+///
+/// # Examples
+/// ```
+/// # use chess_engine::board::{Board, Move};
+/// # use chess_engine::book::{BookBuilder, BookSelection};
+/// # use chess_engine::pgn::read_games;
+/// let games = read_games("1. e4 e5 1-0 1. e4 c5 0-1 1. d4 d5 1/2-1/2").unwrap();
+/// let mut builder = BookBuilder::new(2);
+/// for game in &games {
+///     builder.add_game(game).unwrap();
+/// }
+/// let book = builder.build();
+/// let start = Board::default_board();
+///
+/// // e4 scored 2 half points, d4 only 1
+/// assert_eq!(book.pick(&start, BookSelection::Best, 0), Some(Move::from_uci("e2e4", &start).unwrap()));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookBuilder {
+    max_plies: usize,
+    min_games: u32,
+    positions: HashMap<u64, BookPosition>,
+    games: u32,
+}
+
+impl BookBuilder {
+    /// Create a builder that uses the first `max_plies` plies of
+    /// every game
+    pub fn new(max_plies: usize) -> Self {
+        Self {
+            max_plies,
+            min_games: 1,
+            positions: HashMap::new(),
+            games: 0,
+        }
+    }
+
+    /// Leave out moves that were played in fewer than `min_games`
+    /// games, which are often just mistakes
+    pub fn set_min_games(&mut self, min_games: u32) {
+        self.min_games = min_games;
+    }
+
+    /// The number of games added so far
+    pub fn games(&self) -> u32 {
+        self.games
+    }
+
+    /// Get the statistics of the position of `board`, if it was seen in
+    /// any game
+    pub fn position(&self, board: &Board) -> Option<&BookPosition> {
+        self.positions.get(&board.polyglot_key())
+    }
+
+    /// Add the moves of a game
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the game has an invalid or illegal move
+    /// in the plies used for the book. The moves before it are added
+    /// anyway.
+    pub fn add_game(&mut self, game: &PgnGame) -> Result<(), Error> {
+        self.games += 1;
+        let mut board = game.start()?;
+        for san in game.moves.iter().take(self.max_plies) {
+            let m = Move::from_san(san, &board)?;
+            let position = self
+                .positions
+                .entry(board.polyglot_key())
+                .or_insert_with(|| BookPosition {
+                    board,
+                    moves: HashMap::new(),
+                });
+            position
+                .moves
+                .entry(m)
+                .or_default()
+                .add(game.result, board.turn());
+
+            if let Err(reason) = board.validate_move(m) {
+                return Err(Error::IllegalMove(board.to_string(), m, reason));
+            }
+            let _ = board.make_move(m);
+        }
+        Ok(())
+    }
+
+    /// Get the entries of the book, sorted by key. Weights that don't
+    /// fit in an entry are scaled down for the whole position, and
+    /// moves with a weight of zero are left out.
+    pub fn entries(&self) -> Vec<BookEntry> {
+        let mut entries = Vec::new();
+        for (&key, position) in &self.positions {
+            let moves = position.moves_played(self.min_games);
+            let max = moves
+                .iter()
+                .map(|(_, stats)| stats.weight())
+                .max()
+                .unwrap_or(0);
+            let scale = |weight: u32| {
+                if max <= u32::from(u16::MAX) {
+                    weight as u16
+                } else {
+                    // keep the moves that did score something
+                    (u64::from(weight) * u64::from(u16::MAX) / u64::from(max)).max(1) as u16
+                }
+            };
+
+            for (m, stats) in moves {
+                if stats.weight() == 0 {
+                    continue;
+                }
+                entries.push(BookEntry {
+                    key,
+                    raw_move: encode_move(m, position.board.turn()),
+                    weight: scale(stats.weight()),
+                    learn: 0,
+                });
+            }
+        }
+
+        // the best moves first within a position, like other books
+        entries.sort_by_key(|e| (e.key, std::cmp::Reverse(e.weight), e.raw_move));
+        entries
+    }
+
+    /// Build the book
+    pub fn build(&self) -> Book {
+        Book {
+            entries: self.entries(),
+        }
+    }
+
+    /// Write the positions and moves in a human readable form, like an
+    /// opening explorer. The positions are written as FEN, the most
+    /// played first, with every move's statistics under them:
+    ///
+    /// ```text
+    /// rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 (3 games)
+    ///     e4       2 games  50.0% won  0.0% drawn  50.0% lost
+    ///     d4       1 games  0.0% won  100.0% drawn  0.0% lost
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if writing to `out` fails.
+    pub fn write_explorer(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut positions = self
+            .positions
+            .values()
+            .filter(|position| position.games() >= self.min_games)
+            .collect::<Vec<_>>();
+        positions.sort_by_cached_key(|position| {
+            (
+                std::cmp::Reverse(position.games()),
+                position.board.to_string(),
+            )
+        });
+
+        for position in positions {
+            let moves = position.moves_played(self.min_games);
+            if moves.is_empty() {
+                continue;
+            }
+            writeln!(out, "{} ({} games)", position.board, position.games())?;
+            for (m, stats) in moves {
+                let percent = |n: u32| 100.0 * f64::from(n) / f64::from(stats.games);
+                writeln!(
+                    out,
+                    "    {:8} {} games  {:.1}% won  {:.1}% drawn  {:.1}% lost",
+                    m.to_san(&position.board),
+                    stats.games,
+                    percent(stats.wins),
+                    percent(stats.draws),
+                    percent(stats.losses)
+                )?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::read_games;
+
+    fn builder(pgn: &str, max_plies: usize) -> BookBuilder {
+        let mut builder = BookBuilder::new(max_plies);
+        for game in read_games(pgn).unwrap() {
+            builder.add_game(&game).unwrap();
+        }
+        builder
+    }
+
+    fn uci(board: &Board, m: &str) -> Move {
+        Move::from_uci(m, board).unwrap()
+    }
+
+    #[test]
+    fn counts_results_for_the_player_moving() {
+        let builder = builder("1. e4 e5 1-0 1. e4 c5 0-1 1. e4 e5 1/2-1/2 1. d4 *", 2);
+        let start = Board::default_board();
+        let position = builder.position(&start).unwrap();
+
+        assert_eq!(builder.games(), 4);
+        assert_eq!(position.games(), 4);
+        assert_eq!(
+            position.moves[&uci(&start, "e2e4")],
+            MoveStats {
+                games: 3,
+                wins: 1,
+                draws: 1,
+                losses: 1
+            }
+        );
+
+        let after_e4 = start.perform_move(uci(&start, "e2e4")).unwrap();
+        let black = &builder.position(&after_e4).unwrap().moves;
+        assert_eq!(black[&uci(&after_e4, "c7c5")].wins, 1);
+        assert_eq!(black[&uci(&after_e4, "e7e5")].losses, 1);
+    }
+
+    #[test]
+    fn stops_after_max_plies() {
+        let builder = builder("1. e4 e5 2. Nf3 Nc6 1-0", 3);
+        let board = read_games("1. e4 e5 2. Nf3").unwrap()[0]
+            .replay(usize::MAX)
+            .unwrap()
+            .current_board()
+            .to_owned();
+
+        assert_eq!(builder.positions.len(), 3);
+        assert!(builder.position(&board).is_none());
+    }
+
+    #[test]
+    fn merges_transpositions() {
+        let builder = builder("1. Nf3 Nf6 2. Nc3 d5 1-0 1. Nc3 Nf6 2. Nf3 e6 0-1", 4);
+        let board = read_games("1. Nf3 Nf6 2. Nc3").unwrap()[0]
+            .replay(usize::MAX)
+            .unwrap()
+            .current_board()
+            .to_owned();
+
+        let position = builder.position(&board).unwrap();
+        assert_eq!(position.games(), 2);
+        assert_eq!(position.moves.len(), 2);
+    }
+
+    #[test]
+    fn writes_readable_books() {
+        let mut builder = builder("1. e4 e5 1-0 1. e4 c5 0-1 1. d4 d5 0-1 1. c4 c5 0-1", 2);
+        builder.set_min_games(1);
+        let book = Book::from_bytes(&builder.build().to_bytes()).unwrap();
+        let start = Board::default_board();
+
+        // d4 and c4 only lost, so they aren't in the book
+        let moves = book.moves(&start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].next_move, uci(&start, "e2e4"));
+        assert_eq!(moves[0].weight, 2);
+
+        builder.set_min_games(2);
+        let mut explorer = Vec::new();
+        builder.write_explorer(&mut explorer).unwrap();
+        assert_eq!(
+            String::from_utf8(explorer).unwrap(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 (4 games)\n    \
+             e4       2 games  50.0% won  0.0% drawn  50.0% lost\n\n"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Opening books in the Polyglot format, which is a file of 16 byte
+//! entries sorted by the [`Board::polyglot_key`] of the position they
+//! are for. See <http://hgm.nubati.net/book_format.html> for the
+//! specification.
+//!
+//! # Examples
+//! ```
+//! # use chess_engine::board::{Board, Move};
+//! # use chess_engine::book::{Book, BookEntry, BookSelection};
+//! // a book with only 1. e4
+//! let entry = BookEntry {
+//!     key: Board::default_board().polyglot_key(),
+//!     raw_move: 0x031c,
+//!     weight: 1,
+//!     learn: 0,
+//! };
+//! let book = Book::from_bytes(&entry.to_bytes()).unwrap();
+//!
+//! assert_eq!(
+//!     book.pick(&Board::default_board(), BookSelection::Best, 0),
+//!     Some(Move::Normal {
+//!         from: "e2".parse().unwrap(),
+//!         to: "e4".parse().unwrap(),
+//!     })
+//! );
+//! ```
+use crate::board::{Board, Castling, Move, SquareSpec};
+use crate::error::Error;
+use crate::piece::{Color, Piece, PieceType};
+use std::fs;
+use std::path::Path;
+
+mod builder;
+
+pub use builder::{BookBuilder, BookPosition, MoveStats};
+
+/// The size of an entry in a book file, in bytes
+pub const ENTRY_SIZE: usize = 16;
+
+/// One entry of a book, as it's stored in the file
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BookEntry {
+    /// The [`Board::polyglot_key`] of the position
+    pub key: u64,
+    /// The move, in the encoding used by Polyglot
+    pub raw_move: u16,
+    /// How often the move should be played compared to the other moves
+    /// in the position. A weight of zero means that it shouldn't be
+    /// played at all.
+    pub weight: u16,
+    /// Used by some programs to learn from their games, ignored here
+    pub learn: u32,
+}
+
+impl BookEntry {
+    /// Read an entry from its big endian representation
+    pub fn from_bytes(bytes: [u8; ENTRY_SIZE]) -> Self {
+        let [k0, k1, k2, k3, k4, k5, k6, k7, m0, m1, w0, w1, l0, l1, l2, l3] = bytes;
+        Self {
+            key: u64::from_be_bytes([k0, k1, k2, k3, k4, k5, k6, k7]),
+            raw_move: u16::from_be_bytes([m0, m1]),
+            weight: u16::from_be_bytes([w0, w1]),
+            learn: u32::from_be_bytes([l0, l1, l2, l3]),
+        }
+    }
+
+    /// Get the big endian representation of the entry, as stored in a
+    /// book file
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.raw_move.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+/// A move found in a book
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BookMove {
+    /// The move itself
+    pub next_move: Move,
+    /// How often the move should be played, see [`BookEntry::weight`]
+    pub weight: u16,
+}
+
+/// How to pick between the moves of a position
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BookSelection {
+    /// Always play the move with the highest weight
+    Best,
+    /// Pick a move randomly, where the chance of a move being played
+    /// is proportional to its weight
+    Weighted,
+}
+
+/// An opening book, loaded into memory
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Book {
+    // sorted by key
+    entries: Vec<BookEntry>,
+}
+
+impl Book {
+    /// Load a book from a file
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the file can't be read or isn't a
+    /// valid book, see [`Book::from_bytes`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Read a book from the contents of a book file
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the length isn't a multiple of the
+    /// size of an entry, or if the entries aren't sorted.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(Error::InvalidBook(format!(
+                "the size {} isn't a multiple of {}",
+                bytes.len(),
+                ENTRY_SIZE
+            )));
+        }
+
+        let mut entries = Vec::with_capacity(bytes.len() / ENTRY_SIZE);
+        for chunk in bytes.chunks_exact(ENTRY_SIZE) {
+            let mut entry = [0; ENTRY_SIZE];
+            entry.copy_from_slice(chunk);
+            entries.push(BookEntry::from_bytes(entry));
+        }
+        if entries.windows(2).any(|pair| pair[0].key > pair[1].key) {
+            return Err(Error::InvalidBook(
+                "the entries aren't sorted by key".to_string(),
+            ));
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Get the contents of the book file for the book
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.entries.iter().flat_map(BookEntry::to_bytes).collect()
+    }
+
+    /// Write the book to a file
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the file can't be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Get all the entries of the book
+    pub fn entries(&self) -> &[BookEntry] {
+        &self.entries
+    }
+
+    /// Get the number of entries in the book
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the book has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the entries for the position with the Polyglot key `key`
+    pub fn entries_for(&self, key: u64) -> &[BookEntry] {
+        // the first entry with the key, and the first one after it
+        let start = self.entries.partition_point(|e| e.key < key);
+        let end = self.entries.partition_point(|e| e.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// Get the legal moves the book knows for `board`, with the highest
+    /// weight first. Moves that aren't legal, which can happen if two
+    /// positions have the same key, are left out.
+    pub fn moves(&self, board: &Board) -> Vec<BookMove> {
+        let mut moves = self
+            .entries_for(board.polyglot_key())
+            .iter()
+            .filter_map(|entry| {
+                let next_move = decode_move(entry.raw_move, board)?;
+                if !board.is_legal(next_move, board.turn()) {
+                    return None;
+                }
+                Some(BookMove {
+                    next_move,
+                    weight: entry.weight,
+                })
+            })
+            .collect::<Vec<_>>();
+        moves.sort_by_key(|m| std::cmp::Reverse(m.weight));
+        moves
+    }
+
+    /// Pick a move to play on `board`, or [`None`] if the book doesn't
+    /// know the position or only has moves of weight zero. `random` is
+    /// only used for [`BookSelection::Weighted`] and should be a
+    /// random number, any range is fine.
+    pub fn pick(&self, board: &Board, selection: BookSelection, random: u64) -> Option<Move> {
+        let moves = self.moves(board);
+        let total = moves.iter().map(|m| u64::from(m.weight)).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        match selection {
+            BookSelection::Best => Some(moves[0].next_move),
+            BookSelection::Weighted => {
+                let mut n = random % total;
+                for m in &moves {
+                    if n < u64::from(m.weight) {
+                        return Some(m.next_move);
+                    }
+                    n -= u64::from(m.weight);
+                }
+                unreachable!("The weights add up to the total")
+            }
+        }
+    }
+}
+
+/// Decode a move in the Polyglot encoding, where castling is written
+/// as the king taking its own rook, e.g. `e1h1`. The board is needed to
+/// know which piece is moving, returns [`None`] if there is none.
+///
+/// # Examples
+/// ```
+/// # use chess_engine::board::{Board, Castling, Move};
+/// # use chess_engine::book::decode_move;
+/// let board = Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+/// // e1a1
+/// assert_eq!(decode_move(0x0100, &board), Some(Move::Castling(Castling::Long)));
+/// ```
+pub fn decode_move(raw_move: u16, board: &Board) -> Option<Move> {
+    let square = |bits: u16| SquareSpec::new(u32::from((bits >> 3) & 7), u32::from(bits & 7));
+    let to = square(raw_move);
+    let from = square(raw_move >> 6);
+    let piece = board[from]?;
+
+    if piece.piece == PieceType::King
+        && from == SquareSpec::new(piece.color.home_rank(), 4)
+        && board[to] == Some(Piece::new(PieceType::Rook, piece.color))
+    {
+        return match to.file {
+            7 => Some(Move::Castling(Castling::Short)),
+            0 => Some(Move::Castling(Castling::Long)),
+            _ => None,
+        };
+    }
+
+    let target = match (raw_move >> 12) & 7 {
+        0 => return Move::new(piece, from, to),
+        1 => PieceType::Knight,
+        2 => PieceType::Bishop,
+        3 => PieceType::Rook,
+        4 => PieceType::Queen,
+        _ => return None,
+    };
+    Some(Move::Promotion { from, to, target })
+}
+
+/// Encode a move made by `color` in the Polyglot encoding, see
+/// [`decode_move`]
+///
+/// # Examples
+/// ```
+/// # use chess_engine::board::{Castling, Move};
+/// # use chess_engine::book::{decode_move, encode_move};
+/// # use chess_engine::piece::Color;
+/// # use chess_engine::Board;
+/// let castling = Move::Castling(Castling::Short);
+/// let board = Board::load_fen("4k2r/8/8/8/8/8/8/4K3 b k - 0 1").unwrap();
+///
+/// assert_eq!(decode_move(encode_move(castling, Color::Black), &board), Some(castling));
+/// ```
+pub fn encode_move(m: Move, color: Color) -> u16 {
+    let square = |sq: SquareSpec| (sq.rank * 8 + sq.file) as u16;
+    let rank = color.home_rank();
+    let (from, to, promotion) = match m {
+        Move::Normal { from, to } => (from, to, 0),
+        Move::Promotion { from, to, target } => {
+            let promotion = match target {
+                PieceType::Knight => 1,
+                PieceType::Bishop => 2,
+                PieceType::Rook => 3,
+                _ => 4,
+            };
+            (from, to, promotion)
+        }
+        Move::Castling(Castling::Short) => (SquareSpec::new(rank, 4), SquareSpec::new(rank, 7), 0),
+        Move::Castling(Castling::Long) => (SquareSpec::new(rank, 4), SquareSpec::new(rank, 0), 0),
+    };
+
+    square(to) | square(from) << 6 | promotion << 12
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sq(s: &str) -> SquareSpec {
+        s.parse().unwrap()
+    }
+
+    fn entry(key: u64, m: Move, color: Color, weight: u16) -> BookEntry {
+        BookEntry {
+            key,
+            raw_move: encode_move(m, color),
+            weight,
+            learn: 0,
+        }
+    }
+
+    fn book(entries: &[BookEntry]) -> Book {
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|e| e.key);
+        Book::from_bytes(&Book { entries }.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rejects_bad_books() {
+        assert!(Book::from_bytes(&[0; 17]).is_err());
+
+        let mut bytes = [0; 2 * ENTRY_SIZE];
+        bytes[0] = 1;
+        assert!(Book::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn finds_entries_among_others() {
+        let start = Board::default_board();
+        let e4 = Move::Normal {
+            from: sq("e2"),
+            to: sq("e4"),
+        };
+        let d4 = Move::Normal {
+            from: sq("d2"),
+            to: sq("d4"),
+        };
+        let key = start.polyglot_key();
+        let book = book(&[
+            entry(key - 1, e4, Color::White, 10),
+            entry(key, d4, Color::White, 3),
+            entry(key, e4, Color::White, 5),
+            entry(key + 1, d4, Color::White, 10),
+        ]);
+
+        assert_eq!(
+            book.moves(&start),
+            [
+                BookMove {
+                    next_move: e4,
+                    weight: 5
+                },
+                BookMove {
+                    next_move: d4,
+                    weight: 3
+                }
+            ]
+        );
+        assert_eq!(book.pick(&start, BookSelection::Best, 7), Some(e4));
+        // 0..5 is e4 and 5..8 is d4
+        assert_eq!(book.pick(&start, BookSelection::Weighted, 4), Some(e4));
+        assert_eq!(book.pick(&start, BookSelection::Weighted, 5), Some(d4));
+        assert_eq!(book.pick(&start, BookSelection::Weighted, 16), Some(e4));
+    }
+
+    #[test]
+    fn skips_illegal_and_unweighted_moves() {
+        let board = Board::default_board();
+        let illegal = Move::Normal {
+            from: sq("e2"),
+            to: sq("e5"),
+        };
+        let unweighted = Move::Normal {
+            from: sq("a2"),
+            to: sq("a3"),
+        };
+        let book = book(&[
+            entry(board.polyglot_key(), illegal, Color::White, 5),
+            entry(board.polyglot_key(), unweighted, Color::White, 0),
+        ]);
+
+        assert_eq!(book.moves(&board).len(), 1);
+        assert_eq!(book.pick(&board, BookSelection::Weighted, 0), None);
+    }
+
+    #[test]
+    fn decodes_castling_and_promotions() {
+        let board = Board::load_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+
+        // e1h1, e1a1 and b7a8n
+        assert_eq!(
+            decode_move(0x0107, &board),
+            Some(Move::Castling(Castling::Short))
+        );
+        assert_eq!(
+            decode_move(0x0100, &board),
+            Some(Move::Castling(Castling::Long))
+        );
+        assert_eq!(
+            decode_move(0x1c78, &board),
+            Some(Move::Promotion {
+                from: sq("b7"),
+                to: sq("a8"),
+                target: PieceType::Knight
+            })
+        );
+        // the king moving one step towards the rook isn't castling
+        assert_eq!(
+            decode_move(0x0105, &board),
+            Some(Move::Normal {
+                from: sq("e1"),
+                to: sq("f1")
+            })
+        );
+    }
+}
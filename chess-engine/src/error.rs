@@ -1,23 +1,39 @@
 //! General errors that can happen by the chess engine
-use crate::board::Move;
+use crate::board::{IllegalReason, Move};
 use std::io;
 use thiserror::Error;
 
 /// The general error type
 #[derive(Error, Debug)]
 pub enum Error {
-    /// Error for illegal moves
-    #[error("The move {1} is illegal for the board {0}")]
-    IllegalMove(String, Move),
+    /// Error for illegal moves, with the board and why the move is
+    /// illegal
+    #[error("The move {1} is illegal for the board {0}: {2}")]
+    IllegalMove(String, Move, IllegalReason),
+    /// Error for making a move after the game has ended
+    #[error("the game is already over")]
+    GameOver,
     /// Error for if a string wasn't an valid square
     #[error("`{0}` is not a valid square coordinate")]
     InvalidSquare(String),
     /// Error for trying to parse erroneous FEN
     #[error("`{0}` is invalid FEN")]
     InvalidFen(String),
+    /// Error for parsing something that isn't a move
+    #[error("`{0}` is not a valid move")]
+    InvalidMove(String),
     /// Error for parsing an invalid piece
     #[error("`{0}` is not a valid piece designator")]
     InvalidPiece(String),
+    /// Error for reading something that isn't an opening book
+    #[error("invalid opening book: {0}")]
+    InvalidBook(String),
+    /// Error for reading invalid PGN
+    #[error("invalid PGN: {0}")]
+    InvalidPgn(String),
+    /// Error for tablebases that are missing or can't be read
+    #[error("tablebase error: {0}")]
+    Tablebase(String),
     /// Error for generic IO errors
     #[error(transparent)]
     Io(#[from] io::Error),
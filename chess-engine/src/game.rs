@@ -2,6 +2,7 @@
 //! to create and run a chess game.
 
 use crate::board::{Board, Move};
+use crate::error::Error;
 use crate::piece::Color;
 
 /// The struct representing a chess game, starting in the default
@@ -40,6 +41,29 @@ impl Game {
         }
     }
 
+    /// Create a game starting from any position, e.g. one loaded from
+    /// FEN with [`Board::load_fen`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_engine::game::{BoardState, Game};
+    /// # use chess_engine::board::Board;
+    /// let board = Board::load_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+    /// let game = Game::from_board(board);
+    ///
+    /// assert_eq!(game.board_state(), BoardState::Checkmate);
+    /// ```
+    pub fn from_board(board: Board) -> Self {
+        let mut game = Self {
+            boards: vec![board],
+            moves: vec![],
+            board_state: BoardState::Normal,
+        };
+        game.update_boardstate();
+        game
+    }
+
     /// Get the current board state
     pub fn board_state(&self) -> BoardState {
         self.board_state
@@ -67,22 +91,59 @@ impl Game {
     }
 
     /// Make a move, if it is legal, returns a reference to the new
-    /// board.  If the move was illegal, [None] is returned
+    /// board.  If the move was illegal, [None] is returned. See
+    /// [`Game::try_make_move`] for why.
     pub fn make_move(&mut self, next_move: Move) -> Option<&Board> {
+        self.try_make_move(next_move).ok()
+    }
+
+    /// Make a move, returning a reference to the new board
+    ///
+    /// # Errors
+    ///
+    /// Will return [`Error::GameOver`] if the game has ended, and
+    /// [`Error::IllegalMove`] with the reason if the move is illegal.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_engine::board::{IllegalReason, Move};
+    /// # use chess_engine::error::Error;
+    /// # use chess_engine::game::Game;
+    /// let mut game = Game::new();
+    /// let m = Move::Normal {
+    ///     from: "e2".parse().unwrap(),
+    ///     to: "e5".parse().unwrap(),
+    /// };
+    ///
+    /// match game.try_make_move(m) {
+    ///     Err(Error::IllegalMove(_, _, reason)) => {
+    ///         assert_eq!(reason.to_string(), "the piece on e2 can't move to e5")
+    ///     }
+    ///     _ => panic!("e2e5 is illegal"),
+    /// }
+    /// ```
+    pub fn try_make_move(&mut self, next_move: Move) -> Result<&Board, Error> {
         match self.board_state {
-            BoardState::Draw | BoardState::Stalemate | BoardState::Checkmate => return None,
+            BoardState::Draw | BoardState::Stalemate | BoardState::Checkmate => {
+                return Err(Error::GameOver)
+            }
             _ => (),
         }
 
-        let last_board = self.boards[self.boards.len() - 1];
-        let next_board = match last_board.perform_move(next_move) {
-            Some(board) => board,
-            None => return None,
-        };
+        let mut next_board = *self.current_board();
+        if let Err(reason) = next_board.validate_move(next_move) {
+            return Err(Error::IllegalMove(
+                next_board.to_string(),
+                next_move,
+                reason,
+            ));
+        }
+        let _ = next_board.make_move(next_move);
         self.boards.push(next_board);
         self.moves.push(next_move);
         self.update_boardstate();
-        Some(&self.boards[self.boards.len() - 1])
+        Ok(&self.boards[self.boards.len() - 1])
     }
 
     fn update_boardstate(&mut self) {
@@ -94,8 +155,11 @@ impl Game {
             self.board_state = BoardState::Stalemate;
         } else if board.in_check() {
             self.board_state = BoardState::Check;
-        } else if board.halfmove() == 50 {
+        } else if board.halfmove() >= 100 {
+            // fifty moves by each player
             self.board_state = BoardState::Draw;
+        } else {
+            self.board_state = BoardState::Normal;
         }
     }
 
@@ -139,14 +203,37 @@ impl Game {
     }
 
     /// Undo the last move, returning `None` if there was no last
-    /// move, and the Board/Move combination if there was.
+    /// move, and the Board/Move combination if there was. This also
+    /// makes it possible to keep playing a game that had ended.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use chess_engine::game::{BoardState, Game};
+    /// # use chess_engine::board::{Board, Move};
+    /// let board = Board::load_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    /// let mut game = Game::from_board(board);
+    /// let mate = Move::Normal {
+    ///     from: "a1".parse().unwrap(),
+    ///     to: "a8".parse().unwrap(),
+    /// };
+    ///
+    /// let mated = *game.make_move(mate).unwrap();
+    /// assert_eq!(game.board_state(), BoardState::Checkmate);
+    ///
+    /// assert_eq!(game.undo_move(), Some((mated, mate)));
+    /// assert_eq!(game.current_board(), &board);
+    /// assert_eq!(game.board_state(), BoardState::Normal);
+    /// ```
     ///
     /// # Panics
     ///
     /// This function should be unable to panic as self must at least
     /// contain one board.
     pub fn undo_move(&mut self) -> Option<(Board, Move)> {
-        self.moves.pop().map(|m| (self.boards.pop().unwrap(), m))
+        let undone = self.moves.pop().map(|m| (self.boards.pop().unwrap(), m));
+        self.update_boardstate();
+        undone
     }
 }
 
@@ -155,3 +242,46 @@ impl Default for Game {
         Game::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, moves: &[(&str, &str)]) {
+        for (from, to) in moves {
+            let m = Move::Normal {
+                from: from.parse().unwrap(),
+                to: to.parse().unwrap(),
+            };
+            assert!(game.make_move(m).is_some(), "{}{}", from, to);
+        }
+    }
+
+    #[test]
+    fn fifty_move_rule_takes_a_hundred_halfmoves() {
+        let board = Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w - - 48 60").unwrap();
+        let mut game = Game::from_board(board);
+
+        // fifty halfmoves is only 25 moves by each player
+        play(&mut game, &[("a1", "a2"), ("e8", "d8")]);
+        assert_eq!(game.current_board().halfmove(), 50);
+        assert_eq!(game.board_state(), BoardState::Normal);
+
+        let board = Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w - - 98 60").unwrap();
+        let mut game = Game::from_board(board);
+        play(&mut game, &[("a1", "a2")]);
+        assert_eq!(game.board_state(), BoardState::Normal);
+        play(&mut game, &[("e8", "d8")]);
+        assert_eq!(game.board_state(), BoardState::Draw);
+    }
+
+    #[test]
+    fn check_ends_with_the_next_move() {
+        let mut game = Game::from_board(Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap());
+
+        play(&mut game, &[("a1", "a8")]);
+        assert_eq!(game.board_state(), BoardState::Check);
+        play(&mut game, &[("e8", "e7")]);
+        assert_eq!(game.board_state(), BoardState::Normal);
+    }
+}
@@ -98,9 +98,13 @@
 mod macros;
 
 pub mod board;
+pub mod book;
 pub mod error;
 pub mod game;
+pub mod pgn;
 pub mod piece;
+pub mod search;
+pub mod tablebase;
 
 pub use board::{Board, Move, SquareSpec};
 pub use error::Error;
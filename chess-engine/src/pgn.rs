@@ -0,0 +1,323 @@
+//! Reading games in the Portable Game Notation (PGN). Only the tags,
+//! the moves of the main line and the result are kept, comments,
+//! variations and annotations are skipped.
+//!
+//! # Examples
+//! ```
+//! # use chess_engine::pgn::{read_games, GameResult};
+//! let games = read_games(
+//!     r#"[Event "Casual game"]
+//!
+//! 1. f3 e5 2. g4 {a blunder} Qh4# 0-1"#,
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(games[0].tag("Event"), Some("Casual game"));
+//! assert_eq!(games[0].moves, ["f3", "e5", "g4", "Qh4#"]);
+//! assert_eq!(games[0].result, GameResult::BlackWins);
+//! ```
+use crate::board::{Board, Move};
+use crate::error::Error;
+use crate::game::Game;
+use std::str::FromStr;
+
+/// How a game ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    /// `1-0`
+    WhiteWins,
+    /// `0-1`
+    BlackWins,
+    /// `1/2-1/2`
+    Draw,
+    /// `*`, the game is still going on or the result is unknown
+    Unknown,
+}
+
+impl Default for GameResult {
+    fn default() -> Self {
+        GameResult::Unknown
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<GameResult, Error> {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWins),
+            "0-1" => Ok(GameResult::BlackWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Unknown),
+            _ => Err(Error::InvalidPgn(format!("`{}` is not a result", s))),
+        }
+    }
+}
+
+/// A game read from a PGN file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PgnGame {
+    /// The tag pairs, e.g. `("White", "Carlsen, Magnus")`, in the order
+    /// they were written
+    pub tags: Vec<(String, String)>,
+    /// The moves of the main line, in standard algebraic notation
+    pub moves: Vec<String>,
+    /// The result at the end of the moves, or of the `Result` tag if
+    /// the moves don't end with one
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    /// Get the value of the tag `name`
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get the position the game starts from, which is the default
+    /// position unless there's a `FEN` tag
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the `FEN` tag is invalid.
+    pub fn start(&self) -> Result<Board, Error> {
+        self.tag("FEN")
+            .map_or_else(|| Ok(Board::default_board()), Board::load_fen)
+    }
+
+    /// Play the first `plies` moves of the game, or all of them if
+    /// there are fewer
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the start position is invalid or if a
+    /// move is invalid or illegal.
+    pub fn replay(&self, plies: usize) -> Result<Game, Error> {
+        let mut game = Game::from_board(self.start()?);
+        for san in self.moves.iter().take(plies) {
+            let m = Move::from_san(san, game.current_board())?;
+            let _ = game.try_make_move(m)?;
+        }
+        Ok(game)
+    }
+}
+
+/// Read all the games in `pgn`
+///
+/// # Errors
+///
+/// Will return an error if a tag pair, comment or variation isn't
+/// closed or a result is misspelled. The moves themselves are only
+/// checked when the game is replayed, see [`PgnGame::replay`].
+pub fn read_games(pgn: &str) -> Result<Vec<PgnGame>, Error> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    // whether the moves of the game have started, a tag after that
+    // starts the next game
+    let mut in_moves = false;
+    let mut result_tag = None;
+
+    let mut chars = pgn.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '[' => {
+                if in_moves {
+                    games.push(finish_game(game, result_tag.take()));
+                    game = PgnGame::default();
+                    in_moves = false;
+                }
+                let (name, value) = read_tag(&mut chars)?;
+                if name == "Result" {
+                    result_tag = Some(value.clone());
+                }
+                game.tags.push((name, value));
+            }
+            '{' => {
+                if !chars.any(|(_, c)| c == '}') {
+                    return Err(Error::InvalidPgn("a comment isn't closed".to_string()));
+                }
+            }
+            '(' => skip_variation(&mut chars)?,
+            // a comment until the end of the line, or an escaped line
+            ';' | '%' => {
+                let _ = chars.find(|&(_, c)| c == '\n');
+            }
+            c if c.is_whitespace() => (),
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || "[]{}();".contains(c) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    let _ = chars.next();
+                }
+                let token = &pgn[start..end];
+                in_moves = true;
+
+                if let Ok(result) = token.parse::<GameResult>() {
+                    game.result = result;
+                    games.push(std::mem::take(&mut game));
+                    in_moves = false;
+                    result_tag = None;
+                } else if let Some(san) = strip_move_number(token) {
+                    game.moves.push(san.to_string());
+                }
+            }
+        }
+    }
+
+    if in_moves || !game.tags.is_empty() {
+        games.push(finish_game(game, result_tag));
+    }
+    Ok(games)
+}
+
+/// A game that didn't end with a result takes it from its tag
+fn finish_game(mut game: PgnGame, result_tag: Option<String>) -> PgnGame {
+    game.result = result_tag
+        .and_then(|result| result.parse().ok())
+        .unwrap_or(GameResult::Unknown);
+    game
+}
+
+/// Strip move numbers like `12.` or `12...` from a token, returns
+/// [`None`] if nothing but the number, or a NAG like `$1`, is left
+fn strip_move_number(token: &str) -> Option<&str> {
+    if token.starts_with('$') {
+        return None;
+    }
+    let san = token
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches('.');
+    // castling written with zeros starts with digits too
+    let san = if token.starts_with("0-0") { token } else { san };
+    if san.is_empty() {
+        None
+    } else {
+        Some(san)
+    }
+}
+
+fn read_tag(chars: &mut impl Iterator<Item = (usize, char)>) -> Result<(String, String), Error> {
+    let invalid = || Error::InvalidPgn("invalid tag pair".to_string());
+
+    let mut name = String::new();
+    let mut value = String::new();
+    let mut in_value = false;
+    let mut escaped = false;
+    loop {
+        let (_, c) = chars.next().ok_or_else(invalid)?;
+        match c {
+            '\\' if in_value && !escaped => escaped = true,
+            '"' if !escaped => in_value = !in_value,
+            ']' if !in_value => break,
+            c if in_value => {
+                value.push(c);
+                escaped = false;
+            }
+            c if c.is_whitespace() => (),
+            c => name.push(c),
+        }
+    }
+
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    Ok((name, value))
+}
+
+/// Skip a variation after its opening parenthesis, they may contain
+/// comments and other variations
+fn skip_variation(chars: &mut impl Iterator<Item = (usize, char)>) -> Result<(), Error> {
+    let mut depth = 1;
+    let mut in_comment = false;
+    for (_, c) in chars {
+        match c {
+            '{' => in_comment = true,
+            '}' => in_comment = false,
+            '(' if !in_comment => depth += 1,
+            ')' if !in_comment => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Err(Error::InvalidPgn("a variation isn't closed".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::BoardState;
+
+    const GAMES: &str = r#"[Event "F/S Return Match"]
+[White "Spassky, Boris V."]
+[Black "Fischer, Robert J."]
+[Result "1/2-1/2"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 {This opening is called the Ruy Lopez.} 3... a6
+4. Ba4 Nf6 5. O-O Be7 (5... b5 6. Bb3 (6. Bxb5?! axb5) Bc5) 6. Re1 $1 b5 1/2-1/2
+
+% an escaped line
+[Event "Unfinished"]
+[Result "1-0"]
+
+1.d4 ; a comment until the end of the line
+d5 2.c4
+[Event "Only tags"]
+[Annotator "Someone \"quoted\""]
+"#;
+
+    #[test]
+    fn reads_tags_moves_and_results() {
+        let games = read_games(GAMES).unwrap();
+
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[0].tag("Black"), Some("Fischer, Robert J."));
+        assert_eq!(
+            games[0].moves,
+            ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O", "Be7", "Re1", "b5"]
+        );
+        assert_eq!(games[0].result, GameResult::Draw);
+
+        assert_eq!(games[1].moves, ["d4", "d5", "c4"]);
+        assert_eq!(games[1].result, GameResult::WhiteWins);
+
+        assert!(games[2].moves.is_empty());
+        assert_eq!(games[2].tag("Annotator"), Some("Someone \"quoted\""));
+        assert_eq!(games[2].result, GameResult::Unknown);
+    }
+
+    #[test]
+    fn replays_games() {
+        let games = read_games(GAMES).unwrap();
+        let game = games[0].replay(usize::MAX).unwrap();
+
+        assert_eq!(game.get_moves().len(), 12);
+        assert_eq!(games[0].replay(4).unwrap().get_moves().len(), 4);
+
+        let mate =
+            read_games("[FEN \"6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\"]\n\n1. Ra8# 1-0").unwrap();
+        assert_eq!(
+            mate[0].replay(usize::MAX).unwrap().board_state(),
+            BoardState::Checkmate
+        );
+    }
+
+    #[test]
+    fn rejects_broken_games() {
+        assert!(read_games("1. e4 {never closed").is_err());
+        assert!(read_games("1. e4 (1. d4").is_err());
+        assert!(read_games("[Event \"never closed").is_err());
+
+        let illegal = read_games("1. e4 e5 2. Ke3 *").unwrap();
+        assert!(illegal[0].replay(usize::MAX).is_err());
+    }
+}
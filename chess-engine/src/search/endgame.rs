@@ -0,0 +1,241 @@
+//! Knowledge about endgames that the general evaluation gets wrong:
+//! material that can't win, king and pawn against king, and how to
+//! mate with a queen or a rook.
+use super::eval::piece_value;
+use super::kpk::Kpk;
+use crate::board::{Board, SquareSpec};
+use crate::piece::{Color, Piece, PieceType};
+
+/// A score for positions that are known to be won, but where mate is
+/// too far away to be found. It's well below the mate scores, so that
+/// an actual mate is always preferred.
+pub(crate) const KNOWN_WIN: i32 = 10_000;
+
+/// The pieces of one side, apart from the king
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Material {
+    pawns: u32,
+    knights: u32,
+    bishops: u32,
+    rooks: u32,
+    queens: u32,
+    king: Option<SquareSpec>,
+}
+
+impl Material {
+    fn pieces(&self) -> u32 {
+        self.knights + self.bishops + self.rooks + self.queens
+    }
+
+    /// Whether the side can't possibly mate, even with help
+    fn can_not_mate(&self) -> bool {
+        self.pawns == 0 && self.rooks == 0 && self.queens == 0 && self.knights + self.bishops <= 1
+    }
+
+    /// Whether the side can't force a win, which without pawns takes
+    /// at least a rook, two bishops or a bishop and a knight
+    fn can_not_force_mate(&self) -> bool {
+        self.pawns == 0
+            && self.rooks == 0
+            && self.queens == 0
+            && (self.knights + self.bishops <= 1 || self.bishops == 0)
+    }
+
+    fn is_lone_king(&self) -> bool {
+        self.pawns == 0 && self.pieces() == 0
+    }
+}
+
+/// Adjust `score`, the general evaluation from the perspective of the
+/// side to move, for the endgames we know more about
+pub(crate) fn adjust(board: &Board, score: i32) -> i32 {
+    let (white, black) = count(board);
+    if white.can_not_mate() && black.can_not_mate() {
+        return 0;
+    }
+
+    for (color, strong, weak) in [
+        (Color::White, &white, &black),
+        (Color::Black, &black, &white),
+    ] {
+        if let Some(score) = known_endgame(board, color, strong, weak) {
+            return if color == board.turn() { score } else { -score };
+        }
+    }
+
+    // e.g. a knight against a lone king, or a bishop against a knight,
+    // only keep a bit of the advantage to prefer the better side of a
+    // draw
+    let leader = match (score > 0, board.turn()) {
+        (true, Color::White) | (false, Color::Black) => &white,
+        (true, Color::Black) | (false, Color::White) => &black,
+    };
+    if leader.can_not_force_mate() {
+        return score / 16;
+    }
+    score
+}
+
+/// The score for `strong`, playing `color`, if they're in an endgame
+/// we know
+fn known_endgame(board: &Board, color: Color, strong: &Material, weak: &Material) -> Option<i32> {
+    let (strong_king, weak_king) = (strong.king?, weak.king?);
+    if !weak.is_lone_king() {
+        return None;
+    }
+
+    // king and pawn against king
+    if strong.pawns == 1 && strong.pieces() == 0 {
+        let pawn = find(board, Piece::new(PieceType::Pawn, color))?;
+        let rank = match color {
+            Color::White => pawn.rank,
+            Color::Black => 7 - pawn.rank,
+        } as i32;
+        // only pawns that could be on the board are in the bitbase
+        if !(1..=6).contains(&rank) {
+            return None;
+        }
+        return Some(if Kpk::get().is_win(board) {
+            // push the pawn to get on to a queen
+            KNOWN_WIN + piece_value(PieceType::Pawn) + 10 * rank
+        } else {
+            0
+        });
+    }
+
+    // a queen or rook against a lone king, push the king to the edge
+    // and bring our king closer to mate it there
+    if strong.pawns == 0 && strong.pieces() == 1 && (strong.queens == 1 || strong.rooks == 1) {
+        let material = if strong.queens == 1 {
+            piece_value(PieceType::Queen)
+        } else {
+            piece_value(PieceType::Rook)
+        };
+        let distance = distance(strong_king, weak_king);
+        return Some(KNOWN_WIN + material + push_to_edge(weak_king) + 10 * (7 - distance));
+    }
+
+    // a rook pawn with a bishop of the wrong color can't queen if the
+    // defending king gets to the corner
+    if strong.pawns > 0 && strong.pieces() == 1 && strong.bishops == 1 {
+        let pawn_file = rook_pawn_file(board, color)?;
+        let queening = SquareSpec::new(color.opposite().home_rank(), pawn_file);
+        let bishop = find(board, Piece::new(PieceType::Bishop, color))?;
+        let same_color = (bishop.rank + bishop.file) % 2 == (queening.rank + queening.file) % 2;
+        if !same_color && distance(weak_king, queening) <= 1 {
+            return Some(0);
+        }
+    }
+
+    None
+}
+
+/// The file of the pawns of `color` if they're all on the a or h file
+fn rook_pawn_file(board: &Board, color: Color) -> Option<u32> {
+    let pawn = Some(Piece::new(PieceType::Pawn, color));
+    let files = board
+        .get_board()
+        .iter()
+        .flat_map(|row| row.iter().enumerate())
+        .filter(|(_, piece)| **piece == pawn)
+        .map(|(file, _)| file as u32)
+        .collect::<Vec<_>>();
+    match files.first() {
+        Some(&file) if (file == 0 || file == 7) && files.iter().all(|&f| f == file) => Some(file),
+        _ => None,
+    }
+}
+
+/// A bonus for the lone king being near the edge, and more so in a
+/// corner
+fn push_to_edge(sq: SquareSpec) -> i32 {
+    let from_center = |n: u32| if n < 4 { 3 - n } else { n - 4 } as i32;
+    20 * (from_center(sq.rank) + from_center(sq.file))
+}
+
+fn distance(a: SquareSpec, b: SquareSpec) -> i32 {
+    let diff = (a - b).abs();
+    diff.d_rank.max(diff.d_file)
+}
+
+fn find(board: &Board, piece: Piece) -> Option<SquareSpec> {
+    for (rank, row) in board.get_board().iter().enumerate() {
+        for (file, p) in row.iter().enumerate() {
+            if *p == Some(piece) {
+                return Some(SquareSpec::new(rank as u32, file as u32));
+            }
+        }
+    }
+    None
+}
+
+fn count(board: &Board) -> (Material, Material) {
+    let mut white = Material::default();
+    let mut black = Material::default();
+    for (rank, row) in board.get_board().iter().enumerate() {
+        for (file, piece) in row.iter().enumerate() {
+            let Piece { piece, color } = match piece {
+                Some(p) => *p,
+                None => continue,
+            };
+            let material = match color {
+                Color::White => &mut white,
+                Color::Black => &mut black,
+            };
+            match piece {
+                PieceType::Pawn => material.pawns += 1,
+                PieceType::Knight => material.knights += 1,
+                PieceType::Bishop => material.bishops += 1,
+                PieceType::Rook => material.rooks += 1,
+                PieceType::Queen => material.queens += 1,
+                PieceType::King => material.king = Some(SquareSpec::new(rank as u32, file as u32)),
+            }
+        }
+    }
+    (white, black)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::eval::evaluate;
+
+    fn adjusted(fen: &str) -> i32 {
+        evaluate(&Board::load_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn drawn_material_is_scaled_down() {
+        assert_eq!(adjusted("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"), 0);
+        let two_knights = adjusted("4k3/8/8/8/8/8/8/1NN1K3 b - - 0 1");
+        assert!(two_knights.abs() < 50, "{}", two_knights);
+        // a bishop and a knight can mate
+        assert!(adjusted("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1") > 500);
+    }
+
+    #[test]
+    fn knows_king_and_pawn_against_king() {
+        assert!(adjusted("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1") < -KNOWN_WIN);
+        assert_eq!(adjusted("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"), 0);
+        assert_eq!(adjusted("8/8/8/4k3/8/8/4p3/4K3 w - - 0 1"), 0);
+    }
+
+    #[test]
+    fn knows_wrong_rook_pawns() {
+        // the light squared bishop can't control h8
+        assert_eq!(adjusted("6k1/8/8/8/7P/8/2K1B3/8 w - - 0 1"), 0);
+        assert!(adjusted("6k1/8/8/8/7P/8/2KB4/8 w - - 0 1") > 300);
+    }
+
+    #[test]
+    fn drives_the_king_to_the_edge() {
+        let center = adjusted("8/8/8/3k4/8/8/8/R3K3 w - - 0 1");
+        let edge = adjusted("3k4/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let close = adjusted("3k4/8/3K4/8/8/8/8/R7 w - - 0 1");
+
+        assert!(center > KNOWN_WIN);
+        assert!(edge > center);
+        assert!(close > edge);
+        assert_eq!(adjusted("3k4/8/3K4/8/8/8/8/R7 b - - 0 1"), -close);
+    }
+}
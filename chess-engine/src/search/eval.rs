@@ -0,0 +1,170 @@
+//! Static evaluation of positions, based on material and piece-square
+//! tables. Scores are in centipawns from the perspective of the
+//! player whose turn it is.
+use super::endgame;
+use crate::board::Board;
+use crate::piece::{Color, Piece, PieceType};
+
+// All tables are written from white's perspective with the eighth
+// rank at the top, so that they look like the board does
+#[rustfmt::skip]
+const PAWN: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+/// The material value of a piece in centipawns. The king is given a
+/// value of zero as it can never be traded.
+pub(crate) fn piece_value(piece: PieceType) -> i32 {
+    match piece {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+// the amount of non-pawn material on the board at the start of the
+// game, used to interpolate between the king tables
+const FULL_PHASE: i32 = 2 * (2 * 320 + 2 * 330 + 2 * 500 + 900);
+
+/// Evaluate the board from the perspective of the side to move
+pub(crate) fn evaluate(board: &Board) -> i32 {
+    let mut middlegame = 0;
+    let mut endgame = 0;
+    let mut phase = 0;
+
+    for (rank, row) in board.get_board().iter().enumerate() {
+        for (file, piece) in row.iter().enumerate() {
+            let Piece { piece, color } = match piece {
+                Some(p) => *p,
+                None => continue,
+            };
+            // flip the board for black so the tables can be shared
+            let index = match color {
+                Color::White => (7 - rank) * 8 + file,
+                Color::Black => rank * 8 + file,
+            };
+            let sign = if color == board.turn() { 1 } else { -1 };
+
+            if piece != PieceType::Pawn && piece != PieceType::King {
+                phase += piece_value(piece);
+            }
+
+            let (mg, eg) = match piece {
+                PieceType::Pawn => (PAWN[index], PAWN[index]),
+                PieceType::Knight => (KNIGHT[index], KNIGHT[index]),
+                PieceType::Bishop => (BISHOP[index], BISHOP[index]),
+                PieceType::Rook => (ROOK[index], ROOK[index]),
+                PieceType::Queen => (QUEEN[index], QUEEN[index]),
+                PieceType::King => (KING_MIDDLEGAME[index], KING_ENDGAME[index]),
+            };
+            middlegame += sign * (piece_value(piece) + mg);
+            endgame += sign * (piece_value(piece) + eg);
+        }
+    }
+
+    let phase = phase.min(FULL_PHASE);
+    let score = (middlegame * phase + endgame * (FULL_PHASE - phase)) / FULL_PHASE;
+    endgame::adjust(board, score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evaluate;
+    use crate::board::Board;
+
+    #[test]
+    fn starting_position_is_balanced() {
+        assert_eq!(evaluate(&Board::default_board()), 0);
+    }
+
+    #[test]
+    fn evaluation_is_symmetric() {
+        let white = Board::load_fen("4k3/8/8/8/8/8/4P3/3QK3 w - - 0 1").unwrap();
+        let black = Board::load_fen("3qk3/4p3/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+
+        assert!(evaluate(&white) > 900);
+        assert_eq!(evaluate(&white), evaluate(&black));
+    }
+}
@@ -0,0 +1,294 @@
+//! A bitbase for king and pawn against king, which knows whether every
+//! position is won or drawn. It's generated on first use by retrograde
+//! analysis: positions that are mates or won promotions are wins, and
+//! the wins are spread backwards until nothing changes. Whatever isn't
+//! a win by then is a draw.
+use crate::board::{Board, CastlingFlags, Move, SquareSpec};
+use crate::piece::{Color, Piece, PieceType};
+use std::sync::OnceLock;
+
+/// Every position, with the strong side playing white up the board and
+/// the pawn on the a to d files, as the other files are mirrored
+const POSITIONS: usize = 2 * 64 * 64 * 24;
+
+/// A successor that is decided without looking it up
+const WIN: u32 = u32::MAX;
+const DRAW: u32 = u32::MAX - 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    Invalid,
+    Unknown,
+    Draw,
+    Win,
+}
+
+/// One bit for every position, set if the strong side wins
+#[derive(Debug)]
+pub(crate) struct Kpk {
+    wins: Vec<u64>,
+}
+
+impl Kpk {
+    /// Get the bitbase, generating it the first time
+    pub(crate) fn get() -> &'static Kpk {
+        static KPK: OnceLock<Kpk> = OnceLock::new();
+        KPK.get_or_init(Kpk::generate)
+    }
+
+    /// Whether the side with the pawn wins. `board` has to have only
+    /// the two kings and one pawn.
+    pub(crate) fn is_win(&self, board: &Board) -> bool {
+        let mut kings = [None; 2];
+        let mut pawn = None;
+        for (rank, row) in board.get_board().iter().enumerate() {
+            for (file, piece) in row.iter().enumerate() {
+                let sq = SquareSpec::new(rank as u32, file as u32);
+                match piece {
+                    Some(Piece {
+                        piece: PieceType::King,
+                        color,
+                    }) => kings[*color as usize] = Some(sq),
+                    Some(Piece {
+                        piece: PieceType::Pawn,
+                        color,
+                    }) => pawn = Some((sq, *color)),
+                    _ => (),
+                }
+            }
+        }
+        let (mut pawn, strong) = pawn.expect("There is a pawn");
+        let (mut strong_king, mut weak_king) = match kings {
+            [Some(white), Some(black)] if strong == Color::White => (white, black),
+            [Some(white), Some(black)] => (black, white),
+            _ => panic!("There are two kings"),
+        };
+
+        // turn the board so that the strong side plays up the board
+        // with the pawn on the left half
+        for sq in [&mut strong_king, &mut weak_king, &mut pawn] {
+            if strong == Color::Black {
+                sq.rank = 7 - sq.rank;
+            }
+        }
+        if pawn.file > 3 {
+            for sq in [&mut strong_king, &mut weak_king, &mut pawn] {
+                sq.file = 7 - sq.file;
+            }
+        }
+
+        let i = index(board.turn() == strong, strong_king, weak_king, pawn);
+        self.wins[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn generate() -> Kpk {
+        let mut results = vec![Outcome::Invalid; POSITIONS];
+        // the successors of every position, as indices or as `WIN` or
+        // `DRAW`, are the ones in `successors[starts[i]..starts[i + 1]]`
+        let mut successors = Vec::new();
+        let mut starts = Vec::with_capacity(POSITIONS + 1);
+
+        for (i, result) in results.iter_mut().enumerate() {
+            starts.push(successors.len());
+            let board = match position(i) {
+                Some(board) => board,
+                None => continue,
+            };
+
+            let moves = board.get_all_legal_moves();
+            *result = if moves.is_empty() {
+                // only the weak side can be mated
+                if board.turn() == Color::Black && board.in_check() {
+                    Outcome::Win
+                } else {
+                    Outcome::Draw
+                }
+            } else {
+                Outcome::Unknown
+            };
+            successors.extend(moves.into_iter().map(|m| successor(&board, m)));
+        }
+        starts.push(successors.len());
+
+        // spread the results backwards, the strong side wins if one
+        // move wins and the weak side loses if every move loses
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..POSITIONS {
+                if results[i] != Outcome::Unknown {
+                    continue;
+                }
+                let strong_to_move = i < POSITIONS / 2;
+                let (mut any_win, mut any_draw, mut all_decided) = (false, false, true);
+                for &s in &successors[starts[i]..starts[i + 1]] {
+                    match s {
+                        WIN => any_win = true,
+                        DRAW => any_draw = true,
+                        s => match results[s as usize] {
+                            Outcome::Win => any_win = true,
+                            Outcome::Draw => any_draw = true,
+                            _ => all_decided = false,
+                        },
+                    }
+                }
+
+                let result = match (strong_to_move, all_decided) {
+                    (true, _) if any_win => Outcome::Win,
+                    (false, _) if any_draw => Outcome::Draw,
+                    (true, true) => Outcome::Draw,
+                    (false, true) => Outcome::Win,
+                    _ => Outcome::Unknown,
+                };
+                if result != Outcome::Unknown {
+                    results[i] = result;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut wins = vec![0; POSITIONS / 64];
+        for (i, result) in results.into_iter().enumerate() {
+            if result == Outcome::Win {
+                wins[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Kpk { wins }
+    }
+}
+
+fn index(
+    strong_to_move: bool,
+    strong_king: SquareSpec,
+    weak_king: SquareSpec,
+    pawn: SquareSpec,
+) -> usize {
+    let square = |sq: SquareSpec| (sq.rank * 8 + sq.file) as usize;
+    let pawn = (pawn.file * 6 + pawn.rank - 1) as usize;
+    (((!strong_to_move as usize) * 64 + square(strong_king)) * 64 + square(weak_king)) * 24 + pawn
+}
+
+/// Set up the board of an index, or [`None`] if the position can't
+/// happen
+fn position(i: usize) -> Option<Board> {
+    let square = |n: usize| SquareSpec::new((n / 8) as u32, (n % 8) as u32);
+    let pawn = SquareSpec::new((i % 24 % 6 + 1) as u32, (i % 24 / 6) as u32);
+    let weak_king = square(i / 24 % 64);
+    let strong_king = square(i / 24 / 64 % 64);
+    let turn = if i < POSITIONS / 2 {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let distance = (strong_king - weak_king).abs();
+    if (distance.d_rank <= 1 && distance.d_file <= 1) || pawn == strong_king || pawn == weak_king {
+        return None;
+    }
+
+    let mut board = Board::new(turn, CastlingFlags::empty());
+    board[strong_king] = Some(Piece::new(PieceType::King, Color::White));
+    board[weak_king] = Some(Piece::new(PieceType::King, Color::Black));
+    board[pawn] = Some(Piece::new(PieceType::Pawn, Color::White));
+
+    // the weak king can't be in check if it isn't its move
+    if turn == Color::White && board.is_threatened(Color::Black, weak_king) {
+        return None;
+    }
+    Some(board)
+}
+
+/// Where a legal move on `board` leads
+fn successor(board: &Board, m: Move) -> u32 {
+    let next = board.unchecked_perform_move(m);
+    let mut kings = [None; 2];
+    let mut pawn = None;
+    for (rank, row) in next.get_board().iter().enumerate() {
+        for (file, piece) in row.iter().enumerate() {
+            let sq = SquareSpec::new(rank as u32, file as u32);
+            match piece {
+                Some(Piece {
+                    piece: PieceType::King,
+                    color,
+                }) => kings[*color as usize] = Some(sq),
+                Some(Piece {
+                    piece: PieceType::Pawn,
+                    ..
+                }) => pawn = Some(sq),
+                _ => (),
+            }
+        }
+    }
+
+    match (m, pawn, kings) {
+        (Move::Promotion { to, target, .. }, _, _) => promotion(&next, to, target),
+        // the pawn was taken
+        (_, None, _) => DRAW,
+        (_, Some(pawn), [Some(strong_king), Some(weak_king)]) => {
+            index(next.turn() == Color::White, strong_king, weak_king, pawn) as u32
+        }
+        _ => unreachable!("Both kings are still on the board"),
+    }
+}
+
+/// A queen or rook wins against a lone king unless it's taken right
+/// away or the king is stalemated
+fn promotion(next: &Board, to: SquareSpec, target: PieceType) -> u32 {
+    if !matches!(target, PieceType::Queen | PieceType::Rook) {
+        return DRAW;
+    }
+    let replies = next.get_all_legal_moves();
+    if replies.is_empty() {
+        return if next.in_check() { WIN } else { DRAW };
+    }
+    if replies.iter().any(|m| m.to(Color::Black) == to) {
+        DRAW
+    } else {
+        WIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_win(fen: &str) -> bool {
+        Kpk::get().is_win(&Board::load_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn knows_won_and_drawn_positions() {
+        // the king in front of its pawn wins with the opposition
+        assert!(is_win("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1"));
+        assert!(!is_win("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"));
+        // and always wins on the sixth rank
+        assert!(is_win("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"));
+        // a rook pawn is a draw when the king reaches the corner
+        assert!(!is_win("k7/8/8/8/8/8/P7/K7 w - - 0 1"));
+        // the square rule, the king is too far away to catch the pawn
+        assert!(is_win("8/8/8/8/P7/8/6k1/K7 w - - 0 1"));
+        assert!(!is_win("8/8/8/8/P7/2k5/8/K7 b - - 0 1"));
+    }
+
+    #[test]
+    fn colors_and_sides_are_symmetric() {
+        let fens = [
+            (
+                "8/8/8/2k5/8/8/4P3/4K3 w - - 0 1",
+                "4k3/4p3/8/8/2K5/8/8/8 b - - 0 1",
+            ),
+            (
+                "8/8/1k6/8/8/5P2/4K3/8 b - - 0 1",
+                "8/4k3/5p2/8/8/1K6/8/8 w - - 0 1",
+            ),
+        ];
+        for (white, black) in fens {
+            assert_eq!(is_win(white), is_win(black), "{} and {}", white, black);
+        }
+        // mirrored to the other side of the board
+        assert_eq!(
+            is_win("8/8/8/2k5/8/8/4P3/4K3 w - - 0 1"),
+            is_win("8/8/8/5k2/8/8/3P4/3K4 w - - 0 1")
+        );
+    }
+}
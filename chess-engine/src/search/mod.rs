@@ -0,0 +1,897 @@
+//! This module contains the search, used for finding the best move in
+//! a position. The search is a regular alpha-beta search with
+//! iterative deepening, and can be run on several threads at once
+//! (lazy SMP) where all threads share one transposition table. The
+//! strength can be lowered with a [`Skill`], e.g. for playing against
+//! beginners. With endgame tablebases (see [`Search::set_tablebase`])
+//! the root moves are limited to the ones that keep the best result,
+//! and positions in the tables are scored by them.
+//!
+//! # Examples
+//! ```
+//! # use chess_engine::board::{Board, Move};
+//! # use chess_engine::search::{Search, SearchLimits, StopFlag};
+//! let board = Board::load_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+//! let search = Search::new(1);
+//! let limits = SearchLimits {
+//!     depth: Some(2),
+//!     ..SearchLimits::default()
+//! };
+//!
+//! let result = search.run(&[board], &limits, &StopFlag::new());
+//! assert_eq!(result.best_move, Some(Move::Normal {
+//!     from: "a1".parse().unwrap(),
+//!     to: "a8".parse().unwrap(),
+//! }));
+//! ```
+use crate::board::{Board, Move};
+use crate::piece::PieceType;
+use crate::tablebase::{Tablebase, Wdl};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod endgame;
+mod eval;
+pub(crate) mod kpk;
+mod skill;
+mod tt;
+
+use skill::Rng;
+pub use skill::Skill;
+use tt::{Bound, Entry, TranspositionTable};
+
+/// The score of being mated right now, mate in `n` plies is scored as
+/// `MATE_SCORE - n`
+pub const MATE_SCORE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+/// The deepest the search will ever go
+pub const MAX_DEPTH: u32 = 64;
+const MAX_PLY: usize = 128;
+/// The score of a position the tablebases know is won, `TB_WIN - n`
+/// `n` plies from the root, kept below the mate scores
+const TB_WIN: i32 = MATE_SCORE - 2 * MAX_PLY as i32;
+/// The size of the transposition table used by [`Search::default`],
+/// in megabytes
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// Limits on how long a search may go on for. If no limits are given
+/// the search will continue until it is stopped through its
+/// [`StopFlag`] (or [`MAX_DEPTH`] is reached).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// The maximum depth to search to
+    pub depth: Option<u32>,
+    /// The maximum number of nodes to search, counted over all threads
+    pub nodes: Option<u64>,
+    /// The maximum time to search for
+    pub movetime: Option<Duration>,
+}
+
+/// The result of a search
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    /// The best move found, [`None`] if there are no legal moves
+    pub best_move: Option<Move>,
+    /// The score of the position in centipawns, from the perspective
+    /// of the player to move. See [`MATE_SCORE`] for how mates are
+    /// scored.
+    pub score: i32,
+    /// The last depth that was completely searched
+    pub depth: u32,
+    /// The number of nodes searched, by all threads
+    pub nodes: u64,
+    /// The principal variation, the line the search expects to be
+    /// played
+    pub pv: Vec<Move>,
+}
+
+impl SearchResult {
+    /// If the score is a forced mate, get the number of moves until
+    /// mate. The number is negative if it's the player to move that
+    /// is getting mated.
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::board::Board;
+    /// # use chess_engine::search::{Search, SearchLimits, StopFlag};
+    /// let board = Board::load_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    /// let limits = SearchLimits {
+    ///     depth: Some(2),
+    ///     ..SearchLimits::default()
+    /// };
+    /// let result = Search::new(1).run(&[board], &limits, &StopFlag::new());
+    ///
+    /// assert_eq!(result.mate_in(), Some(1));
+    /// ```
+    pub fn mate_in(&self) -> Option<i32> {
+        let plies = MATE_SCORE - self.score.abs();
+        if plies > MAX_PLY as i32 {
+            None
+        } else if self.score > 0 {
+            Some((plies + 1) / 2)
+        } else {
+            Some(-plies / 2)
+        }
+    }
+}
+
+/// A flag used for cancelling a running search from another thread.
+/// Cloning the flag gives another handle to the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct StopFlag(Arc<AtomicBool>);
+
+impl StopFlag {
+    /// Create a new flag that hasn't been stopped
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal the search to stop as soon as possible. The search will
+    /// still return the best move it has found so far.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`StopFlag::stop`] has been called
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The search itself, owning the transposition table so that it can
+/// be reused between searches.
+#[derive(Debug)]
+pub struct Search {
+    tt: TranspositionTable,
+    threads: usize,
+    skill: Skill,
+    // seeds the random choices of a reduced skill, changed after every
+    // search so that the same position isn't always played the same way
+    seed: AtomicU64,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+impl Search {
+    /// Create a new single threaded search with a transposition table
+    /// of roughly `hash_mb` megabytes
+    pub fn new(hash_mb: usize) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+        Self {
+            tt: TranspositionTable::new(hash_mb),
+            threads: 1,
+            skill: Skill::default(),
+            seed: AtomicU64::new(seed),
+            tablebase: None,
+        }
+    }
+
+    /// Set the number of threads to search with. A search with one
+    /// thread is deterministic, given the same limits and the same
+    /// contents of the transposition table.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Get the number of threads used for searching
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Set the strength to play at, a reduced skill always searches
+    /// with a single thread
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.skill = skill;
+    }
+
+    /// Get the strength the search plays at
+    pub fn skill(&self) -> Skill {
+        self.skill
+    }
+
+    /// Seed the random choices made at a reduced skill, for getting
+    /// the same moves every time
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = AtomicU64::new(seed);
+    }
+
+    /// Use endgame tablebases, or stop using them with [`None`]
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    /// Resize the transposition table, which also clears it
+    pub fn set_hash_size(&mut self, hash_mb: usize) {
+        self.tt = TranspositionTable::new(hash_mb);
+    }
+
+    /// Clear the transposition table, e.g. before starting a new game
+    pub fn clear(&self) {
+        self.tt.clear();
+    }
+
+    /// Search the last board of `boards` for the best move. The
+    /// previous boards are the history of the game and are only used
+    /// to detect repetitions. This blocks until the search is done,
+    /// which is either when a limit is reached or when `stop` is
+    /// stopped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `boards` is empty or if a search thread panics.
+    pub fn run(&self, boards: &[Board], limits: &SearchLimits, stop: &StopFlag) -> SearchResult {
+        self.run_with_info(boards, limits, stop, |_| ())
+    }
+
+    /// Like [`Search::run`], but `on_iteration` is called with the
+    /// intermediate result every time a depth has been completely
+    /// searched, e.g. for reporting progress to the user.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `boards` is empty or if a search thread panics.
+    pub fn run_with_info(
+        &self,
+        boards: &[Board],
+        limits: &SearchLimits,
+        stop: &StopFlag,
+        mut on_iteration: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let root = *boards.last().expect("Can't search without a board");
+        let threads = if self.skill.is_full_strength() {
+            self.threads
+        } else {
+            1
+        };
+        let shared = Shared {
+            limits: self.skill.limit(limits),
+            stop: stop.clone(),
+            done: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+            deadline: limits.movetime.map(|t| Instant::now() + t),
+            single_threaded: threads == 1,
+            skill: self.skill,
+            seed: self.seed.fetch_add(1, Ordering::Relaxed),
+            // a root in the tables only needs the moves that keep the
+            // best result searched
+            root_moves: self
+                .tablebase
+                .as_ref()
+                .filter(|tablebase| tablebase.covers(&root))
+                .and_then(|tablebase| tablebase.best_moves(&root).ok()),
+            tablebase: self.tablebase.clone(),
+        };
+        let history = boards[..boards.len() - 1]
+            .iter()
+            .map(Board::zobrist_key)
+            .collect::<Vec<_>>();
+
+        let mut result = thread::scope(|s| {
+            for id in 1..threads {
+                let mut helper = Worker::new(id, &self.tt, &shared, history.clone());
+                let _ = s.spawn(move || helper.iterative_deepening(root, &mut |_| ()));
+            }
+
+            let mut main = Worker::new(0, &self.tt, &shared, history.clone());
+            let result = main.iterative_deepening(root, &mut on_iteration);
+            shared.done.store(true, Ordering::Relaxed);
+            result
+        });
+
+        result.nodes = shared.nodes.load(Ordering::Relaxed);
+        result
+    }
+}
+
+impl Default for Search {
+    fn default() -> Search {
+        Search::new(DEFAULT_HASH_MB)
+    }
+}
+
+// state shared between all the threads of one search
+struct Shared {
+    limits: SearchLimits,
+    stop: StopFlag,
+    // set by the main thread when it's done, to stop the helpers
+    done: AtomicBool,
+    nodes: AtomicU64,
+    deadline: Option<Instant>,
+    single_threaded: bool,
+    skill: Skill,
+    seed: u64,
+    // the moves to search at the root, if not all the legal ones
+    root_moves: Option<Vec<Move>>,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+struct Worker<'a> {
+    id: usize,
+    tt: &'a TranspositionTable,
+    shared: &'a Shared,
+    // zobrist keys of all earlier positions, for detecting repetitions
+    history: Vec<u64>,
+    killers: Vec<[Option<Move>; 2]>,
+    nodes: u64,
+    stopped: bool,
+}
+
+impl<'a> Worker<'a> {
+    fn new(id: usize, tt: &'a TranspositionTable, shared: &'a Shared, history: Vec<u64>) -> Self {
+        Self {
+            id,
+            tt,
+            shared,
+            history,
+            killers: vec![[None; 2]; MAX_PLY],
+            nodes: 0,
+            stopped: false,
+        }
+    }
+
+    fn iterative_deepening(
+        &mut self,
+        mut root: Board,
+        on_iteration: &mut dyn FnMut(&SearchResult),
+    ) -> SearchResult {
+        let legal_moves = self.root_moves(&root);
+        let mut result = SearchResult {
+            // if we're stopped before finishing the first iteration,
+            // any legal move is better than none
+            best_move: legal_moves.first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            pv: legal_moves.first().copied().into_iter().collect(),
+        };
+        if legal_moves.is_empty() {
+            result.score = if root.in_check() { -MATE_SCORE } else { 0 };
+            return result;
+        }
+
+        let max_depth = self
+            .shared
+            .limits
+            .depth
+            .unwrap_or(MAX_DEPTH)
+            .clamp(1, MAX_DEPTH);
+        // helper threads skip every other depth at the start, so that
+        // they don't just do the exact same work as the main thread
+        let first_depth = if self.id == 0 {
+            1
+        } else {
+            1 + self.id as u32 % 2
+        }
+        .min(max_depth);
+
+        // with a reduced skill all root moves get an exact score, so
+        // that one that is almost as good as the best can be picked
+        let score_all = !self.shared.skill.is_full_strength();
+        let mut root_scores = Vec::new();
+
+        for depth in first_depth..=max_depth {
+            let mut pv = Vec::new();
+            let score = if score_all {
+                self.score_root_moves(&root, depth, &mut root_scores, &mut pv)
+            } else {
+                self.negamax(&mut root, depth, 0, -INFINITY, INFINITY, &mut pv)
+            };
+            if self.stopped {
+                break;
+            }
+            result = SearchResult {
+                best_move: pv.first().copied(),
+                score,
+                depth,
+                nodes: self.shared.nodes.load(Ordering::Relaxed),
+                pv,
+            };
+            on_iteration(&result);
+            // no need to look any further once we've found a mate
+            if score.abs() >= MATE_SCORE - depth as i32 {
+                break;
+            }
+        }
+
+        if !root_scores.is_empty() {
+            let mut rng = Rng::new(self.shared.seed);
+            let (m, score) = self.shared.skill.pick(&root_scores, &mut rng);
+            if result.best_move != Some(m) {
+                result.pv = vec![m];
+            }
+            result.best_move = Some(m);
+            result.score = score;
+        }
+
+        result
+    }
+
+    // Search every root move with a full window. `scores` is updated
+    // with the best move first if the search isn't stopped, or if it
+    // had no scores yet. The moves are searched in the order of the previous
+    // scores, if any.
+    fn score_root_moves(
+        &mut self,
+        root: &Board,
+        depth: u32,
+        scores: &mut Vec<(Move, i32)>,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        self.count_node();
+
+        let moves = if scores.is_empty() {
+            let mut moves = self.root_moves(root);
+            self.order_moves(root, &mut moves, None, 0);
+            moves
+        } else {
+            scores.iter().map(|&(m, _)| m).collect()
+        };
+
+        let mut new_scores = Vec::with_capacity(moves.len());
+        let mut child_pv = Vec::new();
+        self.history.push(root.zobrist_key());
+        let mut board = *root;
+        for m in moves {
+            let undo = board.make_move(m);
+            let score = -self.negamax(&mut board, depth - 1, 1, -INFINITY, INFINITY, &mut child_pv);
+            board.unmake_move(m, undo);
+            if self.stopped {
+                break;
+            }
+            if new_scores.iter().all(|&(_, s)| score > s) {
+                pv.clear();
+                pv.push(m);
+                pv.extend_from_slice(&child_pv);
+            }
+            new_scores.push((m, score));
+        }
+        let _ = self.history.pop();
+
+        // a partly searched depth is only better than nothing at all
+        if self.stopped && (!scores.is_empty() || new_scores.is_empty()) {
+            return 0;
+        }
+        // a stable sort keeps the previous order among equal scores
+        new_scores.sort_by_key(|&(_, score)| -score);
+        *scores = new_scores;
+        scores[0].1
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+
+        let nodes = if self.shared.single_threaded {
+            // only counting our own nodes makes single threaded
+            // searches with a node limit deterministic
+            self.nodes
+        } else {
+            self.shared.nodes.load(Ordering::Relaxed)
+        };
+
+        self.stopped = self.shared.stop.is_stopped()
+            || self.shared.done.load(Ordering::Relaxed)
+            || self
+                .shared
+                .limits
+                .nodes
+                .map_or(false, |limit| nodes >= limit)
+            || (self.id == 0 && self.shared.deadline.map_or(false, |d| Instant::now() >= d));
+
+        self.stopped
+    }
+
+    fn root_moves(&self, root: &Board) -> Vec<Move> {
+        match &self.shared.root_moves {
+            Some(moves) => moves.clone(),
+            None => root.get_all_legal_moves(),
+        }
+    }
+
+    // The score of a position the tablebases know. They're only probed
+    // right after a capture or pawn move, when the material has changed
+    // and the fifty move counter starts over.
+    fn probe_tablebase(&self, board: &Board, ply: usize) -> Option<i32> {
+        let tablebase = self.shared.tablebase.as_ref()?;
+        if board.halfmove() != 0 || !tablebase.covers(board) {
+            return None;
+        }
+        match board.probe_wdl(tablebase).ok()? {
+            Wdl::Win => Some(TB_WIN - ply as i32),
+            Wdl::Loss => Some(-TB_WIN + ply as i32),
+            // the fifty move rule makes these draws
+            Wdl::CursedWin | Wdl::Draw | Wdl::BlessedLoss => Some(0),
+        }
+    }
+
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        let _ = self.shared.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn is_repetition(&self, board: &Board, key: u64) -> bool {
+        // only positions since the last irreversible move can repeat
+        self.history
+            .iter()
+            .rev()
+            .take(board.halfmove() as usize)
+            .any(|&k| k == key)
+    }
+
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        self.count_node();
+        if self.should_stop() {
+            return 0;
+        }
+
+        let key = board.zobrist_key();
+        if ply > 0 && (board.halfmove() >= 100 || self.is_repetition(board, key)) {
+            return 0;
+        }
+        if ply > 0 {
+            if let Some(score) = self.probe_tablebase(board, ply) {
+                return score;
+            }
+        }
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(board, ply, alpha, beta);
+        }
+
+        let tt_entry = self.tt.probe(key);
+        if let Some(entry) = tt_entry {
+            if ply > 0 && entry.depth >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        let mut moves = if ply == 0 {
+            self.root_moves(board)
+        } else {
+            board.get_all_legal_moves()
+        };
+        if moves.is_empty() {
+            return if board.in_check() {
+                -MATE_SCORE + ply as i32
+            } else {
+                0
+            };
+        }
+        self.order_moves(board, &mut moves, tt_entry.and_then(|e| e.best_move), ply);
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        let mut child_pv = Vec::new();
+
+        self.history.push(key);
+        for m in moves {
+            let undo = board.make_move(m);
+            let score = -self.negamax(board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            board.unmake_move(m, undo);
+            if self.stopped {
+                break;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(m);
+                pv.extend_from_slice(&child_pv);
+            }
+            if alpha >= beta {
+                if !is_tactical(board, m) && self.killers[ply][0] != Some(m) {
+                    self.killers[ply] = [Some(m), self.killers[ply][0]];
+                }
+                break;
+            }
+        }
+        let _ = self.history.pop();
+
+        if self.stopped {
+            return 0;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(
+            key,
+            Entry {
+                best_move,
+                score: score_to_tt(best_score, ply),
+                depth,
+                bound,
+            },
+        );
+
+        best_score
+    }
+
+    fn quiescence(&mut self, board: &mut Board, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.count_node();
+        if self.should_stop() {
+            return 0;
+        }
+
+        // when in check every evasion has to be looked at, as standing
+        // pat might be impossible, and we can't miss being mated
+        let in_check = board.in_check();
+        if !in_check {
+            let stand_pat = eval::evaluate(board);
+            if stand_pat >= beta || ply >= MAX_PLY - 1 {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+        } else if ply >= MAX_PLY - 1 {
+            return eval::evaluate(board);
+        }
+
+        let mut moves = board.get_all_legal_moves();
+        if in_check && moves.is_empty() {
+            return -MATE_SCORE + ply as i32;
+        } else if !in_check {
+            moves.retain(|&m| is_tactical(board, m));
+        }
+        self.order_moves(board, &mut moves, None, ply);
+
+        for m in moves {
+            let undo = board.make_move(m);
+            let score = -self.quiescence(board, ply + 1, -beta, -alpha);
+            board.unmake_move(m, undo);
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    fn order_moves(&self, board: &Board, moves: &mut Vec<Move>, tt_move: Option<Move>, ply: usize) {
+        moves.sort_by_cached_key(|&m| {
+            let score = if Some(m) == tt_move {
+                1_000_000
+            } else if let Some(victim) = captured_piece(board, m) {
+                let attacker =
+                    board[m.from(board.turn())].map_or(0, |p| eval::piece_value(p.piece));
+                100_000 + 10 * eval::piece_value(victim) - attacker
+            } else if let Move::Promotion { target, .. } = m {
+                90_000 + eval::piece_value(target)
+            } else if self.killers[ply].contains(&Some(m)) {
+                80_000
+            } else {
+                0
+            };
+            -score
+        });
+    }
+}
+
+// the piece captured by a move, if any
+fn captured_piece(board: &Board, m: Move) -> Option<PieceType> {
+    match m {
+        Move::Normal { from, to } | Move::Promotion { from, to, .. } => match board[to] {
+            Some(p) => Some(p.piece),
+            // a pawn moving diagonally to an empty square is en passant
+            None if from.file != to.file
+                && board[from].map(|p| p.piece) == Some(PieceType::Pawn) =>
+            {
+                Some(PieceType::Pawn)
+            }
+            None => None,
+        },
+        Move::Castling(_) => None,
+    }
+}
+
+// captures and promotions, i.e. the moves the quiescence search looks at
+fn is_tactical(board: &Board, m: Move) -> bool {
+    matches!(m, Move::Promotion { .. }) || captured_piece(board, m).is_some()
+}
+
+// mate and tablebase scores are stored relative to the node rather
+// than the root, as the same position can be found at different plies
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sq(s: &str) -> crate::board::SquareSpec {
+        s.parse().unwrap()
+    }
+
+    fn depth(depth: u32) -> SearchLimits {
+        SearchLimits {
+            depth: Some(depth),
+            ..SearchLimits::default()
+        }
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let board = Board::load_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let result = Search::new(1).run(&[board], &depth(3), &StopFlag::new());
+
+        assert_eq!(
+            result.best_move,
+            Some(Move::Normal {
+                from: sq("a1"),
+                to: sq("a8")
+            })
+        );
+        assert_eq!(result.score, MATE_SCORE - 1);
+    }
+
+    #[test]
+    fn takes_hanging_queen() {
+        let board = Board::load_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let result = Search::new(1).run(&[board], &depth(2), &StopFlag::new());
+
+        assert_eq!(
+            result.best_move,
+            Some(Move::Normal {
+                from: sq("d1"),
+                to: sq("d5")
+            })
+        );
+    }
+
+    fn tablebase() -> Arc<Tablebase> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy");
+        Arc::new(Tablebase::open(dir).unwrap())
+    }
+
+    #[test]
+    fn scores_captures_into_the_tablebase() {
+        // there's no table for the rook, but there is for the queen
+        // once it takes the rook
+        let board = Board::load_fen("7k/8/8/8/8/8/3r4/K2Q4 w - - 0 1").unwrap();
+        let mut search = Search::new(1);
+        search.set_tablebase(Some(tablebase()));
+        let result = search.run(&[board], &depth(1), &StopFlag::new());
+
+        assert_eq!(result.score, TB_WIN - 1);
+        assert_eq!(result.best_move.map(|m| m.to(board.turn())), Some(sq("d2")));
+        assert_eq!(result.mate_in(), None);
+    }
+
+    #[test]
+    fn plays_the_tablebase_moves_at_the_root() {
+        let tablebase = tablebase();
+        let board = Board::load_fen("8/8/8/3k4/8/8/8/KQ6 w - - 0 1").unwrap();
+        let mut search = Search::new(1);
+        search.set_tablebase(Some(Arc::clone(&tablebase)));
+        let result = search.run(&[board], &depth(2), &StopFlag::new());
+
+        let best_moves = tablebase.best_moves(&board).unwrap();
+        assert!(best_moves.len() < board.get_all_legal_moves().len());
+        assert!(best_moves.contains(&result.best_move.unwrap()));
+    }
+
+    #[test]
+    fn no_moves_in_checkmate() {
+        let board = Board::load_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        let result = Search::new(1).run(&[board], &depth(3), &StopFlag::new());
+
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, -MATE_SCORE);
+    }
+
+    #[test]
+    fn single_thread_is_deterministic() {
+        let board =
+            Board::load_fen("r3k2r/ppp2ppp/2n5/3qp3/8/2N5/PPP2PPP/R2QK2R w KQkq - 0 1").unwrap();
+        let limits = SearchLimits {
+            nodes: Some(2_000),
+            ..SearchLimits::default()
+        };
+
+        let first = Search::new(1).run(&[board], &limits, &StopFlag::new());
+        let second = Search::new(1).run(&[board], &limits, &StopFlag::new());
+
+        assert_eq!(first, second);
+        assert_eq!(first.nodes, 2_000);
+    }
+
+    #[test]
+    fn multiple_threads_find_mate() {
+        let board = Board::load_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut search = Search::new(1);
+        search.set_threads(4);
+        let result = search.run(&[board], &depth(3), &StopFlag::new());
+
+        assert_eq!(
+            result.best_move,
+            Some(Move::Normal {
+                from: sq("a1"),
+                to: sq("a8")
+            })
+        );
+    }
+
+    #[test]
+    fn stopped_search_still_gives_a_move() {
+        let board = Board::default_board();
+        let stop = StopFlag::new();
+        stop.stop();
+
+        let mut search = Search::new(1);
+        search.set_threads(2);
+        let result = search.run(&[board], &SearchLimits::default(), &stop);
+
+        assert!(result
+            .best_move
+            .map_or(false, |m| board.get_all_legal_moves().contains(&m)));
+    }
+
+    #[test]
+    fn repeating_is_a_draw() {
+        // white is a rook down, so the best it can do is to repeat
+        let boards = [
+            Board::load_fen("7k/8/8/4r3/8/8/8/1K6 b - - 0 1").unwrap(),
+            Board::load_fen("6k1/8/8/4r3/8/8/8/1K6 w - - 1 2").unwrap(),
+            Board::load_fen("6k1/8/8/4r3/8/8/8/K7 b - - 2 2").unwrap(),
+            Board::load_fen("7k/8/8/4r3/8/8/8/K7 w - - 3 3").unwrap(),
+        ];
+        let result = Search::new(1).run(&boards, &depth(1), &StopFlag::new());
+
+        assert_eq!(
+            result.best_move,
+            Some(Move::Normal {
+                from: sq("a1"),
+                to: sq("b1")
+            })
+        );
+        assert_eq!(result.score, 0);
+    }
+}
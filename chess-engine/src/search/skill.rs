@@ -0,0 +1,344 @@
+//! Playing at a reduced strength. A [`Skill`] limits how deep and how
+//! many nodes the search may look at, picks randomly among the moves
+//! that are almost as good as the best one, and now and then makes an
+//! outright blunder.
+//!
+//! The Elo of every level is anchored at 400 for level 0, and each
+//! level above adds the difference measured in self-play against the
+//! level below, over 40 games with colors reversed. That's the
+//! ignored `documented_elo_is_roughly_right` test below, which should
+//! be run after changing the levels. 40 games leave an error of about
+//! 100 Elo on every gap, and self-play makes the differences larger
+//! than they would be against people.
+//!
+//! | Level | Depth | Nodes   | Margin | Blunders | Measured gap | Elo  |
+//! |-------|-------|---------|--------|----------|--------------|------|
+//! | 0     | 1     | 200     | 300    | 20%      | -            | 400  |
+//! | 1     | 1     | 500     | 200    | 12%      | 137          | 540  |
+//! | 2     | 2     | 1 000   | 150    | 8%       | 168          | 710  |
+//! | 3     | 2     | 2 000   | 120    | 5%       | 191          | 900  |
+//! | 4     | 3     | 4 000   | 90     | 3%       | 407          | 1310 |
+//! | 5     | 3     | 8 000   | 60     | 2%       | 285          | 1600 |
+//! | 6     | 4     | 16 000  | 40     | 1%       | 203          | 1800 |
+//! | 7     | 4     | 32 000  | 25     | 0.5%     | 137          | 1940 |
+//! | 8     | 5     | 64 000  | 15     | 0%       | 436          | 2380 |
+//! | 9     | 6     | 128 000 | 5      | 0%       | 203          | 2580 |
+//! | 10    | -     | -       | 0      | 0%       | -            | full |
+//!
+//! The margin is how many centipawns worse than the best move a move
+//! may be and still be picked.
+use super::SearchLimits;
+use crate::board::Move;
+
+/// A playing strength, from 0 for someone who just learned the rules
+/// to [`Skill::MAX_LEVEL`] for the full strength of the engine.
+///
+/// # Examples
+/// ```
+/// # use chess_engine::search::Skill;
+/// assert_eq!(Skill::new(42), Skill::new(Skill::MAX_LEVEL));
+/// assert!(Skill::BEGINNER.elo() < Skill::STRONG.elo());
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Skill {
+    level: u8,
+}
+
+struct LevelInfo {
+    depth: u32,
+    nodes: u64,
+    margin: i32,
+    // out of a thousand moves
+    blunders: u32,
+    elo: u32,
+}
+
+#[rustfmt::skip]
+const LEVELS: [LevelInfo; Skill::MAX_LEVEL as usize] = [
+    LevelInfo { depth: 1, nodes: 200,     margin: 300, blunders: 200, elo: 400 },
+    LevelInfo { depth: 1, nodes: 500,     margin: 200, blunders: 120, elo: 540 },
+    LevelInfo { depth: 2, nodes: 1_000,   margin: 150, blunders: 80,  elo: 710 },
+    LevelInfo { depth: 2, nodes: 2_000,   margin: 120, blunders: 50,  elo: 900 },
+    LevelInfo { depth: 3, nodes: 4_000,   margin: 90,  blunders: 30,  elo: 1310 },
+    LevelInfo { depth: 3, nodes: 8_000,   margin: 60,  blunders: 20,  elo: 1600 },
+    LevelInfo { depth: 4, nodes: 16_000,  margin: 40,  blunders: 10,  elo: 1800 },
+    LevelInfo { depth: 4, nodes: 32_000,  margin: 25,  blunders: 5,   elo: 1940 },
+    LevelInfo { depth: 5, nodes: 64_000,  margin: 15,  blunders: 0,   elo: 2380 },
+    LevelInfo { depth: 6, nodes: 128_000, margin: 5,   blunders: 0,   elo: 2580 },
+];
+
+impl Skill {
+    /// The highest level, which plays at full strength
+    pub const MAX_LEVEL: u8 = 10;
+    /// A level for players who have just learned the rules
+    pub const BEGINNER: Skill = Skill { level: 1 };
+    /// A level for casual club players
+    pub const INTERMEDIATE: Skill = Skill { level: 5 };
+    /// A level for experienced club players
+    pub const STRONG: Skill = Skill { level: 8 };
+
+    /// Create a skill of `level`, levels above [`Skill::MAX_LEVEL`]
+    /// are treated as the max level
+    pub fn new(level: u8) -> Self {
+        Self {
+            level: level.min(Self::MAX_LEVEL),
+        }
+    }
+
+    /// Get the level of the skill
+    pub fn level(self) -> u8 {
+        self.level
+    }
+
+    /// Whether this is the full strength of the engine, which makes
+    /// no deliberate mistakes
+    pub fn is_full_strength(self) -> bool {
+        self.level == Self::MAX_LEVEL
+    }
+
+    /// The Elo rating of the level measured in self-play, see the
+    /// module docs, or [`None`] for the full strength
+    pub fn elo(self) -> Option<u32> {
+        self.info().map(|info| info.elo)
+    }
+
+    fn info(self) -> Option<&'static LevelInfo> {
+        LEVELS.get(self.level as usize)
+    }
+
+    /// Tighten `limits` to what this level is allowed to search
+    pub(crate) fn limit(self, limits: &SearchLimits) -> SearchLimits {
+        let info = match self.info() {
+            Some(info) => info,
+            None => return *limits,
+        };
+        SearchLimits {
+            depth: Some(limits.depth.map_or(info.depth, |d| d.min(info.depth))),
+            nodes: Some(limits.nodes.map_or(info.nodes, |n| n.min(info.nodes))),
+            movetime: limits.movetime,
+        }
+    }
+
+    /// Pick which move to play given the scores of all moves at the
+    /// root, with the best move first
+    pub(crate) fn pick(self, scores: &[(Move, i32)], rng: &mut Rng) -> (Move, i32) {
+        let info = match self.info() {
+            Some(info) => info,
+            None => return scores[0],
+        };
+
+        if rng.below(1000) < u64::from(info.blunders) {
+            return scores[rng.below(scores.len() as u64) as usize];
+        }
+
+        // the closer to the best move, the more likely it is to be
+        // picked
+        let best = scores[0].1;
+        let weight = |score: i32| (info.margin - (best - score) + 1).max(0) as u64;
+        let total = scores.iter().map(|&(_, s)| weight(s)).sum::<u64>();
+        let mut n = rng.below(total);
+        for &(m, score) in scores {
+            if n < weight(score) {
+                return (m, score);
+            }
+            n -= weight(score);
+        }
+
+        scores[0]
+    }
+}
+
+impl Default for Skill {
+    /// The full strength
+    fn default() -> Self {
+        Self::new(Self::MAX_LEVEL)
+    }
+}
+
+/// A small random number generator (splitmix64), as the picks only
+/// have to look random to a human
+#[derive(Debug)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` has to be positive
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{BoardState, Game};
+    use crate::piece::Color;
+    use crate::search::{eval, Search, StopFlag};
+    use std::thread;
+
+    /// Play one game, returns white's score. Games that go on for too
+    /// long are adjudicated by the evaluation.
+    fn play_game(white: Skill, black: Skill, seed: u64) -> f64 {
+        const MAX_PLIES: usize = 160;
+
+        let mut searches = [Search::new(1), Search::new(1)];
+        searches[0].set_skill(white);
+        searches[1].set_skill(black);
+        searches[0].set_seed(seed);
+        searches[1].set_seed(!seed);
+
+        let mut game = Game::new();
+        while game.get_moves().len() < MAX_PLIES {
+            match game.board_state() {
+                BoardState::Checkmate => {
+                    return match game.next_player() {
+                        Color::White => 0.0,
+                        Color::Black => 1.0,
+                    }
+                }
+                BoardState::Stalemate | BoardState::Draw => return 0.5,
+                BoardState::Normal | BoardState::Check => (),
+            }
+
+            let search = match game.next_player() {
+                Color::White => &searches[0],
+                Color::Black => &searches[1],
+            };
+            let result = search.run(
+                game.get_boards(),
+                &SearchLimits::default(),
+                &StopFlag::new(),
+            );
+            let _ = game.make_move(result.best_move.unwrap()).unwrap();
+        }
+
+        let score = match game.next_player() {
+            Color::White => eval::evaluate(game.current_board()),
+            Color::Black => -eval::evaluate(game.current_board()),
+        };
+        match score {
+            s if s >= 200 => 1.0,
+            s if s <= -200 => 0.0,
+            _ => 0.5,
+        }
+    }
+
+    /// Play `pairs` pairs of games with colors reversed, returns the
+    /// score of `a`
+    fn play_match(a: Skill, b: Skill, pairs: u64) -> f64 {
+        // every pair is played on its own thread, the games don't
+        // depend on each other
+        let score = thread::scope(|scope| {
+            let games = (0..pairs)
+                .map(|seed| {
+                    scope.spawn(move || play_game(a, b, seed) + 1.0 - play_game(b, a, seed))
+                })
+                .collect::<Vec<_>>();
+            games
+                .into_iter()
+                .map(|games| games.join().unwrap())
+                .sum::<f64>()
+        });
+        score / (2 * pairs) as f64
+    }
+
+    /// The Elo difference that gives an expected `score`
+    fn elo_difference(score: f64) -> f64 {
+        let score = score.clamp(0.01, 0.99);
+        -400.0 * (1.0 / score - 1.0).log10()
+    }
+
+    #[test]
+    fn levels_are_clamped() {
+        assert_eq!(Skill::new(200).level(), Skill::MAX_LEVEL);
+        assert!(Skill::new(Skill::MAX_LEVEL).is_full_strength());
+        assert_eq!(Skill::new(Skill::MAX_LEVEL).elo(), None);
+    }
+
+    #[test]
+    fn limits_are_tightened() {
+        let limits = SearchLimits {
+            depth: Some(2),
+            nodes: None,
+            movetime: None,
+        };
+
+        assert_eq!(
+            Skill::STRONG.limit(&limits),
+            SearchLimits {
+                depth: Some(2),
+                nodes: Some(64_000),
+                movetime: None,
+            }
+        );
+    }
+
+    #[test]
+    fn picks_only_near_best_moves() {
+        let a1 = "a1".parse().unwrap();
+        let m = |to: &str| Move::Normal {
+            from: a1,
+            to: to.parse().unwrap(),
+        };
+        let scores = [(m("a2"), 100), (m("a3"), 90), (m("a4"), -500)];
+
+        // level 8 never blunders, and has a margin of 15
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            assert_ne!(Skill::STRONG.pick(&scores, &mut rng).0, m("a4"));
+        }
+        assert_eq!(
+            Skill::new(Skill::MAX_LEVEL).pick(&scores, &mut rng).0,
+            m("a2")
+        );
+    }
+
+    // Self-play is slow, run these with
+    // `cargo test --release -- --ignored --nocapture` after changing
+    // the levels
+
+    #[test]
+    #[ignore]
+    fn stronger_level_wins() {
+        let score = play_match(Skill::STRONG, Skill::new(0), 1);
+
+        assert!(score > 0.5, "level 8 only scored {}", score);
+    }
+
+    #[test]
+    #[ignore]
+    fn documented_elo_is_roughly_right() {
+        for level in 1..Skill::MAX_LEVEL {
+            let (a, b) = (Skill::new(level - 1), Skill::new(level));
+            let measured = elo_difference(play_match(b, a, 20));
+            let documented = f64::from(b.elo().unwrap() - a.elo().unwrap());
+            println!(
+                "level {} vs {}: {:.0} Elo measured, {:.0} documented",
+                b.level(),
+                a.level(),
+                measured,
+                documented
+            );
+
+            assert!(
+                (measured - documented).abs() <= 200.0,
+                "level {} is {:.0} Elo better than {}, documented as {:.0}",
+                b.level(),
+                measured,
+                a.level(),
+                documented
+            );
+        }
+    }
+}
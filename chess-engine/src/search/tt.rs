@@ -0,0 +1,200 @@
+//! The transposition table, shared between all the search threads
+use crate::board::{Castling, Move, SquareSpec};
+use crate::piece::PieceType;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What kind of bound the score of an entry is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) best_move: Option<Move>,
+    pub(crate) score: i32,
+    pub(crate) depth: u32,
+    pub(crate) bound: Bound,
+}
+
+// Every slot is two words, the key xor'd with the data and the data
+// itself. If two threads write to the same slot at the same time the
+// words may end up from different writes, but then the key check will
+// fail, so we never need any locking.
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+/// A lockless hash table from zobrist keys to search results
+pub(crate) struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl TranspositionTable {
+    /// Create a table taking up roughly `mb` megabytes
+    pub(crate) fn new(mb: usize) -> Self {
+        let n_slots = (mb.max(1) * 1024 * 1024 / size_of::<Slot>()).max(1);
+        Self {
+            slots: (0..n_slots)
+                .map(|_| Slot {
+                    check: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    /// Remove all entries from the table
+    pub(crate) fn clear(&self) {
+        for slot in &self.slots {
+            slot.check.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[(key % self.slots.len() as u64) as usize]
+    }
+
+    pub(crate) fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        let check = slot.check.load(Ordering::Relaxed);
+        if data == 0 || check ^ data != key {
+            return None;
+        }
+        Some(unpack(data))
+    }
+
+    pub(crate) fn store(&self, key: u64, entry: Entry) {
+        let slot = self.slot(key);
+        let data = pack(entry);
+        slot.check.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranspositionTable")
+            .field("slots", &self.slots.len())
+            .finish()
+    }
+}
+
+// layout of the data word, from the least significant bit:
+// 16 bits of move, 16 bits of score, 8 bits of depth and 2 bits of
+// bound, which is never zero so that an empty slot never matches
+fn pack(entry: Entry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3,
+    };
+    u64::from(encode_move(entry.best_move))
+        | u64::from(entry.score as i16 as u16) << 16
+        | u64::from(entry.depth.min(255)) << 32
+        | bound << 40
+}
+
+fn unpack(data: u64) -> Entry {
+    Entry {
+        best_move: decode_move(data as u16),
+        score: i32::from((data >> 16) as u16 as i16),
+        depth: ((data >> 32) & 0xff) as u32,
+        bound: match (data >> 40) & 0b11 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        },
+    }
+}
+
+// 6 bits for each square, then 3 bits for the kind of move. Zero is
+// a1a1 which can never be a move, so it's used for `None`.
+fn encode_move(m: Option<Move>) -> u16 {
+    let sq = |sq: SquareSpec| (sq.rank * 8 + sq.file) as u16;
+    match m {
+        None => 0,
+        Some(Move::Normal { from, to }) => sq(from) | sq(to) << 6,
+        Some(Move::Promotion { from, to, target }) => {
+            let kind = match target {
+                PieceType::Knight => 1,
+                PieceType::Bishop => 2,
+                PieceType::Rook => 3,
+                _ => 4,
+            };
+            sq(from) | sq(to) << 6 | kind << 12
+        }
+        Some(Move::Castling(Castling::Short)) => 5 << 12,
+        Some(Move::Castling(Castling::Long)) => 6 << 12,
+    }
+}
+
+fn decode_move(m: u16) -> Option<Move> {
+    let sq = |i: u16| SquareSpec::new(u32::from(i & 0x3f) / 8, u32::from(i & 0x3f) % 8);
+    let (from, to) = (sq(m), sq(m >> 6));
+    match m >> 12 {
+        _ if m == 0 => None,
+        0 => Some(Move::Normal { from, to }),
+        kind @ 1..=4 => Some(Move::Promotion {
+            from,
+            to,
+            target: match kind {
+                1 => PieceType::Knight,
+                2 => PieceType::Bishop,
+                3 => PieceType::Rook,
+                _ => PieceType::Queen,
+            },
+        }),
+        5 => Some(Move::Castling(Castling::Short)),
+        _ => Some(Move::Castling(Castling::Long)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let entry = Entry {
+            best_move: Some(Move::Promotion {
+                from: "b7".parse().unwrap(),
+                to: "a8".parse().unwrap(),
+                target: PieceType::Knight,
+            }),
+            score: -1234,
+            depth: 7,
+            bound: Bound::Lower,
+        };
+
+        tt.store(0xdead_beef, entry);
+
+        assert_eq!(tt.probe(0xdead_beef), Some(entry));
+        assert_eq!(tt.probe(0xbeef_dead), None);
+
+        tt.clear();
+        assert_eq!(tt.probe(0xdead_beef), None);
+    }
+
+    #[test]
+    fn move_encoding_roundtrips() {
+        for m in [
+            None,
+            Some(Move::Castling(Castling::Short)),
+            Some(Move::Castling(Castling::Long)),
+            Some(Move::Normal {
+                from: "h8".parse().unwrap(),
+                to: "a1".parse().unwrap(),
+            }),
+        ] {
+            assert_eq!(decode_move(encode_move(m)), m);
+        }
+    }
+}
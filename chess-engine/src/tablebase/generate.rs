@@ -0,0 +1,849 @@
+//! Writes small Syzygy tables for the tests. The positions of a table
+//! are solved backwards from the mates, with the positions after a
+//! capture or promotion looked up in the tables written before, and
+//! the values are compressed the way the format expects. The tables
+//! only have wins, draws and losses, and the DTZ values are exact in
+//! plies.
+use super::table::{encode, Groups, Material, HAS_PAWNS, LOSS_PLIES, MAPPED, MAX_PIECES};
+use super::table::{SINGLE_VALUE, SPLIT, STM, WIN_PLIES};
+use super::{pieces, Tablebase, Wdl, DTZ_MAGIC, WDL_MAGIC};
+use crate::board::{Board, CastlingFlags, SquareSpec};
+use crate::piece::{Color, Piece, PieceType};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// The fixtures, in the order they have to be written, as the
+/// captures and promotions of one lead to the ones before
+const FIXTURES: [&str; 7] = ["KNvK", "KBvK", "KRvK", "KQvK", "KPvK", "KRvKN", "KNNvK"];
+
+/// Where the fixtures are checked in
+pub(super) fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/syzygy")
+}
+
+/// The piece of a table code
+fn piece(code: u8) -> Piece {
+    let piece = match code & 7 {
+        1 => PieceType::Pawn,
+        2 => PieceType::Knight,
+        3 => PieceType::Bishop,
+        4 => PieceType::Rook,
+        5 => PieceType::Queen,
+        _ => PieceType::King,
+    };
+    let color = if code & 8 == 0 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    Piece::new(piece, color)
+}
+
+/// How the positions of a table are numbered
+struct Layout {
+    material: Material,
+    /// The piece codes in the order they're encoded
+    pieces: [u8; MAX_PIECES],
+    /// The groups for every file
+    groups: Vec<Groups>,
+    /// Where the indices of every side to move and file start
+    starts: [[usize; 4]; 2],
+    total: usize,
+}
+
+impl Layout {
+    fn new(name: &str) -> Self {
+        let material = Material::from_name(name).expect("The name is a table");
+        assert!(
+            !material.symmetric,
+            "Only tables with a stronger side are written"
+        );
+        assert!(!material.both_pawns(), "Only one side can have pawns");
+
+        // the pawns lead, and without pawns the kings have to come
+        // first for the two kings' encoding
+        let (white, black) = name.split_once('v').expect("The name has two sides");
+        let code = |c: char, color: u8| {
+            let piece = match c {
+                'P' => 1,
+                'N' => 2,
+                'B' => 3,
+                'R' => 4,
+                'Q' => 5,
+                _ => 6,
+            };
+            piece | color
+        };
+        let mut codes = white
+            .chars()
+            .map(|c| code(c, 0))
+            .chain(black.chars().map(|c| code(c, 8)))
+            .collect::<Vec<_>>();
+        codes.sort_by_key(|&c| match c & 7 {
+            1 if material.has_pawns => 0,
+            6 if !material.has_pawns => 1 + usize::from(c & 8 != 0),
+            _ => 3,
+        });
+        let mut pieces = [0; MAX_PIECES];
+        pieces[..codes.len()].copy_from_slice(&codes);
+
+        let groups = (0..material.files())
+            .map(|file| Groups::new(&material, pieces, [0, 0xf], file))
+            .collect::<Vec<_>>();
+        let mut starts = [[0; 4]; 2];
+        let mut total = 0;
+        for side_starts in &mut starts {
+            for (file, groups) in groups.iter().enumerate() {
+                side_starts[file] = total;
+                total += groups.size() as usize;
+            }
+        }
+
+        Self {
+            material,
+            pieces,
+            groups,
+            starts,
+            total,
+        }
+    }
+
+    fn node(&self, side: usize, pieces: &[(u8, usize)]) -> usize {
+        let (file, idx) = encode(&self.material, |file| &self.groups[file], pieces);
+        self.starts[side][file] + idx as usize
+    }
+
+    fn size(&self, file: usize) -> usize {
+        self.groups[file].size() as usize
+    }
+}
+
+/// Call `f` with every placement of `codes` with the pawns off the
+/// first and last ranks and the kings apart
+fn placements(codes: &[u8], squares: &mut Vec<(u8, usize)>, f: &mut impl FnMut(&[(u8, usize)])) {
+    let code = match codes.get(squares.len()) {
+        Some(&code) => code,
+        None => {
+            let kings = squares
+                .iter()
+                .filter(|&&(code, _)| code & 7 == 6)
+                .map(|&(_, sq)| sq)
+                .collect::<Vec<_>>();
+            if (kings[0] >> 3).abs_diff(kings[1] >> 3) > 1
+                || (kings[0] & 7).abs_diff(kings[1] & 7) > 1
+            {
+                f(squares);
+            }
+            return;
+        }
+    };
+    for sq in 0..64 {
+        if code & 7 == 1 && !(8..56).contains(&sq) || squares.iter().any(|&(_, s)| s == sq) {
+            continue;
+        }
+        squares.push((code, sq));
+        placements(codes, squares, f);
+        let _ = squares.pop();
+    }
+}
+
+fn board(pieces: &[(u8, usize)], side: usize) -> Board {
+    let turn = if side == 0 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    let mut board = Board::new(turn, CastlingFlags::empty());
+    for &(code, sq) in pieces {
+        board[SquareSpec::new((sq >> 3) as u32, (sq & 7) as u32)] = Some(piece(code));
+    }
+    board
+}
+
+/// The positions of a table, with the moves between them
+struct Graph {
+    /// A position for every index, if one is legal
+    positions: Vec<Option<Vec<(u8, usize)>>>,
+    /// The moves of position `i` that stay in the table are
+    /// `moves[starts[i]..starts[i + 1]]`, with the top bit set for
+    /// zeroing moves
+    moves: Vec<u32>,
+    starts: Vec<usize>,
+    /// The same for the moves into every position
+    unmoves: Vec<u32>,
+    unmove_starts: Vec<usize>,
+    /// The best result of the moves into other tables
+    outside: Vec<Option<Wdl>>,
+    /// and of the captures, which the probes try themselves
+    captures: Vec<Option<Wdl>>,
+    mated: Vec<bool>,
+    stalemate: Vec<bool>,
+}
+
+const ZEROING: u32 = 1 << 31;
+
+impl Graph {
+    fn new(layout: &Layout, tablebase: &Tablebase) -> Self {
+        let n = layout.material.pieces;
+        let mut positions = vec![None; layout.total];
+        let mut seen = vec![false; layout.total];
+        placements(&layout.pieces[..n], &mut Vec::new(), &mut |pieces| {
+            for side in 0..2 {
+                let node = layout.node(side, pieces);
+                if seen[node] {
+                    continue;
+                }
+                seen[node] = true;
+                // the player that just moved can't be in check
+                if !board(pieces, 1 - side).in_check() {
+                    positions[node] = Some(pieces.to_vec());
+                }
+            }
+        });
+
+        let mut material = layout.pieces[..n].to_vec();
+        material.sort_unstable();
+        let mut moves = Vec::new();
+        let mut starts = vec![0];
+        let mut outside = vec![None; layout.total];
+        let mut captures = vec![None; layout.total];
+        let mut mated = vec![false; layout.total];
+        let mut stalemate = vec![false; layout.total];
+        for (node, position) in positions.iter().enumerate() {
+            if let Some(position) = position {
+                let side = usize::from(node >= layout.starts[1][0]);
+                let mut board = board(position, side);
+                let legal = board.get_all_legal_moves();
+                if legal.is_empty() {
+                    mated[node] = board.in_check();
+                    stalemate[node] = !board.in_check();
+                }
+                for m in legal {
+                    let info = board.move_info(m);
+                    let zeroing = info.is_capture() || info.piece().piece == PieceType::Pawn;
+                    let undo = board.make_move(m);
+                    let after = pieces(&board);
+                    let mut codes = after.iter().map(|&(code, _)| code).collect::<Vec<_>>();
+                    codes.sort_unstable();
+                    if codes == material {
+                        let to = layout.node(1 - side, &after) as u32;
+                        moves.push(if zeroing { to | ZEROING } else { to });
+                    } else {
+                        let wdl = -board
+                            .probe_wdl(tablebase)
+                            .expect("The smaller tables are written");
+                        outside[node] = outside[node].max(Some(wdl));
+                        if info.is_capture() {
+                            captures[node] = captures[node].max(Some(wdl));
+                        }
+                    }
+                    board.unmake_move(m, undo);
+                }
+            }
+            starts.push(moves.len());
+        }
+
+        let mut unmove_starts = vec![0; layout.total + 1];
+        for &m in &moves {
+            unmove_starts[(m & !ZEROING) as usize + 1] += 1;
+        }
+        for i in 0..layout.total {
+            unmove_starts[i + 1] += unmove_starts[i];
+        }
+        let mut unmoves = vec![0; moves.len()];
+        let mut next = unmove_starts.clone();
+        for node in 0..layout.total {
+            for &m in &moves[starts[node]..starts[node + 1]] {
+                let to = (m & !ZEROING) as usize;
+                unmoves[next[to]] = node as u32 | (m & ZEROING);
+                next[to] += 1;
+            }
+        }
+
+        Self {
+            positions,
+            moves,
+            starts,
+            unmoves,
+            unmove_starts,
+            outside,
+            captures,
+            mated,
+            stalemate,
+        }
+    }
+
+    fn moves(&self, node: usize) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.moves[self.starts[node]..self.starts[node + 1]]
+            .iter()
+            .map(|&m| ((m & !ZEROING) as usize, m & ZEROING != 0))
+    }
+
+    fn unmoves(&self, node: usize) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.unmoves[self.unmove_starts[node]..self.unmove_starts[node + 1]]
+            .iter()
+            .map(|&m| ((m & !ZEROING) as usize, m & ZEROING != 0))
+    }
+
+    /// Whether a capture or pawn move wins
+    fn zeroing_win(&self, node: usize, wdl: &[Option<Wdl>]) -> bool {
+        self.outside[node] == Some(Wdl::Win)
+            || self
+                .moves(node)
+                .any(|(to, zeroing)| zeroing && wdl[to] == Some(Wdl::Loss))
+    }
+
+    /// The result of every legal position
+    fn solve_wdl(&self) -> Vec<Option<Wdl>> {
+        let total = self.positions.len();
+        let mut wdl = vec![None; total];
+        let mut left = (0..total)
+            .map(|node| self.starts[node + 1] - self.starts[node])
+            .collect::<Vec<_>>();
+        let mut queue = Vec::new();
+        for node in (0..total).filter(|&node| self.positions[node].is_some()) {
+            let outside = self.outside[node];
+            if self.mated[node] {
+                wdl[node] = Some(Wdl::Loss);
+            } else if self.stalemate[node] {
+                wdl[node] = Some(Wdl::Draw);
+            } else if outside == Some(Wdl::Win) || (left[node] == 0 && outside == Some(Wdl::Loss)) {
+                wdl[node] = outside;
+            } else if left[node] == 0 {
+                wdl[node] = Some(Wdl::Draw);
+            }
+            if wdl[node].is_some() {
+                queue.push(node);
+            }
+        }
+
+        while let Some(node) = queue.pop() {
+            let value = wdl[node];
+            for (from, _) in self.unmoves(node) {
+                if wdl[from].is_some() {
+                    continue;
+                }
+                if value == Some(Wdl::Loss) {
+                    wdl[from] = Some(Wdl::Win);
+                    queue.push(from);
+                } else if value == Some(Wdl::Win) {
+                    left[from] -= 1;
+                    if left[from] == 0 && self.outside[from] <= Some(Wdl::Loss) {
+                        wdl[from] = Some(Wdl::Loss);
+                        queue.push(from);
+                    }
+                }
+            }
+        }
+
+        // whatever isn't decided can be held to a draw
+        for node in (0..total).filter(|&node| self.positions[node].is_some()) {
+            wdl[node] = wdl[node].or(Some(Wdl::Draw));
+        }
+        wdl
+    }
+
+    /// The plies to the next zeroing move or mate of every won or lost
+    /// position, with the winner hurrying and the loser holding out
+    fn solve_dtz(&self, wdl: &[Option<Wdl>]) -> Vec<u32> {
+        let total = self.positions.len();
+        let mut dtz = vec![0_u32; total];
+        let mut left = vec![0; total];
+        let mut longest = vec![1_u32; total];
+        let mut layers: Vec<Vec<usize>> = vec![Vec::new(); 2];
+
+        for node in 0..total {
+            match wdl[node] {
+                Some(Wdl::Win) => {
+                    let wins_now = self.outside[node] == Some(Wdl::Win)
+                        || self.moves(node).any(|(to, zeroing)| {
+                            wdl[to] == Some(Wdl::Loss) && (zeroing || self.mated[to])
+                        });
+                    if wins_now {
+                        dtz[node] = 1;
+                        layers[1].push(node);
+                    }
+                }
+                Some(Wdl::Loss) => {
+                    left[node] = self.moves(node).filter(|&(_, zeroing)| !zeroing).count();
+                    if left[node] == 0 {
+                        dtz[node] = 1;
+                        layers[1].push(node);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut plies = 1;
+        while plies < layers.len() {
+            let layer = std::mem::take(&mut layers[plies]);
+            for node in layer {
+                for (from, zeroing) in self.unmoves(node) {
+                    if zeroing {
+                        continue;
+                    }
+                    match (wdl[node], wdl[from]) {
+                        (Some(Wdl::Loss), Some(Wdl::Win)) if dtz[from] == 0 => {
+                            dtz[from] = plies as u32 + 1;
+                        }
+                        (Some(Wdl::Win), Some(Wdl::Loss)) => {
+                            longest[from] = longest[from].max(plies as u32 + 1);
+                            left[from] -= 1;
+                            if left[from] > 0 {
+                                continue;
+                            }
+                            dtz[from] = longest[from];
+                        }
+                        _ => continue,
+                    }
+                    let at = dtz[from] as usize;
+                    if layers.len() <= at {
+                        layers.resize(at + 1, Vec::new());
+                    }
+                    layers[at].push(from);
+                }
+            }
+            plies += 1;
+        }
+
+        for node in 0..total {
+            if matches!(wdl[node], Some(Wdl::Win) | Some(Wdl::Loss)) {
+                assert!(dtz[node] > 0, "Every result is reached");
+                assert!(dtz[node] <= 100, "There are no cursed wins");
+            }
+        }
+        dtz
+    }
+}
+
+/// The compressed values of one side to move and file
+#[derive(Default)]
+struct Compressed {
+    /// Starting with the flags
+    sizes: Vec<u8>,
+    sparse_index: Vec<u8>,
+    block_lengths: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Compressed {
+    fn len(&self) -> usize {
+        self.sizes.len() + self.sparse_index.len() + self.block_lengths.len() + self.data.len()
+    }
+}
+
+const BLOCK_BITS: u8 = 6;
+const SPAN_BITS: u8 = 12;
+/// The most values in a block, which keeps the offsets of the sparse
+/// index in 16 bits
+const BLOCK_VALUES: u32 = 1 << 15;
+/// The symbol that marks a value in the tree
+const LEAF: u16 = 0xfff;
+
+/// Compress `values` with recursive pairing and a canonical Huffman
+/// code
+fn compress(values: &[u16], flags: u8) -> Compressed {
+    if values.iter().all(|&v| v == values[0]) {
+        return Compressed {
+            sizes: vec![flags | SINGLE_VALUE, values[0] as u8],
+            ..Compressed::default()
+        };
+    }
+
+    // every value is a symbol, then the most common pairs of symbols
+    // become symbols until there's no pair worth it
+    let mut symbols = Vec::new();
+    let mut leaves = BTreeMap::new();
+    for &v in values {
+        let _ = leaves.entry(v).or_insert_with(|| {
+            symbols.push((v, LEAF, 1_u32));
+            symbols.len() as u16 - 1
+        });
+    }
+    let mut seq = values.iter().map(|v| leaves[v]).collect::<Vec<_>>();
+    let mut counts = vec![0_u32; 1 << 24];
+    let mut touched = Vec::new();
+    while symbols.len() < usize::from(LEAF) {
+        for pair in seq.windows(2) {
+            let key = (usize::from(pair[0]) << 12) | usize::from(pair[1]);
+            if counts[key] == 0 {
+                touched.push(key);
+            }
+            counts[key] += 1;
+        }
+        let best = touched
+            .iter()
+            .copied()
+            .filter(|&key| symbols[key >> 12].2 + symbols[key & 0xfff].2 <= 256)
+            .max_by_key(|&key| (counts[key], Reverse(key)));
+        let best_count = best.map_or(0, |key| counts[key]);
+        for key in touched.drain(..) {
+            counts[key] = 0;
+        }
+        let best = match best {
+            Some(key) if best_count >= 8 => key,
+            _ => break,
+        };
+
+        let (left, right) = ((best >> 12) as u16, (best & 0xfff) as u16);
+        symbols.push((
+            left,
+            right,
+            symbols[usize::from(left)].2 + symbols[usize::from(right)].2,
+        ));
+        let sym = symbols.len() as u16 - 1;
+        let mut out = Vec::with_capacity(seq.len());
+        let mut i = 0;
+        while i < seq.len() {
+            if i + 1 < seq.len() && seq[i] == left && seq[i + 1] == right {
+                out.push(sym);
+                i += 2;
+            } else {
+                out.push(seq[i]);
+                i += 1;
+            }
+        }
+        seq = out;
+    }
+
+    // Huffman code lengths, flattened until they fit in 32 bits
+    let mut freq = vec![0_u64; symbols.len()];
+    for &sym in &seq {
+        freq[usize::from(sym)] += 1;
+    }
+    let lengths = loop {
+        let lengths = huffman(&freq);
+        if lengths.iter().all(|&len| len <= 32) {
+            break lengths;
+        }
+        for f in freq.iter_mut().filter(|f| **f > 0) {
+            *f = *f / 2 + 1;
+        }
+    };
+
+    // symbols with longer codes get lower numbers, the ones only used
+    // in pairs come last
+    let mut order = (0..symbols.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&sym| (lengths[sym] == 0, Reverse(lengths[sym]), sym));
+    let mut renumber = vec![0_u16; symbols.len()];
+    for (new, &old) in order.iter().enumerate() {
+        renumber[old] = new as u16;
+    }
+    let min_len = lengths
+        .iter()
+        .copied()
+        .filter(|&len| len > 0)
+        .min()
+        .unwrap_or(1);
+    let max_len = lengths.iter().copied().max().unwrap_or(1);
+    let levels = (max_len - min_len + 1) as usize;
+    let mut count = vec![0_u64; levels];
+    for &len in lengths.iter().filter(|&&len| len > 0) {
+        count[(len - min_len) as usize] += 1;
+    }
+    let mut lowest = vec![0_u64; levels];
+    let mut base = vec![0_u64; levels];
+    for i in (0..levels - 1).rev() {
+        lowest[i] = lowest[i + 1] + count[i + 1];
+        base[i] = u64::midpoint(base[i + 1], count[i + 1]);
+    }
+    let code = |sym: usize| {
+        let level = (lengths[sym] - min_len) as usize;
+        (
+            base[level] + u64::from(renumber[sym]) - lowest[level],
+            lengths[sym],
+        )
+    };
+
+    let mut sizes = vec![flags, BLOCK_BITS, SPAN_BITS, 0];
+    // the number of blocks is filled in below
+    sizes.extend_from_slice(&[0; 4]);
+    sizes.push(max_len as u8);
+    sizes.push(min_len as u8);
+    for &low in &lowest {
+        sizes.extend_from_slice(&(low as u16).to_le_bytes());
+    }
+    sizes.extend_from_slice(&(symbols.len() as u16).to_le_bytes());
+    for &old in &order {
+        let (left, right, _) = symbols[old];
+        let (left, right) = if right == LEAF {
+            (left, LEAF)
+        } else {
+            (renumber[usize::from(left)], renumber[usize::from(right)])
+        };
+        sizes.push(left as u8);
+        sizes.push(((left >> 8) as u8 & 0xf) | ((right as u8 & 0xf) << 4));
+        sizes.push((right >> 4) as u8);
+    }
+    if symbols.len() % 2 == 1 {
+        sizes.push(0);
+    }
+
+    // the symbols are packed into blocks that start with a whole
+    // symbol
+    let block_bits = 8 << BLOCK_BITS;
+    let mut blocks: Vec<Vec<bool>> = vec![Vec::new()];
+    let mut block_values = vec![0_u32];
+    for &sym in &seq {
+        let (code, len) = code(usize::from(sym));
+        let values = symbols[usize::from(sym)].2;
+        let last = blocks.len() - 1;
+        if blocks[last].len() + len as usize > block_bits
+            || block_values[last] + values > BLOCK_VALUES
+        {
+            blocks.push(Vec::new());
+            block_values.push(0);
+        }
+        let last = blocks.len() - 1;
+        blocks[last].extend((0..len).rev().map(|bit| (code >> bit) & 1 == 1));
+        block_values[last] += values;
+    }
+    sizes[4..8].copy_from_slice(&(blocks.len() as u32).to_le_bytes());
+
+    let mut data = Vec::new();
+    for block in &blocks {
+        let mut bytes = vec![0_u8; 1 << BLOCK_BITS];
+        for (i, _) in block.iter().enumerate().filter(|&(_, &bit)| bit) {
+            bytes[i / 8] |= 0x80 >> (i % 8);
+        }
+        data.extend_from_slice(&bytes);
+    }
+    let mut block_lengths = Vec::new();
+    for &values in &block_values {
+        block_lengths.extend_from_slice(&(values as u16 - 1).to_le_bytes());
+    }
+
+    // the sparse index points at the middle of every span, past the
+    // end it points into the last block
+    let span = 1_usize << SPAN_BITS;
+    let mut sparse_index = Vec::new();
+    let mut block = 0;
+    let mut start = 0;
+    for k in 0..values.len().div_ceil(span) {
+        let middle = k * span + span / 2;
+        while block + 1 < blocks.len() && start + block_values[block] as usize <= middle {
+            start += block_values[block] as usize;
+            block += 1;
+        }
+        sparse_index.extend_from_slice(&(block as u32).to_le_bytes());
+        sparse_index.extend_from_slice(&((middle - start) as u16).to_le_bytes());
+    }
+
+    Compressed {
+        sizes,
+        sparse_index,
+        block_lengths,
+        data,
+    }
+}
+
+/// The lengths of the Huffman codes for symbols with frequencies
+/// `freq`, 0 for symbols that aren't used
+fn huffman(freq: &[u64]) -> Vec<u32> {
+    let mut lengths = vec![0; freq.len()];
+    let used = (0..freq.len())
+        .filter(|&sym| freq[sym] > 0)
+        .collect::<Vec<_>>();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+        return lengths;
+    }
+
+    // the trees are merged with their leaves, which get one bit
+    // longer every time
+    let mut heap = used
+        .iter()
+        .map(|&sym| Reverse((freq[sym], vec![sym])))
+        .collect::<BinaryHeap<_>>();
+    while heap.len() > 1 {
+        let Reverse((a, mut left)) = heap.pop().expect("There are two trees");
+        let Reverse((b, right)) = heap.pop().expect("There are two trees");
+        left.extend(right);
+        for &sym in &left {
+            lengths[sym] += 1;
+        }
+        heap.push(Reverse((a + b, left)));
+    }
+    lengths
+}
+
+/// Assemble a file from the compressed values of every file and side
+/// to move, and for DTZ tables the maps of every file
+fn file(layout: &Layout, magic: [u8; 4], parts: &[Vec<Compressed>], maps: &[Vec<u8>]) -> Vec<u8> {
+    let material = &layout.material;
+    let mut out = magic.to_vec();
+    let mut flags = SPLIT;
+    if material.has_pawns {
+        flags |= HAS_PAWNS;
+    }
+    out.push(flags);
+    for _ in 0..material.files() {
+        out.push(0);
+        out.extend(
+            layout.pieces[..material.pieces]
+                .iter()
+                .map(|&code| code | (code << 4)),
+        );
+    }
+    let align = |out: &mut Vec<u8>, n: usize| {
+        while out.len() % n != 0 {
+            out.push(0);
+        }
+    };
+    align(&mut out, 2);
+
+    for file in parts {
+        for part in file {
+            out.extend_from_slice(&part.sizes);
+        }
+    }
+    if magic == DTZ_MAGIC {
+        for map in maps {
+            out.extend_from_slice(map);
+        }
+        align(&mut out, 2);
+    }
+    for file in parts {
+        for part in file {
+            out.extend_from_slice(&part.sparse_index);
+        }
+    }
+    for file in parts {
+        for part in file {
+            out.extend_from_slice(&part.block_lengths);
+        }
+    }
+    for file in parts {
+        for part in file {
+            align(&mut out, 64);
+            out.extend_from_slice(&part.data);
+        }
+    }
+    out
+}
+
+/// Pick values in the ranges the positions allow, as close to the one
+/// before as possible so that they compress well. Positions that
+/// aren't legal are never looked up and allow any value.
+fn fill(values: impl Iterator<Item = RangeInclusive<u16>>) -> Vec<u16> {
+    let mut last = 0;
+    values
+        .map(|range| {
+            last = last.clamp(*range.start(), *range.end());
+            last
+        })
+        .collect()
+}
+
+/// Write the WDL and DTZ tables for `name` into `dir`, which has to
+/// have the tables that captures and promotions lead to
+pub(super) fn generate(dir: &Path, name: &str) {
+    let layout = Layout::new(name);
+    let tablebase = Tablebase::open(dir).expect("The directory can be read");
+    let graph = Graph::new(&layout, &tablebase);
+    let wdl = graph.solve_wdl();
+    let dtz = graph.solve_dtz(&wdl);
+    let files = layout.material.files();
+    let range = |side: usize, file: usize| {
+        let start = layout.starts[side][file];
+        start..start + layout.size(file)
+    };
+
+    let wdl_parts = (0..files)
+        .map(|file| {
+            (0..2)
+                .map(|side| {
+                    // the probes find the result of a capture themselves,
+                    // so if that's the best move the table may have any
+                    // result that isn't better
+                    let values = fill(range(side, file).map(|node| match wdl[node] {
+                        Some(wdl) if graph.captures[node] == Some(wdl) => 0..=wdl as u16,
+                        Some(wdl) => wdl as u16..=wdl as u16,
+                        None => 0..=Wdl::Win as u16,
+                    }));
+                    compress(&values, 0)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let wdl_file = file(&layout, WDL_MAGIC, &wdl_parts, &[]);
+
+    // the DTZ table has the side to move that compresses better, with
+    // the values of the wins and losses mapped to the most common
+    // first
+    let len =
+        |parts: &[Vec<Compressed>]| parts.iter().flatten().map(Compressed::len).sum::<usize>();
+    let (parts, maps) = (0..2)
+        .map(|side| {
+            let mut parts = Vec::new();
+            let mut maps = Vec::new();
+            for file in 0..files {
+                let mut lists = [BTreeMap::new(), BTreeMap::new()];
+                for node in range(side, file) {
+                    match wdl[node] {
+                        Some(Wdl::Win) if !graph.zeroing_win(node, &wdl) => {
+                            *lists[0].entry(dtz[node] - 1).or_insert(0) += 1
+                        }
+                        Some(Wdl::Loss) => *lists[1].entry(dtz[node] - 1).or_insert(0) += 1,
+                        _ => (),
+                    }
+                }
+                let lists = lists
+                    .iter()
+                    .map(|list| {
+                        let mut list = list
+                            .iter()
+                            .map(|(&value, &count)| (value, count))
+                            .collect::<Vec<_>>();
+                        list.sort_by_key(|&(value, count): &(u32, u32)| (Reverse(count), value));
+                        list.into_iter().map(|(value, _)| value).collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                // the probes don't look at the draws, or at the wins with
+                // a zeroing move
+                let values = fill(range(side, file).map(|node| {
+                    let list = match wdl[node] {
+                        Some(Wdl::Win) if !graph.zeroing_win(node, &wdl) => &lists[0],
+                        Some(Wdl::Loss) => &lists[1],
+                        _ => return 0..=u16::MAX,
+                    };
+                    let i = list
+                        .iter()
+                        .position(|&value| value == dtz[node] - 1)
+                        .expect("The value is in the list") as u16;
+                    i..=i
+                }));
+
+                let flags = (side as u8 * STM) | MAPPED | WIN_PLIES | LOSS_PLIES;
+                let part = compress(&values, flags);
+                // wins, losses, and no cursed wins or blessed losses
+                let mut map = Vec::new();
+                for list in lists.iter().chain(&[Vec::new(), Vec::new()]) {
+                    assert!(list.len() < 256, "The values fit in a byte");
+                    map.push(list.len() as u8);
+                    map.extend(list.iter().map(|&value| value as u8));
+                }
+                parts.push(vec![part]);
+                maps.push(map);
+            }
+            (parts, maps)
+        })
+        .min_by_key(|(parts, _)| len(parts))
+        .expect("There are two sides to move");
+    let dtz_file = file(&layout, DTZ_MAGIC, &parts, &maps);
+
+    fs::write(dir.join(format!("{}.rtbw", name)), wdl_file).expect("The table can be written");
+    fs::write(dir.join(format!("{}.rtbz", name)), dtz_file).expect("The table can be written");
+}
+
+/// Write the fixtures into `dir`
+pub(super) fn write_fixtures(dir: &Path) {
+    fs::create_dir_all(dir).expect("The directory can be made");
+    for name in FIXTURES {
+        generate(dir, name);
+    }
+}
@@ -0,0 +1,809 @@
+//! Syzygy endgame tablebases, which know the result of every position
+//! with few enough pieces. The tables come in pairs of files named by
+//! the material in them, e.g. `KQvK.rtbw` for win/draw/loss (WDL) and
+//! `KQvK.rtbz` for the distance to zeroing the fifty move counter
+//! (DTZ). See [`Board::probe_wdl`] and [`Board::probe_dtz`] for
+//! looking positions up.
+//!
+//! The tables leave out positions with castling rights and don't know
+//! about en passant, and they may store any value for a position where
+//! a capture is at least as good as what's stored. The probes make up
+//! for that by trying the captures themselves, so they're always right
+//! for legal positions.
+//!
+//! A table is read into memory the first time it's needed.
+use crate::board::{Board, Move};
+use crate::error::Error;
+use crate::piece::{Color, PieceType};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[cfg(test)]
+mod generate;
+mod table;
+
+use table::{Material, Table};
+
+/// The first bytes of every WDL file
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+/// The first bytes of every DTZ file
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+/// The result of a position with perfect play, from the perspective
+/// of the player to move. Cursed wins and blessed losses are wins and
+/// losses that take too long for the fifty move rule, and so are
+/// draws.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    /// The player to move loses
+    Loss,
+    /// The player to move loses, but can claim a draw by the fifty
+    /// move rule
+    BlessedLoss,
+    /// The position is a draw
+    Draw,
+    /// The player to move wins, but the opponent can claim a draw by
+    /// the fifty move rule
+    CursedWin,
+    /// The player to move wins
+    Win,
+}
+
+impl Wdl {
+    /// 1 for wins, -1 for losses and 0 for draws, cursed or blessed
+    /// results included
+    pub fn signum(self) -> i32 {
+        match self {
+            Wdl::Loss | Wdl::BlessedLoss => -1,
+            Wdl::Draw => 0,
+            Wdl::CursedWin | Wdl::Win => 1,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    /// The result for the other player
+    fn neg(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+/// A table file, which is read the first time it's probed
+#[derive(Debug)]
+struct TableFile {
+    path: PathBuf,
+    table: OnceLock<Result<Table, String>>,
+}
+
+impl TableFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            table: OnceLock::new(),
+        }
+    }
+
+    fn get(&self, material: &Material, dtz: bool) -> Result<&Table, Error> {
+        self.table
+            .get_or_init(|| {
+                let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+                fs::read(&self.path)
+                    .map_err(Error::from)
+                    .and_then(|data| Table::new(data, material.clone(), magic, dtz))
+                    .map_err(|e| format!("can't read {}: {}", self.path.display(), e))
+            })
+            .as_ref()
+            .map_err(|e| Error::Tablebase(e.clone()))
+    }
+}
+
+/// The files found for a material combination
+#[derive(Debug)]
+struct TableFiles {
+    material: Material,
+    wdl: Option<TableFile>,
+    dtz: Option<TableFile>,
+}
+
+/// A directory of Syzygy tables
+///
+/// # Examples
+/// ```no_run
+/// # use chess_engine::board::Board;
+/// # use chess_engine::tablebase::{Tablebase, Wdl};
+/// let tablebase = Tablebase::open("/path/to/syzygy").unwrap();
+/// println!("found tables for up to {} pieces", tablebase.max_pieces());
+///
+/// let board = Board::load_fen("8/8/8/8/8/8/8/KQ5k w - - 0 1").unwrap();
+/// assert_eq!(board.probe_wdl(&tablebase).unwrap(), Wdl::Win);
+/// ```
+#[derive(Debug, Default)]
+pub struct Tablebase {
+    tables: HashMap<String, TableFiles>,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    /// Find the tables in a directory. Files that aren't tables are
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the directory can't be read, or if a
+    /// file named like a table doesn't start like one.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut tablebase = Self::default();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let (name, extension) = match (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) {
+                (Some(name), Some(extension)) => (name.to_string(), extension),
+                _ => continue,
+            };
+            let magic = match extension {
+                "rtbw" => WDL_MAGIC,
+                "rtbz" => DTZ_MAGIC,
+                _ => continue,
+            };
+            let material = match Material::from_name(&name) {
+                Some(material) => material,
+                None => continue,
+            };
+
+            let mut header = [0; 4];
+            File::open(&path)?.read_exact(&mut header)?;
+            if header != magic {
+                return Err(Error::Tablebase(format!(
+                    "{} isn't a Syzygy table",
+                    path.display()
+                )));
+            }
+
+            tablebase.max_pieces = tablebase.max_pieces.max(material.pieces);
+            let files = tablebase.tables.entry(name).or_insert(TableFiles {
+                material,
+                wdl: None,
+                dtz: None,
+            });
+            if magic == WDL_MAGIC {
+                files.wdl = Some(TableFile::new(path));
+            } else {
+                files.dtz = Some(TableFile::new(path));
+            }
+        }
+
+        Ok(tablebase)
+    }
+
+    /// The most pieces, kings included, in any of the tables
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether no tables were found
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Whether `board` could be in the tables, which doesn't mean that
+    /// its table was found
+    pub(crate) fn covers(&self, board: &Board) -> bool {
+        board.castling().is_empty() && pieces(board).len() <= self.max_pieces
+    }
+
+    /// Find the files for the material on `board`, and whether the
+    /// colors have to be turned around to look the board up in them
+    fn files(&self, board: &Board) -> Result<(&TableFiles, bool), Error> {
+        if !board.castling().is_empty() {
+            return Err(Error::Tablebase(
+                "positions with castling rights aren't in the tables".to_string(),
+            ));
+        }
+
+        // the stronger side comes first in the name, it's easier to
+        // try both than to work out which one is stronger
+        let name = table_name(board, Color::White);
+        if let Some(files) = self.tables.get(&name) {
+            // both sides have the same pieces, and the table only has
+            // white to move
+            let flip = files.material.symmetric && board.turn() == Color::Black;
+            return Ok((files, flip));
+        }
+        let flipped = table_name(board, Color::Black);
+        self.tables
+            .get(&flipped)
+            .map(|files| (files, true))
+            .ok_or_else(|| Error::Tablebase(format!("there is no table for {}", name)))
+    }
+
+    /// The pieces of `board` as the table has them, and the side to
+    /// move in the table
+    fn orient(board: &Board, flip: bool) -> (Vec<(u8, usize)>, usize) {
+        let pieces = pieces(board)
+            .into_iter()
+            .map(|(code, sq)| {
+                if flip {
+                    (code ^ 8, sq ^ 56)
+                } else {
+                    (code, sq)
+                }
+            })
+            .collect();
+        let side = usize::from(flip) ^ usize::from(board.turn() == Color::Black);
+        (pieces, side)
+    }
+
+    /// The WDL value stored for `board`, which may be wrong if a
+    /// capture is at least as good
+    fn table_wdl(&self, board: &Board) -> Result<Wdl, Error> {
+        if pieces(board).len() == 2 {
+            return Ok(Wdl::Draw);
+        }
+        let (files, flip) = self.files(board)?;
+        let table = files
+            .wdl
+            .as_ref()
+            .ok_or_else(|| {
+                Error::Tablebase(format!(
+                    "there is no WDL table for {}",
+                    table_name(board, Color::White)
+                ))
+            })?
+            .get(&files.material, false)?;
+        let (pieces, side) = Self::orient(board, flip);
+        table.wdl(&pieces, side)
+    }
+
+    /// The DTZ value stored for `board`, which has the result `wdl`,
+    /// or [`None`] if the table has the other side to move
+    fn table_dtz(&self, board: &Board, wdl: Wdl) -> Result<Option<i32>, Error> {
+        let (files, flip) = self.files(board)?;
+        let table = files
+            .dtz
+            .as_ref()
+            .ok_or_else(|| {
+                Error::Tablebase(format!(
+                    "there is no DTZ table for {}",
+                    table_name(board, Color::White)
+                ))
+            })?
+            .get(&files.material, true)?;
+        let (pieces, side) = Self::orient(board, flip);
+        table.dtz(&pieces, side, wdl)
+    }
+
+    /// The legal moves on `board` that keep the best result, and that
+    /// reach it the fastest when winning or the slowest when losing,
+    /// judged by the DTZ after the move. All drawing moves are equally
+    /// good.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the position or one after a move isn't
+    /// in the tables.
+    pub(crate) fn best_moves(&self, board: &Board) -> Result<Vec<Move>, Error> {
+        let mut board = *board;
+        let mut ranked = Vec::new();
+
+        for m in board.get_all_legal_moves() {
+            let undo = board.make_move(m);
+            let dtz = if board.halfmove() == 0 {
+                Ok(dtz_before_zeroing(-search(&mut board, self, false)?.0))
+            } else {
+                dtz(&mut board, self).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = board.in_check() && board.get_all_legal_moves().is_empty();
+            board.unmake_move(m, undo);
+
+            let dtz = match dtz? {
+                2 if mates => 1,
+                dtz => dtz,
+            };
+            let rank = match dtz {
+                d if d > 0 => 1000 - d,
+                d if d < 0 => -1000 - d,
+                _ => 0,
+            };
+            ranked.push((m, rank));
+        }
+
+        let best = ranked.iter().map(|&(_, rank)| rank).max().unwrap_or(0);
+        Ok(ranked
+            .into_iter()
+            .filter(|&(_, rank)| rank == best)
+            .map(|(m, _)| m)
+            .collect())
+    }
+}
+
+impl Board {
+    /// Look up the result of the position in `tablebase`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the position has castling rights, or if
+    /// there's no table for it, or for a position after a capture.
+    pub fn probe_wdl(&self, tablebase: &Tablebase) -> Result<Wdl, Error> {
+        check_pawns(self)?;
+        let mut board = *self;
+        search(&mut board, tablebase, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Look up the number of plies until the fifty move counter is
+    /// reset with perfect play, by a capture or a pawn move, or by
+    /// mate. It's positive if the player to move wins and negative if
+    /// they lose, 0 for draws, and more than 100 plies away from 0 for
+    /// cursed wins and blessed losses. As most tables store moves
+    /// rather than plies, it may be one ply too small.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the position has castling rights, or if
+    /// there's no table for it, or for a position after a capture.
+    pub fn probe_dtz(&self, tablebase: &Tablebase) -> Result<i32, Error> {
+        check_pawns(self)?;
+        let mut board = *self;
+        dtz(&mut board, tablebase)
+    }
+}
+
+/// The codes and squares of the pieces on `board`
+fn pieces(board: &Board) -> Vec<(u8, usize)> {
+    let mut pieces = Vec::new();
+    for (rank, row) in board.get_board().iter().enumerate() {
+        for (file, piece) in row.iter().enumerate() {
+            if let Some(piece) = piece {
+                pieces.push((table::piece_code(*piece), rank * 8 + file));
+            }
+        }
+    }
+    pieces
+}
+
+/// Pawns on the first or last rank can't be looked up
+fn check_pawns(board: &Board) -> Result<(), Error> {
+    let rows = board.get_board();
+    if rows[0]
+        .iter()
+        .chain(rows[7].iter())
+        .flatten()
+        .any(|piece| piece.piece == PieceType::Pawn)
+    {
+        return Err(Error::Tablebase(
+            "pawns on the first or last rank aren't in the tables".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The DTZ of the move before a capture or pawn move that leads to a
+/// position with the result `wdl` for the player that made it
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/// The result of `board`, from the table or from the captures if one
+/// is at least as good, which the table might not store. With
+/// `zeroing_moves`, pawn moves are tried as well. Also returns whether
+/// the best move resets the fifty move counter, in which case the DTZ
+/// table can't be trusted either.
+fn search(
+    board: &mut Board,
+    tablebase: &Tablebase,
+    zeroing_moves: bool,
+) -> Result<(Wdl, bool), Error> {
+    let moves = board.get_all_legal_moves();
+    let mut best = Wdl::Loss;
+    let mut searched = 0;
+
+    for &m in &moves {
+        let info = board.move_info(m);
+        let tried = info.is_capture() || (zeroing_moves && info.piece().piece == PieceType::Pawn);
+        if !tried {
+            continue;
+        }
+        searched += 1;
+
+        let undo = board.make_move(m);
+        let value = search(board, tablebase, false);
+        board.unmake_move(m, undo);
+        let value = -value?.0;
+        if value > best {
+            best = value;
+            if value == Wdl::Win {
+                return Ok((value, true));
+            }
+        }
+    }
+
+    // if every move has been tried there's no need to look at the
+    // table, which may not even know the position if the only moves
+    // are en passant
+    let no_more_moves = searched > 0 && searched == moves.len();
+    let value = if no_more_moves {
+        best
+    } else {
+        tablebase.table_wdl(board)?
+    };
+
+    if best >= value {
+        Ok((best, best > Wdl::Draw || no_more_moves))
+    } else {
+        Ok((value, false))
+    }
+}
+
+/// See [`Board::probe_dtz`]
+fn dtz(board: &mut Board, tablebase: &Tablebase) -> Result<i32, Error> {
+    let (wdl, zeroing) = search(board, tablebase, true)?;
+    if wdl == Wdl::Draw {
+        return Ok(0);
+    }
+    if zeroing {
+        return Ok(dtz_before_zeroing(wdl));
+    }
+    if let Some(dtz) = tablebase.table_dtz(board, wdl)? {
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        return Ok((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+    }
+
+    // the table only has the other side to move, so look one move
+    // ahead for the best DTZ
+    let mut best = None;
+    for m in board.get_all_legal_moves() {
+        let info = board.move_info(m);
+        let zeroing = info.is_capture() || info.piece().piece == PieceType::Pawn;
+
+        let undo = board.make_move(m);
+        // zeroing moves only need the result after them
+        let value = if zeroing {
+            search(board, tablebase, false).map(|(wdl, _)| -dtz_before_zeroing(wdl))
+        } else {
+            dtz(board, tablebase).map(|dtz| -dtz)
+        };
+        let mates = board.in_check() && board.get_all_legal_moves().is_empty();
+        board.unmake_move(m, undo);
+
+        let mut value = value?;
+        if value == 1 && mates {
+            best = Some(1);
+        }
+        if !zeroing {
+            value += value.signum();
+        }
+        if value.signum() == wdl.signum() && best.map_or(true, |best| value < best) {
+            best = Some(value);
+        }
+    }
+
+    // without moves the player to move is mated
+    Ok(best.unwrap_or(-1))
+}
+
+/// The name of the table for the material on `board`, with the pieces
+/// of `first` first, e.g. `KRPvKR`
+pub fn table_name(board: &Board, first: Color) -> String {
+    const ORDER: [PieceType; 6] = [
+        PieceType::King,
+        PieceType::Queen,
+        PieceType::Rook,
+        PieceType::Bishop,
+        PieceType::Knight,
+        PieceType::Pawn,
+    ];
+
+    let side = |color: Color| {
+        let mut pieces = board
+            .get_board()
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|piece| piece.color == color)
+            .map(|piece| piece.piece)
+            .collect::<Vec<_>>();
+        pieces.sort_by_key(|piece| ORDER.iter().position(|p| p == piece));
+        pieces.iter().map(PieceType::to_string).collect::<String>()
+    };
+
+    format!("{}v{}", side(first), side(first.opposite()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{CastlingFlags, SquareSpec};
+    use crate::piece::Piece;
+    use crate::search::kpk::Kpk;
+    use std::env;
+
+    /// A directory with empty tables that only have the header
+    fn tables(name: &str, files: &[(&str, [u8; 4])]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tablebase-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, header) in files {
+            fs::write(dir.join(file), header).unwrap();
+        }
+        dir
+    }
+
+    fn fixtures() -> Tablebase {
+        Tablebase::open(generate::fixtures()).unwrap()
+    }
+
+    fn probe(tablebase: &Tablebase, fen: &str) -> (Wdl, i32) {
+        let board = Board::load_fen(fen).unwrap();
+        (
+            board.probe_wdl(tablebase).unwrap(),
+            board.probe_dtz(tablebase).unwrap(),
+        )
+    }
+
+    #[test]
+    #[ignore = "rewrites the fixtures, run with --release"]
+    fn write_fixtures() {
+        generate::write_fixtures(&generate::fixtures());
+    }
+
+    #[test]
+    fn names_tables_by_material() {
+        let board = Board::load_fen("8/8/4k3/8/2P5/8/1R2K3/3r4 w - - 0 1").unwrap();
+
+        assert_eq!(table_name(&board, Color::White), "KRPvKR");
+        assert_eq!(table_name(&board, Color::Black), "KRvKRP");
+        assert_eq!(Material::from_name("KRPvKR").map(|m| m.pieces), Some(5));
+        assert_eq!(Material::from_name("README"), None);
+    }
+
+    #[test]
+    fn finds_tables() {
+        let dir = tables(
+            "finds",
+            &[
+                ("KQvK.rtbw", WDL_MAGIC),
+                ("KQvK.rtbz", DTZ_MAGIC),
+                ("KRvKP.rtbw", WDL_MAGIC),
+                ("notes.txt", [0; 4]),
+            ],
+        );
+        let tablebase = Tablebase::open(&dir);
+        let tablebase = tablebase.unwrap();
+
+        assert_eq!(tablebase.max_pieces(), 4);
+        // black has the queen, so the table is found flipped
+        let kqk = Board::load_fen("8/8/4k3/8/8/8/3q4/4K3 w - - 0 1").unwrap();
+        assert!(tablebase.files(&kqk).unwrap().1);
+
+        let krk = Board::load_fen("8/8/4k3/8/8/8/3R4/4K3 w - - 0 1").unwrap();
+        assert!(krk.probe_wdl(&tablebase).is_err());
+        let castling = Board::load_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        assert!(tablebase.files(&castling).is_err());
+        // the table only has a header
+        assert!(kqk.probe_wdl(&tablebase).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_broken_tables() {
+        let dir = tables("broken", &[("KQvK.rtbw", DTZ_MAGIC)]);
+        let tablebase = Tablebase::open(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(tablebase.is_err());
+    }
+
+    #[test]
+    fn knows_mates_and_stalemates() {
+        let tablebase = fixtures();
+        assert_eq!(tablebase.max_pieces(), 4);
+
+        // mated and stalemated by the queen
+        assert_eq!(
+            probe(&tablebase, "k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"),
+            (Wdl::Loss, -1)
+        );
+        assert_eq!(
+            probe(&tablebase, "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+        // mate in one, and the same with the colors turned around
+        assert_eq!(
+            probe(&tablebase, "k7/8/1K6/8/8/8/8/7R w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        assert_eq!(
+            probe(&tablebase, "7r/8/8/8/8/1k6/8/K7 b - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        // the rook hangs
+        assert_eq!(
+            probe(&tablebase, "8/8/8/3k4/3R4/8/8/K7 b - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+        // a bishop can't win
+        assert_eq!(
+            probe(&tablebase, "8/8/8/3k4/8/8/8/KB6 w - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+    }
+
+    #[test]
+    fn knows_distances_to_zeroing() {
+        let tablebase = fixtures();
+
+        // the queen mates in at most ten moves, the loser holds out the
+        // longest with the king in the middle
+        let (wdl, dtz) = probe(&tablebase, "8/8/8/3k4/8/8/8/KQ6 w - - 0 1");
+        assert_eq!(wdl, Wdl::Win);
+        assert!((3..=19).contains(&dtz), "{}", dtz);
+        let (wdl, dtz) = probe(&tablebase, "8/8/8/3k4/8/8/8/KQ6 b - - 0 1");
+        assert_eq!(wdl, Wdl::Loss);
+        assert!((-20..=-2).contains(&dtz), "{}", dtz);
+
+        // a capture of the knight is the fastest way to reset the
+        // counter
+        assert_eq!(
+            probe(&tablebase, "8/8/8/3k4/8/8/2n5/K1R5 w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        // and a pawn move
+        assert_eq!(
+            probe(&tablebase, "k7/8/8/8/8/8/5P2/5K2 w - - 0 1"),
+            (Wdl::Win, 1)
+        );
+        // two knights only mate if the king walks into it
+        assert_eq!(
+            probe(&tablebase, "k7/2NN4/1K6/8/8/8/8/8 b - - 0 1"),
+            (Wdl::Loss, -1)
+        );
+        assert_eq!(
+            probe(&tablebase, "8/8/8/3k4/8/8/8/KNN5 w - - 0 1"),
+            (Wdl::Draw, 0)
+        );
+    }
+
+    /// Legal boards with `pieces` on random squares, and random sides
+    /// to move
+    fn random_boards(pieces: &[Piece], count: usize) -> Vec<Board> {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut boards = Vec::new();
+        while boards.len() < count {
+            let turn = if random() & 1 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let mut board = Board::new(turn, CastlingFlags::empty());
+            let mut other = Board::new(turn.opposite(), CastlingFlags::empty());
+            for &piece in pieces {
+                let sq = loop {
+                    let sq = if piece.piece == PieceType::Pawn {
+                        SquareSpec::new(1 + (random() % 6) as u32, (random() % 8) as u32)
+                    } else {
+                        SquareSpec::new((random() % 8) as u32, (random() % 8) as u32)
+                    };
+                    if board[sq].is_none() {
+                        break sq;
+                    }
+                };
+                board[sq] = Some(piece);
+                other[sq] = Some(piece);
+            }
+            // the player that just moved can't be in check
+            if !other.in_check() {
+                boards.push(board);
+            }
+        }
+        boards
+    }
+
+    #[test]
+    fn agrees_with_the_kpk_bitbase() {
+        let tablebase = fixtures();
+        let kpk = Kpk::get();
+
+        for strong in [Color::White, Color::Black] {
+            let pieces = [
+                Piece::new(PieceType::King, strong),
+                Piece::new(PieceType::Pawn, strong),
+                Piece::new(PieceType::King, strong.opposite()),
+            ];
+            for board in random_boards(&pieces, 200) {
+                let expected = match (kpk.is_win(&board), board.turn() == strong) {
+                    (false, _) => Wdl::Draw,
+                    (true, true) => Wdl::Win,
+                    (true, false) => Wdl::Loss,
+                };
+                assert_eq!(board.probe_wdl(&tablebase).unwrap(), expected, "{}", board);
+            }
+        }
+    }
+
+    #[test]
+    fn values_follow_from_the_moves() {
+        let tablebase = fixtures();
+        let piece = |c: char| {
+            let color = if "KQRBNP".contains(c) {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let piece = match c.to_ascii_uppercase() {
+                'K' => PieceType::King,
+                'Q' => PieceType::Queen,
+                'R' => PieceType::Rook,
+                'N' => PieceType::Knight,
+                _ => PieceType::Pawn,
+            };
+            Piece::new(piece, color)
+        };
+
+        // both colors of every table, as the black pieces are found by
+        // turning the board around
+        for material in ["KQk", "KRk", "KPk", "KRkn", "KNNk", "Kkr", "Kkp", "KNkr"] {
+            let pieces = material.chars().map(piece).collect::<Vec<_>>();
+            for mut board in random_boards(&pieces, 40) {
+                let wdl = board.probe_wdl(&tablebase).unwrap();
+                let dtz = board.probe_dtz(&tablebase).unwrap();
+
+                let mut best = None;
+                let mut best_dtz = None;
+                for m in board.get_all_legal_moves() {
+                    let info = board.move_info(m);
+                    let zeroing = info.is_capture() || info.piece().piece == PieceType::Pawn;
+                    let undo = board.make_move(m);
+                    let after = -board.probe_wdl(&tablebase).unwrap();
+                    let after_dtz = if zeroing || board.get_all_legal_moves().is_empty() {
+                        after.signum()
+                    } else {
+                        -board.probe_dtz(&tablebase).unwrap() + after.signum()
+                    };
+                    board.unmake_move(m, undo);
+
+                    best = best.max(Some(after));
+                    if after == wdl && after != Wdl::Draw {
+                        // the fastest win and the slowest loss
+                        best_dtz = Some(best_dtz.map_or(after_dtz, |d: i32| d.min(after_dtz)));
+                    }
+                }
+
+                let mated = board.in_check() && best.is_none();
+                let expected = if mated {
+                    Wdl::Loss
+                } else {
+                    best.unwrap_or(Wdl::Draw)
+                };
+                assert_eq!(wdl, expected, "{}", board);
+                let expected_dtz = if mated { -1 } else { best_dtz.unwrap_or(0) };
+                assert_eq!(dtz, expected_dtz, "{}", board);
+            }
+        }
+    }
+}
@@ -0,0 +1,962 @@
+//! The Syzygy file format. A table stores one value for every
+//! position of its material, for white and black to move (WDL) or for
+//! only one of them (DTZ), under an index that removes the symmetries
+//! of the board. The values are compressed with recursive pairing,
+//! where a symbol stands for a pair of other symbols, and the symbols
+//! are stored with a canonical Huffman code in blocks that can be
+//! found through a sparse index.
+//!
+//! This follows the layout used by the original probing code by Ronald
+//! de Man and by Stockfish, with squares numbered from 0 for a1 to 63
+//! for h8 and pieces numbered like in those files: 1 to 6 for a white
+//! pawn, knight, bishop, rook, queen and king, and 8 more for black.
+use super::Wdl;
+use crate::error::Error;
+use crate::piece::{Color, Piece, PieceType};
+use std::convert::TryFrom;
+use std::sync::OnceLock;
+
+/// The most pieces in any table
+pub(super) const MAX_PIECES: usize = 7;
+
+/// The table stores the values for black to move (DTZ)
+pub(super) const STM: u8 = 1;
+/// The DTZ values are remapped by how often they occur
+pub(super) const MAPPED: u8 = 2;
+/// Wins are stored in plies rather than moves (DTZ)
+pub(super) const WIN_PLIES: u8 = 4;
+/// Losses are stored in plies rather than moves (DTZ)
+pub(super) const LOSS_PLIES: u8 = 8;
+/// The remapped values are 16 bits (DTZ)
+pub(super) const WIDE: u8 = 16;
+/// Every position has the same value, which is stored right after
+/// the flags
+pub(super) const SINGLE_VALUE: u8 = 128;
+
+/// The first byte of a file says whether the WDL values for the two
+/// sides to move are stored apart
+pub(super) const SPLIT: u8 = 1;
+/// and whether there are pawns
+pub(super) const HAS_PAWNS: u8 = 2;
+
+/// The code of a piece in the tables
+pub(super) fn piece_code(piece: Piece) -> u8 {
+    let code = match piece.piece {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+    };
+    match piece.color {
+        Color::White => code,
+        Color::Black => code | 8,
+    }
+}
+
+/// What can be known about a table from its name, with the first side
+/// of the name playing white
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Material {
+    pub(super) pieces: usize,
+    pub(super) has_pawns: bool,
+    /// Both sides have the same pieces
+    pub(super) symmetric: bool,
+    /// Some piece other than a king is the only one of its kind
+    pub(super) unique_pieces: bool,
+    /// The pawns of the leading color, which has the fewest pawns
+    /// but at least one, and of the other color
+    pub(super) pawns: [usize; 2],
+}
+
+impl Material {
+    /// Parse a name like `KRPvKR`, or [`None`] if it isn't a table
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let is_side = |side: &str| {
+            side.starts_with('K')
+                && side.chars().filter(|&c| c == 'K').count() == 1
+                && side.chars().all(|c| "KQRBNP".contains(c))
+        };
+        if !is_side(white) || !is_side(black) || white.len() + black.len() > MAX_PIECES {
+            return None;
+        }
+
+        let count = |side: &str, piece: char| side.chars().filter(|&c| c == piece).count();
+        let unique_pieces = "QRBNP"
+            .chars()
+            .any(|piece| count(white, piece) == 1 || count(black, piece) == 1);
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+
+        Some(Self {
+            pieces: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            symmetric: white == black,
+            unique_pieces,
+            pawns: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+        })
+    }
+
+    /// Whether both sides have pawns, then the pawns of the other
+    /// color are the second group
+    pub(super) fn both_pawns(&self) -> bool {
+        self.has_pawns && self.pawns[1] > 0
+    }
+
+    /// The number of files with their own table, the leading pawn is
+    /// mirrored to the a to d files
+    pub(super) fn files(&self) -> usize {
+        if self.has_pawns {
+            4
+        } else {
+            1
+        }
+    }
+}
+
+/// The tables that turn squares into indices, the same for every table
+struct Maps {
+    /// The squares a2 to h7 numbered from 47 down, with the edge files
+    /// and low ranks first, so that the leading pawn has the highest
+    pawns: [u64; 64],
+    /// The 28 squares below the a1-h8 diagonal
+    b1h1h7: [u64; 64],
+    /// The squares of the a1-d1-d4 triangle, the diagonal last
+    a1d1d4: [u64; 64],
+    /// The 462 placements of two kings with the first in the a1-d1-d4
+    /// triangle
+    kk: [[u64; 64]; 10],
+    /// `binomial[k][n]` ways to pick `k` of `n` squares
+    binomial: [[u64; 64]; 6],
+    /// The index of the leading pawns' placements for the square of
+    /// the leading one
+    lead_pawn_idx: [[u64; 64]; 6],
+    /// The number of placements of the leading pawns on every file
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+/// The rank minus the file, 0 on the a1-h8 diagonal and negative
+/// below it
+fn off_diagonal(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut maps = Maps {
+            pawns: [0; 64],
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                maps.b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for sq in 0..28 {
+            if off_diagonal(sq) < 0 && sq & 7 <= 3 {
+                maps.a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && sq & 7 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            maps.a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // placements with both kings on the diagonal come last, and if
+        // the first king is on it the other one can't be above it
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            // b1 is the only square of the triangle mapped to 0
+            let squares = (0..28)
+                .filter(|&sq| maps.a1d1d4[sq] == idx && (idx > 0 || sq == 1))
+                .collect::<Vec<_>>();
+            for k1 in squares {
+                for k2 in 0..64 {
+                    let touching =
+                        (k1 >> 3).abs_diff(k2 >> 3) <= 1 && (k1 & 7).abs_diff(k2 & 7) <= 1;
+                    if touching || (off_diagonal(k1) == 0 && off_diagonal(k2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(k1) == 0 && off_diagonal(k2) == 0 {
+                        both_on_diagonal.push((idx, k2));
+                    } else {
+                        maps.kk[idx as usize][k2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, k2) in both_on_diagonal {
+            maps.kk[idx as usize][k2] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                maps.binomial[k][n] = if k > 0 {
+                    maps.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        maps.pawns[sq] = available;
+                        maps.pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    maps.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += maps.binomial[lead_pawns - 1][maps.pawns[sq] as usize];
+                }
+                maps.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        maps
+    })
+}
+
+/// How the pieces of a table are grouped and in what order the groups
+/// make up the index
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(super) struct Groups {
+    /// The piece codes, in the order they're encoded
+    pub(super) pieces: [u8; MAX_PIECES],
+    /// The number of pieces in each group, ending with a 0
+    lens: [usize; MAX_PIECES + 1],
+    /// What the index of each group is multiplied by, and the size of
+    /// the table after the last group
+    factors: [u64; MAX_PIECES + 1],
+}
+
+impl Groups {
+    /// Group the `pieces` of a table. Pieces of the same kind make up
+    /// a group, except for the leading group, which has the leading
+    /// pawns, three unique pieces or the two kings. `order` says where
+    /// the leading group and the pawns of the other color go in the
+    /// index, `file` is the file of the leading pawn.
+    pub(super) fn new(
+        material: &Material,
+        pieces: [u8; MAX_PIECES],
+        order: [u8; 2],
+        file: usize,
+    ) -> Self {
+        let maps = maps();
+        let mut groups = Self {
+            pieces,
+            ..Self::default()
+        };
+
+        let mut first_len: i32 = if material.has_pawns {
+            0
+        } else if material.unique_pieces {
+            3
+        } else {
+            2
+        };
+        let mut n = 0;
+        groups.lens[0] = 1;
+        for i in 1..material.pieces {
+            first_len -= 1;
+            if first_len > 0 || pieces[i] == pieces[i - 1] {
+                groups.lens[n] += 1;
+            } else {
+                n += 1;
+                groups.lens[n] = 1;
+            }
+        }
+        n += 1;
+        groups.lens[n] = 0;
+
+        let both_pawns = material.both_pawns();
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - groups.lens[0] - if both_pawns { groups.lens[1] } else { 0 };
+        let mut factor = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                groups.factors[0] = factor;
+                factor *= if material.has_pawns {
+                    maps.lead_pawns_size[groups.lens[0]][file]
+                } else if material.unique_pieces {
+                    31_332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                groups.factors[1] = factor;
+                factor *= maps.binomial[groups.lens[1]][48 - groups.lens[0]];
+            } else {
+                groups.factors[next] = factor;
+                factor *= maps.binomial[groups.lens[next].min(5)][free];
+                free = free.saturating_sub(groups.lens[next]);
+                next += 1;
+            }
+            k += 1;
+        }
+        groups.factors[n] = factor;
+
+        groups
+    }
+
+    /// The number of indices
+    pub(super) fn size(&self) -> u64 {
+        let last = self.lens.iter().position(|&len| len == 0).unwrap_or(0);
+        self.factors[last]
+    }
+}
+
+/// The file the leading pawn is on and the index of the position,
+/// `pieces` are the codes and squares of the pieces with the colors
+/// already turned around to how the table has them. `groups` has the
+/// grouping of the pieces for every file.
+pub(super) fn encode<'g>(
+    material: &Material,
+    groups: impl Fn(usize) -> &'g Groups,
+    pieces: &[(u8, usize)],
+) -> (usize, u64) {
+    let maps = maps();
+    let mut squares = [0; MAX_PIECES];
+    let mut codes = [0; MAX_PIECES];
+    let mut size = 0;
+    let mut lead_pawns = 0;
+    let mut file = 0;
+
+    // the leading pawn is the one closest to the edge and to its own
+    // side of the board, it decides the file
+    let lead = groups(0).pieces[0];
+    if material.has_pawns {
+        for &(code, sq) in pieces.iter().filter(|&&(code, _)| code == lead) {
+            squares[size] = sq;
+            codes[size] = code;
+            size += 1;
+        }
+        lead_pawns = size;
+        let first = (0..lead_pawns)
+            .max_by_key(|&i| maps.pawns[squares[i]])
+            .unwrap_or(0);
+        squares.swap(0, first);
+        file = (squares[0] & 7).min(7 - (squares[0] & 7));
+    }
+    for &(code, sq) in pieces
+        .iter()
+        .filter(|&&(code, _)| !material.has_pawns || code != lead)
+    {
+        squares[size] = sq;
+        codes[size] = code;
+        size += 1;
+    }
+
+    // put the pieces in the order of the table
+    let groups = groups(file);
+    for i in lead_pawns..size.saturating_sub(1) {
+        if let Some(j) = (i + 1..size).find(|&j| codes[j] == groups.pieces[i]) {
+            codes.swap(i, j);
+            squares.swap(i, j);
+        }
+    }
+
+    // mirror the leading piece to the a to d files
+    if squares[0] & 7 > 3 {
+        for sq in &mut squares[..size] {
+            *sq ^= 7;
+        }
+    }
+
+    let mut idx;
+    if material.has_pawns {
+        idx = maps.lead_pawn_idx[lead_pawns][squares[0]];
+        squares[1..lead_pawns].sort_by_key(|&sq| maps.pawns[sq]);
+        for (i, &sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+            idx += maps.binomial[i][maps.pawns[sq] as usize];
+        }
+    } else {
+        // without pawns the leading piece also goes below the fifth
+        // rank, and the first one of its group that's off the a1-h8
+        // diagonal below it
+        if squares[0] >> 3 > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 56;
+            }
+        }
+        for i in 0..groups.lens[0] {
+            let off = off_diagonal(squares[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                for sq in &mut squares[i..size] {
+                    *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                }
+            }
+            break;
+        }
+
+        if material.unique_pieces {
+            let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+            let adjust1 = u64::from(s1 > s0);
+            let adjust2 = u64::from(s2 > s0) + u64::from(s2 > s1);
+            let rank = |sq: usize| (sq >> 3) as u64;
+            idx = if off_diagonal(s0) != 0 {
+                (maps.a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s1) != 0 {
+                (6 * 63 + rank(s0) * 28 + maps.b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s2) != 0 {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + rank(s0) * 7 * 28
+                    + (rank(s1) - adjust1) * 28
+                    + maps.b1h1h7[s2]
+            } else {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + 4 * 7 * 28
+                    + rank(s0) * 7 * 6
+                    + (rank(s1) - adjust1) * 6
+                    + (rank(s2) - adjust2)
+            };
+        } else {
+            idx = maps.kk[maps.a1d1d4[squares[0]] as usize][squares[1]];
+        }
+    }
+    idx *= groups.factors[0];
+
+    // the other groups are encoded by which of the squares that are
+    // left they're on
+    let mut start = groups.lens[0];
+    let mut other_pawns = material.both_pawns();
+    let mut next = 1;
+    while groups.lens[next] > 0 {
+        let end = start + groups.lens[next];
+        squares[start..end].sort_unstable();
+        let mut n = 0;
+        for i in 0..groups.lens[next] {
+            let sq = squares[start + i];
+            let below = squares[..start].iter().filter(|&&s| sq > s).count();
+            n += maps.binomial[i + 1][sq - below - if other_pawns { 8 } else { 0 }];
+        }
+        other_pawns = false;
+        idx += n * groups.factors[next];
+        start = end;
+        next += 1;
+    }
+
+    (file, idx)
+}
+
+/// Everything needed to decompress the values of one side to move
+/// and file
+#[derive(Clone, Debug, Default)]
+struct Pairs {
+    groups: Groups,
+    flags: u8,
+    min_len: u32,
+    block_size: usize,
+    span: u64,
+    blocks: usize,
+    // offsets into the file
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_lengths: usize,
+    block_lengths_size: usize,
+    data: usize,
+    /// The lowest code of every length, left aligned
+    base: Vec<u64>,
+    /// How many values each symbol stands for, minus one
+    symlen: Vec<u8>,
+    /// Where the remapped DTZ values for wins, losses, cursed wins and
+    /// blessed losses start
+    map_idx: [usize; 4],
+}
+
+/// A WDL or DTZ table read into memory
+#[derive(Debug)]
+pub(super) struct Table {
+    dtz: bool,
+    data: Vec<u8>,
+    material: Material,
+    /// Indexed by file of the leading pawn and side to move
+    pairs: [[Pairs; 2]; 4],
+    /// Where the DTZ value maps start
+    map: usize,
+}
+
+fn corrupt() -> Error {
+    Error::Tablebase("the table is corrupt".to_string())
+}
+
+/// Reads the little endian numbers of a file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + n).ok_or_else(corrupt)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Skip ahead to a multiple of `n`
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.div_ceil(n) * n;
+    }
+}
+
+fn le_u16(data: &[u8], at: usize) -> Result<u16, Error> {
+    Reader { data, pos: at }.u16()
+}
+
+/// A big endian number from the compressed data, which may be read a
+/// little past the end of the file
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = data.get(at + i).copied().unwrap_or(0);
+    }
+    u32::from_be_bytes(bytes)
+}
+
+impl Table {
+    /// Read a table of `material` from the contents of its file,
+    /// which start with `magic`
+    pub(super) fn new(
+        data: Vec<u8>,
+        material: Material,
+        magic: [u8; 4],
+        dtz: bool,
+    ) -> Result<Self, Error> {
+        if data.get(..4) != Some(&magic[..]) {
+            return Err(Error::Tablebase(
+                "the table has the wrong header".to_string(),
+            ));
+        }
+        let mut table = Self {
+            dtz,
+            data,
+            material,
+            pairs: Default::default(),
+            map: 0,
+        };
+        let material = &table.material;
+        let data = &table.data;
+        let mut reader = Reader { data, pos: 4 };
+
+        let flags = reader.u8()?;
+        if (flags & HAS_PAWNS != 0) != material.has_pawns
+            || (flags & SPLIT != 0) == material.symmetric
+        {
+            return Err(Error::Tablebase(
+                "the table doesn't have the material it's named after".to_string(),
+            ));
+        }
+
+        let sides = if dtz || material.symmetric { 1 } else { 2 };
+        let files = material.files();
+        let both_pawns = material.both_pawns();
+
+        let mut pairs: [[Pairs; 2]; 4] = Default::default();
+        for (file, file_pairs) in pairs.iter_mut().enumerate().take(files) {
+            let first = reader.u8()?;
+            let second = if both_pawns { reader.u8()? } else { 0xff };
+            let orders = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            let mut pieces = [[0; MAX_PIECES]; 2];
+            for k in 0..material.pieces {
+                let byte = reader.u8()?;
+                pieces[0][k] = byte & 0xf;
+                pieces[1][k] = byte >> 4;
+            }
+            for (side, p) in file_pairs.iter_mut().enumerate().take(sides) {
+                p.groups = Groups::new(material, pieces[side], orders[side], file);
+            }
+        }
+        reader.align(2);
+
+        for p in pairs
+            .iter_mut()
+            .take(files)
+            .flat_map(|f| f.iter_mut().take(sides))
+        {
+            read_sizes(&mut reader, p)?;
+        }
+
+        let map = reader.pos;
+        if dtz {
+            for p in pairs.iter_mut().take(files).map(|f| &mut f[0]) {
+                if p.flags & MAPPED == 0 {
+                    continue;
+                }
+                if p.flags & WIDE != 0 {
+                    reader.align(2);
+                    for idx in &mut p.map_idx {
+                        *idx = (reader.pos - map) / 2 + 1;
+                        let len = reader.u16()?;
+                        let _ = reader.bytes(2 * usize::from(len))?;
+                    }
+                } else {
+                    for idx in &mut p.map_idx {
+                        *idx = reader.pos - map + 1;
+                        let len = reader.u8()?;
+                        let _ = reader.bytes(len.into())?;
+                    }
+                }
+            }
+            reader.align(2);
+        }
+
+        for p in pairs
+            .iter_mut()
+            .take(files)
+            .flat_map(|f| f.iter_mut().take(sides))
+        {
+            p.sparse_index = reader.pos;
+            let _ = reader.bytes(6 * p.sparse_index_size)?;
+        }
+        for p in pairs
+            .iter_mut()
+            .take(files)
+            .flat_map(|f| f.iter_mut().take(sides))
+        {
+            p.block_lengths = reader.pos;
+            let _ = reader.bytes(2 * p.block_lengths_size)?;
+        }
+        for p in pairs
+            .iter_mut()
+            .take(files)
+            .flat_map(|f| f.iter_mut().take(sides))
+        {
+            reader.align(64);
+            p.data = reader.pos;
+            let _ = reader.bytes(p.blocks * p.block_size)?;
+        }
+
+        table.pairs = pairs;
+        table.map = map;
+        Ok(table)
+    }
+
+    fn pairs(&self, side: usize, file: usize) -> &Pairs {
+        let side = if self.dtz || self.material.symmetric {
+            0
+        } else {
+            side
+        };
+        &self.pairs[file][side]
+    }
+
+    /// Find the value of a position in the table. `pieces` have the
+    /// colors turned around to how the table has them, and `side` is
+    /// the side to move after that. Returns [`None`] if the table only
+    /// has the other side to move.
+    fn value(&self, pieces: &[(u8, usize)], side: usize) -> Result<Option<(usize, u16)>, Error> {
+        let (file, idx) = encode(
+            &self.material,
+            |file| &self.pairs(side, file).groups,
+            pieces,
+        );
+        let pairs = self.pairs(side, file);
+        if self.dtz
+            && usize::from(pairs.flags & STM) != side
+            && (!self.material.symmetric || self.material.has_pawns)
+        {
+            return Ok(None);
+        }
+        if idx >= pairs.groups.size() {
+            return Err(corrupt());
+        }
+        Ok(Some((file, self.decompress(pairs, idx)?)))
+    }
+
+    /// Look up the WDL value of a position, see [`Table::value`]
+    pub(super) fn wdl(&self, pieces: &[(u8, usize)], side: usize) -> Result<Wdl, Error> {
+        match self.value(pieces, side)? {
+            Some((_, 0)) => Ok(Wdl::Loss),
+            Some((_, 1)) => Ok(Wdl::BlessedLoss),
+            Some((_, 2)) => Ok(Wdl::Draw),
+            Some((_, 3)) => Ok(Wdl::CursedWin),
+            Some((_, 4)) => Ok(Wdl::Win),
+            _ => Err(corrupt()),
+        }
+    }
+
+    /// Look up the DTZ value of a position that has the result `wdl`,
+    /// in plies. It may be one too small if the table stores moves.
+    /// See [`Table::value`].
+    pub(super) fn dtz(
+        &self,
+        pieces: &[(u8, usize)],
+        side: usize,
+        wdl: Wdl,
+    ) -> Result<Option<i32>, Error> {
+        let (file, value) = match self.value(pieces, side)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let pairs = &self.pairs[file][0];
+
+        // the maps are in the order wins, losses, cursed wins and
+        // blessed losses
+        let list = match wdl {
+            Wdl::Win | Wdl::Draw => 0,
+            Wdl::Loss => 1,
+            Wdl::CursedWin => 2,
+            Wdl::BlessedLoss => 3,
+        };
+        let mut value = i32::from(value);
+        if pairs.flags & MAPPED != 0 {
+            let at = pairs.map_idx[list] + value as usize;
+            value = if pairs.flags & WIDE != 0 {
+                le_u16(&self.data, self.map + 2 * at)?.into()
+            } else {
+                (*self.data.get(self.map + at).ok_or_else(corrupt)?).into()
+            };
+        }
+
+        let plies = match wdl {
+            Wdl::Win => pairs.flags & WIN_PLIES != 0,
+            Wdl::Loss => pairs.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        if !plies {
+            value *= 2;
+        }
+        Ok(Some(value + 1))
+    }
+
+    /// Decompress the value at `idx`
+    fn decompress(&self, pairs: &Pairs, idx: u64) -> Result<u16, Error> {
+        if pairs.flags & SINGLE_VALUE != 0 {
+            return Ok(pairs.min_len as u16);
+        }
+        let data = &self.data[..];
+        let symbol = |sym: u16| {
+            pairs
+                .symlen
+                .get(usize::from(sym))
+                .map(|&len| i64::from(len) + 1)
+        };
+        let btree = |sym: u16, right: bool| -> Result<u16, Error> {
+            let at = pairs.btree + 3 * usize::from(sym);
+            let lr = data.get(at..at + 3).ok_or_else(corrupt)?;
+            Ok(if right {
+                (u16::from(lr[2]) << 4) | (u16::from(lr[1]) >> 4)
+            } else {
+                (u16::from(lr[1] & 0xf) << 8) | u16::from(lr[0])
+            })
+        };
+        let block_length = |block: usize| -> Result<i64, Error> {
+            if block >= pairs.block_lengths_size {
+                return Err(corrupt());
+            }
+            Ok(i64::from(le_u16(data, pairs.block_lengths + 2 * block)?))
+        };
+
+        // the sparse index has the block and offset in the block of
+        // every `span`th value, starting in the middle of the first
+        // span, from which the block with `idx` is found
+        let k = (idx / pairs.span) as usize;
+        let mut entry = Reader {
+            data,
+            pos: pairs.sparse_index + 6 * k,
+        };
+        if k >= pairs.sparse_index_size {
+            return Err(corrupt());
+        }
+        let mut block = entry.u32()? as usize;
+        let mut offset = i64::from(entry.u16()?);
+        offset += (idx % pairs.span) as i64 - (pairs.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1).ok_or_else(corrupt)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+        if block >= pairs.blocks {
+            return Err(corrupt());
+        }
+
+        // then the symbols of the block are read until the one that
+        // stands for the value
+        let mut ptr = pairs.data + block * pairs.block_size;
+        let mut buf = (u64::from(be_u32(data, ptr)) << 32) | u64::from(be_u32(data, ptr + 4));
+        ptr += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < pairs.base[len] {
+                len += 1;
+            }
+            let bits = len as u32 + pairs.min_len;
+            sym = ((buf - pairs.base[len]) >> (64 - bits)) as u16;
+            sym = sym.wrapping_add(le_u16(data, pairs.lowest_sym + 2 * len)?);
+            let values = symbol(sym).ok_or_else(corrupt)?;
+            if offset < values {
+                break;
+            }
+            offset -= values;
+            buf <<= bits;
+            buf_size -= bits;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= u64::from(be_u32(data, ptr)) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // and the symbol is split into its pair until the offset is
+        // at a single value
+        while symbol(sym).ok_or_else(corrupt)? > 1 {
+            let left = btree(sym, false)?;
+            let values = symbol(left).ok_or_else(corrupt)?;
+            if offset < values {
+                sym = left;
+            } else {
+                offset -= values;
+                sym = btree(sym, true)?;
+            }
+        }
+        btree(sym, false)
+    }
+}
+
+/// Read the sizes of the compressed data of one side to move and file
+fn read_sizes(reader: &mut Reader<'_>, pairs: &mut Pairs) -> Result<(), Error> {
+    pairs.flags = reader.u8()?;
+    if pairs.flags & SINGLE_VALUE != 0 {
+        pairs.min_len = reader.u8()?.into();
+        return Ok(());
+    }
+
+    let size = pairs.groups.size();
+    let (block_bits, span_bits) = (reader.u8()?, reader.u8()?);
+    if block_bits > 16 || span_bits > 32 {
+        return Err(corrupt());
+    }
+    pairs.block_size = 1 << block_bits;
+    pairs.span = 1 << span_bits;
+    pairs.sparse_index_size = size.div_ceil(pairs.span) as usize;
+    let padding = reader.u8()?;
+    pairs.blocks = reader.u32()? as usize;
+    pairs.block_lengths_size = pairs.blocks + usize::from(padding);
+    let max_len = u32::from(reader.u8()?);
+    pairs.min_len = reader.u8()?.into();
+    if pairs.min_len == 0 || max_len < pairs.min_len || max_len > 32 {
+        return Err(corrupt());
+    }
+
+    // canonical Huffman codes, where longer codes have lower values,
+    // so that the lowest code of every length is found from the
+    // lowest symbol of the lengths
+    pairs.lowest_sym = reader.pos;
+    let lengths = (max_len - pairs.min_len + 1) as usize;
+    let mut lowest = Vec::with_capacity(lengths);
+    for _ in 0..lengths {
+        lowest.push(u64::from(reader.u16()?));
+    }
+    pairs.base = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        pairs.base[i] = (pairs.base[i + 1] + lowest[i])
+            .checked_sub(lowest[i + 1])
+            .ok_or_else(corrupt)?
+            / 2;
+    }
+    for (i, base) in pairs.base.iter_mut().enumerate() {
+        *base <<= 64 - i as u32 - pairs.min_len;
+    }
+
+    // every symbol is a value or a pair of symbols
+    let symbols = usize::from(reader.u16()?);
+    pairs.btree = reader.pos;
+    let tree = reader.bytes(3 * symbols)?;
+    let mut symlen = vec![None; symbols];
+    for sym in 0..symbols {
+        let _ = set_symlen(tree, sym, &mut symlen, 0)?;
+    }
+    pairs.symlen = symlen.into_iter().map(|len| len.unwrap_or(0)).collect();
+    if symbols % 2 == 1 {
+        let _ = reader.u8()?;
+    }
+
+    Ok(())
+}
+
+/// Work out how many values `sym` stands for, minus one
+fn set_symlen(
+    tree: &[u8],
+    sym: usize,
+    symlen: &mut [Option<u8>],
+    depth: usize,
+) -> Result<u8, Error> {
+    if let Some(len) = symlen[sym] {
+        return Ok(len);
+    }
+    // the symbols of a table can't stand for more than 256 values,
+    // which also keeps a broken table from going around in circles
+    if depth > 256 {
+        return Err(corrupt());
+    }
+    let lr = &tree[3 * sym..3 * sym + 3];
+    let left = (usize::from(lr[1] & 0xf) << 8) | usize::from(lr[0]);
+    let right = (usize::from(lr[2]) << 4) | (usize::from(lr[1]) >> 4);
+    let len = if right == 0xfff {
+        0
+    } else if left >= symlen.len() || right >= symlen.len() {
+        return Err(corrupt());
+    } else {
+        let left = set_symlen(tree, left, symlen, depth + 1)?;
+        let right = set_symlen(tree, right, symlen, depth + 1)?;
+        u8::try_from(u32::from(left) + u32::from(right) + 1).map_err(|_| corrupt())?
+    };
+    symlen[sym] = Some(len);
+    Ok(len)
+}
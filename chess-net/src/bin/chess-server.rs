@@ -0,0 +1,125 @@
+//! Host games without a GUI, e.g. on a server with no display. Both
+//! sides are played by clients, the first two to say hello play white
+//! and black, and when a game ends the next one starts after a while.
+//! At most `--max-spectators` clients can watch, 16 if it isn't given,
+//! and a player who loses their connection has `--grace-period` seconds
+//! to come back, 60 if it isn't given, before losing the game. Browsers
+//! can connect too if `--websocket-port` is given. When a game ends
+//! its record is printed as PGN, with what the players said and agreed
+//! on as comments.
+//!
+//! ```text
+//! chess-server [--address ADDR] [--port N] [--websocket-port N] [--config FILE]
+//!              [--max-spectators N] [--grace-period SECONDS]
+//! ```
+use chess_engine::game::BoardState;
+use chess_engine::{Color, Game};
+use chess_net::config::ListenConfig;
+use chess_net::host::{Host, DEFAULT_GRACE_PERIOD, DEFAULT_MAX_SPECTATORS};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: chess-server [--address ADDR] [--port N] [--websocket-port N] \
+                     [--config FILE] [--max-spectators N] [--grace-period SECONDS]";
+
+/// How often the clients are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a finished game stays up before the next one starts
+const NEXT_GAME_DELAY: Duration = Duration::from_secs(30);
+
+struct Options {
+    config: ListenConfig,
+    max_spectators: usize,
+    grace_period: Duration,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        config: ListenConfig::default(),
+        max_spectators: DEFAULT_MAX_SPECTATORS,
+        grace_period: DEFAULT_GRACE_PERIOD,
+    };
+    while let Some(arg) = args.next() {
+        if options
+            .config
+            .parse_arg(&arg, &mut args)
+            .map_err(|e| e.to_string())?
+        {
+            continue;
+        }
+        match arg.as_str() {
+            "--max-spectators" => {
+                let max = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                options.max_spectators = max
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't a number of spectators", max))?;
+            }
+            "--grace-period" => {
+                let seconds = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                options.grace_period = Duration::from_secs(
+                    seconds
+                        .parse()
+                        .map_err(|_| format!("`{}` isn't a number of seconds", seconds))?,
+                );
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let config = &options.config;
+    let mut host = Host::bind(config.to_string(), &[Color::White, Color::Black])
+        .map_err(|e| format!("can't listen on {}: {}", config, e))?;
+    host.set_max_spectators(options.max_spectators);
+    host.set_grace_period(options.grace_period);
+    match host.local_addr() {
+        Ok(addr) => println!("listening on {}", addr),
+        Err(_) => println!("listening on {}", config),
+    }
+    if let Some(addr) = config.websocket_addr() {
+        host.listen_websocket(&addr)
+            .map_err(|e| format!("can't listen on {}: {}", addr, e))?;
+        println!("listening for WebSocket clients on {}", addr);
+    }
+
+    let mut game = Game::new();
+    let mut game_over_since = None;
+    loop {
+        let _ = host.update(&mut game);
+
+        let over = host.adjudication().is_some()
+            || matches!(
+                game.board_state(),
+                BoardState::Checkmate | BoardState::Stalemate | BoardState::Draw
+            );
+        if over {
+            if game_over_since.is_none() {
+                print!("{}", host.record().to_pgn(&game, host.adjudication()));
+            }
+            let since = *game_over_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= NEXT_GAME_DELAY {
+                println!("starting a new game");
+                game = Game::new();
+                host.new_game();
+                game_over_since = None;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn main() {
+    let result = parse_args(std::env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,623 @@
+//! The wire protocol, kept apart from Bevy and the sockets so that it
+//! can be tested on its own.
+//!
+//! # Packets
+//!
+//! A packet is UTF-8 text ended by a `;`:
+//!
+//! ```text
+//! [<id>@]<kind>[:<field>[|<field>...]];
+//! ```
+//!
+//! Inside a field `\` escapes the next character, so names and
+//! messages can contain `\;`, `\|` and `\\`. The id is an optional
+//! number picked by the client, the reply to that packet carries the
+//! same id, e.g. `7@move:e2e4;` is answered with `7@ok;` or
+//! `7@error:...;`. Packets that aren't replies have no id.
+//!
+//! # Messages
+//!
+//! A client first says `hello`, anything else before that is answered
+//! with an error. The server answers with its own `hello`, the `seat`
+//! the client has been given, its `session`, and then the `state` of
+//! the game. After that the client can ask for another seat whenever it
+//! likes. A player who lost its connection can say hello again and
+//! `resume` its session to get its seat back, if it's within the grace
+//! period the host gives it.
+//!
+//! Players can also chat, offer a draw, ask to take back a move, and
+//! resign, which are sent to the server and, if the client's seat
+//! allows it, passed on to everyone, the client included. An offer
+//! lapses once a move is made. An accepted takeback takes back the
+//! last move of the side that asked for it, and the reply to it if
+//! there's been one.
+//!
+//! | Packet                       | Sent by | Meaning                                  |
+//! |------------------------------|---------|------------------------------------------|
+//! | `hello:<version>\|<name>`    | both    | the protocol version and who's talking   |
+//! | `seat:<seat>`                | both    | from a client, ask to play `white` or `black`, or to be a `spectator`. From the server, the seat the client has |
+//! | `session:<token>`            | server  | the token to resume the session with after reconnecting |
+//! | `resume:<token>`             | client  | take back the seat of a session that lost its connection |
+//! | `move:<uci>`                 | both    | from a client, make the move. From the server, a move was made |
+//! | `state:<fen>\|<moves>`       | server  | the position the game started from, and the moves since, in UCI separated by spaces |
+//! | `result:<result>\|<reason>`  | server  | the game is over, e.g. `result:1-0\|checkmate` |
+//! | `spectators:<count>`         | server  | the number of clients watching the game  |
+//! | `chat:<name>\|<text>`        | both    | someone said something, the server fills in the name of who it was |
+//! | `offer:<side>\|<offer>`      | both    | the player of `side` offers a `draw`, or asks for a `takeback` |
+//! | `accept:<side>\|<offer>`     | both    | the player of `side` accepts the other side's offer |
+//! | `decline:<side>\|<offer>`    | both    | the player of `side` turns down the other side's offer |
+//! | `resign:<side>`              | both    | the player of `side` resigns             |
+//! | `ok`                         | server  | the request with the same id worked      |
+//! | `error:<message>`            | server  | a request failed, or a packet couldn't be understood |
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use chess_engine::pgn::GameResult;
+use chess_engine::Color;
+
+/// The version of the protocol in this file, a client with a
+/// different version is turned away
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The messages that can be sent either way, see the module docs for
+/// which side sends which
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// Who's talking, the first thing both sides say
+    Hello {
+        /// The version of the protocol spoken, see [`PROTOCOL_VERSION`]
+        version: u32,
+        /// The name of the player, or of the host
+        name: String,
+    },
+    /// The side the client plays, `None` for a spectator
+    Seat(Option<Color>),
+    /// The token that identifies the client's session
+    Session(String),
+    /// Resume the session with the token
+    Resume(String),
+    /// A move in UCI notation, it takes a board to turn it into a
+    /// [`chess_engine::Move`]
+    Move(String),
+    /// The whole game, sent when a client joins or the game is
+    /// replaced
+    State {
+        /// The position the game started from
+        fen: String,
+        /// The moves since, in UCI notation
+        moves: Vec<String>,
+    },
+    /// The number of clients watching the game, sent when it changes
+    Spectators(u32),
+    /// The game is over
+    Result {
+        /// Who won
+        result: GameResult,
+        /// How the game ended, e.g. `checkmate`
+        reason: String,
+    },
+    /// Something said to everyone in the game
+    Chat {
+        /// Who said it, which the host fills in
+        name: String,
+        /// What they said
+        text: String,
+    },
+    /// A player asks their opponent to agree to something
+    Offer {
+        /// The side asking
+        side: Color,
+        /// What they ask for
+        offer: Offer,
+    },
+    /// A player agrees to their opponent's offer
+    Accept {
+        /// The side agreeing
+        side: Color,
+        /// What was offered
+        offer: Offer,
+    },
+    /// A player turns down their opponent's offer
+    Decline {
+        /// The side turning it down
+        side: Color,
+        /// What was offered
+        offer: Offer,
+    },
+    /// The player of the side gives up the game
+    Resign(Color),
+    /// The request with the same id worked
+    Ok,
+    /// A request failed, or a packet couldn't be understood
+    Error(String),
+}
+
+/// What a player can ask their opponent to agree to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Offer {
+    /// End the game in a draw
+    Draw,
+    /// Take back the last move of the side asking, and the reply to
+    /// it if there's been one
+    Takeback,
+}
+
+/// A message together with the id that ties requests and replies
+/// together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// The id of the request, which its reply has too
+    pub id: Option<u32>,
+    /// What the packet says
+    pub message: Message,
+}
+
+impl Packet {
+    /// A packet that isn't a request or a reply
+    pub fn new(message: Message) -> Self {
+        Self { id: None, message }
+    }
+
+    /// A reply to the request with the id `id`
+    pub fn reply(id: Option<u32>, message: Message) -> Self {
+        Self { id, message }
+    }
+}
+
+/// Why a packet couldn't be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    /// The packet isn't one of the messages
+    UnknownKind(String),
+    /// The packet has too few or too many fields for its message
+    WrongFieldCount {
+        /// The kind of message
+        kind: &'static str,
+        /// The number of fields it should have
+        expected: usize,
+        /// The number of fields it has
+        found: usize,
+    },
+    /// The field named by the first string has a value that doesn't
+    /// make sense
+    InvalidField(&'static str, String),
+    /// The id isn't a number
+    InvalidId(String),
+    /// A `\` at the very end, with nothing to escape
+    DanglingEscape,
+    /// The text isn't JSON, see [`crate::json`]
+    InvalidJson(String),
+    /// The JSON object doesn't have the member
+    MissingField(&'static str),
+}
+
+impl StdError for CodecError {}
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKind(kind) => write!(f, "unknown packet `{}`", kind),
+            Self::WrongFieldCount {
+                kind,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` packets have {} fields, not {}",
+                kind, expected, found
+            ),
+            Self::InvalidField(field, value) => write!(f, "`{}` isn't a valid {}", value, field),
+            Self::InvalidId(id) => write!(f, "`{}` isn't a valid packet id", id),
+            Self::DanglingEscape => write!(f, "the packet ends with a `\\`"),
+            Self::InvalidJson(what) => write!(f, "invalid JSON: {}", what),
+            Self::MissingField(name) => write!(f, "the packet has no `{}`", name),
+        }
+    }
+}
+
+/// Find the `;` ending the first packet in `buffer`, skipping escaped
+/// ones. `None` means the packet hasn't all arrived yet.
+pub fn frame_end(buffer: &[u8]) -> Option<usize> {
+    let mut escaped = false;
+    for (i, &b) in buffer.iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b';' => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+impl Message {
+    /// The name of the message in a packet, e.g. `hello`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::Seat(_) => "seat",
+            Self::Session(_) => "session",
+            Self::Resume(_) => "resume",
+            Self::Move(_) => "move",
+            Self::State { .. } => "state",
+            Self::Spectators(_) => "spectators",
+            Self::Result { .. } => "result",
+            Self::Chat { .. } => "chat",
+            Self::Offer { .. } => "offer",
+            Self::Accept { .. } => "accept",
+            Self::Decline { .. } => "decline",
+            Self::Resign(_) => "resign",
+            Self::Ok => "ok",
+            Self::Error(_) => "error",
+        }
+    }
+
+    /// The fields of the message in a packet, as text
+    pub(crate) fn fields(&self) -> Vec<String> {
+        match self {
+            Self::Hello { version, name } => vec![version.to_string(), name.clone()],
+            Self::Seat(seat) => vec![seat_str(*seat).to_string()],
+            Self::Session(token) | Self::Resume(token) => vec![token.clone()],
+            Self::Move(m) => vec![m.clone()],
+            Self::State { fen, moves } => vec![fen.clone(), moves.join(" ")],
+            Self::Spectators(count) => vec![count.to_string()],
+            Self::Result { result, reason } => {
+                vec![result_str(*result).to_string(), reason.clone()]
+            }
+            Self::Chat { name, text } => vec![name.clone(), text.clone()],
+            Self::Offer { side, offer }
+            | Self::Accept { side, offer }
+            | Self::Decline { side, offer } => vec![
+                seat_str(Some(*side)).to_string(),
+                offer_str(*offer).to_string(),
+            ],
+            Self::Resign(side) => vec![seat_str(Some(*side)).to_string()],
+            Self::Ok => vec![],
+            Self::Error(message) => vec![message.clone()],
+        }
+    }
+
+    /// Read a message of the kind from the fields of a packet
+    pub(crate) fn from_fields(kind: &str, fields: Vec<String>) -> Result<Self, CodecError> {
+        let (kind, expected) = match kind {
+            "hello" => ("hello", 2),
+            "seat" => ("seat", 1),
+            "session" => ("session", 1),
+            "resume" => ("resume", 1),
+            "move" => ("move", 1),
+            "state" => ("state", 2),
+            "spectators" => ("spectators", 1),
+            "result" => ("result", 2),
+            "chat" => ("chat", 2),
+            "offer" => ("offer", 2),
+            "accept" => ("accept", 2),
+            "decline" => ("decline", 2),
+            "resign" => ("resign", 1),
+            "ok" => ("ok", 0),
+            "error" => ("error", 1),
+            _ => return Err(CodecError::UnknownKind(kind.to_string())),
+        };
+        if fields.len() != expected {
+            return Err(CodecError::WrongFieldCount {
+                kind,
+                expected,
+                found: fields.len(),
+            });
+        }
+
+        let mut fields = fields.into_iter();
+        let mut next = || fields.next().unwrap_or_default();
+        Ok(match kind {
+            "hello" => {
+                let version = next();
+                Self::Hello {
+                    version: version
+                        .parse()
+                        .map_err(|_| CodecError::InvalidField("version", version))?,
+                    name: next(),
+                }
+            }
+            "seat" => Self::Seat(parse_seat(&next())?),
+            "session" => Self::Session(next()),
+            "resume" => Self::Resume(next()),
+            "move" => Self::Move(next()),
+            "state" => Self::State {
+                fen: next(),
+                moves: next().split_whitespace().map(String::from).collect(),
+            },
+            "spectators" => {
+                let count = next();
+                Self::Spectators(
+                    count
+                        .parse()
+                        .map_err(|_| CodecError::InvalidField("spectator count", count))?,
+                )
+            }
+            "result" => {
+                let result = next();
+                Self::Result {
+                    result: result
+                        .parse()
+                        .map_err(|_| CodecError::InvalidField("result", result))?,
+                    reason: next(),
+                }
+            }
+            "chat" => Self::Chat {
+                name: next(),
+                text: next(),
+            },
+            "offer" | "accept" | "decline" => {
+                let side = parse_side(&next())?;
+                let offer = parse_offer(&next())?;
+                match kind {
+                    "offer" => Self::Offer { side, offer },
+                    "accept" => Self::Accept { side, offer },
+                    _ => Self::Decline { side, offer },
+                }
+            }
+            "resign" => Self::Resign(parse_side(&next())?),
+            "ok" => Self::Ok,
+            _ => Self::Error(next()),
+        })
+    }
+}
+
+/// The name of a seat in a packet, `white`, `black` or `spectator`
+pub fn seat_str(seat: Option<Color>) -> &'static str {
+    match seat {
+        Some(Color::White) => "white",
+        Some(Color::Black) => "black",
+        None => "spectator",
+    }
+}
+
+/// Read the name of a seat, see [`seat_str`]
+///
+/// # Errors
+///
+/// Will return an error if it isn't the name of a seat.
+pub fn parse_seat(seat: &str) -> Result<Option<Color>, CodecError> {
+    match seat {
+        "white" => Ok(Some(Color::White)),
+        "black" => Ok(Some(Color::Black)),
+        "spectator" => Ok(None),
+        _ => Err(CodecError::InvalidField("seat", seat.to_string())),
+    }
+}
+
+/// Read the name of a side, a seat that isn't a spectator's
+fn parse_side(side: &str) -> Result<Color, CodecError> {
+    parse_seat(side)?.ok_or_else(|| CodecError::InvalidField("side", side.to_string()))
+}
+
+fn offer_str(offer: Offer) -> &'static str {
+    match offer {
+        Offer::Draw => "draw",
+        Offer::Takeback => "takeback",
+    }
+}
+
+fn parse_offer(offer: &str) -> Result<Offer, CodecError> {
+    match offer {
+        "draw" => Ok(Offer::Draw),
+        "takeback" => Ok(Offer::Takeback),
+        _ => Err(CodecError::InvalidField("offer", offer.to_string())),
+    }
+}
+
+pub(crate) fn result_str(result: GameResult) -> &'static str {
+    match result {
+        GameResult::WhiteWins => "1-0",
+        GameResult::BlackWins => "0-1",
+        GameResult::Draw => "1/2-1/2",
+        GameResult::Unknown => "*",
+    }
+}
+
+/// Writes the packet, `;` included
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, "{}@", id)?;
+        }
+        write!(f, "{}", self.message.kind())?;
+        for (i, field) in self.message.fields().iter().enumerate() {
+            f.write_str(if i == 0 { ":" } else { "|" })?;
+            for c in field.chars() {
+                if matches!(c, '\\' | ';' | '|') {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{}", c)?;
+            }
+        }
+        f.write_str(";")
+    }
+}
+
+/// Reads one packet, with or without its `;`
+impl FromStr for Packet {
+    type Err = CodecError;
+
+    fn from_str(s: &str) -> Result<Self, CodecError> {
+        let s = match frame_end(s.as_bytes()) {
+            Some(end) if end == s.len() - 1 => &s[..end],
+            _ => s,
+        };
+        let (head, body) = match s.split_once(':') {
+            Some((head, body)) => (head, Some(body)),
+            None => (s, None),
+        };
+        let (id, kind) = match head.split_once('@') {
+            Some((id, kind)) => {
+                let id = id.trim();
+                let id = id
+                    .parse()
+                    .map_err(|_| CodecError::InvalidId(id.to_string()))?;
+                (Some(id), kind)
+            }
+            None => (None, head),
+        };
+
+        let mut fields = vec![];
+        if let Some(body) = body {
+            let mut field = String::new();
+            let mut chars = body.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => field.push(chars.next().ok_or(CodecError::DanglingEscape)?),
+                    '|' => fields.push(std::mem::take(&mut field)),
+                    c => field.push(c),
+                }
+            }
+            fields.push(field);
+        }
+
+        Ok(Self {
+            id,
+            message: Message::from_fields(kind.trim(), fields)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) {
+        let encoded = packet.to_string();
+        assert_eq!(frame_end(encoded.as_bytes()), Some(encoded.len() - 1));
+        assert_eq!(encoded.parse(), Ok(packet), "{}", encoded);
+    }
+
+    #[test]
+    fn round_trips() {
+        let messages = vec![
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+                name: "Magnus".to_string(),
+            },
+            Message::Seat(Some(Color::Black)),
+            Message::Seat(None),
+            Message::Session("0123456789abcdef".to_string()),
+            Message::Resume("0123456789abcdef".to_string()),
+            Message::Move("e7e8q".to_string()),
+            Message::State {
+                fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+                moves: vec!["e2e4".to_string(), "e7e5".to_string()],
+            },
+            Message::State {
+                fen: "4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string(),
+                moves: vec![],
+            },
+            Message::Spectators(3),
+            Message::Result {
+                result: GameResult::Draw,
+                reason: "stalemate".to_string(),
+            },
+            Message::Chat {
+                name: "Magnus".to_string(),
+                text: "good luck; have fun | gg".to_string(),
+            },
+            Message::Offer {
+                side: Color::White,
+                offer: Offer::Draw,
+            },
+            Message::Accept {
+                side: Color::Black,
+                offer: Offer::Takeback,
+            },
+            Message::Decline {
+                side: Color::White,
+                offer: Offer::Takeback,
+            },
+            Message::Resign(Color::Black),
+            Message::Ok,
+            Message::Error(String::new()),
+        ];
+        for message in messages {
+            round_trip(Packet::new(message.clone()));
+            round_trip(Packet::reply(Some(42), message));
+        }
+    }
+
+    #[test]
+    fn escapes_fields() {
+        let packet = Packet::new(Message::Hello {
+            version: 1,
+            name: r"a;b|c\d:e@f".to_string(),
+        });
+        assert_eq!(packet.to_string(), r"hello:1|a\;b\|c\\d:e@f;");
+        round_trip(packet);
+
+        let error = Packet::reply(Some(3), Message::Error("no; really\\".to_string()));
+        let mut stream = error.to_string().into_bytes();
+        stream.extend_from_slice(b"ok;");
+        let end = frame_end(&stream).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&stream[..=end]).unwrap().parse(),
+            Ok(error)
+        );
+        assert_eq!(frame_end(&stream[end + 1..]), Some(2));
+        assert_eq!(frame_end(br"move:e2e4\;"), None);
+    }
+
+    #[test]
+    fn reads_packets() {
+        assert_eq!(
+            "7@move:e2e4".parse(),
+            Ok(Packet::reply(Some(7), Message::Move("e2e4".to_string())))
+        );
+        assert_eq!(" ok ;".parse(), Ok(Packet::new(Message::Ok)));
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        assert_eq!(
+            "hi:there;".parse::<Packet>(),
+            Err(CodecError::UnknownKind("hi".to_string()))
+        );
+        assert_eq!(
+            "move;".parse::<Packet>(),
+            Err(CodecError::WrongFieldCount {
+                kind: "move",
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(
+            "hello:one|me;".parse::<Packet>(),
+            Err(CodecError::InvalidField("version", "one".to_string()))
+        );
+        assert_eq!(
+            "result:2-0|resigned;".parse::<Packet>(),
+            Err(CodecError::InvalidField("result", "2-0".to_string()))
+        );
+        assert_eq!(
+            "spectators:-1;".parse::<Packet>(),
+            Err(CodecError::InvalidField(
+                "spectator count",
+                "-1".to_string()
+            ))
+        );
+        assert_eq!(
+            "seat:red;".parse::<Packet>(),
+            Err(CodecError::InvalidField("seat", "red".to_string()))
+        );
+        assert_eq!(
+            "resign:spectator;".parse::<Packet>(),
+            Err(CodecError::InvalidField("side", "spectator".to_string()))
+        );
+        assert_eq!(
+            "offer:white|rematch;".parse::<Packet>(),
+            Err(CodecError::InvalidField("offer", "rematch".to_string()))
+        );
+        assert_eq!(
+            "x@ok;".parse::<Packet>(),
+            Err(CodecError::InvalidId("x".to_string()))
+        );
+        assert_eq!(
+            r"error:oops\".parse::<Packet>(),
+            Err(CodecError::DanglingEscape)
+        );
+    }
+}
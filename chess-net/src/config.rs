@@ -0,0 +1,255 @@
+//! Where a host listens, from command line options or a config file.
+//!
+//! The options are `--address ADDR`, `--port N`, `--websocket-port N`
+//! and `--config FILE`, applied in order, so
+//! `--config host.conf --port 4000` uses the file but listens on port
+//! 4000. WebSocket clients are only listened for if their port is
+//! given. A config file has a `key = value` per line, with the keys
+//! `address`, `port` and `websocket_port`, and `#` starting a comment:
+//!
+//! ```text
+//! # only listen for clients on this machine
+//! address = 127.0.0.1
+//! port = 1337
+//! # and browsers
+//! websocket_port = 8080
+//! ```
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// The address listened on if none is given, which is every interface
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0";
+/// The port listened on if none is given
+pub const DEFAULT_PORT: u16 = 1337;
+
+/// Where a host listens
+///
+/// # Examples
+/// ```
+/// # use chess_net::config::ListenConfig;
+/// let mut config = ListenConfig::default();
+/// let mut args = vec!["--port".to_string(), "4000".to_string()].into_iter();
+/// let arg = args.next().unwrap();
+///
+/// assert!(config.parse_arg(&arg, &mut args).unwrap());
+/// assert_eq!(config.to_string(), "0.0.0.0:4000");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenConfig {
+    /// The address or host name to listen on
+    pub address: String,
+    /// The port to listen on, 0 picks any free port
+    pub port: u16,
+    /// The port to listen on for WebSocket clients, if any
+    pub websocket_port: Option<u16>,
+}
+
+/// Why the options or the config file couldn't be used
+#[derive(Debug)]
+pub enum ConfigError {
+    /// An option was given without its value
+    MissingValue(String),
+    /// The port isn't a number from 0 to 65535
+    InvalidPort(String),
+    /// The config file couldn't be read
+    Io(String, io::Error),
+    /// A line of the config file isn't `key = value`, with the line
+    /// number
+    Syntax(usize, String),
+    /// The config file has a key that isn't one of those in the module
+    /// docs
+    UnknownKey(String),
+}
+
+impl StdError for ConfigError {}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingValue(option) => write!(f, "{} needs a value", option),
+            Self::InvalidPort(port) => write!(f, "`{}` isn't a valid port", port),
+            Self::Io(path, err) => write!(f, "can't read {}: {}", path, err),
+            Self::Syntax(line, text) => {
+                write!(f, "line {} should look like `key = value`: {}", line, text)
+            }
+            Self::UnknownKey(key) => write!(f, "unknown setting `{}`", key),
+        }
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.to_string(),
+            port: DEFAULT_PORT,
+            websocket_port: None,
+        }
+    }
+}
+
+/// Writes the address and port to listen on, e.g. `0.0.0.0:1337`
+impl fmt::Display for ListenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", socket_addr(&self.address, self.port))
+    }
+}
+
+impl ListenConfig {
+    /// The address and port to listen on for WebSocket clients, if any
+    pub fn websocket_addr(&self) -> Option<String> {
+        self.websocket_port
+            .map(|port| socket_addr(&self.address, port))
+    }
+
+    /// Apply the option `arg` if it's one of the options in the module
+    /// docs, taking its value from `args`, and return whether it was
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the option has no value or a bad one,
+    /// or if the config file can't be used.
+    pub fn parse_arg(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, ConfigError> {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| ConfigError::MissingValue(arg.to_string()))
+        };
+        match arg {
+            "--address" => self.address = value()?,
+            "--port" => self.port = parse_port(&value()?)?,
+            "--websocket-port" => self.websocket_port = Some(parse_port(&value()?)?),
+            "--config" => self.load(&value()?)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Apply the settings in the config file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the file can't be read or has a mistake
+    /// in it.
+    pub fn load(&mut self, path: &str) -> Result<(), ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_string(), err))?;
+        self.apply(&text)
+    }
+
+    /// Apply the settings in the text of a config file
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if there's a mistake in it.
+    pub fn apply(&mut self, text: &str) -> Result<(), ConfigError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.split_once('#') {
+                Some((line, _comment)) => line,
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| ConfigError::Syntax(i + 1, line.to_string()))?;
+            let value = value.trim();
+            match key.trim() {
+                "address" => self.address = value.to_string(),
+                "port" => self.port = parse_port(value)?,
+                "websocket_port" => self.websocket_port = Some(parse_port(value)?),
+                key => return Err(ConfigError::UnknownKey(key.to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn socket_addr(address: &str, port: u16) -> String {
+    if address.contains(':') {
+        // an IPv6 address
+        format!("[{}]:{}", address, port)
+    } else {
+        format!("{}:{}", address, port)
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, ConfigError> {
+    port.parse()
+        .map_err(|_| ConfigError::InvalidPort(port.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ListenConfig, ConfigError> {
+        let mut config = ListenConfig::default();
+        let mut args = args.iter().map(ToString::to_string);
+        while let Some(arg) = args.next() {
+            assert!(config.parse_arg(&arg, &mut args)?, "{}", arg);
+        }
+        Ok(config)
+    }
+
+    #[test]
+    fn reads_options() {
+        assert_eq!(parse(&[]).unwrap().to_string(), "0.0.0.0:1337");
+        let config = parse(&["--address", "127.0.0.1", "--port", "4000"]).unwrap();
+        assert_eq!(config.to_string(), "127.0.0.1:4000");
+        assert_eq!(config.websocket_addr(), None);
+        let config = parse(&["--websocket-port", "8080", "--address", "::1"]).unwrap();
+        assert_eq!(config.websocket_addr().unwrap(), "[::1]:8080");
+        assert_eq!(
+            parse(&["--address", "::1"]).unwrap().to_string(),
+            "[::1]:1337"
+        );
+
+        assert!(matches!(
+            parse(&["--port", "70000"]),
+            Err(ConfigError::InvalidPort(_))
+        ));
+        assert!(matches!(
+            parse(&["--port"]),
+            Err(ConfigError::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse(&["--config", "/there/is/no/such/file"]),
+            Err(ConfigError::Io(..))
+        ));
+        let mut args = std::iter::empty();
+        assert!(!ListenConfig::default()
+            .parse_arg("--connect", &mut args)
+            .unwrap());
+    }
+
+    #[test]
+    fn reads_config_files() {
+        let mut config = ListenConfig::default();
+        config
+            .apply("# a comment\n\naddress = localhost  # the same machine\nport=4000\n")
+            .unwrap();
+        assert_eq!(config.to_string(), "localhost:4000");
+
+        // what isn't in the file stays the same
+        config.apply("port = 5000").unwrap();
+        assert_eq!(config.to_string(), "localhost:5000");
+        config.apply("websocket_port = 8080").unwrap();
+        assert_eq!(config.websocket_addr().unwrap(), "localhost:8080");
+
+        assert!(matches!(
+            config.apply("port 5000"),
+            Err(ConfigError::Syntax(1, _))
+        ));
+        assert!(matches!(
+            config.apply("\nhost = example.com"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+    }
+}
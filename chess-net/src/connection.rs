@@ -0,0 +1,610 @@
+//! A TCP connection speaking the protocol in [`crate::codec`], which
+//! never blocks so that it can be polled once a frame. The packets are
+//! either sent as they are, or as [`crate::json`] over a
+//! [`crate::websocket`] for browsers.
+//!
+//! What's been read of a packet that hasn't all arrived is kept until
+//! the rest does, up to [`MAX_PACKET_SIZE`]. What's sent is queued and
+//! written as the other end takes it, and if it stops reading for long
+//! enough that [`MAX_QUEUED_SIZE`] is reached, the connection fails.
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::{self, Utf8Error};
+
+use crate::codec::{frame_end, CodecError, Packet};
+use crate::json;
+use crate::websocket::{self, Opcode};
+
+/// The most bytes a packet can have, or a WebSocket handshake or
+/// message. The connection fails if the other end sends a longer one,
+/// as what it's sending can't be made sense of.
+pub const MAX_PACKET_SIZE: usize = 64 * 1024;
+/// The most bytes that can be waiting to be sent before the other end
+/// is taken to have stopped reading, and the connection fails
+pub const MAX_QUEUED_SIZE: usize = 1024 * 1024;
+
+/// Indicates that a packet couldn't be handled, the reason is sent back
+/// to whoever sent it
+#[derive(Debug)]
+pub struct NetworkError(pub String);
+impl StdError for NetworkError {}
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl From<Utf8Error> for NetworkError {
+    fn from(_: Utf8Error) -> Self {
+        Self("packets have to be UTF-8".to_string())
+    }
+}
+impl From<CodecError> for NetworkError {
+    fn from(err: CodecError) -> Self {
+        Self(err.to_string())
+    }
+}
+
+/// A connection to the other end of a game, see the module docs
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    /// What's been read and not split into packets yet
+    buffer: Vec<u8>,
+    /// What's waiting to be written
+    queue: Vec<u8>,
+    framing: Framing,
+    /// Whether the other end has closed the stream, which is reported
+    /// once the packets read before it have been
+    closed: bool,
+}
+
+/// How packets are told apart in the stream
+#[derive(Debug)]
+enum Framing {
+    /// The packets of [`crate::codec`], each ended by a `;`
+    Packets,
+    /// JSON in WebSocket text frames, from a client
+    WebSocket {
+        /// Whether the opening handshake is done
+        open: bool,
+        /// Whether the client has sent a close frame, after which
+        /// nothing more is read
+        closed: bool,
+        /// Whether a message split across frames has been started and
+        /// not finished yet
+        in_message: bool,
+        /// The frames so far of a message split across several
+        message: Vec<u8>,
+    },
+}
+
+impl Connection {
+    /// Use a stream that's already connected, e.g. one from a
+    /// listener
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        Self::with_framing(stream, Framing::Packets)
+    }
+
+    /// Use a stream from a WebSocket client that's just connected, the
+    /// handshake is answered when it arrives
+    pub fn websocket(stream: TcpStream) -> io::Result<Self> {
+        Self::with_framing(
+            stream,
+            Framing::WebSocket {
+                open: false,
+                closed: false,
+                in_message: false,
+                message: vec![],
+            },
+        )
+    }
+
+    fn with_framing(stream: TcpStream, framing: Framing) -> io::Result<Self> {
+        stream.set_nodelay(true)?; // auto flush
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            addr: stream.peer_addr()?,
+            stream,
+            buffer: vec![],
+            queue: vec![],
+            framing,
+            closed: false,
+        })
+    }
+
+    /// Connect to `addr`, which blocks until the connection is made
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Who's on the other end
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The number of bytes sent that haven't been written yet, because
+    /// the other end hasn't taken them
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Send a packet, or queue it if it can't all be written yet
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if writing to the connection fails, or
+    /// too much is queued.
+    pub fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        match self.framing {
+            Framing::Packets => self.queue.extend_from_slice(packet.to_string().as_bytes()),
+            Framing::WebSocket { .. } => {
+                let json = json::to_json(packet);
+                self.queue
+                    .extend(websocket::write_frame(Opcode::Text, json.as_bytes()));
+            }
+        }
+        self.flush()
+    }
+
+    /// Write as much of the queue as the other end takes. This is done
+    /// whenever something is sent or received, so only needs to be
+    /// called to get the rest out when nothing else is happening.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if writing to the connection fails, or
+    /// too much is queued.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.queue.is_empty() {
+            match self.stream.write(&self.queue) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n_bytes) => {
+                    let _ = self.queue.drain(..n_bytes);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        if self.queue.len() > MAX_QUEUED_SIZE {
+            return Err(io::Error::other(NetworkError(format!(
+                "{} bytes are waiting to be sent, the other end isn't reading",
+                self.queue.len()
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Read everything that has arrived, and split it into packets.
+    /// A packet that couldn't be decoded is an error of its own, and
+    /// the rest of a packet that hasn't all arrived is kept for next
+    /// time.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading or writing fails, or
+    /// [`ErrorKind::UnexpectedEof`] if the other end has closed the
+    /// connection, or [`ErrorKind::InvalidData`] if it sent something
+    /// that can't be split into packets.
+    pub fn receive(&mut self) -> io::Result<Vec<Result<Packet, NetworkError>>> {
+        self.flush()?;
+        let mut packets = vec![];
+        let mut buffer = [0_u8; 4096];
+        while !self.is_closed() {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(n_bytes) => {
+                    // split as it comes in, so a packet that's too long
+                    // is caught before much of it is kept
+                    self.buffer.extend_from_slice(&buffer[..n_bytes]);
+                    packets.extend(self.split_packets()?);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        if !self.closed {
+            self.flush()?;
+        }
+        if packets.is_empty() && self.is_closed() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(packets)
+    }
+
+    /// Whether the other end has closed the connection, or said it's
+    /// closing it
+    fn is_closed(&self) -> bool {
+        self.closed || matches!(self.framing, Framing::WebSocket { closed: true, .. })
+    }
+
+    /// Take the packets that have all arrived out of the buffer,
+    /// queueing anything the framing needs to answer
+    fn split_packets(&mut self) -> io::Result<Vec<Result<Packet, NetworkError>>> {
+        let mut packets = vec![];
+        match self.framing {
+            Framing::Packets => {
+                while let Some(end) = frame_end(&self.buffer) {
+                    if end >= MAX_PACKET_SIZE {
+                        return Err(too_long());
+                    }
+                    let packet = self.buffer.drain(..=end).collect::<Vec<_>>();
+                    packets.push(decode(&packet));
+                }
+                if self.buffer.len() > MAX_PACKET_SIZE {
+                    return Err(too_long());
+                }
+            }
+            Framing::WebSocket { .. } => self.split_frames(&mut packets)?,
+        }
+        Ok(packets)
+    }
+
+    /// Answer the handshake and read the frames from a WebSocket client
+    fn split_frames(&mut self, packets: &mut Vec<Result<Packet, NetworkError>>) -> io::Result<()> {
+        let (open, closed, in_message, message) = match &mut self.framing {
+            Framing::WebSocket {
+                open,
+                closed,
+                in_message,
+                message,
+            } => (open, closed, in_message, message),
+            Framing::Packets => unreachable!(),
+        };
+        let invalid = |err: NetworkError| io::Error::new(ErrorKind::InvalidData, err);
+
+        if !*open {
+            let end = match websocket::handshake_end(&self.buffer) {
+                Some(end) => end,
+                None if self.buffer.len() > MAX_PACKET_SIZE => return Err(too_long()),
+                None => return Ok(()),
+            };
+            let request = self.buffer.drain(..end).collect::<Vec<_>>();
+            match websocket::handshake_response(&request) {
+                Ok(response) => self.queue.extend_from_slice(response.as_bytes()),
+                Err(err) => {
+                    self.queue
+                        .extend_from_slice(websocket::bad_request(&err).as_bytes());
+                    // try to get the reason out before the connection's
+                    // dropped
+                    let _ = self.flush();
+                    return Err(invalid(err));
+                }
+            }
+            *open = true;
+        }
+
+        while !*closed {
+            let (frame, len) = match websocket::read_frame(&self.buffer, MAX_PACKET_SIZE) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => return Err(invalid(err)),
+            };
+            let _ = self.buffer.drain(..len);
+            match frame.opcode {
+                Opcode::Text if *in_message => {
+                    return Err(invalid(NetworkError(
+                        "a message was started before the last one ended".to_string(),
+                    )))
+                }
+                Opcode::Continuation if !*in_message => {
+                    return Err(invalid(NetworkError(
+                        "there's no message to continue".to_string(),
+                    )))
+                }
+                Opcode::Text | Opcode::Continuation => {
+                    if message.len() + frame.payload.len() > MAX_PACKET_SIZE {
+                        return Err(too_long());
+                    }
+                    message.extend_from_slice(&frame.payload);
+                    *in_message = !frame.fin;
+                    if frame.fin {
+                        let text = std::mem::take(message);
+                        packets.push(
+                            str::from_utf8(&text)
+                                .map_err(NetworkError::from)
+                                .and_then(|text| Ok(json::from_json(text)?)),
+                        );
+                    }
+                }
+                Opcode::Binary => packets.push(Err(NetworkError(
+                    "packets are sent as text frames".to_string(),
+                ))),
+                Opcode::Ping => self
+                    .queue
+                    .extend(websocket::write_frame(Opcode::Pong, &frame.payload)),
+                Opcode::Pong => (),
+                Opcode::Close => {
+                    // echo the status code, as the client waits for it
+                    let status = frame.payload.get(..2).unwrap_or_default();
+                    self.queue
+                        .extend(websocket::write_frame(Opcode::Close, status));
+                    *closed = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode(packet: &[u8]) -> Result<Packet, NetworkError> {
+    Ok(str::from_utf8(packet)?.parse()?)
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        NetworkError(format!(
+            "packets can't be longer than {} bytes",
+            MAX_PACKET_SIZE
+        )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Message;
+    use crate::websocket::tests::client_frame;
+    use chess_engine::Color;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A connection and the stream on its other end
+    fn connected(framing: fn(TcpStream) -> io::Result<Connection>) -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let other = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (framing(stream).unwrap(), other)
+    }
+
+    fn packets() -> Vec<Packet> {
+        vec![
+            Packet::reply(
+                Some(1),
+                Message::Hello {
+                    version: 1,
+                    name: "a; b | c \\ d".to_string(),
+                },
+            ),
+            Packet::new(Message::Seat(Some(Color::Black))),
+            Packet::new(Message::State {
+                fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+                moves: vec!["e2e4".to_string(), "e7e5".to_string()],
+            }),
+            Packet::reply(Some(7), Message::Move("g1f3".to_string())),
+            Packet::reply(Some(8), Message::Error("ünïcödé ♞ reasons".to_string())),
+            Packet::new(Message::Ok),
+        ]
+    }
+
+    /// Cut `bytes` into pieces of random lengths, the same ones for the
+    /// same seed
+    fn random_splits(bytes: &[u8], seed: u64) -> Vec<&[u8]> {
+        let mut state = seed;
+        let mut pieces = vec![];
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            // xorshift
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let max = [2, 16, 256][(state >> 40) as usize % 3];
+            let len = 1 + (state % max) as usize;
+            let (piece, tail) = rest.split_at(len.min(rest.len()));
+            pieces.push(piece);
+            rest = tail;
+        }
+        pieces
+    }
+
+    /// Split the pieces into packets as if they'd arrived one by one
+    fn split_all(connection: &mut Connection, pieces: Vec<&[u8]>) -> Vec<Packet> {
+        let mut packets = vec![];
+        for piece in pieces {
+            connection.buffer.extend_from_slice(piece);
+            for packet in connection.split_packets().unwrap() {
+                packets.push(packet.unwrap());
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn splits_packets_however_they_arrive() {
+        let packets = packets();
+        let bytes = packets
+            .iter()
+            .flat_map(|packet| packet.to_string().into_bytes())
+            .collect::<Vec<_>>();
+        let (mut connection, _other) = connected(Connection::new);
+        for seed in 1..=500 {
+            let pieces = random_splits(&bytes, seed);
+            assert_eq!(split_all(&mut connection, pieces), packets, "{}", seed);
+            assert!(connection.buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn splits_frames_however_they_arrive() {
+        let packets = packets();
+        let mut bytes = b"GET / HTTP/1.1\r\n\
+                          Upgrade: websocket\r\n\
+                          Sec-WebSocket-Version: 13\r\n\
+                          Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            .to_vec();
+        for (i, packet) in packets.iter().enumerate() {
+            let json = json::to_json(packet);
+            if i % 2 == 0 {
+                bytes.extend(client_frame(0x81, json.as_bytes()));
+            } else {
+                // in two frames, with a ping between them
+                let (start, end) = json.as_bytes().split_at(json.len() / 2);
+                bytes.extend(client_frame(0x01, start));
+                bytes.extend(client_frame(0x89, b"ping"));
+                bytes.extend(client_frame(0x80, end));
+            }
+        }
+        for seed in 1..=500 {
+            let (mut connection, _other) = connected(Connection::websocket);
+            let pieces = random_splits(&bytes, seed);
+            assert_eq!(split_all(&mut connection, pieces), packets, "{}", seed);
+            assert!(connection.buffer.is_empty());
+            assert!(connection.queue.starts_with(b"HTTP/1.1 101 "));
+            let pong = websocket::write_frame(Opcode::Pong, b"ping");
+            assert!(connection.queue.ends_with(&pong));
+        }
+    }
+
+    #[test]
+    fn follows_messages_across_frames() {
+        let websocket = || {
+            let (mut connection, other) = connected(Connection::websocket);
+            if let Framing::WebSocket { open, .. } = &mut connection.framing {
+                *open = true;
+            }
+            (connection, other)
+        };
+        let json = json::to_json(&Packet::new(Message::Ok));
+
+        // the first frame can be empty
+        let (mut connection, _other) = websocket();
+        let mut bytes = client_frame(0x01, b"");
+        bytes.extend(client_frame(0x80, json.as_bytes()));
+        let packets = split_all(&mut connection, vec![&bytes]);
+        assert_eq!(packets, [Packet::new(Message::Ok)]);
+
+        let (mut connection, _other) = websocket();
+        connection.buffer = client_frame(0x80, json.as_bytes());
+        let err = connection.split_packets().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let (mut connection, _other) = websocket();
+        connection.buffer = client_frame(0x01, b"");
+        connection
+            .buffer
+            .extend(client_frame(0x81, json.as_bytes()));
+        let err = connection.split_packets().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn receives_packets_written_in_pieces() {
+        let packets = packets();
+        let bytes = packets
+            .iter()
+            .flat_map(|packet| packet.to_string().into_bytes())
+            .collect::<Vec<_>>();
+        let (mut connection, mut other) = connected(Connection::new);
+        let writer = thread::spawn(move || {
+            for piece in random_splits(&bytes, 42) {
+                other.write_all(piece).unwrap();
+                thread::sleep(Duration::from_micros(100));
+            }
+            other
+        });
+
+        let mut received = vec![];
+        let start = Instant::now();
+        while received.len() < packets.len() {
+            for packet in connection.receive().unwrap() {
+                received.push(packet.unwrap());
+            }
+            assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+        }
+        assert_eq!(received, packets);
+        let _ = writer.join().unwrap();
+    }
+
+    #[test]
+    fn keeps_packets_read_before_the_other_end_closes() {
+        let (mut connection, mut other) = connected(Connection::new);
+        other.write_all(b"resign:white;").unwrap();
+        drop(other);
+        thread::sleep(Duration::from_millis(50));
+
+        let packets = connection.receive().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(
+            packets[0].as_ref().unwrap(),
+            &Packet::new(Message::Resign(Color::White))
+        );
+        let err = connection.receive().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn turns_down_packets_that_are_too_long() {
+        let (mut connection, _other) = connected(Connection::new);
+        connection.buffer = vec![b'x'; MAX_PACKET_SIZE];
+        assert!(connection.split_packets().unwrap().is_empty());
+        connection.buffer.push(b'x');
+        let err = connection.split_packets().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // even if it's all there
+        connection.buffer = vec![b'x'; MAX_PACKET_SIZE];
+        connection.buffer.push(b';');
+        let err = connection.split_packets().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // a message split across frames is as long as all of them
+        let (mut connection, _other) = connected(Connection::websocket);
+        if let Framing::WebSocket { open, .. } = &mut connection.framing {
+            *open = true;
+        }
+        let half = vec![b' '; MAX_PACKET_SIZE / 2 + 1];
+        connection.buffer = client_frame(0x01, &half);
+        assert!(connection.split_packets().unwrap().is_empty());
+        connection.buffer = client_frame(0x80, &half);
+        let err = connection.split_packets().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn queues_what_cant_be_written_yet() {
+        let (mut connection, mut other) = connected(Connection::new);
+        let packet = Packet::new(Message::Error("x".repeat(1000)));
+        // nothing is read until the socket's buffers are full
+        let mut sent = 0;
+        while connection.queued() == 0 {
+            connection.send(&packet).unwrap();
+            sent += 1;
+        }
+
+        let reader = thread::spawn(move || {
+            let mut bytes = vec![];
+            let _ = other.read_to_end(&mut bytes).unwrap();
+            bytes
+        });
+        let start = Instant::now();
+        while connection.queued() > 0 {
+            connection.flush().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(connection);
+        let bytes = reader.join().unwrap();
+        assert!(bytes == packet.to_string().repeat(sent).into_bytes());
+    }
+
+    #[test]
+    fn fails_when_the_other_end_stops_reading() {
+        let (mut connection, _other) = connected(Connection::new);
+        let packet = Packet::new(Message::Error("x".repeat(1000)));
+        let start = Instant::now();
+        let err = loop {
+            if let Err(err) = connection.send(&packet) {
+                break err;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        };
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(connection.queued() > MAX_QUEUED_SIZE);
+    }
+}
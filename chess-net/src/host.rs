@@ -0,0 +1,832 @@
+//! Hosting a game for clients to connect to. The host is given the
+//! sides that are played remotely, the first clients to say hello play
+//! those and everyone after that spectates, up to a limit. A client can
+//! ask for a side that's free, or to spectate. Moves are checked
+//! against the game before they're made, and every change to the game
+//! is sent to everyone who has said hello, whoever made it.
+//!
+//! Every client is given a session token, which is random so that it
+//! can't be guessed. When a player disconnects their side is kept for
+//! the grace period, and a client that resumes the session in time gets
+//! it back. Otherwise the game is adjudicated
+//! as abandoned, and the side is free again.
+//!
+//! Browsers can join too if the host also listens for WebSocket
+//! clients, who are treated the same as the rest.
+//!
+//! Players can chat, offer a draw, ask for a takeback or resign, from
+//! a client or for a side played here. Everything said and agreed on is
+//! passed on to every client and kept in the game's [`Record`].
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use chess_engine::game::BoardState;
+use chess_engine::pgn::GameResult;
+use chess_engine::{Board, Color, Error, Game, Move};
+
+use crate::codec::{Message, Offer, Packet, PROTOCOL_VERSION};
+use crate::connection::{Connection, NetworkError};
+use crate::record::Record;
+
+/// The name the host gives in its `hello`
+const HOST_NAME: &str = "chess gui";
+/// How many clients can spectate if no other limit is set
+pub const DEFAULT_MAX_SPECTATORS: usize = 16;
+/// How long a side is kept for a player who lost their connection if
+/// no other grace period is set
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Hosts a game, see the module docs
+#[derive(Debug)]
+pub struct Host {
+    listener: TcpListener,
+    /// Where WebSocket clients connect, if anywhere
+    websocket_listener: Option<TcpListener>,
+    /// The sides played by clients, in the order they're handed out
+    seats: Vec<Color>,
+    max_spectators: usize,
+    grace_period: Duration,
+    clients: Vec<Client>,
+    /// The players who lost their connection and may come back
+    absent: Vec<Absent>,
+    adjudication: Option<Adjudication>,
+    /// The offer waiting for an answer, and the side that made it
+    offer: Option<(Color, Offer)>,
+    record: Record,
+    sent: SentGame,
+}
+
+/// A result the host gave the game that isn't on the board
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Adjudication {
+    /// Who won
+    pub result: GameResult,
+    /// Why, e.g. `abandoned`
+    pub reason: String,
+}
+
+#[derive(Debug)]
+struct Client {
+    connection: Connection,
+    /// The name from the client's `hello`, `None` until it says hello
+    name: Option<String>,
+    /// The side the client plays, `None` for a spectator
+    seat: Option<Color>,
+    session: String,
+    /// Whether the client has been sent the `state` of the game, after
+    /// which it only gets what changes
+    synced: bool,
+    /// Whether the client is dropped once its packets are handled
+    turned_away: bool,
+}
+
+/// A player who lost their connection
+#[derive(Debug)]
+struct Absent {
+    name: String,
+    seat: Color,
+    session: String,
+    since: Instant,
+}
+
+/// What the clients have been told about the game, to work out what to
+/// tell them when it changes
+#[derive(Debug, Default)]
+struct SentGame {
+    start: Option<Board>,
+    moves: Vec<Move>,
+    over: bool,
+    spectators: usize,
+}
+
+impl Host {
+    /// Listen for clients on `addr`, who play the sides in `seats`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the address can't be listened on, e.g.
+    /// because something else already is.
+    pub fn bind(addr: impl ToSocketAddrs, seats: &[Color]) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            websocket_listener: None,
+            seats: seats.to_vec(),
+            max_spectators: DEFAULT_MAX_SPECTATORS,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            clients: vec![],
+            absent: vec![],
+            adjudication: None,
+            offer: None,
+            record: Record::new(),
+            sent: SentGame::default(),
+        })
+    }
+
+    /// The address the host listens on
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the address can't be found out.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Also listen for WebSocket clients on `addr`, who speak the same
+    /// protocol as JSON
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the address can't be listened on.
+    pub fn listen_websocket(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        self.websocket_listener = Some(listener);
+        Ok(())
+    }
+
+    /// The address WebSocket clients connect to, if the host listens
+    /// for them
+    pub fn websocket_addr(&self) -> Option<SocketAddr> {
+        self.websocket_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Whether a client is playing `color`, or it's kept for a player
+    /// who lost their connection
+    pub fn is_seated(&self, color: Color) -> bool {
+        self.clients.iter().any(|c| c.seat == Some(color))
+            || self.absent.iter().any(|a| a.seat == color)
+    }
+
+    /// Change how long a side is kept for a player who lost their
+    /// connection
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// The result given to the game if it didn't end on the board, e.g.
+    /// because a player abandoned it. It's forgotten once the game is
+    /// replaced by another one, or moves are taken back.
+    pub fn adjudication(&self) -> Option<&Adjudication> {
+        self.adjudication.as_ref()
+    }
+
+    /// Forget the adjudication, the pending offer and the record when a
+    /// new game starts, which can't always be told apart from the old
+    /// one, e.g. when it was abandoned before the first move
+    pub fn new_game(&mut self) {
+        self.adjudication = None;
+        self.offer = None;
+        self.record = Record::new();
+    }
+
+    /// What was said and agreed on in the game, to write it out with
+    /// [`Record::to_pgn`]
+    pub fn record(&self) -> &Record {
+        &self.record
+    }
+
+    /// The offer waiting for an answer, and the side that made it. It
+    /// lapses once a move is made.
+    pub fn pending_offer(&self) -> Option<(Color, Offer)> {
+        self.offer
+    }
+
+    /// Say something to everyone in the game as `name`
+    pub fn chat(&mut self, name: &str, text: &str, game: &Game) {
+        self.broadcast(
+            game,
+            Message::Chat {
+                name: name.to_string(),
+                text: text.to_string(),
+            },
+        );
+    }
+
+    /// Offer a draw, or ask for a takeback, for the player of `side`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the game is over, or there's no move to
+    /// take back.
+    pub fn make_offer(
+        &mut self,
+        side: Color,
+        offer: Offer,
+        game: &Game,
+    ) -> Result<(), NetworkError> {
+        self.send_updates(game);
+        if self.is_over(game) {
+            return Err(NetworkError("the game is over".to_string()));
+        }
+        if offer == Offer::Takeback && takeback_plies(game, side).is_none() {
+            return Err(NetworkError("there's no move to take back".to_string()));
+        }
+        self.offer = Some((side, offer));
+        self.broadcast(game, Message::Offer { side, offer });
+        Ok(())
+    }
+
+    /// Accept the other side's offer for the player of `side`, drawing
+    /// the game or taking back moves
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the other side hasn't made the offer, or
+    /// the game is over.
+    pub fn accept(
+        &mut self,
+        side: Color,
+        offer: Offer,
+        game: &mut Game,
+    ) -> Result<(), NetworkError> {
+        self.answer(side, offer, game, "accept")?;
+        self.broadcast(game, Message::Accept { side, offer });
+        match offer {
+            Offer::Draw => {
+                self.adjudication = Some(Adjudication {
+                    result: GameResult::Draw,
+                    reason: "agreement".to_string(),
+                })
+            }
+            Offer::Takeback => {
+                for _ in 0..takeback_plies(game, side.opposite()).unwrap_or(0) {
+                    let _ = game.undo_move();
+                }
+                // the clients take the moves back themselves
+                self.sent.moves.truncate(game.get_moves().len());
+                self.record.take_back(game);
+            }
+        }
+        self.send_updates(game);
+        Ok(())
+    }
+
+    /// Turn down the other side's offer for the player of `side`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the other side hasn't made the offer, or
+    /// the game is over.
+    pub fn decline(&mut self, side: Color, offer: Offer, game: &Game) -> Result<(), NetworkError> {
+        self.answer(side, offer, game, "decline")?;
+        self.broadcast(game, Message::Decline { side, offer });
+        Ok(())
+    }
+
+    /// Resign the game for the player of `side`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the game is already over.
+    pub fn resign(&mut self, side: Color, game: &Game) -> Result<(), NetworkError> {
+        self.send_updates(game);
+        if self.is_over(game) {
+            return Err(NetworkError("the game is over".to_string()));
+        }
+        self.offer = None;
+        self.broadcast(game, Message::Resign(side));
+        self.adjudication = Some(Adjudication {
+            result: match side {
+                Color::White => GameResult::BlackWins,
+                Color::Black => GameResult::WhiteWins,
+            },
+            reason: "resignation".to_string(),
+        });
+        self.send_updates(game);
+        Ok(())
+    }
+
+    /// Check that the other side of `side` has made the offer, and take
+    /// it off the table to answer it
+    fn answer(
+        &mut self,
+        side: Color,
+        offer: Offer,
+        game: &Game,
+        verb: &str,
+    ) -> Result<(), NetworkError> {
+        self.send_updates(game);
+        if self.is_over(game) {
+            return Err(NetworkError("the game is over".to_string()));
+        }
+        if self.offer != Some((side.opposite(), offer)) {
+            return Err(NetworkError(format!("there's no such offer to {}", verb)));
+        }
+        self.offer = None;
+        Ok(())
+    }
+
+    /// Note `message` in the record and send it to every client who
+    /// knows the game
+    fn broadcast(&mut self, game: &Game, message: Message) {
+        self.record.note(game, &message);
+        for client in self.clients.iter_mut().filter(|c| c.synced) {
+            client.send(&Packet::new(message.clone()));
+        }
+    }
+
+    fn is_over(&self, game: &Game) -> bool {
+        self.adjudication.is_some() || board_result(game).is_some()
+    }
+
+    /// Limit how many clients can spectate, later ones are turned away.
+    /// Clients already spectating are left alone.
+    pub fn set_max_spectators(&mut self, max: usize) {
+        self.max_spectators = max;
+    }
+
+    /// The number of clients spectating
+    pub fn spectators(&self) -> usize {
+        self.clients
+            .iter()
+            .filter(|c| c.name.is_some() && c.seat.is_none())
+            .count()
+    }
+
+    /// Change the sides played by clients, e.g. for a new game. Clients
+    /// playing a side that isn't offered anymore become spectators.
+    pub fn set_seats(&mut self, seats: &[Color]) {
+        if self.seats == seats {
+            return;
+        }
+        self.seats = seats.to_vec();
+        self.absent.retain(|absent| seats.contains(&absent.seat));
+        for client in &mut self.clients {
+            if matches!(client.seat, Some(color) if !seats.contains(&color)) {
+                client.seat = None;
+                client.send(&Packet::new(Message::Seat(None)));
+            }
+        }
+    }
+
+    /// The first side nobody is playing yet
+    fn free_seat(&self) -> Option<Color> {
+        self.seats
+            .iter()
+            .copied()
+            .find(|&color| !self.is_seated(color))
+    }
+
+    /// Accept new clients, make the moves they send and tell everyone
+    /// what changed, returning whether the game changed
+    pub fn update(&mut self, game: &mut Game) -> bool {
+        self.accept_clients();
+
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.clients.len() {
+            let packets = match self.clients[i].connection.receive() {
+                Ok(packets) => packets,
+                Err(err) => {
+                    let client = self.clients.remove(i);
+                    match err.kind() {
+                        ErrorKind::UnexpectedEof => {
+                            println!("{} disconnected", client.connection.addr())
+                        }
+                        _ => println!(
+                            "Network error from {}: {}\nDropping connection",
+                            client.connection.addr(),
+                            err
+                        ),
+                    }
+                    self.leave(client, game);
+                    continue;
+                }
+            };
+
+            for packet in packets {
+                let (id, result) = match packet {
+                    Ok(packet) => (packet.id, self.handle_message(i, packet, game)),
+                    Err(err) => (None, Err(err)),
+                };
+                match result {
+                    Ok(moved) => changed |= moved,
+                    Err(err) => {
+                        self.clients[i].send(&Packet::reply(id, Message::Error(err.to_string())))
+                    }
+                }
+            }
+            if self.clients[i].turned_away {
+                let client = self.clients.remove(i);
+                println!("Turned away {}", client.connection.addr());
+                continue;
+            }
+            i += 1;
+        }
+
+        self.check_absent(game);
+        self.send_updates(game);
+        changed
+    }
+
+    fn accept_clients(&mut self) {
+        let mut connections = accept(&self.listener, Connection::new);
+        if let Some(listener) = &self.websocket_listener {
+            connections.extend(accept(listener, Connection::websocket));
+        }
+        for connection in connections {
+            // a client that can't be given a session is dropped
+            let session = match new_session() {
+                Ok(session) => session,
+                Err(_) => continue,
+            };
+            self.clients.push(Client {
+                connection,
+                name: None,
+                seat: None,
+                session,
+                synced: false,
+                turned_away: false,
+            });
+        }
+    }
+
+    /// Handle a packet from the `i`th client, returning whether it made
+    /// a move
+    fn handle_message(
+        &mut self,
+        i: usize,
+        packet: Packet,
+        game: &mut Game,
+    ) -> Result<bool, NetworkError> {
+        let over = self.adjudication.is_some();
+        let free_seat = self.free_seat();
+        let full = self.spectators() >= self.max_spectators;
+        let client = &mut self.clients[i];
+        let id = packet.id;
+        match packet.message {
+            Message::Hello { version, name } => {
+                if version != PROTOCOL_VERSION {
+                    return Err(NetworkError(format!(
+                        "the host speaks version {} of the protocol, not {}",
+                        PROTOCOL_VERSION, version
+                    )));
+                }
+                if client.name.is_none() {
+                    if free_seat.is_none() && full {
+                        client.turned_away = true;
+                        return Err(NetworkError(
+                            "there's no room for more spectators".to_string(),
+                        ));
+                    }
+                    client.seat = free_seat;
+                }
+                println!("{} is {}", client.connection.addr(), name);
+                client.name = Some(name);
+                client.send(&Packet::reply(
+                    id,
+                    Message::Hello {
+                        version: PROTOCOL_VERSION,
+                        name: HOST_NAME.to_string(),
+                    },
+                ));
+                client.send(&Packet::new(Message::Seat(client.seat)));
+                client.send(&Packet::new(Message::Session(client.session.clone())));
+                Ok(false)
+            }
+            _ if client.name.is_none() => Err(NetworkError("say hello first".to_string())),
+            Message::Resume(session) => self.resume(i, id, session),
+            Message::Seat(seat) => {
+                self.check_seat(i, seat)?;
+                let client = &mut self.clients[i];
+                client.seat = seat;
+                client.send(&Packet::reply(id, Message::Ok));
+                client.send(&Packet::new(Message::Seat(seat)));
+                Ok(false)
+            }
+            Message::Offer { side, .. }
+            | Message::Accept { side, .. }
+            | Message::Decline { side, .. }
+            | Message::Resign(side)
+                if client.seat != Some(side) =>
+            {
+                Err(NetworkError(format!("you don't play {:?}", side)))
+            }
+            Message::Chat { text, .. } => {
+                let name = client.name.clone().unwrap_or_default();
+                self.chat(&name, &text, game);
+                self.clients[i].send(&Packet::reply(id, Message::Ok));
+                Ok(false)
+            }
+            Message::Offer { side, offer } => {
+                self.make_offer(side, offer, game)?;
+                self.clients[i].send(&Packet::reply(id, Message::Ok));
+                Ok(false)
+            }
+            Message::Accept { side, offer } => {
+                self.accept(side, offer, game)?;
+                self.clients[i].send(&Packet::reply(id, Message::Ok));
+                Ok(offer == Offer::Takeback)
+            }
+            Message::Decline { side, offer } => {
+                self.decline(side, offer, game)?;
+                self.clients[i].send(&Packet::reply(id, Message::Ok));
+                Ok(false)
+            }
+            Message::Resign(side) => {
+                self.resign(side, game)?;
+                self.clients[i].send(&Packet::reply(id, Message::Ok));
+                Ok(false)
+            }
+            Message::Move(_) if over => Err(NetworkError("the game is over".to_string())),
+            Message::Move(uci) => {
+                match client.seat {
+                    None => return Err(NetworkError("spectators can't move".to_string())),
+                    Some(color) if color != game.next_player() => {
+                        return Err(NetworkError("it's not your move".to_string()))
+                    }
+                    Some(_) => (),
+                }
+                let m = Move::from_uci(&uci, game.current_board())
+                    .map_err(|err| NetworkError(err.to_string()))?;
+                match game.try_make_move(m) {
+                    Ok(_) => {
+                        client.send(&Packet::reply(id, Message::Ok));
+                        Ok(true)
+                    }
+                    Err(Error::IllegalMove(_, _, reason)) => {
+                        Err(NetworkError(format!("{} is illegal: {}", uci, reason)))
+                    }
+                    Err(err) => Err(NetworkError(err.to_string())),
+                }
+            }
+            message => Err(NetworkError(format!(
+                "clients can't send `{}` packets",
+                message.kind()
+            ))),
+        }
+    }
+
+    /// Give the `i`th client the seat of the player with the session,
+    /// who lost their connection
+    fn resume(&mut self, i: usize, id: Option<u32>, session: String) -> Result<bool, NetworkError> {
+        let client = &mut self.clients[i];
+        if client.session == session {
+            // it's already this client's
+            client.send(&Packet::reply(id, Message::Ok));
+            return Ok(false);
+        }
+        if let Some(seat) = client.seat {
+            return Err(NetworkError(format!(
+                "you play {:?}, spectate before resuming another session",
+                seat
+            )));
+        }
+        let seat = if let Some(j) = self.absent.iter().position(|a| a.session == session) {
+            self.absent.remove(j).seat
+        } else if let Some(old) = self
+            .clients
+            .iter_mut()
+            .enumerate()
+            .find(|(j, c)| *j != i && c.session == session && c.seat.is_some())
+            .map(|(_, c)| c)
+        {
+            // the old connection is dead but hasn't been noticed yet
+            old.turned_away = true;
+            old.seat.take().unwrap()
+        } else {
+            return Err(NetworkError(
+                "there's no such session, it may have run out".to_string(),
+            ));
+        };
+        let client = &mut self.clients[i];
+        println!(
+            "{} is back, playing {:?}",
+            client.name.as_deref().unwrap_or_default(),
+            seat
+        );
+        client.seat = Some(seat);
+        client.session = session;
+        client.send(&Packet::reply(id, Message::Ok));
+        client.send(&Packet::new(Message::Seat(client.seat)));
+        client.send(&Packet::new(Message::Session(client.session.clone())));
+        Ok(false)
+    }
+
+    /// Keep the side of a player who disconnected for the grace period,
+    /// unless the game is over anyway
+    fn leave(&mut self, client: Client, game: &Game) {
+        let (name, seat) = match (client.name, client.seat) {
+            (Some(name), Some(seat)) => (name, seat),
+            _ => return,
+        };
+        if self.is_over(game) {
+            println!("{} left, {:?} is free", name, seat);
+            return;
+        }
+        println!(
+            "{} left, keeping {:?} for {} seconds",
+            name,
+            seat,
+            self.grace_period.as_secs()
+        );
+        self.absent.push(Absent {
+            name,
+            seat,
+            session: client.session,
+            since: Instant::now(),
+        });
+    }
+
+    /// Adjudicate the game as abandoned by the players who haven't come
+    /// back within the grace period
+    fn check_absent(&mut self, game: &Game) {
+        let grace_period = self.grace_period;
+        let (gone, absent): (Vec<Absent>, Vec<Absent>) = self
+            .absent
+            .drain(..)
+            .partition(|a| a.since.elapsed() >= grace_period);
+        self.absent = absent;
+        for gone in gone {
+            if self.is_over(game) {
+                println!("{} didn't come back, {:?} is free", gone.name, gone.seat);
+                continue;
+            }
+            println!("{} abandoned the game", gone.name);
+            self.adjudication = Some(Adjudication {
+                result: match gone.seat {
+                    Color::White => GameResult::BlackWins,
+                    Color::Black => GameResult::WhiteWins,
+                },
+                reason: "abandoned".to_string(),
+            });
+        }
+    }
+
+    /// Check that the `i`th client can move to `seat`
+    fn check_seat(&self, i: usize, seat: Option<Color>) -> Result<(), NetworkError> {
+        if self.clients[i].seat == seat {
+            return Ok(());
+        }
+        match seat {
+            Some(color) if !self.seats.contains(&color) => Err(NetworkError(format!(
+                "{:?} isn't played by a client",
+                color
+            ))),
+            Some(color) if self.is_seated(color) => {
+                Err(NetworkError(format!("{:?} is taken", color)))
+            }
+            None if self.spectators() >= self.max_spectators => Err(NetworkError(
+                "there's no room for more spectators".to_string(),
+            )),
+            Some(_) | None => Ok(()),
+        }
+    }
+
+    /// Tell the clients about any change to the game: the moves made
+    /// since last time, or the whole `state` if it's a different game
+    /// or moves were taken back, the result when the game ends and the
+    /// number of spectators when it changes
+    fn send_updates(&mut self, game: &Game) {
+        let start = game.get_boards()[0];
+        let moves = game.get_moves();
+        let replaced = self.sent.start != Some(start) || !moves.starts_with(&self.sent.moves);
+        if replaced {
+            self.adjudication = None;
+            self.record = Record::new();
+        }
+        if replaced || moves.len() > self.sent.moves.len() {
+            self.offer = None;
+        }
+        let sent = &self.sent;
+        let mut updates = if replaced {
+            vec![state_message(game)]
+        } else {
+            uci_moves(game, sent.moves.len())
+                .into_iter()
+                .map(Message::Move)
+                .collect()
+        };
+        let result = result_message(game).or_else(|| {
+            self.adjudication
+                .as_ref()
+                .map(|adjudication| Message::Result {
+                    result: adjudication.result,
+                    reason: adjudication.reason.clone(),
+                })
+        });
+        if let Some(result) = result.clone() {
+            if !sent.over || !updates.is_empty() {
+                updates.push(result);
+            }
+        }
+        let spectators = self.spectators();
+        let spectators_message = Message::Spectators(spectators as u32);
+        if spectators != sent.spectators {
+            updates.push(spectators_message.clone());
+        }
+        self.sent = SentGame {
+            start: Some(start),
+            moves: moves.to_vec(),
+            over: result.is_some(),
+            spectators,
+        };
+
+        for client in self.clients.iter_mut().filter(|c| c.name.is_some()) {
+            if client.synced {
+                for message in &updates {
+                    client.send(&Packet::new(message.clone()));
+                }
+            } else {
+                client.send(&Packet::new(state_message(game)));
+                if let Some(result) = result.clone() {
+                    client.send(&Packet::new(result));
+                }
+                client.send(&Packet::new(spectators_message.clone()));
+                client.synced = true;
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Send a packet to the client. Failing to send isn't fatal here,
+    /// reading from a broken connection, or one that has stopped taking
+    /// what's sent, drops it.
+    fn send(&mut self, packet: &Packet) {
+        if let Err(err) = self.connection.send(packet) {
+            println!("Couldn't send to {}: {}", self.connection.addr(), err);
+        }
+    }
+}
+
+/// The moves of `game` from the `from`th one onwards, in UCI notation
+fn uci_moves(game: &Game, from: usize) -> Vec<String> {
+    let boards = game.get_boards();
+    game.get_moves()
+        .iter()
+        .enumerate()
+        .skip(from)
+        .map(|(i, m)| m.to_uci(boards[i].turn()))
+        .collect()
+}
+
+fn state_message(game: &Game) -> Message {
+    Message::State {
+        fen: game.get_boards()[0].to_string(),
+        moves: uci_moves(game, 0),
+    }
+}
+
+fn result_message(game: &Game) -> Option<Message> {
+    board_result(game).map(|(result, reason)| Message::Result {
+        result,
+        reason: reason.to_string(),
+    })
+}
+
+/// The result of `game` if it's over on the board, and why
+pub(crate) fn board_result(game: &Game) -> Option<(GameResult, &'static str)> {
+    match game.board_state() {
+        BoardState::Checkmate => match game.next_player() {
+            Color::White => Some((GameResult::BlackWins, "checkmate")),
+            Color::Black => Some((GameResult::WhiteWins, "checkmate")),
+        },
+        BoardState::Stalemate => Some((GameResult::Draw, "stalemate")),
+        BoardState::Draw => Some((GameResult::Draw, "draw")),
+        BoardState::Normal | BoardState::Check => None,
+    }
+}
+
+/// The number of moves a takeback asked for by the player of `side`
+/// takes back: their last move, and the reply to it if there's been
+/// one. `None` if they haven't moved yet.
+pub fn takeback_plies(game: &Game, side: Color) -> Option<usize> {
+    let plies = if game.next_player() == side { 2 } else { 1 };
+    (game.get_moves().len() >= plies).then_some(plies)
+}
+
+/// A token for a new session, 128 bits from the OS's random number
+/// generator, so that knowing some tokens doesn't help to guess another
+fn new_session() -> Result<String, getrandom::Error> {
+    let mut bytes = [0_u8; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Accept every client waiting on `listener`
+fn accept(
+    listener: &TcpListener,
+    connection: fn(TcpStream) -> io::Result<Connection>,
+) -> Vec<Connection> {
+    let mut connections = vec![];
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => match connection(stream) {
+                Ok(connection) => connections.push(connection),
+                Err(e) => eprintln!("{:?}", e),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                break;
+            }
+        }
+    }
+    connections
+}
@@ -0,0 +1,579 @@
+//! The packets of [`crate::codec`] as JSON, for clients that would
+//! rather not write a parser for the packet format, e.g. browsers over
+//! a WebSocket. A packet is an object with the `kind` of message, the
+//! `id` if it has one, and a member for each field:
+//!
+//! ```text
+//! {"id": 7, "kind": "move", "move": "e2e4"}
+//! {"id": 7, "kind": "ok"}
+//! {"kind": "state", "fen": "...", "moves": ["e2e4", "e7e5"]}
+//! ```
+//!
+//! | Kind         | Members                                  |
+//! |--------------|------------------------------------------|
+//! | `hello`      | `version` number, `name` string          |
+//! | `seat`       | `seat`, `"white"`, `"black"` or `"spectator"` |
+//! | `session`    | `token` string                           |
+//! | `resume`     | `token` string                           |
+//! | `move`       | `move` string, in UCI notation           |
+//! | `state`      | `fen` string, `moves` array of strings   |
+//! | `spectators` | `count` number                           |
+//! | `result`     | `result` string, e.g. `"1-0"`, `reason` string |
+//! | `chat`       | `name` string, `text` string             |
+//! | `offer`      | `side`, `"white"` or `"black"`, `offer`, `"draw"` or `"takeback"` |
+//! | `accept`     | `side` and `offer`, as for `offer`       |
+//! | `decline`    | `side` and `offer`, as for `offer`       |
+//! | `resign`     | `side`, `"white"` or `"black"`           |
+//! | `ok`         |                                          |
+//! | `error`      | `message` string                         |
+//!
+//! Members that aren't used are ignored.
+use std::fmt::{self, Write};
+
+use crate::codec::{CodecError, Message, Packet};
+
+/// Write a packet as a JSON object
+pub fn to_json(packet: &Packet) -> String {
+    let mut object = vec![];
+    if let Some(id) = packet.id {
+        object.push(("id".to_string(), Value::Number(id.to_string())));
+    }
+    let kind = packet.message.kind();
+    object.push(("kind".to_string(), Value::String(kind.to_string())));
+    for (&name, field) in field_names(kind).iter().zip(packet.message.fields()) {
+        let value = match name {
+            "version" | "count" => Value::Number(field),
+            "moves" => Value::Array(
+                field
+                    .split_whitespace()
+                    .map(|m| Value::String(m.to_string()))
+                    .collect(),
+            ),
+            _ => Value::String(field),
+        };
+        object.push((name.to_string(), value));
+    }
+    Value::Object(object).to_string()
+}
+
+/// Read a packet from a JSON object
+///
+/// # Errors
+///
+/// Will return an error if the text isn't JSON, or the object isn't one
+/// of the packets in the module docs.
+pub fn from_json(text: &str) -> Result<Packet, CodecError> {
+    let object = match parse(text)? {
+        Value::Object(object) => object,
+        value => {
+            return Err(CodecError::InvalidJson(format!(
+                "{} isn't an object",
+                value
+            )))
+        }
+    };
+    let member = |name: &'static str| {
+        object
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    };
+
+    let id = match member("id") {
+        None | Some(Value::Null) => None,
+        Some(Value::Number(id)) => Some(
+            id.parse()
+                .map_err(|_| CodecError::InvalidId(id.to_string()))?,
+        ),
+        Some(id) => return Err(CodecError::InvalidId(id.to_string())),
+    };
+    let kind = match member("kind") {
+        Some(Value::String(kind)) => kind,
+        Some(kind) => return Err(CodecError::UnknownKind(kind.to_string())),
+        None => return Err(CodecError::MissingField("kind")),
+    };
+
+    // the fields are checked the same way as in the packet format
+    let mut fields = vec![];
+    for &name in field_names(kind) {
+        let field = match member(name) {
+            Some(Value::String(field)) if !matches!(name, "version" | "count" | "moves") => {
+                field.clone()
+            }
+            Some(Value::Number(field)) if matches!(name, "version" | "count") => field.clone(),
+            Some(Value::Array(moves)) if name == "moves" => {
+                let moves = moves
+                    .iter()
+                    .map(|m| match m {
+                        Value::String(m) if !m.is_empty() && !m.contains(' ') => Ok(m.as_str()),
+                        m => Err(CodecError::InvalidField("move", m.to_string())),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                moves.join(" ")
+            }
+            Some(value) => return Err(CodecError::InvalidField(name, value.to_string())),
+            None => return Err(CodecError::MissingField(name)),
+        };
+        fields.push(field);
+    }
+    Ok(Packet::reply(id, Message::from_fields(kind, fields)?))
+}
+
+/// The names of the members holding the fields of a message of the
+/// kind, in the order of its fields
+fn field_names(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "hello" => &["version", "name"],
+        "seat" => &["seat"],
+        "session" | "resume" => &["token"],
+        "move" => &["move"],
+        "state" => &["fen", "moves"],
+        "spectators" => &["count"],
+        "result" => &["result", "reason"],
+        "chat" => &["name", "text"],
+        "offer" | "accept" | "decline" => &["side", "offer"],
+        "resign" => &["side"],
+        "error" => &["message"],
+        _ => &[],
+    }
+}
+
+/// A JSON value, as much of it as is needed for packets
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    /// The number as it's written, so that ids don't go through a float
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    /// The members in the order they're written
+    Object(Vec<(String, Value)>),
+}
+
+/// Writes the value as compact JSON
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) => f.write_str(n),
+            Self::String(s) => write_string(f, s),
+            Self::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// How deep arrays and objects can be nested, which is far deeper than
+/// any packet needs
+const MAX_DEPTH: usize = 32;
+
+fn parse(text: &str) -> Result<Value, CodecError> {
+    let mut parser = Parser {
+        text,
+        chars: text.char_indices().peekable(),
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some((i, _)) => Err(parser.error(i, "there's more after the value")),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    /// How many arrays and objects the parser is in
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, at: usize, what: &str) -> CodecError {
+        CodecError::InvalidJson(format!("{} at byte {}", what, at))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some((_, ' ' | '\t' | '\n' | '\r'))) {
+            let _ = self.chars.next();
+        }
+    }
+
+    /// Take the next character, which has to be `expected`
+    fn expect(&mut self, expected: char) -> Result<(), CodecError> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((i, _)) => Err(self.error(i, &format!("expected `{}`", expected))),
+            None => Err(self.error(self.text.len(), &format!("expected `{}`", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, CodecError> {
+        self.skip_whitespace();
+        let (start, c) = match self.chars.peek() {
+            Some(&next) => next,
+            None => return Err(self.error(self.text.len(), "expected a value")),
+        };
+        match c {
+            '{' | '[' => {
+                // values are parsed recursively, so a packet of nothing
+                // but `[` would run out of stack
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error(start, "too deeply nested"));
+                }
+                self.depth += 1;
+                let value = if c == '{' {
+                    self.object()
+                } else {
+                    self.array()
+                };
+                self.depth -= 1;
+                value
+            }
+            '"' => Ok(Value::String(self.string()?)),
+            '-' | '0'..='9' => Ok(Value::Number(self.number(start))),
+            _ => {
+                let rest = &self.text[start..];
+                let (word, value) = [
+                    ("null", Value::Null),
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                ]
+                .into_iter()
+                .find(|(word, _)| rest.starts_with(word))
+                .ok_or_else(|| self.error(start, "expected a value"))?;
+                for _ in 0..word.len() {
+                    let _ = self.chars.next();
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, CodecError> {
+        self.expect('{')?;
+        let mut members = vec![];
+        self.skip_whitespace();
+        if matches!(self.chars.peek(), Some((_, '}'))) {
+            let _ = self.chars.next();
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => (),
+                Some((_, '}')) => return Ok(Value::Object(members)),
+                Some((i, _)) => return Err(self.error(i, "expected `,` or `}`")),
+                None => return Err(self.error(self.text.len(), "expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, CodecError> {
+        self.expect('[')?;
+        let mut values = vec![];
+        self.skip_whitespace();
+        if matches!(self.chars.peek(), Some((_, ']'))) {
+            let _ = self.chars.next();
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => (),
+                Some((_, ']')) => return Ok(Value::Array(values)),
+                Some((i, _)) => return Err(self.error(i, "expected `,` or `]`")),
+                None => return Err(self.error(self.text.len(), "expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, CodecError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => s.push(self.escape()?),
+                Some((i, c)) if c.is_control() => {
+                    return Err(self.error(i, "control characters have to be escaped"))
+                }
+                Some((_, c)) => s.push(c),
+                None => return Err(self.error(self.text.len(), "the string isn't closed")),
+            }
+        }
+    }
+
+    /// The character escaped after a `\`
+    fn escape(&mut self) -> Result<char, CodecError> {
+        let (i, c) = self
+            .chars
+            .next()
+            .ok_or_else(|| self.error(self.text.len(), "the string isn't closed"))?;
+        Ok(match c {
+            '"' | '\\' | '/' => c,
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let high = self.hex(i)?;
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    // the first half of a surrogate pair
+                    self.expect('\\')?;
+                    self.expect('u')?;
+                    let low = self.hex(i)?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(self.error(i, "invalid surrogate pair"));
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| self.error(i, "invalid escape"))?
+            }
+            _ => return Err(self.error(i, "invalid escape")),
+        })
+    }
+
+    /// The four hex digits of a `\u` escape
+    fn hex(&mut self, at: usize) -> Result<u32, CodecError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error(at, "invalid escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// A number starting at `start`, which is checked when it's used
+    fn number(&mut self, start: usize) -> String {
+        let mut end = start;
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+                break;
+            }
+            end = i + c.len_utf8();
+            let _ = self.chars.next();
+        }
+        self.text[start..end].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Offer, PROTOCOL_VERSION};
+    use chess_engine::pgn::GameResult;
+    use chess_engine::Color;
+
+    #[test]
+    fn round_trips() {
+        let messages = vec![
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+                name: "Magnus \"the\" Carlsen\n".to_string(),
+            },
+            Message::Seat(Some(Color::White)),
+            Message::Seat(None),
+            Message::Session("0123456789abcdef".to_string()),
+            Message::Move("e7e8q".to_string()),
+            Message::State {
+                fen: "4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string(),
+                moves: vec!["e1e2".to_string(), "e8e7".to_string()],
+            },
+            Message::State {
+                fen: "4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string(),
+                moves: vec![],
+            },
+            Message::Spectators(3),
+            Message::Result {
+                result: GameResult::WhiteWins,
+                reason: "abandoned".to_string(),
+            },
+            Message::Chat {
+                name: "Magnus".to_string(),
+                text: "gg \u{265e}".to_string(),
+            },
+            Message::Decline {
+                side: Color::Black,
+                offer: Offer::Draw,
+            },
+            Message::Resign(Color::White),
+            Message::Ok,
+            Message::Error("no; really\\".to_string()),
+        ];
+        for message in messages {
+            for packet in [
+                Packet::new(message.clone()),
+                Packet::reply(Some(42), message.clone()),
+            ] {
+                let json = to_json(&packet);
+                assert_eq!(from_json(&json), Ok(packet), "{}", json);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_objects() {
+        assert_eq!(
+            to_json(&Packet::reply(Some(7), Message::Move("e2e4".to_string()))),
+            r#"{"id":7,"kind":"move","move":"e2e4"}"#
+        );
+        assert_eq!(
+            to_json(&Packet::new(Message::State {
+                fen: "8/8/8/8/8/8/8/8 w - - 0 1".to_string(),
+                moves: vec!["e2e4".to_string()],
+            })),
+            r#"{"kind":"state","fen":"8/8/8/8/8/8/8/8 w - - 0 1","moves":["e2e4"]}"#
+        );
+        assert_eq!(
+            to_json(&Packet::new(Message::Error("\u{1}".to_string()))),
+            r#"{"kind":"error","message":"\u0001"}"#
+        );
+    }
+
+    #[test]
+    fn reads_objects() {
+        assert_eq!(
+            from_json(
+                " {\n \"move\" : \"e2e4\", \"kind\": \"move\", \"extra\": [1, {}, null, true] } "
+            ),
+            Ok(Packet::new(Message::Move("e2e4".to_string())))
+        );
+        assert_eq!(
+            from_json(r#"{"id": null, "kind": "hello", "version": 1, "name": "é😀\/"}"#),
+            Ok(Packet::new(Message::Hello {
+                version: 1,
+                name: "é😀/".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_objects() {
+        for text in [
+            "",
+            "{",
+            r#"{"kind": "ok",}"#,
+            r#"{"kind": "ok"} {}"#,
+            r#"{"kind": "error", "message": "\x"}"#,
+            r#"{"kind": "error", "message": "\ud83d"}"#,
+            "{\"kind\": \"error\", \"message\": \"\n\"}",
+            "nul",
+        ] {
+            assert!(
+                matches!(from_json(text), Err(CodecError::InvalidJson(_))),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            from_json("[]"),
+            Err(CodecError::InvalidJson("[] isn't an object".to_string()))
+        );
+
+        // unused members can nest, but not without end
+        let nested = |depth| {
+            format!(
+                r#"{{"kind": "ok", "x": {}{}}}"#,
+                "[".repeat(depth),
+                "]".repeat(depth)
+            )
+        };
+        assert_eq!(from_json(&nested(31)), Ok(Packet::new(Message::Ok)));
+        assert!(matches!(
+            from_json(&nested(32)),
+            Err(CodecError::InvalidJson(_))
+        ));
+        let deep = format!(r#"{{"kind": "ok", "x": {}"#, "[".repeat(60_000));
+        assert_eq!(
+            from_json(&deep),
+            Err(CodecError::InvalidJson(
+                "too deeply nested at byte 51".to_string()
+            ))
+        );
+        assert_eq!(
+            from_json(r#"{"id": 1.5, "kind": "ok"}"#),
+            Err(CodecError::InvalidId("1.5".to_string()))
+        );
+        assert_eq!(
+            from_json(r#"{"id": 1}"#),
+            Err(CodecError::MissingField("kind"))
+        );
+        assert_eq!(
+            from_json(r#"{"kind": "draw"}"#),
+            Err(CodecError::UnknownKind("draw".to_string()))
+        );
+        assert_eq!(
+            from_json(r#"{"kind": "hello", "version": "1", "name": "a"}"#),
+            Err(CodecError::InvalidField("version", r#""1""#.to_string()))
+        );
+        assert_eq!(
+            from_json(r#"{"kind": "move"}"#),
+            Err(CodecError::MissingField("move"))
+        );
+        assert_eq!(
+            from_json(r#"{"kind": "state", "fen": "", "moves": ["e2e4 e7e5"]}"#),
+            Err(CodecError::InvalidField(
+                "move",
+                r#""e2e4 e7e5""#.to_string()
+            ))
+        );
+        assert_eq!(
+            from_json(r#"{"kind": "spectators", "count": -1}"#),
+            Err(CodecError::InvalidField(
+                "spectator count",
+                "-1".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! Playing chess over the network, without anything to do with how
+//! the game is shown. The protocol is described in [`codec`], and is
+//! spoken over TCP, or as [`json`] over a [`websocket`] for browsers. A
+//! [`host::Host`] has the game and lets clients connect to play or
+//! watch it, and a [`remote::Remote`] is a client playing a game
+//! hosted somewhere else. Both are polled, so they fit in a GUI's frame
+//! loop as well as in the `chess-server` binary. Both keep a
+//! [`record::Record`] of what the players said and agreed on, to write
+//! the game out as PGN.
+#![warn(missing_docs, missing_debug_implementations)]
+
+pub mod codec;
+pub mod config;
+pub mod connection;
+pub mod host;
+pub mod json;
+pub mod record;
+pub mod remote;
+pub mod websocket;
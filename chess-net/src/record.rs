@@ -0,0 +1,338 @@
+//! The record of a game played over the network: its moves, and what
+//! was said and agreed on along the way. It's written as PGN, with the
+//! chat, offers and answers to them as comments after the move they
+//! came after, and a `Termination` tag for how the game ended.
+//!
+//! Both the host and its clients keep one, from the messages they
+//! send each other, so they end up with the same record.
+use chess_engine::pgn::GameResult;
+use chess_engine::{Board, Color, Game};
+
+use crate::codec::{result_str, Message, Offer};
+use crate::host::{board_result, takeback_plies, Adjudication};
+
+/// How long the lines of the moves are allowed to get, as the PGN
+/// export format asks
+const LINE_LENGTH: usize = 79;
+
+/// What happened in a game besides the moves, see the module docs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    /// What happened, with the number of moves made by then
+    notes: Vec<(usize, String)>,
+}
+
+impl Record {
+    /// An empty record, for a new game
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The notes so far, with the number of moves made when each was
+    /// made, oldest first
+    pub fn notes(&self) -> &[(usize, String)] {
+        &self.notes
+    }
+
+    /// Note down a message about `game` if it's one worth keeping:
+    /// chat, offers and the answers to them, and resignations. An
+    /// accepted takeback has to be noted before the moves are taken
+    /// back, and [`Record::take_back`] called after.
+    pub fn note(&mut self, game: &Game, message: &Message) {
+        let text = match message {
+            Message::Chat { name, text } => format!("{}: {}", name, text),
+            Message::Offer {
+                side,
+                offer: Offer::Draw,
+            } => format!("{} offers a draw", side_name(*side)),
+            Message::Offer {
+                side,
+                offer: Offer::Takeback,
+            } => format!("{} asks for a takeback", side_name(*side)),
+            Message::Accept {
+                side,
+                offer: Offer::Draw,
+            } => format!("{} accepts the draw", side_name(*side)),
+            Message::Accept {
+                side,
+                offer: Offer::Takeback,
+            } => {
+                let plies = takeback_plies(game, side.opposite()).unwrap_or(0);
+                let moves = game.get_moves().len();
+                let boards = game.get_boards();
+                let taken_back = game.get_moves()[moves - plies..]
+                    .iter()
+                    .zip(&boards[moves - plies..])
+                    .map(|(m, board)| m.to_san(board))
+                    .collect::<Vec<_>>();
+                format!(
+                    "{} accepts the takeback of {}",
+                    side_name(*side),
+                    taken_back.join(" ")
+                )
+            }
+            Message::Decline {
+                side,
+                offer: Offer::Draw,
+            } => format!("{} declines the draw", side_name(*side)),
+            Message::Decline {
+                side,
+                offer: Offer::Takeback,
+            } => format!("{} declines the takeback", side_name(*side)),
+            Message::Resign(side) => format!("{} resigns", side_name(*side)),
+            _ => return,
+        };
+        self.notes.push((game.get_moves().len(), text));
+    }
+
+    /// Move the notes made after moves that have been taken back to
+    /// after the moves that are left
+    pub fn take_back(&mut self, game: &Game) {
+        let moves = game.get_moves().len();
+        for (ply, _) in &mut self.notes {
+            *ply = (*ply).min(moves);
+        }
+    }
+
+    /// Write `game` as PGN with the notes, and the result it was given
+    /// if it isn't over on the board
+    ///
+    /// # Examples
+    /// ```
+    /// # use chess_engine::{Color, Game, Move};
+    /// # use chess_net::codec::{Message, Offer};
+    /// # use chess_net::record::Record;
+    /// let mut game = Game::new();
+    /// let mut record = Record::new();
+    /// let m = Move::from_uci("e2e4", game.current_board()).unwrap();
+    /// let _ = game.make_move(m);
+    /// record.note(
+    ///     &game,
+    ///     &Message::Offer {
+    ///         side: Color::White,
+    ///         offer: Offer::Draw,
+    ///     },
+    /// );
+    ///
+    /// assert!(record
+    ///     .to_pgn(&game, None)
+    ///     .ends_with("1. e4 {White offers a draw} *\n"));
+    /// ```
+    pub fn to_pgn(&self, game: &Game, adjudication: Option<&Adjudication>) -> String {
+        let (result, reason) = match (board_result(game), adjudication) {
+            (Some((result, reason)), _) => (result, Some(reason)),
+            (None, Some(adjudication)) => (adjudication.result, Some(adjudication.reason.as_str())),
+            (None, None) => (GameResult::Unknown, None),
+        };
+        let termination = match reason {
+            Some("abandoned") => "abandoned",
+            Some(_) => "normal",
+            None => "unterminated",
+        };
+
+        let start = game.get_boards()[0];
+        let mut pgn = String::new();
+        let mut tags = vec![
+            ("Event", "Network game"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "-"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", result_str(result)),
+            ("Termination", termination),
+        ];
+        let fen = start.to_string();
+        if start != Board::default_board() {
+            tags.push(("SetUp", "1"));
+            tags.push(("FEN", &fen));
+        }
+        for (name, value) in tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        pgn.push('\n');
+
+        let mut tokens = vec![];
+        let mut notes = self.notes.iter().peekable();
+        let mut comment = |tokens: &mut Vec<String>, ply: usize| {
+            let mut any = false;
+            while let Some((_, text)) = notes.next_if(|(at, _)| *at <= ply) {
+                tokens.push(format!("{{{}}}", text.replace('}', ")")));
+                any = true;
+            }
+            any
+        };
+        let mut fullmove = fullmove_number(&start);
+        // black's first move needs its number, as does one after a
+        // comment
+        let mut numbered = false;
+        let boards = game.get_boards();
+        for (i, m) in game.get_moves().iter().enumerate() {
+            numbered &= !comment(&mut tokens, i);
+            let san = m.to_san(&boards[i]);
+            tokens.push(match boards[i].turn() {
+                Color::White => format!("{}. {}", fullmove, san),
+                Color::Black if !numbered => format!("{}... {}", fullmove, san),
+                Color::Black => san,
+            });
+            numbered = true;
+            if boards[i].turn() == Color::Black {
+                fullmove += 1;
+            }
+        }
+        let _ = comment(&mut tokens, usize::MAX);
+        if let Some(reason) = reason {
+            tokens.push(format!("{{{}}}", reason));
+        }
+        tokens.push(result_str(result).to_string());
+
+        // comments can be broken between words, moves are kept together
+        // with their numbers
+        let words = tokens.iter().flat_map(|token| {
+            if token.starts_with('{') {
+                token.split_whitespace().collect()
+            } else {
+                vec![token.as_str()]
+            }
+        });
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_LENGTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn side_name(side: Color) -> &'static str {
+    match side {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+/// The number of the move to be made on `board`, the last field of its
+/// FEN
+fn fullmove_number(board: &Board) -> u32 {
+    board
+        .to_string()
+        .rsplit(' ')
+        .next()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess_engine::pgn::read_games;
+    use chess_engine::Move;
+
+    fn play(game: &mut Game, moves: &[&str]) {
+        for uci in moves {
+            let m = Move::from_uci(uci, game.current_board()).unwrap();
+            let _ = game.try_make_move(m).unwrap();
+        }
+    }
+
+    #[test]
+    fn writes_notes_as_comments() {
+        let mut game = Game::new();
+        let mut record = Record::new();
+        record.note(
+            &game,
+            &Message::Chat {
+                name: "magnus".to_string(),
+                text: "good luck {and} have fun".to_string(),
+            },
+        );
+        play(&mut game, &["e2e4", "e7e5"]);
+        record.note(
+            &game,
+            &Message::Offer {
+                side: Color::Black,
+                offer: Offer::Draw,
+            },
+        );
+        record.note(
+            &game,
+            &Message::Decline {
+                side: Color::White,
+                offer: Offer::Draw,
+            },
+        );
+        play(&mut game, &["g1f3"]);
+        record.note(&game, &Message::Resign(Color::Black));
+        // not worth keeping
+        record.note(&game, &Message::Spectators(2));
+        let adjudication = Adjudication {
+            result: GameResult::WhiteWins,
+            reason: "resignation".to_string(),
+        };
+
+        let pgn = record.to_pgn(&game, Some(&adjudication));
+        assert!(pgn.starts_with("[Event \"Network game\"]\n"));
+        assert!(pgn.contains("\n[Result \"1-0\"]\n[Termination \"normal\"]\n\n"));
+        assert!(pgn.ends_with(
+            "\n{magnus: good luck {and) have fun} 1. e4 e5 {Black offers a draw} {White\n\
+             declines the draw} 2. Nf3 {Black resigns} {resignation} 1-0\n"
+        ));
+
+        // it can be read back
+        let games = read_games(&pgn).unwrap();
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3"]);
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+    }
+
+    #[test]
+    fn keeps_notes_over_takebacks() {
+        let mut game = Game::from_board(
+            Board::load_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap(),
+        );
+        let mut record = Record::new();
+        play(&mut game, &["e7e5", "g1f3", "b8c6"]);
+        let takeback = Offer::Takeback;
+        record.note(
+            &game,
+            &Message::Offer {
+                side: Color::White,
+                offer: takeback,
+            },
+        );
+        record.note(
+            &game,
+            &Message::Accept {
+                side: Color::Black,
+                offer: takeback,
+            },
+        );
+        assert_eq!(takeback_plies(&game, Color::White), Some(2));
+        let _ = game.undo_move();
+        let _ = game.undo_move();
+        record.take_back(&game);
+        play(&mut game, &["f1c4"]);
+
+        assert_eq!(
+            record.notes(),
+            [
+                (1, "White asks for a takeback".to_string()),
+                (1, "Black accepts the takeback of Nf3 Nc6".to_string()),
+            ]
+        );
+        let pgn = record.to_pgn(&game, None);
+        assert!(pgn.contains("[Termination \"unterminated\"]\n[SetUp \"1\"]\n[FEN \"rnbqkbnr"));
+        assert!(pgn.ends_with(
+            "\n1... e5 {White asks for a takeback} {Black accepts the takeback of Nf3 Nc6}\n\
+             2. Bc4 *\n"
+        ));
+    }
+}
@@ -0,0 +1,1081 @@
+//! Playing a game hosted by another GUI, see [`crate::host`]. The host
+//! has the final say: the moves made here are sent to it, and if it
+//! turns one down, or the game here stops following the host's, the
+//! game is put back the way the host has it.
+//!
+//! If the connection is lost, a player can connect again with
+//! [`Remote::resume`] and the session from the old connection to get
+//! their side back.
+//!
+//! Chat, offers and resignations are sent to the host too, and only
+//! take effect once the host passes them back, the same as for
+//! everyone else in the game.
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use chess_engine::game::BoardState;
+use chess_engine::{Board, Color, Game, Move};
+
+use crate::codec::{Message, Offer, Packet, PROTOCOL_VERSION};
+use crate::connection::Connection;
+use crate::host::{takeback_plies, Adjudication};
+use crate::record::Record;
+
+/// A client of a game hosted somewhere else, see the module docs
+#[derive(Debug)]
+pub struct Remote {
+    connection: Connection,
+    /// The side played here, `None` until the host says, or when
+    /// spectating
+    seat: Option<Color>,
+    /// The game as the host has it, `None` until it sends the `state`
+    host_game: Option<Game>,
+    /// The number of moves of the game here that the host knows about
+    sent: usize,
+    /// The number of clients watching the game, as the host last said
+    spectators: u32,
+    /// The token to resume the session with, once the host has sent it
+    session: Option<String>,
+    adjudication: Option<Adjudication>,
+    /// The offer waiting for an answer, and the side that made it
+    offer: Option<(Color, Offer)>,
+    record: Record,
+    /// The id of the last request for a seat, or to resume a session
+    seat_request: Option<u32>,
+    next_id: u32,
+}
+
+impl Remote {
+    /// Connect to the host at `addr` and say hello
+    pub fn connect(addr: impl ToSocketAddrs, name: &str) -> io::Result<Self> {
+        let mut connection = Connection::connect(addr)?;
+        connection.send(&Packet::new(Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+        }))?;
+        Ok(Self {
+            connection,
+            seat: None,
+            host_game: None,
+            sent: 0,
+            spectators: 0,
+            session: None,
+            adjudication: None,
+            offer: None,
+            record: Record::new(),
+            seat_request: None,
+            next_id: 0,
+        })
+    }
+
+    /// Connect to the host at `addr` again, and take back the side of
+    /// the session, if the host has kept it
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the host can't be reached.
+    pub fn resume(addr: impl ToSocketAddrs, name: &str, session: &str) -> io::Result<Self> {
+        let mut remote = Self::connect(addr, name)?;
+        remote.connection.send(&Packet::reply(
+            Some(remote.next_id),
+            Message::Resume(session.to_string()),
+        ))?;
+        remote.seat_request = Some(remote.next_id);
+        remote.next_id += 1;
+        Ok(remote)
+    }
+
+    /// The address of the host
+    pub fn host_addr(&self) -> SocketAddr {
+        self.connection.addr()
+    }
+
+    /// The token to resume the session with after losing the
+    /// connection, `None` until the host sends it
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// The result the host gave the game if it didn't end on the board,
+    /// e.g. because a player abandoned it
+    pub fn adjudication(&self) -> Option<&Adjudication> {
+        self.adjudication.as_ref()
+    }
+
+    /// What was said and agreed on in the game, as the host passed it
+    /// on, to write it out with [`Record::to_pgn`]
+    pub fn record(&self) -> &Record {
+        &self.record
+    }
+
+    /// The offer waiting for an answer, and the side that made it
+    pub fn pending_offer(&self) -> Option<(Color, Offer)> {
+        self.offer
+    }
+
+    /// Say something to everyone in the game
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the message can't be sent.
+    pub fn chat(&mut self, text: &str) -> io::Result<()> {
+        self.connection.send(&Packet::new(Message::Chat {
+            // the host fills in the name
+            name: String::new(),
+            text: text.to_string(),
+        }))
+    }
+
+    /// Offer the opponent a draw, or ask them for a takeback
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if nothing is played here, or the offer
+    /// can't be sent.
+    pub fn make_offer(&mut self, offer: Offer) -> io::Result<()> {
+        let side = self.player()?;
+        self.connection
+            .send(&Packet::new(Message::Offer { side, offer }))
+    }
+
+    /// Accept the opponent's offer
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if nothing is played here, or the answer
+    /// can't be sent.
+    pub fn accept(&mut self, offer: Offer) -> io::Result<()> {
+        let side = self.player()?;
+        self.connection
+            .send(&Packet::new(Message::Accept { side, offer }))
+    }
+
+    /// Turn down the opponent's offer
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if nothing is played here, or the answer
+    /// can't be sent.
+    pub fn decline(&mut self, offer: Offer) -> io::Result<()> {
+        let side = self.player()?;
+        self.connection
+            .send(&Packet::new(Message::Decline { side, offer }))
+    }
+
+    /// Resign the game
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if nothing is played here, or the
+    /// resignation can't be sent.
+    pub fn resign(&mut self) -> io::Result<()> {
+        let side = self.player()?;
+        self.connection.send(&Packet::new(Message::Resign(side)))
+    }
+
+    /// The side played here, for what only a player can do
+    fn player(&self) -> io::Result<Color> {
+        self.seat
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "spectators can't do that"))
+    }
+
+    /// The side played here, `None` until the host says, or when
+    /// spectating
+    pub fn seat(&self) -> Option<Color> {
+        self.seat
+    }
+
+    /// Ask the host for a side to play, or to spectate. The seat only
+    /// changes once the host agrees.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the request can't be sent.
+    pub fn request_seat(&mut self, seat: Option<Color>) -> io::Result<()> {
+        self.connection
+            .send(&Packet::reply(Some(self.next_id), Message::Seat(seat)))?;
+        self.seat_request = Some(self.next_id);
+        self.next_id += 1;
+        Ok(())
+    }
+
+    /// The number of clients watching the game, as the host last said
+    pub fn spectators(&self) -> u32 {
+        self.spectators
+    }
+
+    /// Make the moves the host sends, and send it the moves made here
+    /// since last time, returning whether the game changed
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the connection to the host is lost.
+    pub fn update(&mut self, game: &mut Game) -> io::Result<bool> {
+        let mut changed = false;
+        for packet in self.connection.receive()? {
+            let packet = match packet {
+                Ok(packet) => packet,
+                Err(err) => {
+                    println!("Bad packet from the host: {}", err);
+                    continue;
+                }
+            };
+            match packet.message {
+                Message::Hello { name, .. } => println!("Connected to {}", name),
+                Message::Seat(seat) => self.seat = seat,
+                Message::Session(session) => self.session = Some(session),
+                Message::State { fen, moves } => match load_state(&fen, &moves) {
+                    Ok(state) => {
+                        self.adjudication = None;
+                        self.offer = None;
+                        self.record = Record::new();
+                        *game = state.clone();
+                        self.sent = moves.len();
+                        self.host_game = Some(state);
+                        changed = true;
+                    }
+                    Err(err) => println!("Bad state from the host: {}", err),
+                },
+                Message::Move(uci) => {
+                    self.offer = None;
+                    if let Err(err) = self.host_move(&uci) {
+                        println!("Bad move {} from the host: {}", uci, err);
+                    }
+                }
+                Message::Chat { .. } => self.note(&packet.message),
+                Message::Decline { .. } | Message::Resign(_) => {
+                    self.note(&packet.message);
+                    self.offer = None;
+                }
+                Message::Offer { side, offer } => {
+                    self.note(&packet.message);
+                    self.offer = Some((side, offer));
+                }
+                Message::Accept { side, offer } => {
+                    self.note(&packet.message);
+                    self.offer = None;
+                    if offer == Offer::Takeback {
+                        changed |= self.take_back(side.opposite(), game);
+                    }
+                }
+                Message::Error(message) => {
+                    println!("The host says: {}", message);
+                    // anything else sent with an id is a move, so one
+                    // of ours was turned down
+                    if packet.id.is_some() && packet.id == self.seat_request {
+                        continue;
+                    }
+                    if let (Some(_), Some(host_game)) = (packet.id, &self.host_game) {
+                        *game = host_game.clone();
+                        self.sent = host_game.get_moves().len();
+                        changed = true;
+                    }
+                }
+                Message::Spectators(count) => self.spectators = count,
+                Message::Result { result, reason } => {
+                    let on_board = self.host_game.as_ref().is_some_and(|host_game| {
+                        !matches!(
+                            host_game.board_state(),
+                            BoardState::Normal | BoardState::Check
+                        )
+                    });
+                    if !on_board {
+                        self.adjudication = Some(Adjudication { result, reason });
+                    }
+                }
+                Message::Resume(_) | Message::Ok => (),
+            }
+        }
+
+        let host_game = match &self.host_game {
+            Some(host_game) => host_game,
+            None => return Ok(changed),
+        };
+        let host_moves = host_game.get_moves().len();
+        if self.sent < host_moves || !follows(game, host_game) {
+            *game = host_game.clone();
+            self.sent = host_moves;
+            changed = true;
+        }
+
+        let boards = game.get_boards();
+        for (i, m) in game.get_moves().iter().enumerate().skip(self.sent) {
+            let uci = m.to_uci(boards[i].turn());
+            self.connection
+                .send(&Packet::reply(Some(self.next_id), Message::Move(uci)))?;
+            self.next_id += 1;
+        }
+        self.sent = game.get_moves().len();
+        Ok(changed)
+    }
+
+    /// Note a message from the host in the record
+    fn note(&mut self, message: &Message) {
+        if let Some(host_game) = &self.host_game {
+            self.record.note(host_game, message);
+        }
+    }
+
+    /// Take back the moves of the takeback `side` asked for, which the
+    /// host has taken back in its game, returning whether the game here
+    /// changed
+    fn take_back(&mut self, side: Color, game: &mut Game) -> bool {
+        let host_game = match &mut self.host_game {
+            Some(host_game) => host_game,
+            None => return false,
+        };
+        let plies = takeback_plies(host_game, side).unwrap_or(0);
+        let same = game.get_boards()[0] == host_game.get_boards()[0]
+            && game.get_moves() == host_game.get_moves();
+        for _ in 0..plies {
+            let _ = host_game.undo_move();
+        }
+        if same {
+            for _ in 0..plies {
+                let _ = game.undo_move();
+            }
+        } else {
+            *game = host_game.clone();
+        }
+        self.sent = host_game.get_moves().len();
+        self.record.take_back(host_game);
+        plies > 0
+    }
+
+    /// Make a move the host has made in its game
+    fn host_move(&mut self, uci: &str) -> Result<(), chess_engine::Error> {
+        if let Some(host_game) = &mut self.host_game {
+            let m = Move::from_uci(uci, host_game.current_board())?;
+            let _ = host_game.try_make_move(m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `game` is `host_game`, with or without moves made after it
+fn follows(game: &Game, host_game: &Game) -> bool {
+    game.get_boards()[0] == host_game.get_boards()[0]
+        && game.get_moves().starts_with(host_game.get_moves())
+}
+
+fn load_state(fen: &str, moves: &[String]) -> Result<Game, chess_engine::Error> {
+    let mut game = Game::from_board(Board::load_fen(fen)?);
+    for uci in moves {
+        let m = Move::from_uci(uci, game.current_board())?;
+        let _ = game.try_make_move(m)?;
+    }
+    Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::Host;
+    use chess_engine::pgn::GameResult;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A host on a free port with black played remotely, and a remote
+    /// connected to it
+    fn connect() -> (Host, Remote) {
+        let host = Host::bind("127.0.0.1:0", &[Color::Black]).unwrap();
+        let remote = Remote::connect(host.local_addr().unwrap(), "remote").unwrap();
+        (host, remote)
+    }
+
+    fn uci(game: &Game, s: &str) -> Move {
+        Move::from_uci(s, game.current_board()).unwrap()
+    }
+
+    /// Keep updating the host and the remotes until `done`, which has
+    /// to happen within a couple of seconds
+    fn run_until(
+        host: &mut Host,
+        host_game: &mut Game,
+        remotes: &mut [(&mut Remote, &mut Game)],
+        done: impl Fn(&Host, &Game, &[(&mut Remote, &mut Game)]) -> bool,
+    ) {
+        let start = Instant::now();
+        loop {
+            let _ = host.update(host_game);
+            for (remote, game) in remotes.iter_mut() {
+                let _ = remote.update(game).unwrap();
+            }
+            if done(host, host_game, remotes) {
+                return;
+            }
+            assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Keep updating the host and `remote` until the host drops the
+    /// connection
+    fn run_until_dropped(host: &mut Host, host_game: &mut Game, remote: &mut Remote) {
+        let mut game = Game::new();
+        let start = Instant::now();
+        while remote.update(&mut game).is_ok() {
+            let _ = host.update(host_game);
+            assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn plays_over_loopback() {
+        let (mut host, mut remote) = connect();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        // a move made before connecting is thrown away for the host's
+        // game
+        let _ = game.make_move(uci(&game, "d2d4")).unwrap();
+
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |host, _, remotes| {
+                host.is_seated(Color::Black)
+                    && remotes[0].0.seat().is_some()
+                    && remotes[0].1.get_moves().is_empty()
+            },
+        );
+        assert_eq!(remote.seat(), Some(Color::Black));
+
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 1,
+        );
+
+        let _ = game.make_move(uci(&game, "e7e5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, host_game, _| host_game.get_moves().len() == 2,
+        );
+        assert_eq!(host_game.current_board(), game.current_board());
+    }
+
+    #[test]
+    fn host_turns_down_moves() {
+        let (mut host, mut remote) = connect();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.seat().is_some() && remotes[0].0.host_game.is_some(),
+        );
+
+        // it's white's move, which is the host's
+        let _ = game.make_move(uci(&game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].1.get_moves().is_empty(),
+        );
+        assert!(host_game.get_moves().is_empty());
+    }
+
+    #[test]
+    fn later_clients_spectate() {
+        let (mut host, mut remote) = connect();
+        let mut spectator = Remote::connect(host.local_addr().unwrap(), "spectator").unwrap();
+        let mut host_game = Game::new();
+        let (mut game, mut spectator_game) = (Game::new(), Game::new());
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes.iter().all(|(remote, _)| remote.host_game.is_some()),
+        );
+        assert_eq!(remote.seat(), Some(Color::Black));
+        assert_eq!(spectator.seat(), None);
+        assert_eq!(host.spectators(), 1);
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes.iter().all(|(remote, _)| remote.spectators() == 1),
+        );
+
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes.iter().all(|(_, game)| game.get_moves().len() == 1),
+        );
+
+        // a spectator's move is taken back, and the player's counts
+        let _ = spectator_game
+            .make_move(uci(&spectator_game, "e7e5"))
+            .unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes[1].1.get_moves().len() == 1,
+        );
+        let _ = game.make_move(uci(&game, "c7c5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes[1].1.get_moves().len() == 2,
+        );
+        assert_eq!(spectator_game.current_board(), host_game.current_board());
+    }
+
+    #[test]
+    fn turns_away_spectators_over_the_limit() {
+        let (mut host, mut remote) = connect();
+        host.set_max_spectators(1);
+        let addr = host.local_addr().unwrap();
+        let mut spectator = Remote::connect(addr, "spectator").unwrap();
+        let mut host_game = Game::new();
+        let (mut game, mut spectator_game) = (Game::new(), Game::new());
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |host, _, _| host.spectators() == 1,
+        );
+
+        let mut late = Remote::connect(addr, "late").unwrap();
+        run_until_dropped(&mut host, &mut host_game, &mut late);
+        assert!(late.host_game.is_none());
+        assert_eq!(host.spectators(), 1);
+    }
+
+    #[test]
+    fn clients_pick_seats() {
+        let mut host = Host::bind("127.0.0.1:0", &[Color::White, Color::Black]).unwrap();
+        host.set_grace_period(Duration::ZERO);
+        let addr = host.local_addr().unwrap();
+        let mut host_game = Game::new();
+        let mut first = Remote::connect(addr, "first").unwrap();
+        let mut first_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut first, &mut first_game)],
+            |_, _, remotes| remotes[0].0.seat().is_some(),
+        );
+        assert_eq!(first.seat(), Some(Color::White));
+
+        first.request_seat(Some(Color::Black)).unwrap();
+        let mut second = Remote::connect(addr, "second").unwrap();
+        let mut second_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut first, &mut first_game),
+                (&mut second, &mut second_game),
+            ],
+            |_, _, remotes| {
+                remotes[0].0.seat() == Some(Color::Black) && remotes[1].0.seat().is_some()
+            },
+        );
+        assert_eq!(second.seat(), Some(Color::White));
+
+        // black is taken, so nothing changes until asking to spectate
+        second.request_seat(Some(Color::Black)).unwrap();
+        second.request_seat(None).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut first, &mut first_game),
+                (&mut second, &mut second_game),
+            ],
+            |_, _, remotes| remotes[1].0.seat().is_none(),
+        );
+        assert_eq!(first.seat(), Some(Color::Black));
+        assert_eq!(host.spectators(), 1);
+
+        // a seat is free again once its player has been gone for the grace
+        // period
+        drop(first);
+        run_until(&mut host, &mut host_game, &mut [], |host, _, _| {
+            !host.is_seated(Color::Black)
+        });
+        let mut third = Remote::connect(addr, "third").unwrap();
+        let mut third_game = Game::new();
+        third.request_seat(Some(Color::Black)).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut third, &mut third_game)],
+            |_, _, remotes| remotes[0].0.seat() == Some(Color::Black),
+        );
+
+        // and a side the host takes back is spectated
+        host.set_seats(&[Color::White]);
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut third, &mut third_game)],
+            |_, _, remotes| remotes[0].0.seat().is_none(),
+        );
+        assert!(!host.is_seated(Color::Black));
+    }
+
+    #[test]
+    fn players_resume_sessions() {
+        let (mut host, mut remote) = connect();
+        let addr = host.local_addr().unwrap();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.session().is_some() && remotes[0].0.seat().is_some(),
+        );
+        let session = remote.session().unwrap().to_string();
+        drop(remote);
+
+        // the side is kept, and the moves made meanwhile are caught up
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        let mut stranger = Remote::connect(addr, "stranger").unwrap();
+        let mut stranger_game = Game::new();
+        stranger.request_seat(Some(Color::Black)).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut stranger, &mut stranger_game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 1,
+        );
+        assert_eq!(stranger.seat(), None);
+
+        let mut remote = Remote::resume(addr, "remote", &session).unwrap();
+        let mut game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| {
+                remotes[0].0.seat() == Some(Color::Black) && remotes[0].1.get_moves().len() == 1
+            },
+        );
+        assert_eq!(remote.session(), Some(session.as_str()));
+
+        // resuming again takes the side from the old connection
+        let mut again = Remote::resume(addr, "remote", &session).unwrap();
+        let mut again_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut again, &mut again_game)],
+            |_, _, remotes| remotes[0].0.seat() == Some(Color::Black),
+        );
+        run_until_dropped(&mut host, &mut host_game, &mut remote);
+        assert!(host.is_seated(Color::Black));
+    }
+
+    /// Send `remote`'s host a request to resume `session` over the
+    /// connection it already has
+    fn resume_here(remote: &mut Remote, session: &str) {
+        remote
+            .connection
+            .send(&Packet::new(Message::Resume(session.to_string())))
+            .unwrap();
+    }
+
+    #[test]
+    fn resuming_the_current_session_keeps_it() {
+        let (mut host, mut remote) = connect();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.session().is_some() && remotes[0].0.seat().is_some(),
+        );
+        let session = remote.session().unwrap().to_string();
+
+        resume_here(&mut remote, &session);
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 1,
+        );
+        let _ = game.make_move(uci(&game, "e7e5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, host_game, _| host_game.get_moves().len() == 2,
+        );
+        assert_eq!(remote.seat(), Some(Color::Black));
+        assert_eq!(remote.session(), Some(session.as_str()));
+    }
+
+    #[test]
+    fn players_cant_resume_another_session() {
+        let mut host = Host::bind("127.0.0.1:0", &[Color::White, Color::Black]).unwrap();
+        let addr = host.local_addr().unwrap();
+        let mut host_game = Game::new();
+        let mut white = Remote::connect(addr, "white").unwrap();
+        let mut white_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game)],
+            |_, _, remotes| remotes[0].0.session().is_some() && remotes[0].0.seat().is_some(),
+        );
+        let mut black = Remote::connect(addr, "black").unwrap();
+        let mut black_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game), (&mut black, &mut black_game)],
+            |_, _, remotes| remotes[1].0.seat().is_some(),
+        );
+        let session = white.session().unwrap().to_string();
+        drop(white);
+
+        // the chat comes back once the request before it is handled
+        resume_here(&mut black, &session);
+        black.chat("did it work?").unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut black, &mut black_game)],
+            |_, _, remotes| !remotes[0].0.record().notes().is_empty(),
+        );
+        assert_eq!(black.seat(), Some(Color::Black));
+        assert!(host.is_seated(Color::White));
+
+        // and white's player can still come back
+        let mut white = Remote::resume(addr, "white", &session).unwrap();
+        let mut white_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game)],
+            |_, _, remotes| remotes[0].0.seat() == Some(Color::White),
+        );
+        assert_eq!(black.seat(), Some(Color::Black));
+    }
+
+    #[test]
+    fn sessions_are_random() {
+        let mut tokens = vec![];
+        for _ in 0..2 {
+            let (mut host, mut first) = connect();
+            let mut second = Remote::connect(host.local_addr().unwrap(), "second").unwrap();
+            let mut host_game = Game::new();
+            let (mut first_game, mut second_game) = (Game::new(), Game::new());
+            run_until(
+                &mut host,
+                &mut host_game,
+                &mut [
+                    (&mut first, &mut first_game),
+                    (&mut second, &mut second_game),
+                ],
+                |_, _, remotes| remotes.iter().all(|(remote, _)| remote.session().is_some()),
+            );
+            tokens.push(first.session().unwrap().to_string());
+            tokens.push(second.session().unwrap().to_string());
+        }
+
+        for (i, token) in tokens.iter().enumerate() {
+            assert_eq!(token.len(), 32, "{}", token);
+            assert!(token.chars().all(|c| c.is_ascii_hexdigit()), "{}", token);
+            assert!(!tokens[..i].contains(token), "{} came up twice", token);
+        }
+    }
+
+    #[test]
+    fn players_who_dont_come_back_lose() {
+        let (mut host, mut remote) = connect();
+        host.set_grace_period(Duration::from_millis(50));
+        let mut spectator = Remote::connect(host.local_addr().unwrap(), "spectator").unwrap();
+        let mut host_game = Game::new();
+        let (mut game, mut spectator_game) = (Game::new(), Game::new());
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |host, _, _| host.spectators() == 1,
+        );
+        drop(remote);
+
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut spectator, &mut spectator_game)],
+            |_, _, remotes| remotes[0].0.adjudication().is_some(),
+        );
+        let adjudication = host.adjudication().unwrap();
+        assert_eq!(adjudication.result, GameResult::WhiteWins);
+        assert_eq!(adjudication.reason, "abandoned");
+        assert_eq!(spectator.adjudication(), host.adjudication());
+        assert!(!host.is_seated(Color::Black));
+
+        // a new game forgets it
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut spectator, &mut spectator_game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 1,
+        );
+        assert!(host.adjudication().is_some());
+        host_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut spectator, &mut spectator_game)],
+            |host, _, remotes| {
+                host.adjudication().is_none() && remotes[0].0.adjudication().is_none()
+            },
+        );
+    }
+
+    #[test]
+    fn players_chat_and_agree_to_draws() {
+        let (mut host, mut remote) = connect();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.seat().is_some() && remotes[0].0.host_game.is_some(),
+        );
+
+        host.chat("host", "good luck", &host_game);
+        remote.chat("you too").unwrap();
+        // there's nothing to accept yet
+        remote.accept(Offer::Draw).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |host, _, remotes| {
+                remotes[0].0.record().notes().len() == 2 && host.record() == remotes[0].0.record()
+            },
+        );
+        assert_eq!(
+            remote.record().notes()[1],
+            (0, "remote: you too".to_string())
+        );
+        assert!(host.adjudication().is_none());
+
+        remote.make_offer(Offer::Draw).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |host, _, _| host.pending_offer().is_some(),
+        );
+        assert_eq!(host.pending_offer(), Some((Color::Black, Offer::Draw)));
+        host.decline(Color::White, Offer::Draw, &host_game).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.record().notes().len() == 4,
+        );
+        assert_eq!(remote.pending_offer(), None);
+
+        host.make_offer(Color::White, Offer::Draw, &host_game)
+            .unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.pending_offer().is_some(),
+        );
+        remote.accept(Offer::Draw).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.adjudication().is_some(),
+        );
+        let adjudication = host.adjudication().unwrap();
+        assert_eq!(adjudication.result, GameResult::Draw);
+        assert_eq!(adjudication.reason, "agreement");
+        assert_eq!(remote.adjudication(), host.adjudication());
+        assert_eq!(host.record(), remote.record());
+        assert!(host
+            .make_offer(Color::White, Offer::Draw, &host_game)
+            .is_err());
+    }
+
+    #[test]
+    fn takebacks_undo_moves_on_both_ends() {
+        let (mut host, mut remote) = connect();
+        let mut host_game = Game::new();
+        let mut game = Game::new();
+        let _ = host_game.make_move(uci(&host_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].0.seat().is_some() && remotes[0].1.get_moves().len() == 1,
+        );
+        let _ = game.make_move(uci(&game, "e7e5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, host_game, _| host_game.get_moves().len() == 2,
+        );
+
+        remote.make_offer(Offer::Takeback).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |host, _, _| host.pending_offer().is_some(),
+        );
+        host.accept(Color::White, Offer::Takeback, &mut host_game)
+            .unwrap();
+        assert_eq!(host_game.get_moves().len(), 1);
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 1,
+        );
+        assert_eq!(
+            remote.record().notes().last().unwrap().1,
+            "White accepts the takeback of e5"
+        );
+        assert_eq!(host.record(), remote.record());
+
+        // and the game goes on from there
+        let _ = game.make_move(uci(&game, "c7c5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut remote, &mut game)],
+            |_, host_game, _| host_game.get_moves().len() == 2,
+        );
+        assert_eq!(host_game.current_board(), game.current_board());
+    }
+
+    #[test]
+    fn players_resign() {
+        let (mut host, mut remote) = connect();
+        let mut spectator = Remote::connect(host.local_addr().unwrap(), "spectator").unwrap();
+        let mut host_game = Game::new();
+        let (mut game, mut spectator_game) = (Game::new(), Game::new());
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| remotes.iter().all(|(remote, _)| remote.host_game.is_some()),
+        );
+        assert_eq!(
+            spectator.resign().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        remote.resign().unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [
+                (&mut remote, &mut game),
+                (&mut spectator, &mut spectator_game),
+            ],
+            |_, _, remotes| {
+                remotes
+                    .iter()
+                    .all(|(remote, _)| remote.adjudication().is_some())
+            },
+        );
+        let adjudication = host.adjudication().unwrap();
+        assert_eq!(adjudication.result, GameResult::WhiteWins);
+        assert_eq!(adjudication.reason, "resignation");
+        assert!(host.resign(Color::White, &host_game).is_err());
+
+        let pgn = spectator
+            .record()
+            .to_pgn(&spectator_game, spectator.adjudication());
+        assert!(pgn.contains("[Termination \"normal\"]"));
+        assert!(pgn.ends_with("\n{Black resigns} {resignation} 1-0\n"));
+    }
+
+    #[test]
+    fn two_remotes_play_each_other() {
+        let mut host = Host::bind("127.0.0.1:0", &[Color::White, Color::Black]).unwrap();
+        let mut white = Remote::connect(host.local_addr().unwrap(), "white").unwrap();
+        let mut host_game = Game::new();
+        let mut white_game = Game::new();
+        // say hello in turn, so that the seats are handed out in order
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game)],
+            |_, _, remotes| remotes[0].0.seat().is_some(),
+        );
+        let mut black = Remote::connect(host.local_addr().unwrap(), "black").unwrap();
+        let mut black_game = Game::new();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game), (&mut black, &mut black_game)],
+            |_, _, remotes| remotes[1].0.seat().is_some(),
+        );
+        assert_eq!(white.seat(), Some(Color::White));
+        assert_eq!(black.seat(), Some(Color::Black));
+
+        let _ = white_game.make_move(uci(&white_game, "e2e4")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game), (&mut black, &mut black_game)],
+            |_, _, remotes| remotes[1].1.get_moves().len() == 1,
+        );
+        let _ = black_game.make_move(uci(&black_game, "e7e5")).unwrap();
+        run_until(
+            &mut host,
+            &mut host_game,
+            &mut [(&mut white, &mut white_game), (&mut black, &mut black_game)],
+            |_, _, remotes| remotes[0].1.get_moves().len() == 2,
+        );
+        assert_eq!(white_game.current_board(), host_game.current_board());
+        assert_eq!(black_game.current_board(), host_game.current_board());
+    }
+}
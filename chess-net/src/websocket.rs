@@ -0,0 +1,445 @@
+//! Just enough of WebSocket ([RFC 6455]) for a host to serve browsers:
+//! the opening handshake and the framing, with no extensions. The
+//! packets are sent as [`crate::json`] in text frames, one each.
+//!
+//! [RFC 6455]: https://www.rfc-editor.org/rfc/rfc6455
+use crate::connection::NetworkError;
+
+/// Added to the client's key to make the accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// What a frame has in it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// The rest of a message started by an earlier frame
+    Continuation,
+    /// UTF-8 text
+    Text,
+    /// Bytes, which the protocol doesn't use
+    Binary,
+    /// The other end is closing the connection
+    Close,
+    /// Asks for a pong with the same payload
+    Ping,
+    /// The answer to a ping
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(opcode: u8) -> Option<Self> {
+        Some(match opcode {
+            0x0 => Self::Continuation,
+            0x1 => Self::Text,
+            0x2 => Self::Binary,
+            0x8 => Self::Close,
+            0x9 => Self::Ping,
+            0xa => Self::Pong,
+            _ => return None,
+        })
+    }
+
+    /// Whether the frame is about the connection rather than a message,
+    /// which can be sent between the frames of one
+    fn is_control(self) -> bool {
+        self.to_u8() & 0x8 != 0
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Continuation => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xa,
+        }
+    }
+}
+
+/// A frame from a client, unmasked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Whether this is the last frame of the message
+    pub fin: bool,
+    /// What the frame has in it
+    pub opcode: Opcode,
+    /// What's in it
+    pub payload: Vec<u8>,
+}
+
+/// Find the end of the client's opening handshake, the blank line
+/// after its headers
+pub fn handshake_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4)
+}
+
+/// The server's answer to a client's opening handshake
+///
+/// # Errors
+///
+/// Will return an error if the request isn't a WebSocket handshake
+/// this understands.
+pub fn handshake_response(request: &[u8]) -> Result<String, NetworkError> {
+    let request = std::str::from_utf8(request)?;
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    if !request_line.starts_with("GET ") {
+        return Err(NetworkError(
+            "a WebSocket handshake starts with a GET".to_string(),
+        ));
+    }
+
+    let mut key = None;
+    let mut upgrade = false;
+    let mut version = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+                "sec-websocket-key" => key = Some(value),
+                "sec-websocket-version" => version = Some(value),
+                _ => (),
+            }
+        }
+    }
+    if !upgrade {
+        return Err(NetworkError(
+            "only WebSocket connections are served here".to_string(),
+        ));
+    }
+    if version != Some("13") {
+        return Err(NetworkError(
+            "only version 13 of WebSocket is spoken".to_string(),
+        ));
+    }
+    let key = key.ok_or_else(|| NetworkError("the handshake has no key".to_string()))?;
+    Ok(format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    ))
+}
+
+/// The answer to a handshake that isn't understood
+pub fn bad_request(err: &NetworkError) -> String {
+    format!(
+        "HTTP/1.1 400 Bad Request\r\n\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        err.0.len(),
+        err.0
+    )
+}
+
+/// The key proving to the client that the server speaks WebSocket
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Read the first frame in `buffer`, returning it and its length, or
+/// `None` if it hasn't all arrived
+///
+/// # Errors
+///
+/// Will return an error if the frame breaks the rules of WebSocket, or
+/// has more than `max_len` bytes in it, after which the connection
+/// can't be used anymore.
+pub fn read_frame(buffer: &[u8], max_len: usize) -> Result<Option<(Frame, usize)>, NetworkError> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    if buffer[0] & 0x70 != 0 {
+        return Err(NetworkError("no extensions were agreed on".to_string()));
+    }
+    let opcode = Opcode::from_u8(buffer[0] & 0x0f)
+        .ok_or_else(|| NetworkError(format!("unknown opcode {}", buffer[0] & 0x0f)))?;
+    if buffer[1] & 0x80 == 0 {
+        return Err(NetworkError(
+            "frames from clients have to be masked".to_string(),
+        ));
+    }
+
+    let (len, mut at) = match buffer[1] & 0x7f {
+        126 if buffer.len() >= 4 => (u64::from(u16::from_be_bytes([buffer[2], buffer[3]])), 4),
+        127 if buffer.len() >= 10 => {
+            let mut len = [0; 8];
+            len.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (u64::from(len), 2),
+    };
+    if opcode.is_control() && (!fin || len > 125) {
+        return Err(NetworkError(
+            "control frames can't be split or longer than 125 bytes".to_string(),
+        ));
+    }
+    if len > max_len as u64 {
+        return Err(NetworkError(format!(
+            "frames can't be longer than {} bytes",
+            max_len
+        )));
+    }
+    if buffer.len() < at + 4 {
+        return Ok(None);
+    }
+    let mut mask = [0; 4];
+    mask.copy_from_slice(&buffer[at..at + 4]);
+    at += 4;
+
+    // no bigger than `max_len`, so it fits
+    let end = match at.checked_add(len as usize) {
+        Some(end) if end <= buffer.len() => end,
+        _ => return Ok(None),
+    };
+    let payload = buffer[at..end]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        end,
+    )))
+}
+
+/// A whole, unmasked frame from the server
+pub fn write_frame(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode.to_u8()];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= usize::from(u16::MAX) => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The SHA-1 hash of `data`, which the handshake needs even though it's
+/// no good for anything else anymore
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0_u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut hash = [0; 20];
+    for (bytes, h) in hash.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    hash
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// A frame as a client would send it, masked
+    pub(crate) fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = write_frame(Opcode::Text, payload);
+        frame[0] = first;
+        frame[1] |= 0x80;
+        let at = frame.len() - payload.len();
+        let mut masked = frame[..at].to_vec();
+        masked.extend_from_slice(&mask);
+        masked.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        masked
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1000])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn answers_handshakes() {
+        // the example in the RFC
+        let request = "GET /chat HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Origin: http://example.com\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let mut stream = request.as_bytes().to_vec();
+        stream.extend_from_slice(b"\x81");
+        assert_eq!(handshake_end(&stream), Some(request.len()));
+        assert_eq!(handshake_end(&stream[..request.len() - 1]), None);
+
+        let response = handshake_response(request.as_bytes()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(
+            response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+            "{:?}",
+            response
+        );
+
+        assert!(handshake_response(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(handshake_response(
+            b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .is_err());
+        assert!(handshake_response(b"hello:1|a;").is_err());
+    }
+
+    #[test]
+    fn reads_frames() {
+        let frame = client_frame(0x81, b"Hello");
+        assert_eq!(
+            read_frame(&frame, usize::MAX).unwrap(),
+            Some((
+                Frame {
+                    fin: true,
+                    opcode: Opcode::Text,
+                    payload: b"Hello".to_vec(),
+                },
+                frame.len()
+            ))
+        );
+        for len in 0..frame.len() {
+            assert_eq!(read_frame(&frame[..len], usize::MAX).unwrap(), None);
+        }
+
+        for len in [125, 126, 65535, 65536] {
+            let payload = vec![b'x'; len];
+            let frame = client_frame(0x02, &payload);
+            let (read, end) = read_frame(&frame, usize::MAX).unwrap().unwrap();
+            assert_eq!((read.fin, read.opcode), (false, Opcode::Binary));
+            assert_eq!((read.payload, end), (payload, frame.len()));
+        }
+
+        let unmasked = write_frame(Opcode::Text, b"Hello");
+        assert!(read_frame(&unmasked, usize::MAX).is_err());
+        assert!(read_frame(&client_frame(0xc1, b""), usize::MAX).is_err());
+        assert!(read_frame(&client_frame(0x83, b""), usize::MAX).is_err());
+        // control frames have to be whole and short
+        assert!(read_frame(&client_frame(0x89, &[0; 125]), usize::MAX)
+            .unwrap()
+            .is_some());
+        assert!(read_frame(&client_frame(0x89, &[0; 126]), usize::MAX).is_err());
+        assert!(read_frame(&client_frame(0x09, b"ping"), usize::MAX).is_err());
+        assert!(read_frame(&client_frame(0x08, b""), usize::MAX).is_err());
+        // a frame that's too long is turned down before it's all arrived
+        let frame = client_frame(0x81, &[b'x'; 200]);
+        assert!(read_frame(&frame[..4], 199).is_err());
+        assert!(read_frame(&frame, 200).unwrap().is_some());
+    }
+
+    #[test]
+    fn writes_frames() {
+        assert_eq!(
+            write_frame(Opcode::Text, b"Hello"),
+            b"\x81\x05Hello".to_vec()
+        );
+        assert_eq!(write_frame(Opcode::Pong, b""), vec![0x8a, 0]);
+        assert_eq!(
+            write_frame(Opcode::Binary, &[0; 256])[..4],
+            [0x82, 126, 1, 0]
+        );
+        assert_eq!(
+            write_frame(Opcode::Binary, &[0; 65536])[..10],
+            [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+}
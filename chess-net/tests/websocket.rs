@@ -0,0 +1,204 @@
+//! Browsers playing through a host's WebSocket listener, with a
+//! WebSocket client from outside the crate
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use chess_engine::{Color, Game, Move};
+use chess_net::codec::{Message, Packet, PROTOCOL_VERSION};
+use chess_net::host::Host;
+use chess_net::json;
+use chess_net::remote::Remote;
+use tungstenite::{Message as Frame, WebSocket};
+
+type Client = WebSocket<TcpStream>;
+
+/// A host updated on a thread of its own until it's stopped
+struct Server {
+    addr: SocketAddr,
+    websocket_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Game>,
+}
+
+impl Server {
+    fn start(seats: &[Color]) -> Self {
+        let mut host = Host::bind("127.0.0.1:0", seats).unwrap();
+        host.listen_websocket("127.0.0.1:0").unwrap();
+        let addr = host.local_addr().unwrap();
+        let websocket_addr = host.websocket_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut game = Game::new();
+            while !stopped.load(Ordering::Relaxed) {
+                let _ = host.update(&mut game);
+                thread::sleep(Duration::from_millis(1));
+            }
+            game
+        });
+        Self {
+            addr,
+            websocket_addr,
+            stop,
+            thread,
+        }
+    }
+
+    /// Stop the host, returning its game
+    fn stop(self) -> Game {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap()
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(self.websocket_addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let url = format!("ws://{}/", self.websocket_addr);
+        tungstenite::client(url, stream).unwrap().0
+    }
+}
+
+fn send(client: &mut Client, json: &str) {
+    client.send(Frame::Text(json.to_string())).unwrap();
+}
+
+/// Read packets until one that `wanted` picks out, failing if it
+/// doesn't come within a couple of seconds
+fn receive_until(client: &mut Client, wanted: impl Fn(&Packet) -> bool) -> Packet {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(2) {
+        if let Frame::Text(text) = client.read().unwrap() {
+            let packet = json::from_json(&text).unwrap();
+            if wanted(&packet) {
+                return packet;
+            }
+        }
+    }
+    panic!("timed out");
+}
+
+fn hello(client: &mut Client, name: &str) -> Option<Color> {
+    send(
+        client,
+        &format!(
+            r#"{{"kind":"hello","version":{},"name":"{}"}}"#,
+            PROTOCOL_VERSION, name
+        ),
+    );
+    let seat = receive_until(client, |p| matches!(p.message, Message::Seat(_)));
+    let _ = receive_until(client, |p| matches!(p.message, Message::State { .. }));
+    match seat.message {
+        Message::Seat(seat) => seat,
+        _ => unreachable!(),
+    }
+}
+
+fn uci(game: &Game, s: &str) -> Move {
+    Move::from_uci(s, game.current_board()).unwrap()
+}
+
+#[test]
+fn plays_in_a_browser() {
+    let server = Server::start(&[Color::White]);
+    let mut client = server.connect();
+    assert_eq!(hello(&mut client, "browser"), Some(Color::White));
+
+    send(&mut client, r#"{"id":1,"kind":"move","move":"e7e5"}"#);
+    let reply = receive_until(&mut client, |p| p.id == Some(1));
+    assert!(matches!(reply.message, Message::Error(_)), "{:?}", reply);
+
+    send(&mut client, r#"{"id":2,"kind":"move","move":"e2e4"}"#);
+    let reply = receive_until(&mut client, |p| p.id == Some(2));
+    assert_eq!(reply.message, Message::Ok);
+    client.close(None).unwrap();
+
+    let game = server.stop();
+    assert_eq!(game.get_moves().len(), 1);
+}
+
+#[test]
+fn shares_a_game_with_tcp_clients() {
+    let server = Server::start(&[Color::White, Color::Black]);
+    let mut client = server.connect();
+    assert_eq!(hello(&mut client, "browser"), Some(Color::White));
+
+    let mut remote = Remote::connect(server.addr, "remote").unwrap();
+    let mut game = Game::new();
+    let start = Instant::now();
+    while remote.seat().is_none() {
+        let _ = remote.update(&mut game).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(remote.seat(), Some(Color::Black));
+
+    send(&mut client, r#"{"kind":"move","move":"e2e4"}"#);
+    while game.get_moves().is_empty() {
+        let _ = remote.update(&mut game).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let _ = game.make_move(uci(&game, "e7e5")).unwrap();
+    let _ = remote.update(&mut game).unwrap();
+
+    let black_move = receive_until(&mut client, |p| {
+        p.message == Message::Move("e7e5".to_string())
+    });
+    assert_eq!(black_move.id, None);
+
+    let host_game = server.stop();
+    assert_eq!(host_game.current_board(), game.current_board());
+}
+
+#[test]
+fn answers_bad_frames() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+
+    send(&mut client, "not json");
+    let reply = receive_until(&mut client, |_| true);
+    assert!(matches!(reply.message, Message::Error(_)), "{:?}", reply);
+    send(&mut client, r#"{"kind":"move","move":"e2e4"}"#);
+    let reply = receive_until(&mut client, |_| true);
+    assert_eq!(reply.message, Message::Error("say hello first".to_string()));
+
+    client.send(Frame::Ping(b"ping".to_vec())).unwrap();
+    let start = Instant::now();
+    loop {
+        if client.read().unwrap() == Frame::Pong(b"ping".to_vec()) {
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(2), "timed out");
+    }
+
+    // the close is answered, and the connection dropped after that
+    client.close(None).unwrap();
+    loop {
+        match client.read() {
+            Ok(_) => (),
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    let _ = server.stop();
+}
+
+#[test]
+fn turns_down_other_requests() {
+    let server = Server::start(&[]);
+    let mut stream = TcpStream::connect(server.websocket_addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+    let _ = server.stop();
+}
@@ -0,0 +1,106 @@
+//! Build a Polyglot opening book from PGN files, and optionally a
+//! human readable dump of it to look through like an opening explorer.
+//!
+//! ```text
+//! build-book [--plies N] [--min-games N] [--explorer FILE] BOOK.bin GAMES.pgn...
+//! ```
+use chess_engine::book::BookBuilder;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+
+const USAGE: &str =
+    "usage: build-book [--plies N] [--min-games N] [--explorer FILE] BOOK.bin GAMES.pgn...";
+
+/// The number of plies used of every game if it's not given
+const DEFAULT_PLIES: usize = 16;
+
+struct Options {
+    plies: usize,
+    min_games: u32,
+    explorer: Option<String>,
+    book: String,
+    pgn_files: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut plies = DEFAULT_PLIES;
+    let mut min_games = 1;
+    let mut explorer = None;
+    let mut files = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--plies" => {
+                plies = value()?
+                    .parse()
+                    .map_err(|_| "--plies needs a number".to_string())?;
+            }
+            "--min-games" => {
+                min_games = value()?
+                    .parse()
+                    .map_err(|_| "--min-games needs a number".to_string())?;
+            }
+            "--explorer" => explorer = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let book = files.remove(0);
+    Ok(Options {
+        plies,
+        min_games,
+        explorer,
+        book,
+        pgn_files: files,
+    })
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut builder = BookBuilder::new(options.plies);
+    builder.set_min_games(options.min_games);
+
+    for path in &options.pgn_files {
+        let pgn = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+        let games = chess_engine::pgn::read_games(&pgn).map_err(|e| format!("{}: {}", path, e))?;
+        for (i, game) in games.iter().enumerate() {
+            // one broken game shouldn't spoil a whole collection
+            if let Err(e) = builder.add_game(game) {
+                eprintln!("{}: skipping the rest of game {}: {}", path, i + 1, e);
+            }
+        }
+    }
+
+    let book = builder.build();
+    book.save(&options.book)
+        .map_err(|e| format!("can't write {}: {}", options.book, e))?;
+    println!(
+        "wrote {} entries from {} games to {}",
+        book.len(),
+        builder.games(),
+        options.book
+    );
+
+    if let Some(path) = &options.explorer {
+        let write = || -> std::io::Result<()> {
+            let mut out = BufWriter::new(File::create(path)?);
+            builder.write_explorer(&mut out)?;
+            out.flush()
+        };
+        write().map_err(|e| format!("can't write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args(std::env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
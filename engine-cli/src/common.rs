@@ -0,0 +1,47 @@
+//! Things shared between the protocols
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A protocol for talking to a GUI, handling one line of input at a time
+pub(crate) trait Protocol {
+    /// Handle one line of input, returns `false` when the engine
+    /// should quit
+    fn handle_command(&mut self, line: &str) -> bool;
+
+    /// Wait for a running search to finish, stopping it if it would
+    /// otherwise run forever
+    fn finish(&mut self);
+}
+
+/// Write a line of output and flush it right away, as the GUI is
+/// waiting for it
+pub(crate) fn send<W: Write>(out: &Mutex<W>, line: &str) {
+    let mut out = out.lock().expect("A thread panicked while writing output");
+    // there is nobody to tell if stdout is gone, so errors are ignored
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// How long to think when there's `time` left on the clock
+pub(crate) fn time_budget(time: Duration, inc: Duration, movestogo: Option<u32>) -> Duration {
+    let moves = movestogo.unwrap_or(30).max(1);
+    let budget = time / moves + inc * 3 / 4;
+
+    // keep a little margin for the overhead of talking to the GUI
+    budget
+        .min(time.saturating_sub(Duration::from_millis(50)))
+        .max(Duration::from_millis(10))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_uses_the_whole_clock() {
+        let budget = time_budget(Duration::from_millis(100), Duration::from_secs(1), None);
+
+        assert_eq!(budget, Duration::from_millis(50));
+    }
+}
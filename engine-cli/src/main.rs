@@ -0,0 +1,50 @@
+//! A command line front end for the chess engine, speaking either UCI
+//! or the XBoard protocol over stdin and stdout so that it can be used
+//! from chess GUIs and tournament managers such as cutechess-cli. The
+//! protocol is picked from the first command, which is `xboard` for
+//! XBoard and usually `uci` for UCI.
+use common::Protocol;
+use std::io::{self, BufRead};
+
+mod common;
+mod uci;
+mod xboard;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    let first = loop {
+        match lines.next() {
+            Some(line) if line.as_ref().is_ok_and(|l| l.trim().is_empty()) => continue,
+            Some(line) => break line?,
+            None => return Ok(()),
+        }
+    };
+
+    if first.trim() == "xboard" {
+        run(xboard::Xboard::new(io::stdout()), &first, lines)
+    } else {
+        run(uci::Uci::new(io::stdout()), &first, lines)
+    }
+}
+
+fn run(
+    mut protocol: impl Protocol,
+    first: &str,
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> io::Result<()> {
+    if !protocol.handle_command(first) {
+        return Ok(());
+    }
+    for line in lines {
+        if !protocol.handle_command(&line?) {
+            return Ok(());
+        }
+    }
+
+    // stdin was closed without a `quit`, let a running search finish
+    // so that scripted input still gets its answer
+    protocol.finish();
+    Ok(())
+}
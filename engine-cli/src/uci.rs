@@ -0,0 +1,469 @@
+//! The Universal Chess Interface, see
+//! <https://backscattering.de/chess/uci/> for the specification. Every
+//! `go` starts a search on a separate thread so that `stop` and
+//! `isready` can be answered while it is running.
+use crate::common::{send, time_budget, Protocol};
+use chess_engine::book::{Book, BookSelection};
+use chess_engine::search::{Search, SearchLimits, SearchResult, Skill, StopFlag, DEFAULT_HASH_MB};
+use chess_engine::tablebase::Tablebase;
+use chess_engine::{Board, Color, Error, Game, Move};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+const MAX_HASH_MB: usize = 4096;
+const MAX_THREADS: usize = 256;
+
+/// The state of the engine between commands
+pub(crate) struct Uci<W> {
+    game: Game,
+    search: Arc<Mutex<Search>>,
+    out: Arc<Mutex<W>>,
+    running: Option<RunningSearch>,
+    book: BookOptions,
+}
+
+/// The opening book and the options for using it
+#[derive(Default)]
+struct BookOptions {
+    enabled: bool,
+    best_only: bool,
+    book: Option<Book>,
+}
+
+impl BookOptions {
+    fn load(&mut self, path: &str) -> Result<(), String> {
+        self.book = None;
+        if path.is_empty() || path == "<empty>" {
+            return Ok(());
+        }
+        let book = Book::open(path).map_err(|e| format!("can't load book `{}`: {}", path, e))?;
+        self.book = Some(book);
+        Ok(())
+    }
+}
+
+struct RunningSearch {
+    stop: StopFlag,
+    handle: JoinHandle<()>,
+    infinite: bool,
+}
+
+impl<W: Write + Send + 'static> Uci<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Self {
+            game: Game::new(),
+            search: Arc::new(Mutex::new(Search::new(DEFAULT_HASH_MB))),
+            out: Arc::new(Mutex::new(out)),
+            running: None,
+            book: BookOptions::default(),
+        }
+    }
+
+    fn lock_search(&self) -> std::sync::MutexGuard<'_, Search> {
+        self.search.lock().expect("A search thread panicked")
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.stop.stop();
+            let _ = running.handle.join();
+        }
+    }
+
+    fn position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_index = args.iter().position(|&arg| arg == "moves");
+        let (setup, moves) = match moves_index {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &[][..]),
+        };
+
+        let board = match setup.split_first() {
+            Some((&"startpos", [])) => Board::default_board(),
+            Some((&"fen", fen)) => Board::load_fen(&fen.join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err(format!("invalid position `{}`", args.join(" "))),
+        };
+
+        self.game = Game::from_board(board);
+        for m in moves {
+            let parsed = Move::from_uci(m, self.game.current_board()).map_err(|e| e.to_string())?;
+            if let Err(e) = self.game.try_make_move(parsed) {
+                return Err(match e {
+                    Error::IllegalMove(_, _, reason) => format!("illegal move `{}`: {}", m, reason),
+                    e => format!("illegal move `{}`: {}", m, e),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn go(&mut self, options: &GoOptions) {
+        self.stop_search();
+
+        let turn = self.game.next_player();
+        // there's no point in analysing a position with a book
+        if !options.infinite {
+            if let Some(m) = self.book_move() {
+                send(&self.out, &format!("bestmove {}", m.to_uci(turn)));
+                return;
+            }
+        }
+
+        let boards = self.game.get_boards().to_vec();
+        let limits = options.limits(turn);
+        let infinite = options.infinite;
+        let stop = StopFlag::new();
+
+        let search = Arc::clone(&self.search);
+        let out = Arc::clone(&self.out);
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let search = search.lock().expect("A search thread panicked");
+            let result = search.run_with_info(&boards, &limits, &thread_stop, |info| {
+                send(&out, &info_line(info, turn, start.elapsed()));
+            });
+
+            // during `go infinite` we may not send `bestmove` until
+            // we've been told to stop, even if the search is done
+            while infinite && !thread_stop.is_stopped() {
+                thread::sleep(Duration::from_millis(5));
+            }
+
+            let best_move = result
+                .best_move
+                .map_or_else(|| "0000".to_string(), |m| m.to_uci(turn));
+            send(&out, &format!("bestmove {}", best_move));
+        });
+
+        self.running = Some(RunningSearch {
+            stop,
+            handle,
+            infinite,
+        });
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        let (name, value) = match parse_setoption(args) {
+            Some(option) => option,
+            None => return,
+        };
+
+        // the options may only be changed while no search is running
+        self.stop_search();
+        let result = match name.to_ascii_lowercase().as_str() {
+            "hash" => parse_spin(&value).map(|value| {
+                self.lock_search()
+                    .set_hash_size(value.clamp(1, MAX_HASH_MB));
+            }),
+            "threads" => parse_spin(&value).map(|value| {
+                self.lock_search().set_threads(value.clamp(1, MAX_THREADS));
+            }),
+            "skill level" => parse_spin(&value).map(|value| {
+                self.lock_search()
+                    .set_skill(Skill::new(value.min(Skill::MAX_LEVEL.into()) as u8));
+            }),
+            "ownbook" => parse_check(&value).map(|value| self.book.enabled = value),
+            "best book move" => parse_check(&value).map(|value| self.book.best_only = value),
+            "book file" => self.book.load(&value),
+            "syzygypath" => self.load_tablebase(&value),
+            _ => Err(format!("unknown option `{}`", name)),
+        };
+
+        if let Err(message) = result {
+            send(&self.out, &format!("info string {}", message));
+        }
+    }
+
+    /// Use the Syzygy tables in `path`, or none for `<empty>`
+    fn load_tablebase(&mut self, path: &str) -> Result<(), String> {
+        let tablebase = if path.is_empty() || path == "<empty>" {
+            None
+        } else {
+            let tablebase = Tablebase::open(path)
+                .map_err(|e| format!("can't load tablebases from `{}`: {}", path, e))?;
+            send(
+                &self.out,
+                &format!(
+                    "info string found tablebases for up to {} pieces",
+                    tablebase.max_pieces()
+                ),
+            );
+            Some(Arc::new(tablebase))
+        };
+        self.lock_search().set_tablebase(tablebase);
+        Ok(())
+    }
+
+    /// A move from the opening book, if it's enabled and knows the
+    /// position
+    fn book_move(&self) -> Option<Move> {
+        if !self.book.enabled {
+            return None;
+        }
+        let selection = if self.book.best_only {
+            BookSelection::Best
+        } else {
+            BookSelection::Weighted
+        };
+        // the nanoseconds of the clock are random enough for picking
+        // a move
+        let random = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |t| t.subsec_nanos().into());
+
+        self.book
+            .book
+            .as_ref()?
+            .pick(self.game.current_board(), selection, random)
+    }
+}
+
+impl<W: Write + Send + 'static> Protocol for Uci<W> {
+    fn handle_command(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        match command {
+            "uci" => {
+                send(&self.out, "id name thstro-chess");
+                send(&self.out, "id author the thstro-chess authors");
+                send(
+                    &self.out,
+                    &format!(
+                        "option name Hash type spin default {} min 1 max {}",
+                        DEFAULT_HASH_MB, MAX_HASH_MB
+                    ),
+                );
+                send(
+                    &self.out,
+                    &format!(
+                        "option name Threads type spin default 1 min 1 max {}",
+                        MAX_THREADS
+                    ),
+                );
+                send(
+                    &self.out,
+                    &format!(
+                        "option name Skill Level type spin default {0} min 0 max {0}",
+                        Skill::MAX_LEVEL
+                    ),
+                );
+                send(&self.out, "option name OwnBook type check default false");
+                send(
+                    &self.out,
+                    "option name Book File type string default <empty>",
+                );
+                send(
+                    &self.out,
+                    "option name Best Book Move type check default false",
+                );
+                send(
+                    &self.out,
+                    "option name SyzygyPath type string default <empty>",
+                );
+                send(&self.out, "uciok");
+            }
+            "isready" => send(&self.out, "readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.game = Game::new();
+                self.lock_search().clear();
+            }
+            "position" => {
+                if let Err(message) = self.position(args) {
+                    send(&self.out, &format!("info string {}", message));
+                }
+            }
+            "go" => self.go(&parse_go(args)),
+            "stop" => self.stop_search(),
+            "setoption" => self.set_option(args),
+            "quit" => {
+                self.stop_search();
+                return false;
+            }
+            // unknown commands should be ignored according to the spec
+            _ => (),
+        }
+
+        true
+    }
+
+    fn finish(&mut self) {
+        if let Some(running) = self.running.take() {
+            if running.infinite {
+                running.stop.stop();
+            }
+            let _ = running.handle.join();
+        }
+    }
+}
+
+fn info_line(info: &SearchResult, turn: Color, elapsed: Duration) -> String {
+    let score = match info.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", info.score),
+    };
+    let millis = elapsed.as_millis().max(1);
+
+    format!(
+        "info depth {} score {} nodes {} time {} nps {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        millis,
+        u128::from(info.nodes) * 1000 / millis,
+        pv_to_uci(&info.pv, turn),
+    )
+}
+
+fn pv_to_uci(pv: &[Move], mut turn: Color) -> String {
+    let mut moves = Vec::with_capacity(pv.len());
+    for m in pv {
+        moves.push(m.to_uci(turn));
+        turn = turn.opposite();
+    }
+    moves.join(" ")
+}
+
+/// The arguments given to `go`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct GoOptions {
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<Duration>,
+    wtime: Option<Duration>,
+    btime: Option<Duration>,
+    winc: Option<Duration>,
+    binc: Option<Duration>,
+    movestogo: Option<u32>,
+    infinite: bool,
+}
+
+impl GoOptions {
+    fn limits(&self, turn: Color) -> SearchLimits {
+        let (time, inc) = match turn {
+            Color::White => (self.wtime, self.winc),
+            Color::Black => (self.btime, self.binc),
+        };
+        let clock_time =
+            time.map(|time| time_budget(time, inc.unwrap_or_default(), self.movestogo));
+
+        SearchLimits {
+            depth: self.depth,
+            nodes: self.nodes,
+            movetime: match (self.movetime, clock_time) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+fn parse_go(args: &[&str]) -> GoOptions {
+    let mut options = GoOptions::default();
+    let mut args = args.iter();
+
+    while let Some(&arg) = args.next() {
+        if arg == "infinite" {
+            options.infinite = true;
+            continue;
+        }
+        let value = match args.next().and_then(|v| v.parse::<u64>().ok()) {
+            Some(value) => value,
+            None => continue,
+        };
+        let millis = Some(Duration::from_millis(value));
+        match arg {
+            "depth" => options.depth = Some(value as u32),
+            "nodes" => options.nodes = Some(value),
+            "movetime" => options.movetime = millis,
+            "wtime" => options.wtime = millis,
+            "btime" => options.btime = millis,
+            "winc" => options.winc = millis,
+            "binc" => options.binc = millis,
+            "movestogo" => options.movestogo = Some(value as u32),
+            _ => (),
+        }
+    }
+
+    options
+}
+
+fn parse_spin(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number `{}`", value))
+}
+
+fn parse_check(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("invalid check value `{}`", value)),
+    }
+}
+
+/// Parse `name <name> value <value>`, where the name may contain spaces
+fn parse_setoption(args: &[&str]) -> Option<(String, String)> {
+    let name_index = args.iter().position(|&arg| arg == "name")?;
+    let value_index = args.iter().position(|&arg| arg == "value")?;
+    if value_index <= name_index {
+        return None;
+    }
+
+    Some((
+        args[name_index + 1..value_index].join(" "),
+        args[value_index + 1..].join(" "),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_go() {
+        let options = parse_go(&["wtime", "1000", "btime", "2000", "winc", "10", "depth", "5"]);
+
+        assert_eq!(
+            options,
+            GoOptions {
+                depth: Some(5),
+                wtime: Some(Duration::from_millis(1000)),
+                btime: Some(Duration::from_millis(2000)),
+                winc: Some(Duration::from_millis(10)),
+                ..GoOptions::default()
+            }
+        );
+        assert!(parse_go(&["infinite"]).infinite);
+    }
+
+    #[test]
+    fn uses_own_clock() {
+        let options = parse_go(&["wtime", "30000", "btime", "60000"]);
+
+        assert_eq!(
+            options.limits(Color::White).movetime,
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            options.limits(Color::Black).movetime,
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn parses_setoption() {
+        assert_eq!(
+            parse_setoption(&["name", "Clear", "Hash", "value", "1"]),
+            Some(("Clear Hash".to_string(), "1".to_string()))
+        );
+        assert_eq!(parse_setoption(&["name", "Threads"]), None);
+    }
+}
@@ -0,0 +1,411 @@
+//! The Chess Engine Communication Protocol used by XBoard and
+//! WinBoard, see <https://www.gnu.org/software/xboard/engine-intf.html>
+//! for the specification. Unlike UCI the engine keeps track of the
+//! game itself, and makes its own moves on the shared [`Game`] when
+//! it's done thinking.
+use crate::common::{send, time_budget, Protocol};
+use chess_engine::game::BoardState;
+use chess_engine::search::{Search, SearchLimits, SearchResult, StopFlag, DEFAULT_HASH_MB};
+use chess_engine::{Board, Color, Game, Move};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long to think when the GUI hasn't told us anything about time
+const DEFAULT_MOVETIME: Duration = Duration::from_secs(1);
+
+/// The state of the engine between commands
+pub(crate) struct Xboard<W> {
+    game: Arc<Mutex<Game>>,
+    search: Arc<Mutex<Search>>,
+    out: Arc<Mutex<W>>,
+    thinking: Option<Thinking>,
+    // in force mode the engine plays neither side
+    force: bool,
+    post: bool,
+    clock: Clock,
+}
+
+struct Thinking {
+    stop: StopFlag,
+    // set when the search result should be thrown away instead of
+    // being played
+    abandon: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// Everything the GUI has told us about how long we may think
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Clock {
+    /// From `level`, the number of moves per time control, or `None`
+    /// if the base time is for the whole game
+    moves_per_session: Option<u32>,
+    /// From `level`, the time for each session
+    base: Option<Duration>,
+    /// From `level`, the time added after every move
+    increment: Duration,
+    /// From `time`, how much time is left on our clock
+    time_left: Option<Duration>,
+    /// From `st`, an exact time for every move
+    movetime: Option<Duration>,
+    /// From `sd`, a depth limit
+    depth: Option<u32>,
+}
+
+impl Clock {
+    fn limits(&self, plies_played: usize) -> SearchLimits {
+        let movetime = if let Some(movetime) = self.movetime {
+            movetime
+        } else if let Some(time) = self.time_left.or(self.base) {
+            let moves_played = (plies_played / 2) as u32;
+            let moves_to_go = self
+                .moves_per_session
+                .map(|session| session - moves_played % session);
+            time_budget(time, self.increment, moves_to_go)
+        } else {
+            DEFAULT_MOVETIME
+        };
+
+        SearchLimits {
+            depth: self.depth,
+            nodes: None,
+            movetime: Some(movetime),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Xboard<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Self {
+            game: Arc::new(Mutex::new(Game::new())),
+            search: Arc::new(Mutex::new(Search::new(DEFAULT_HASH_MB))),
+            out: Arc::new(Mutex::new(out)),
+            thinking: None,
+            force: false,
+            post: false,
+            clock: Clock::default(),
+        }
+    }
+
+    fn lock_game(&self) -> MutexGuard<'_, Game> {
+        self.game.lock().expect("A search thread panicked")
+    }
+
+    fn lock_search(&self) -> MutexGuard<'_, Search> {
+        self.search.lock().expect("A search thread panicked")
+    }
+
+    /// Stop thinking without making a move
+    fn abandon(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            thinking.abandon.store(true, Ordering::Relaxed);
+            thinking.stop.stop();
+            let _ = thinking.handle.join();
+        }
+    }
+
+    /// Stop thinking and make the best move found so far
+    fn move_now(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            thinking.stop.stop();
+            let _ = thinking.handle.join();
+        }
+    }
+
+    fn user_move(&mut self, s: &str) {
+        self.abandon();
+
+        let mut game = self.lock_game();
+        let legal = Move::from_uci(s, game.current_board())
+            .ok()
+            .and_then(|m| game.make_move(m))
+            .is_some();
+        if !legal {
+            send(&self.out, &format!("Illegal move: {}", s));
+            return;
+        }
+        if let Some(result) = result_line(&game) {
+            send(&self.out, result);
+            return;
+        }
+        drop(game);
+
+        if !self.force {
+            self.think();
+        }
+    }
+
+    fn think(&mut self) {
+        self.abandon();
+
+        let game = self.lock_game();
+        if result_line(&game).is_some() {
+            return;
+        }
+        let boards = game.get_boards().to_vec();
+        let turn = game.next_player();
+        let limits = self.clock.limits(game.get_moves().len());
+        drop(game);
+
+        let stop = StopFlag::new();
+        let abandon = Arc::new(AtomicBool::new(false));
+        let post = self.post;
+
+        let game = Arc::clone(&self.game);
+        let search = Arc::clone(&self.search);
+        let out = Arc::clone(&self.out);
+        let (thread_stop, thread_abandon) = (stop.clone(), Arc::clone(&abandon));
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let search = search.lock().expect("A search thread panicked");
+            let result = search.run_with_info(&boards, &limits, &thread_stop, |info| {
+                if post {
+                    send(&out, &thinking_line(info, turn, start.elapsed()));
+                }
+            });
+
+            let mut game = game.lock().expect("The main thread panicked");
+            if thread_abandon.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Some(m) = result.best_move {
+                if game.make_move(m).is_some() {
+                    send(&out, &format!("move {}", m.to_uci(turn)));
+                    if let Some(result) = result_line(&game) {
+                        send(&out, result);
+                    }
+                }
+            }
+        });
+
+        self.thinking = Some(Thinking {
+            stop,
+            abandon,
+            handle,
+        });
+    }
+
+    fn set_board(&mut self, fen: &str) {
+        self.abandon();
+        match Board::load_fen(fen) {
+            Ok(board) => *self.lock_game() = Game::from_board(board),
+            Err(_) => send(&self.out, "tellusererror Illegal position"),
+        }
+    }
+
+    fn undo(&mut self, n: usize) {
+        self.abandon();
+        let mut game = self.lock_game();
+        for _ in 0..n {
+            let _ = game.undo_move();
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Protocol for Xboard<W> {
+    fn handle_command(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+        let number = |i: usize| args.get(i).and_then(|n| n.parse::<u64>().ok());
+
+        match command {
+            "protover" => send(
+                &self.out,
+                "feature myname=\"thstro-chess\" setboard=1 usermove=1 ping=1 \
+                 colors=0 sigint=0 sigterm=0 analyze=0 reuse=1 memory=1 smp=1 done=1",
+            ),
+            "new" => {
+                self.abandon();
+                *self.lock_game() = Game::new();
+                self.lock_search().clear();
+                self.force = false;
+                self.clock.depth = None;
+            }
+            "setboard" => self.set_board(&args.join(" ")),
+            "usermove" => match args.first() {
+                Some(m) => self.user_move(m),
+                None => send(&self.out, "Error (missing move): usermove"),
+            },
+            "go" => {
+                self.force = false;
+                self.think();
+            }
+            "force" => {
+                self.abandon();
+                self.force = true;
+            }
+            "?" => self.move_now(),
+            "level" => match parse_level(args) {
+                Some((moves_per_session, base, increment)) => {
+                    self.clock.moves_per_session = moves_per_session;
+                    self.clock.base = Some(base);
+                    self.clock.increment = increment;
+                    self.clock.movetime = None;
+                }
+                None => send(&self.out, &format!("Error (invalid level): {}", line)),
+            },
+            "st" => self.clock.movetime = number(0).map(Duration::from_secs),
+            "sd" => self.clock.depth = number(0).map(|d| d as u32),
+            // the clock is given in centiseconds
+            "time" => self.clock.time_left = number(0).map(|t| Duration::from_millis(t * 10)),
+            "undo" => self.undo(1),
+            "remove" => self.undo(2),
+            "result" => {
+                // the game is over, wait for `new`
+                self.abandon();
+                self.force = true;
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => send(&self.out, &format!("pong {}", args.join(" "))),
+            "memory" => {
+                if let Some(mb) = number(0) {
+                    self.abandon();
+                    self.lock_search().set_hash_size(mb as usize);
+                }
+            }
+            "cores" => {
+                if let Some(cores) = number(0) {
+                    self.abandon();
+                    self.lock_search().set_threads(cores as usize);
+                }
+            }
+            "quit" => {
+                self.abandon();
+                return false;
+            }
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "name" | "rating" | "otim" | "ics" => (),
+            // GUIs that haven't accepted `usermove=1` send bare moves
+            _ if Move::from_uci(command, self.lock_game().current_board()).is_ok() => {
+                self.user_move(command);
+            }
+            _ => send(&self.out, &format!("Error (unknown command): {}", command)),
+        }
+
+        true
+    }
+
+    fn finish(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            let _ = thinking.handle.join();
+        }
+    }
+}
+
+/// The line to send if the game is over
+fn result_line(game: &Game) -> Option<&'static str> {
+    match game.board_state() {
+        BoardState::Checkmate => Some(match game.next_player() {
+            Color::White => "0-1 {Black mates}",
+            Color::Black => "1-0 {White mates}",
+        }),
+        BoardState::Stalemate => Some("1/2-1/2 {Stalemate}"),
+        BoardState::Draw => Some("1/2-1/2 {Fifty move rule}"),
+        BoardState::Normal | BoardState::Check => None,
+    }
+}
+
+/// Thinking output, sent after every depth in `post` mode
+fn thinking_line(info: &SearchResult, mut turn: Color, elapsed: Duration) -> String {
+    // mates are shown as 100000 + moves to mate by convention
+    let score = match info.mate_in() {
+        Some(moves) if moves > 0 => 100_000 + moves,
+        Some(moves) => -100_000 + moves,
+        None => info.score,
+    };
+
+    let mut pv = Vec::with_capacity(info.pv.len());
+    for m in &info.pv {
+        pv.push(m.to_uci(turn));
+        turn = turn.opposite();
+    }
+
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        score,
+        elapsed.as_millis() / 10,
+        info.nodes,
+        pv.join(" ")
+    )
+}
+
+/// Parse `level MPS BASE INC`, where `BASE` is either minutes or
+/// `minutes:seconds` and `INC` is seconds, possibly fractional
+fn parse_level(args: &[&str]) -> Option<(Option<u32>, Duration, Duration)> {
+    let (mps, base, inc) = match args {
+        [mps, base, inc] => (mps.parse::<u32>().ok()?, *base, inc.parse::<f64>().ok()?),
+        _ => return None,
+    };
+
+    let base = match base.split_once(':') {
+        Some((minutes, seconds)) => {
+            minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?
+        }
+        None => base.parse::<u64>().ok()? * 60,
+    };
+    if inc < 0.0 || !inc.is_finite() {
+        return None;
+    }
+
+    Some((
+        if mps == 0 { None } else { Some(mps) },
+        Duration::from_secs(base),
+        Duration::from_secs_f64(inc),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_level() {
+        assert_eq!(
+            parse_level(&["40", "5", "0"]),
+            Some((Some(40), Duration::from_secs(300), Duration::ZERO))
+        );
+        assert_eq!(
+            parse_level(&["0", "2:30", "1.5"]),
+            Some((None, Duration::from_secs(150), Duration::from_millis(1500)))
+        );
+        assert_eq!(parse_level(&["0", "2:30"]), None);
+    }
+
+    #[test]
+    fn session_moves_left() {
+        let clock = Clock {
+            moves_per_session: Some(40),
+            time_left: Some(Duration::from_secs(100)),
+            ..Clock::default()
+        };
+
+        // 38 moves have been played, so two moves are left
+        assert_eq!(clock.limits(76).movetime, Some(Duration::from_secs(50)));
+    }
+
+    #[test]
+    fn exact_time_per_move() {
+        let clock = Clock {
+            movetime: Some(Duration::from_secs(3)),
+            time_left: Some(Duration::from_secs(100)),
+            depth: Some(4),
+            ..Clock::default()
+        };
+
+        assert_eq!(
+            clock.limits(0),
+            SearchLimits {
+                depth: Some(4),
+                nodes: None,
+                movetime: Some(Duration::from_secs(3)),
+            }
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use chess_engine::book::{Book, BookSelection};
+use chess_engine::{Board, Move};
+use std::process::Command;
+use std::{env, fs, process};
+
+#[test]
+fn builds_book_and_explorer() {
+    let dir = env::temp_dir().join(format!("build-book-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let pgn = dir.join("games.pgn");
+    let book = dir.join("book.bin");
+    let explorer = dir.join("explorer.txt");
+    fs::write(
+        &pgn,
+        "[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n\n1. d4 d5 0-1\n\n1. e4 Kf2 *\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-book"))
+        .args(["--plies", "2", "--explorer"])
+        .args([&explorer, &book, &pgn])
+        .output()
+        .unwrap();
+    let book = Book::open(&book);
+    let explorer = fs::read_to_string(&explorer);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    // the illegal move is reported, but the game is still used
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("skipping the rest of game 3"));
+
+    let start = Board::default_board();
+    let e4 = Move::from_san("e4", &start).unwrap();
+    assert_eq!(book.unwrap().pick(&start, BookSelection::Best, 0), Some(e4));
+    assert!(explorer
+        .unwrap()
+        .starts_with("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 (3 games)\n    e4"));
+}
+
+#[test]
+fn rejects_missing_files() {
+    let output = Command::new(env!("CARGO_BIN_EXE_build-book"))
+        .arg("book.bin")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("usage:"));
+}
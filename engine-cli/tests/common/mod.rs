@@ -0,0 +1,33 @@
+//! Helpers shared by the integration tests
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Run the engine with `input`, sleeping for `pause` before sending the
+/// last line, and return everything it printed
+pub fn run_engine(input: &[&str], pause: Duration) -> Vec<String> {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_engine-cli"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = engine.stdin.take().unwrap();
+    let (last, rest) = input.split_last().unwrap();
+    for line in rest {
+        writeln!(stdin, "{}", line).unwrap();
+    }
+    stdin.flush().unwrap();
+    thread::sleep(pause);
+    writeln!(stdin, "{}", last).unwrap();
+    drop(stdin);
+
+    let output = engine.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,153 @@
+//! Tests driving the engine binary in UCI mode by piping commands to
+//! its stdin
+mod common;
+
+use chess_engine::book::{encode_move, BookEntry};
+use chess_engine::{Board, Color, Move};
+use common::run_engine;
+use std::time::Duration;
+use std::{env, fs, process};
+
+#[test]
+fn handshake() {
+    let output = run_engine(&["uci", "isready", "quit"], Duration::ZERO);
+
+    assert!(output[0].starts_with("id name "));
+    assert!(output
+        .iter()
+        .any(|l| l.starts_with("option name Hash type spin")));
+    assert!(output
+        .iter()
+        .any(|l| l.starts_with("option name Threads type spin")));
+    assert!(output
+        .iter()
+        .any(|l| l.starts_with("option name Skill Level type spin")));
+    assert!(output
+        .iter()
+        .any(|l| l == "option name OwnBook type check default false"));
+    assert_eq!(output[output.len() - 2], "uciok");
+    assert_eq!(output[output.len() - 1], "readyok");
+}
+
+#[test]
+fn finds_mate_from_fen() {
+    let output = run_engine(
+        &[
+            "uci",
+            "setoption name Hash value 1",
+            "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "go depth 2",
+        ],
+        Duration::ZERO,
+    );
+
+    assert!(output
+        .iter()
+        .any(|l| l.starts_with("info depth 1 score mate 1")));
+    assert_eq!(output.last().unwrap(), "bestmove a1a8");
+}
+
+#[test]
+fn applies_moves() {
+    // after 1. f3 e5 2. g4 black mates with Qh4
+    let output = run_engine(
+        &[
+            "ucinewgame",
+            "position startpos moves f2f3 e7e5 g2g4",
+            "go depth 1",
+        ],
+        Duration::ZERO,
+    );
+
+    assert_eq!(output.last().unwrap(), "bestmove d8h4");
+}
+
+#[test]
+fn castling_and_promotion_moves() {
+    let output = run_engine(
+        &[
+            "position fen 4k2r/P7/8/8/8/8/8/R3K3 w Qk - 0 1 moves e1c1 e8g8 a7a8q",
+            "go depth 1",
+        ],
+        Duration::ZERO,
+    );
+
+    // the rook has castled on to the new queen's rank and can take it
+    assert_eq!(output.last().unwrap(), "bestmove f8a8");
+}
+
+#[test]
+fn weakest_skill_still_plays() {
+    let output = run_engine(
+        &[
+            "setoption name Skill Level value 0",
+            "position startpos",
+            "go",
+        ],
+        Duration::ZERO,
+    );
+
+    assert!(output.last().unwrap().starts_with("bestmove "));
+    assert_ne!(output.last().unwrap(), "bestmove 0000");
+}
+
+#[test]
+fn plays_from_own_book() {
+    // a book with only 1. e4
+    let entry = BookEntry {
+        key: Board::default_board().polyglot_key(),
+        raw_move: encode_move(
+            Move::Normal {
+                from: "e2".parse().unwrap(),
+                to: "e4".parse().unwrap(),
+            },
+            Color::White,
+        ),
+        weight: 1,
+        learn: 0,
+    };
+    let path = env::temp_dir().join(format!("engine-cli-test-{}.bin", process::id()));
+    fs::write(&path, entry.to_bytes()).unwrap();
+
+    let output = run_engine(
+        &[
+            "setoption name OwnBook value true",
+            &format!("setoption name Book File value {}", path.display()),
+            "position startpos",
+            "go depth 3",
+            "position startpos moves e2e4",
+            "go depth 1",
+        ],
+        Duration::ZERO,
+    );
+    fs::remove_file(&path).unwrap();
+
+    // the first move is played right away, the second has to be
+    // searched
+    assert_eq!(output[0], "bestmove e2e4");
+    assert!(output[1].starts_with("info depth 1"));
+}
+
+#[test]
+fn stop_infinite_search() {
+    let output = run_engine(
+        &[
+            "setoption name Threads value 2",
+            "position startpos",
+            "go infinite",
+            "stop",
+        ],
+        Duration::from_millis(200),
+    );
+
+    assert!(output.last().unwrap().starts_with("bestmove "));
+    assert_ne!(output.last().unwrap(), "bestmove 0000");
+}
+
+#[test]
+fn reports_bad_positions() {
+    let output = run_engine(&["position startpos moves e2e5", "isready"], Duration::ZERO);
+
+    assert!(output[0].starts_with("info string"));
+    assert_eq!(output[1], "readyok");
+}
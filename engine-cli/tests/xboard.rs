@@ -0,0 +1,84 @@
+//! Tests driving the engine binary in XBoard mode by piping commands to
+//! its stdin
+mod common;
+
+use common::run_engine;
+use std::time::Duration;
+
+#[test]
+fn announces_features() {
+    let output = run_engine(&["xboard", "protover 2", "ping 7"], Duration::ZERO);
+
+    assert!(output[0].starts_with("feature myname=\"thstro-chess\""));
+    assert!(output[0].contains("usermove=1"));
+    assert!(output[0].ends_with("done=1"));
+    assert_eq!(output[1], "pong 7");
+}
+
+#[test]
+fn plays_mate_from_setboard() {
+    let output = run_engine(
+        &[
+            "xboard",
+            "new",
+            "force",
+            "setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "sd 2",
+            "go",
+        ],
+        Duration::ZERO,
+    );
+
+    assert_eq!(output, ["move a1a8", "1-0 {White mates}"]);
+}
+
+#[test]
+fn answers_user_moves() {
+    // after 1. f3 e5 2. g4 black mates with Qh4
+    let output = run_engine(
+        &[
+            "xboard",
+            "new",
+            "sd 1",
+            "force",
+            "usermove f2f3",
+            "usermove e7e5",
+            "g2g4",
+            "go",
+        ],
+        Duration::ZERO,
+    );
+
+    assert_eq!(output, ["move d8h4", "0-1 {Black mates}"]);
+}
+
+#[test]
+fn undo_after_mate() {
+    let output = run_engine(
+        &[
+            "xboard",
+            "force",
+            "setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "usermove a1a8",
+            "undo",
+            "usermove a1a7",
+            "ping 1",
+        ],
+        Duration::ZERO,
+    );
+
+    assert_eq!(output, ["1-0 {White mates}", "pong 1"]);
+}
+
+#[test]
+fn rejects_illegal_moves() {
+    let output = run_engine(
+        &["xboard", "force", "usermove e2e5", "e7e5", "ping 2"],
+        Duration::ZERO,
+    );
+
+    assert_eq!(
+        output,
+        ["Illegal move: e2e5", "Illegal move: e7e5", "pong 2"]
+    );
+}
@@ -0,0 +1,487 @@
+//! Playing against the computer. Who plays each side is picked in the
+//! new game dialog, a human here, the computer, or a client of the game
+//! hosted here, and when it's the computer's turn the search runs
+//! on the async compute task pool so that it never blocks a frame.
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::ui::FocusPolicy;
+use chess_engine::book::{Book, BookSelection};
+use chess_engine::game::BoardState;
+use chess_engine::search::{Search, SearchLimits, SearchResult, Skill, StopFlag, DEFAULT_HASH_MB};
+use chess_engine::{Board, Game, Move};
+use futures_lite::future;
+
+use crate::{BoardUpdateEvent, NewGameEvent, UIState};
+
+pub struct ComputerPlugin;
+
+impl Plugin for ComputerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Players>()
+            .init_resource::<NewGameSettings>()
+            .init_resource::<Computer>()
+            .add_system(open_new_game_dialog.system())
+            .add_system(new_game_dialog.system())
+            .add_system(update_dialog_labels.system())
+            .add_system(start_thinking.system())
+            .add_system(finish_thinking.system());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    Human,
+    Computer,
+    /// Whoever is on the other end of the network connection
+    Remote,
+}
+
+/// How hard the computer tries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strength {
+    /// A reduced skill, which makes deliberate mistakes
+    Skill(Skill),
+    Depth(u32),
+    MoveTime(Duration),
+}
+
+/// The strengths that can be picked in the dialog, from weakest to
+/// strongest
+const STRENGTHS: [Strength; 11] = [
+    Strength::Skill(Skill::BEGINNER),
+    Strength::Skill(Skill::INTERMEDIATE),
+    Strength::Skill(Skill::STRONG),
+    Strength::Depth(1),
+    Strength::Depth(2),
+    Strength::Depth(3),
+    Strength::Depth(4),
+    Strength::MoveTime(Duration::from_millis(500)),
+    Strength::MoveTime(Duration::from_secs(1)),
+    Strength::MoveTime(Duration::from_secs(2)),
+    Strength::MoveTime(Duration::from_secs(5)),
+];
+
+impl Strength {
+    fn limits(self) -> SearchLimits {
+        match self {
+            // the skill limits the search itself
+            Strength::Skill(_) => SearchLimits::default(),
+            Strength::Depth(depth) => SearchLimits {
+                depth: Some(depth),
+                ..Default::default()
+            },
+            Strength::MoveTime(movetime) => SearchLimits {
+                movetime: Some(movetime),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn skill(self) -> Skill {
+        match self {
+            Strength::Skill(skill) => skill,
+            Strength::Depth(_) | Strength::MoveTime(_) => Skill::default(),
+        }
+    }
+
+    fn next(self) -> Self {
+        let i = STRENGTHS.iter().position(|&s| s == self).unwrap_or(0);
+        STRENGTHS[(i + 1) % STRENGTHS.len()]
+    }
+}
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Strength::Skill(skill) => {
+                let name = match skill {
+                    Skill::BEGINNER => "Beginner",
+                    Skill::INTERMEDIATE => "Intermediate",
+                    Skill::STRONG => "Strong",
+                    _ => "Level",
+                };
+                match skill.elo() {
+                    Some(elo) => write!(f, "{} (~{} Elo)", name, elo),
+                    None => write!(f, "{}", name),
+                }
+            }
+            Strength::Depth(depth) => write!(f, "Depth {}", depth),
+            Strength::MoveTime(movetime) => {
+                write!(f, "{:.1} s per move", movetime.as_secs_f32())
+            }
+        }
+    }
+}
+
+/// Who is playing each side of the current game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Players {
+    pub white: Player,
+    pub black: Player,
+    pub strength: Strength,
+}
+
+impl Players {
+    pub fn get(&self, color: chess_engine::Color) -> Player {
+        match color {
+            chess_engine::Color::White => self.white,
+            chess_engine::Color::Black => self.black,
+        }
+    }
+
+    pub fn get_mut(&mut self, color: chess_engine::Color) -> &mut Player {
+        match color {
+            chess_engine::Color::White => &mut self.white,
+            chess_engine::Color::Black => &mut self.black,
+        }
+    }
+}
+
+impl Default for Players {
+    fn default() -> Self {
+        Self {
+            white: Player::Human,
+            black: Player::Human,
+            strength: Strength::Depth(3),
+        }
+    }
+}
+
+/// What has been picked in the dialog, used once the game is started
+struct NewGameSettings(Players);
+
+impl Default for NewGameSettings {
+    fn default() -> Self {
+        Self(Players {
+            black: Player::Computer,
+            ..Default::default()
+        })
+    }
+}
+
+/// Where the opening book is looked for, the computer only searches
+/// if there is none
+const BOOK_PATH: &str = "assets/books/book.bin";
+
+/// The search shared by both sides, and the move being thought about
+struct Computer {
+    search: Arc<Search>,
+    book: Option<Book>,
+    thinking: Option<Thinking>,
+}
+
+struct Thinking {
+    task: Task<SearchResult>,
+    stop: StopFlag,
+    // the board the search started from, so that a result for a board
+    // that isn't shown anymore is never played
+    board: Board,
+}
+
+impl Default for Computer {
+    fn default() -> Self {
+        Self {
+            search: Arc::new(Search::new(DEFAULT_HASH_MB)),
+            book: Book::open(BOOK_PATH).ok(),
+            thinking: None,
+        }
+    }
+}
+
+impl Computer {
+    /// Get ready for a new game at `strength`
+    fn reset(&mut self, strength: Strength) {
+        self.cancel();
+        // the search of a cancelled move may still be using the old
+        // search for a little while, so it can't be changed in place
+        let mut search = Search::new(DEFAULT_HASH_MB);
+        search.set_skill(strength.skill());
+        self.search = Arc::new(search);
+    }
+
+    /// A move from the opening book, picked randomly so that the
+    /// computer doesn't play the same opening every game
+    fn book_move(&self, board: &Board) -> Option<Move> {
+        let random = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |t| t.subsec_nanos().into());
+        self.book
+            .as_ref()?
+            .pick(board, BookSelection::Weighted, random)
+    }
+
+    fn cancel(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            // dropping the task won't interrupt the search if it is
+            // already running
+            thinking.stop.stop();
+        }
+    }
+}
+
+struct NewGameDialog;
+struct NewGameButton;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DialogButton {
+    Side(chess_engine::Color),
+    Strength,
+    Start,
+}
+
+/// Add the button that opens the new game dialog
+pub fn spawn_new_game_button(
+    parent: &mut ChildBuilder,
+    font: Handle<Font>,
+    materials: &mut Assets<ColorMaterial>,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(10.0)),
+                padding: Rect::all(Val::Px(5.0)),
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb_u8(0x4c, 0x56, 0x6a).into()),
+            ..Default::default()
+        })
+        .insert(NewGameButton)
+        .with_children(|button| {
+            button.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    "New game",
+                    TextStyle {
+                        font,
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
+        });
+}
+
+/// Add the new game dialog, which is shown when the game starts
+pub fn spawn_new_game_dialog(
+    root: &mut ChildBuilder,
+    font: Handle<Font>,
+    materials: &mut Assets<ColorMaterial>,
+) {
+    let button_material = materials.add(Color::rgb_u8(0x4c, 0x56, 0x6a).into());
+    let text_style = TextStyle {
+        font,
+        font_size: 24.0,
+        color: Color::WHITE,
+    };
+
+    root.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::ColumnReverse,
+            padding: Rect::all(Val::Px(20.0)),
+            ..Default::default()
+        },
+        material: materials.add(Color::rgb_u8(0x3b, 0x42, 0x52).into()),
+        ..Default::default()
+    })
+    // keep clicks from reaching the board below
+    .insert(Interaction::default())
+    .insert(FocusPolicy::Block)
+    .insert(NewGameDialog)
+    .with_children(|dialog| {
+        dialog.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "New game",
+                TextStyle {
+                    font_size: 32.0,
+                    ..text_style.clone()
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        });
+        for button in [
+            DialogButton::Side(chess_engine::Color::White),
+            DialogButton::Side(chess_engine::Color::Black),
+            DialogButton::Strength,
+            DialogButton::Start,
+        ] {
+            dialog
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(5.0)),
+                        padding: Rect::all(Val::Px(5.0)),
+                        justify_content: JustifyContent::Center,
+                        ..Default::default()
+                    },
+                    material: button_material.clone(),
+                    ..Default::default()
+                })
+                .insert(button)
+                .with_children(|button| {
+                    // the labels are set by `update_dialog_labels`
+                    button.spawn_bundle(TextBundle {
+                        text: Text::with_section("", text_style.clone(), Default::default()),
+                        ..Default::default()
+                    });
+                });
+        }
+    });
+}
+
+fn open_new_game_dialog(
+    query: Query<&Interaction, (Changed<Interaction>, With<NewGameButton>)>,
+    mut dialog_query: Query<&mut Style, With<NewGameDialog>>,
+) {
+    for &interaction in query.iter() {
+        if interaction == Interaction::Clicked {
+            dialog_query.single_mut().unwrap().display = Display::Flex;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn new_game_dialog(
+    query: Query<(&Interaction, &DialogButton), Changed<Interaction>>,
+    mut dialog_query: Query<&mut Style, With<NewGameDialog>>,
+    mut settings: ResMut<NewGameSettings>,
+    mut players: ResMut<Players>,
+    mut game: ResMut<Game>,
+    mut computer: ResMut<Computer>,
+    mut state: ResMut<UIState>,
+    mut new_game_event: EventWriter<NewGameEvent>,
+    mut board_update_event: EventWriter<BoardUpdateEvent>,
+) {
+    for (&interaction, &button) in query.iter() {
+        if interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            DialogButton::Side(chess_engine::Color::White) => {
+                settings.0.white = toggle(settings.0.white);
+            }
+            DialogButton::Side(chess_engine::Color::Black) => {
+                settings.0.black = toggle(settings.0.black);
+            }
+            DialogButton::Strength => settings.0.strength = settings.0.strength.next(),
+            DialogButton::Start => {
+                computer.reset(settings.0.strength);
+                *players = settings.0;
+                *game = Game::new();
+                *state = UIState::Default;
+                dialog_query.single_mut().unwrap().display = Display::None;
+                new_game_event.send(NewGameEvent);
+                board_update_event.send(BoardUpdateEvent);
+            }
+        }
+    }
+}
+
+fn toggle(player: Player) -> Player {
+    match player {
+        Player::Human => Player::Computer,
+        Player::Computer => Player::Remote,
+        Player::Remote => Player::Human,
+    }
+}
+
+fn update_dialog_labels(
+    settings: Res<NewGameSettings>,
+    button_query: Query<(&DialogButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (&button, children) in button_query.iter() {
+        let label = match button {
+            DialogButton::Side(color) => format!("{:?}: {:?}", color, settings.0.get(color)),
+            DialogButton::Strength => format!("Strength: {}", settings.0.strength),
+            DialogButton::Start => "Start".to_string(),
+        };
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
+
+fn start_thinking(
+    game: Res<Game>,
+    players: Res<Players>,
+    state: Res<UIState>,
+    mut computer: ResMut<Computer>,
+    pool: Res<AsyncComputeTaskPool>,
+) {
+    if computer.thinking.is_some()
+        || players.get(game.next_player()) != Player::Computer
+        || *state == UIState::GameOver
+    {
+        return;
+    }
+    match game.board_state() {
+        BoardState::Checkmate | BoardState::Stalemate | BoardState::Draw => return,
+        BoardState::Normal | BoardState::Check => (),
+    }
+
+    let boards = game.get_boards().to_vec();
+    let limits = players.strength.limits();
+    let stop = StopFlag::new();
+
+    let task = match computer.book_move(game.current_board()) {
+        Some(m) => pool.spawn(async move {
+            SearchResult {
+                best_move: Some(m),
+                score: 0,
+                depth: 0,
+                nodes: 0,
+                pv: vec![m],
+            }
+        }),
+        None => {
+            let search = Arc::clone(&computer.search);
+            let task_stop = stop.clone();
+            pool.spawn(async move { search.run(&boards, &limits, &task_stop) })
+        }
+    };
+
+    computer.thinking = Some(Thinking {
+        task,
+        stop,
+        board: *game.current_board(),
+    });
+}
+
+fn finish_thinking(
+    mut game: ResMut<Game>,
+    mut computer: ResMut<Computer>,
+    mut board_update_event: EventWriter<BoardUpdateEvent>,
+) {
+    let thinking = match &mut computer.thinking {
+        Some(thinking) => thinking,
+        None => return,
+    };
+    let result = match future::block_on(future::poll_once(&mut thinking.task)) {
+        Some(result) => result,
+        None => return,
+    };
+    let board = thinking.board;
+    computer.thinking = None;
+
+    if *game.current_board() != board {
+        return;
+    }
+    if let Some(m) = result.best_move {
+        if game.make_move(m).is_some() {
+            board_update_event.send(BoardUpdateEvent);
+        }
+    }
+}
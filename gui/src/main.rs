@@ -4,12 +4,55 @@ use bevy::{
     render::camera::Camera,
     ui::FocusPolicy,
 };
+use chess_engine::board::{MoveFlags, MoveInfo};
 use chess_engine::{Game, Piece, PieceType, SquareSpec};
-use std::collections::{HashMap, HashSet};
+use chess_net::codec;
+use chess_net::config::ListenConfig;
+use std::collections::HashMap;
+use std::process;
 
+mod computer;
 mod net;
 
+const USAGE: &str = "usage: gui [--connect HOST:PORT [--side white|black|spectator]] \
+                     [--address ADDR] [--port N] [--websocket-port N] [--config FILE]";
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<net::NetworkMode, String> {
+    let mut listen = ListenConfig::default();
+    let mut connect = None;
+    let mut seat = None;
+    while let Some(arg) = args.next() {
+        if listen
+            .parse_arg(&arg, &mut args)
+            .map_err(|e| e.to_string())?
+        {
+            continue;
+        }
+        match arg.as_str() {
+            "--connect" => connect = Some(args.next().ok_or(format!("{} needs a value", arg))?),
+            "--side" => {
+                let side = args.next().ok_or(format!("{} needs a value", arg))?;
+                seat = Some(codec::parse_seat(&side).map_err(|_| USAGE.to_string())?);
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(match connect {
+        Some(addr) => net::NetworkMode::Connect { addr, seat },
+        None if seat.is_some() => return Err(USAGE.to_string()),
+        None => net::NetworkMode::Host(listen),
+    })
+}
+
 fn main() {
+    let mode = match parse_args(std::env::args().skip(1)) {
+        Ok(mode) => mode,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
     App::build()
         .insert_resource(WindowDescriptor {
             title: "Chess? Yes!".into(),
@@ -18,13 +61,15 @@ fn main() {
         // Plugins
         .add_plugins(DefaultPlugins)
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
-        .add_plugin(net::NetworkPlugin)
+        .add_plugin(net::NetworkPlugin(mode))
+        .add_plugin(computer::ComputerPlugin)
         // Resources
         .insert_resource(Game::new())
         .init_resource::<PieceAssetMap>()
         .insert_resource(UIState::Default)
         // Event types
         .add_event::<BoardUpdateEvent>()
+        .add_event::<NewGameEvent>()
         // Startup systems
         .add_startup_system(setup_game_ui.system())
         // Systems
@@ -38,7 +83,6 @@ fn main() {
         .add_system(cancel_move.system())
         .add_system(get_pawn_promotion.system())
         .add_system(update_end_game_text.system())
-        .add_system(test.system())
         //
         .run();
 }
@@ -48,6 +92,7 @@ struct PieceSprite;
 #[derive(Clone, Copy)]
 struct PawnPromotionOption(PieceType);
 struct BoardUpdateEvent;
+struct NewGameEvent;
 struct GameEndElement;
 struct GameEndText;
 struct DiagnosticsInfoText;
@@ -56,6 +101,9 @@ enum UIState {
     Default,
     PickedUpPiece(Entity),
     PromotionAsked(SquareSpec, SquareSpec),
+    /// The game was given a result over the network, e.g. because a
+    /// player abandoned it, and no moves can be made until a new game
+    GameOver,
 }
 struct PickedUpPieceParent(Entity);
 struct PawnPromotionElement(Entity);
@@ -68,12 +116,6 @@ enum ChessSquare {
     Promotable,
 }
 
-fn test(mut e_r: EventReader<net::MoveReceivedEvent>) {
-    for e in e_r.iter() {
-        println!("{:?}", e);
-    }
-}
-
 fn get_pawn_promotion(
     mut state: ResMut<UIState>,
     mut game: ResMut<Game>,
@@ -158,12 +200,16 @@ fn pick_up_piece(
     query: Query<(Entity, &Interaction, &SquareSpec), (Changed<Interaction>, With<PieceSprite>)>,
     mut fp_query: Query<&mut FocusPolicy, With<PieceSprite>>,
     chess_game: Res<Game>,
+    players: Res<computer::Players>,
     mut state: ResMut<UIState>,
     picked_up_piece_parent: Res<PickedUpPieceParent>,
 ) {
     if *state != UIState::Default {
         return;
     }
+    if players.get(chess_game.current_board().turn()) != computer::Player::Human {
+        return;
+    }
 
     for (entity, &interaction, &sq_spec) in query.iter() {
         if interaction != Interaction::Clicked {
@@ -230,7 +276,7 @@ fn put_down_piece(
         .current_board()
         .get_legal_moves(from)
         .iter()
-        .any(|m| m.to(piece.color) == to)
+        .any(|m| m.to() == to)
     {
         *state = UIState::PromotionAsked(from, to);
         board_update_event.send(BoardUpdateEvent);
@@ -284,22 +330,22 @@ fn possible_moves_hover(
         Some(hovered) => hovered,
         None => return,
     };
-    let color = chess_game.current_board()[hovered].map(|p| p.color);
-    let piece = chess_game.current_board()[hovered].map(|p| p.piece);
-    let moves = chess_game.current_board().get_legal_moves(hovered);
-    let destinations: HashSet<SquareSpec> = moves.iter().map(|m| m.to(color.unwrap())).collect();
+    let moves: HashMap<SquareSpec, MoveInfo> = chess_game
+        .current_board()
+        .get_legal_moves(hovered)
+        .into_iter()
+        .map(|m| (m.to(), m))
+        .collect();
 
     for (&sq_spec, mut chess_square) in square_query.iter_mut() {
-        if destinations.contains(&sq_spec) {
-            if Some(sq_spec.rank) == color.map(|c| c.opposite().home_rank())
-                && piece == Some(PieceType::Pawn)
-            {
-                *chess_square = ChessSquare::Promotable;
-            } else if chess_game.current_board()[sq_spec].is_some() {
-                *chess_square = ChessSquare::Capturable;
+        if let Some(m) = moves.get(&sq_spec) {
+            *chess_square = if m.flags().contains(MoveFlags::PROMOTION) {
+                ChessSquare::Promotable
+            } else if m.is_capture() {
+                ChessSquare::Capturable
             } else {
-                *chess_square = ChessSquare::Movable;
-            }
+                ChessSquare::Movable
+            };
         }
     }
 }
@@ -482,6 +528,7 @@ fn setup_game_ui(
                                         ..Default::default()
                                     },
                                     size: Size::new(Val::Px(200.0), Val::Percent(100.0)),
+                                    flex_direction: FlexDirection::ColumnReverse,
                                     ..Default::default()
                                 },
                                 material: materials.add(Color::rgb_u8(30, 30, 30).into()),
@@ -502,6 +549,13 @@ fn setup_game_ui(
                                         ..Default::default()
                                     })
                                     .insert(DiagnosticsInfoText);
+                                net::spawn_spectators_text(side_panel, font.clone());
+                                computer::spawn_new_game_button(
+                                    side_panel,
+                                    font.clone(),
+                                    &mut materials,
+                                );
+                                net::spawn_negotiation(side_panel, font.clone(), &mut materials);
                             });
                     });
                 // grid
@@ -615,6 +669,7 @@ fn setup_game_ui(
                     }
                 })
                 .id();
+            computer::spawn_new_game_dialog(root, font.clone(), &mut materials);
         });
 
     commands.insert_resource(PickedUpPieceParent(picked_up_piece_parent));
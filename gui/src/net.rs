@@ -1,170 +1,555 @@
-use std::error::Error as StdError;
-use std::io::{ErrorKind, Read};
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::str::{FromStr, Utf8Error};
-use std::{fmt, str};
+//! Playing over the network with [`chess_net`]. A GUI either hosts a
+//! game, offering clients the sides set to remote in the new game
+//! dialog, or connects to a game hosted by another GUI or a
+//! `chess-server`. Neither knows about Bevy, the plugin just updates
+//! them once a frame. A client that loses its connection keeps trying to
+//! get back to the game until the host won't have kept its side anymore.
+//!
+//! Whatever is typed is chat, sent with enter, and the side panel has
+//! buttons to offer a draw, ask for a takeback, resign and answer the
+//! opponent's offers, for the side played here against a remote one.
+//! When a game ends its record is printed as PGN.
+use std::net::SocketAddr;
+use std::process;
+use std::time::{Duration, Instant};
 
 use bevy::prelude::*;
-use chess_engine::{Board, Game, Move, PieceType, SquareSpec};
+use chess_engine::game::BoardState;
+use chess_engine::pgn::GameResult;
+use chess_engine::{Color, Game};
+use chess_net::codec::Offer;
+use chess_net::config::ListenConfig;
+use chess_net::host::{Host, DEFAULT_GRACE_PERIOD};
+use chess_net::remote::Remote;
 
-#[derive(Debug)]
-pub struct MoveReceivedEvent(Move);
+use crate::computer::{Player, Players};
+use crate::{BoardUpdateEvent, GameEndElement, GameEndText, NewGameEvent, UIState};
 
-pub struct NetworkPlugin;
+/// How often to try getting back to the host after losing the
+/// connection
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many lines of chat and offers are shown
+const CHAT_LINES: usize = 8;
+
+/// Whether to host a game or to connect to one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkMode {
+    /// Host a game, listening where the config says
+    Host(ListenConfig),
+    /// Connect to the host at the address, asking for a side to play,
+    /// `None` to spectate, or taking whatever the host gives
+    Connect {
+        addr: String,
+        seat: Option<Option<Color>>,
+    },
+}
+
+pub struct NetworkPlugin(pub NetworkMode);
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_event::<MoveReceivedEvent>()
-            .init_resource::<Listener>()
-            .add_system(accept_connections.system())
-            .add_system(read_packets.system());
+        let network = match &self.0 {
+            // playing here still works without hosting, e.g. when
+            // another instance already listens on the port
+            NetworkMode::Host(config) => match Host::bind(config.to_string(), &[]) {
+                Ok(mut host) => {
+                    println!("Hosting on {}", config);
+                    if let Some(addr) = config.websocket_addr() {
+                        match host.listen_websocket(&addr) {
+                            Ok(()) => println!("Hosting browsers on {}", addr),
+                            Err(err) => eprintln!("Couldn't host browsers on {}: {}", addr, err),
+                        }
+                    }
+                    Network::Host(host)
+                }
+                Err(err) => {
+                    eprintln!("Couldn't host on {}, playing offline: {}", config, err);
+                    Network::Offline
+                }
+            },
+            NetworkMode::Connect { addr, seat } => match connect(addr, *seat) {
+                Ok(remote) => Network::Remote(remote),
+                Err(err) => {
+                    eprintln!("Couldn't connect to {}: {}", addr, err);
+                    process::exit(1);
+                }
+            },
+        };
+        app.insert_resource(network)
+            .init_resource::<ChatInput>()
+            .add_system(update_network.system())
+            .add_system(show_spectators.system())
+            .add_system(show_adjudication.system())
+            .add_system(press_negotiation_buttons.system())
+            .add_system(update_negotiation_buttons.system())
+            .add_system(type_chat.system())
+            .add_system(show_chat.system())
+            .add_system(print_record.system());
     }
 }
 
-struct Listener(TcpListener);
-struct ConnectedClient {
-    stream: TcpStream,
-    addr: SocketAddr,
-    buffer: Vec<u8>,
-    kind: ClientKind,
+struct SpectatorsText;
+struct ChatText;
+
+/// The chat message being typed
+#[derive(Default)]
+struct ChatInput(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NegotiationButton {
+    Offer(Offer),
+    Accept,
+    Decline,
+    Resign,
 }
-enum ClientKind {
-    Playing,
-    Spectating,
+
+enum Network {
+    Host(Host),
+    Remote(Remote),
+    Reconnecting(Reconnect),
+    /// Not hosting or connected, e.g. because the connection to the
+    /// host was lost, and the game goes on here
+    Offline,
 }
 
-/// Indicates that something went wrong so that the connection should be dropped
-#[derive(Debug)]
-struct NetworkError;
-impl StdError for NetworkError {}
-impl fmt::Display for NetworkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Network error")
-    }
+/// Trying to get back to the host after losing the connection
+struct Reconnect {
+    addr: SocketAddr,
+    session: String,
+    lost: Instant,
+    last_try: Instant,
 }
-impl From<Utf8Error> for NetworkError {
-    fn from(_: Utf8Error) -> Self {
-        Self
+
+impl Reconnect {
+    fn new(remote: &Remote) -> Option<Self> {
+        Some(Self {
+            addr: remote.host_addr(),
+            session: remote.session()?.to_string(),
+            lost: Instant::now(),
+            last_try: Instant::now(),
+        })
+    }
+
+    /// Try to resume the session every so often, returning what to do
+    /// next if it worked, or it's time to give up
+    fn retry(&mut self) -> Option<Network> {
+        if self.last_try.elapsed() < RECONNECT_INTERVAL {
+            return None;
+        }
+        self.last_try = Instant::now();
+        match Remote::resume(self.addr, &player_name(), &self.session) {
+            Ok(remote) => {
+                println!("Reconnected to {}", self.addr);
+                Some(Network::Remote(remote))
+            }
+            Err(_) if self.lost.elapsed() >= DEFAULT_GRACE_PERIOD => {
+                println!("Couldn't get back to {}, playing offline", self.addr);
+                Some(Network::Offline)
+            }
+            Err(_) => None,
+        }
     }
 }
 
-impl FromWorld for Listener {
-    fn from_world(_: &mut World) -> Self {
-        let listener = TcpListener::bind("0.0.0.0:1337").unwrap();
-        listener.set_nonblocking(true).unwrap();
-        Self(listener)
+fn connect(addr: &str, seat: Option<Option<Color>>) -> std::io::Result<Remote> {
+    let mut remote = Remote::connect(addr, &player_name())?;
+    if let Some(seat) = seat {
+        remote.request_seat(seat)?;
     }
+    Ok(remote)
 }
 
-enum NetworkEvent {
-    MoveReceivedEvent(MoveReceivedEvent),
+/// The name sent to the host
+fn player_name() -> String {
+    std::env::var("USER").unwrap_or_else(|_| "player".to_string())
 }
 
-fn accept_connections(
-    mut commands: Commands,
-    listener: Res<Listener>,
-    client_query: Query<(), With<ConnectedClient>>,
+fn update_network(
+    mut network: ResMut<Network>,
+    mut game: ResMut<Game>,
+    mut players: ResMut<Players>,
+    mut new_game_event: EventReader<NewGameEvent>,
+    mut board_update_event: EventWriter<BoardUpdateEvent>,
 ) {
-    match listener.0.accept() {
-        Ok((stream, addr)) => {
-            stream.set_nodelay(true).unwrap(); // auto flush
-            stream.set_nonblocking(true).unwrap();
-            let kind = if client_query.single().ok().is_none() {
-                ClientKind::Playing
-            } else {
-                ClientKind::Spectating
+    let mut next = None;
+    let changed = match &mut *network {
+        Network::Host(host) => {
+            if new_game_event.iter().next().is_some() {
+                host.new_game();
+            }
+            let seats: Vec<_> = [Color::White, Color::Black]
+                .iter()
+                .copied()
+                .filter(|&color| players.get(color) == Player::Remote)
+                .collect();
+            host.set_seats(&seats);
+            Ok(host.update(&mut game))
+        }
+        Network::Remote(remote) => {
+            let changed = remote.update(&mut game);
+            for color in [Color::White, Color::Black] {
+                set_remote(&mut players, color, remote.seat() != Some(color));
+            }
+            changed
+        }
+        Network::Reconnecting(reconnect) => {
+            next = reconnect.retry();
+            Ok(false)
+        }
+        Network::Offline => {
+            // there's nobody to play the sides set to remote
+            for color in [Color::White, Color::Black] {
+                set_remote(&mut players, color, false);
+            }
+            Ok(false)
+        }
+    };
+
+    match changed {
+        Ok(true) => board_update_event.send(BoardUpdateEvent),
+        Ok(false) => (),
+        Err(err) => {
+            println!("Lost the connection to the host: {}", err);
+            next = match &*network {
+                Network::Remote(remote) => Reconnect::new(remote).map(Network::Reconnecting),
+                _ => None,
             };
-            commands.spawn().insert(ConnectedClient {
-                stream,
-                addr,
-                buffer: vec![],
-                kind,
-            });
+            if next.is_none() {
+                next = Some(Network::Offline);
+            }
         }
-        Err(e) => eprintln!("{:?}", e),
+    }
+    if let Some(next) = next {
+        *network = next;
     }
 }
 
-fn read_packets(
-    mut commands: Commands,
-    mut clients: Query<(Entity, &mut ConnectedClient)>,
-    game: Res<Game>,
+/// Add the text that shows how many are watching the game, which is
+/// empty when playing offline
+pub fn spawn_spectators_text(parent: &mut ChildBuilder, font: Handle<Font>) {
+    parent
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 12.0,
+                    color: bevy::prelude::Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(SpectatorsText);
+}
+
+fn show_spectators(network: Res<Network>, mut query: Query<&mut Text, With<SpectatorsText>>) {
+    let spectators = match &*network {
+        Network::Host(host) => Some(host.spectators()),
+        Network::Remote(remote) => Some(remote.spectators() as usize),
+        Network::Reconnecting(_) | Network::Offline => None,
+    };
+    if let Ok(mut text) = query.single_mut() {
+        text.sections[0].value = match spectators {
+            Some(count) => format!("Spectators: {}", count),
+            None => String::new(),
+        };
+    }
+}
+
+/// Add the buttons to offer a draw, ask for a takeback, resign and
+/// answer offers, which are only shown when they can be used, and the
+/// text with the chat
+pub fn spawn_negotiation(
+    parent: &mut ChildBuilder,
+    font: Handle<Font>,
+    materials: &mut Assets<ColorMaterial>,
+) {
+    let button_material = materials.add(bevy::prelude::Color::rgb_u8(0x4c, 0x56, 0x6a).into());
+    let text_style = TextStyle {
+        font,
+        font_size: 16.0,
+        color: bevy::prelude::Color::WHITE,
+    };
+    for button in [
+        NegotiationButton::Offer(Offer::Draw),
+        NegotiationButton::Offer(Offer::Takeback),
+        NegotiationButton::Resign,
+        NegotiationButton::Accept,
+        NegotiationButton::Decline,
+    ] {
+        parent
+            .spawn_bundle(ButtonBundle {
+                style: Style {
+                    display: Display::None,
+                    margin: Rect::all(Val::Px(5.0)),
+                    padding: Rect::all(Val::Px(5.0)),
+                    justify_content: JustifyContent::Center,
+                    ..Default::default()
+                },
+                material: button_material.clone(),
+                ..Default::default()
+            })
+            .insert(button)
+            .with_children(|button| {
+                // the labels are set by `update_negotiation_buttons`
+                button.spawn_bundle(TextBundle {
+                    text: Text::with_section("", text_style.clone(), Default::default()),
+                    ..Default::default()
+                });
+            });
+    }
+    parent
+        .spawn_bundle(TextBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(5.0)),
+                max_size: Size::new(Val::Px(190.0), Val::Undefined),
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font_size: 12.0,
+                    ..text_style
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ChatText);
+}
+
+/// The side played here against a remote one, `None` when spectating,
+/// offline or not playing anyone over the network
+fn local_side(network: &Network, players: &Players) -> Option<Color> {
+    match network {
+        Network::Host(_) => [Color::White, Color::Black].iter().copied().find(|&color| {
+            players.get(color) == Player::Human && players.get(color.opposite()) == Player::Remote
+        }),
+        Network::Remote(remote) => remote.seat(),
+        Network::Reconnecting(_) | Network::Offline => None,
+    }
+}
+
+/// The offer that can be answered here, and the side answering it
+fn offer_to_answer(network: &Network, players: &Players) -> Option<(Color, Offer)> {
+    let (side, offer) = match network {
+        Network::Host(host) => host.pending_offer()?,
+        Network::Remote(remote) => remote.pending_offer()?,
+        Network::Reconnecting(_) | Network::Offline => return None,
+    };
+    if local_side(network, players) == Some(side.opposite()) {
+        Some((side.opposite(), offer))
+    } else {
+        None
+    }
+}
+
+fn press_negotiation_buttons(
+    query: Query<(&Interaction, &NegotiationButton), Changed<Interaction>>,
+    mut network: ResMut<Network>,
+    mut game: ResMut<Game>,
+    players: Res<Players>,
+    mut board_update_event: EventWriter<BoardUpdateEvent>,
 ) {
-    let mut buffer = [0_u8; 1024];
-    for (entity, mut client) in clients.iter_mut() {
-        let n_bytes = match client.stream.read(&mut buffer) {
-            Ok(n_bytes) => n_bytes,
-            Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
-            Err(err) => {
-                println!(
-                    "Network error from {}: {}\nDropping connection",
-                    client.addr, err
-                );
-                commands.entity(entity).despawn();
-                continue;
+    for (&interaction, &button) in query.iter() {
+        if interaction != Interaction::Clicked {
+            continue;
+        }
+        let side = local_side(&network, &players);
+        let answer = offer_to_answer(&network, &players);
+        let result = match &mut *network {
+            Network::Host(host) => match (button, side, answer) {
+                (NegotiationButton::Offer(offer), Some(side), _) => {
+                    host.make_offer(side, offer, &game)
+                }
+                (NegotiationButton::Resign, Some(side), _) => host.resign(side, &game),
+                (NegotiationButton::Accept, _, Some((side, offer))) => {
+                    host.accept(side, offer, &mut game)
+                }
+                (NegotiationButton::Decline, _, Some((side, offer))) => {
+                    host.decline(side, offer, &game)
+                }
+                _ => continue,
             }
-        };
-        let buffer = &buffer[..n_bytes];
-        for packet in buffer.split_inclusive(|&b| b == b';') {
-            if !packet.ends_with(&[b';']) {
-                client.buffer.extend_from_slice(packet);
-                break;
+            .map_err(|err| err.to_string()),
+            Network::Remote(remote) => match (button, answer) {
+                (NegotiationButton::Offer(offer), _) => remote.make_offer(offer),
+                (NegotiationButton::Resign, _) => remote.resign(),
+                (NegotiationButton::Accept, Some((_, offer))) => remote.accept(offer),
+                (NegotiationButton::Decline, Some((_, offer))) => remote.decline(offer),
+                _ => continue,
             }
-            match client.handle_packet(packet, game.current_board()) {
-                Ok(_) => {}
-                Err(_) => {
-                    commands.entity(entity).despawn();
-                    continue;
+            .map_err(|err| err.to_string()),
+            Network::Reconnecting(_) | Network::Offline => continue,
+        };
+        match result {
+            // an accepted takeback changes the board
+            Ok(()) => board_update_event.send(BoardUpdateEvent),
+            Err(err) => println!("Can't do that: {}", err),
+        }
+    }
+}
+
+/// Show the buttons that can be used, labelled with what they do
+fn update_negotiation_buttons(
+    network: Res<Network>,
+    players: Res<Players>,
+    mut button_query: Query<(&NegotiationButton, &mut Style, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    let side = local_side(&network, &players);
+    let answer = offer_to_answer(&network, &players);
+    for (&button, mut style, children) in button_query.iter_mut() {
+        let label = match (button, answer) {
+            (NegotiationButton::Offer(Offer::Draw), _) if side.is_some() => "Offer draw",
+            (NegotiationButton::Offer(Offer::Takeback), _) if side.is_some() => "Take back",
+            (NegotiationButton::Resign, _) if side.is_some() => "Resign",
+            (NegotiationButton::Accept, Some((_, Offer::Draw))) => "Accept draw",
+            (NegotiationButton::Accept, Some((_, Offer::Takeback))) => "Accept takeback",
+            (NegotiationButton::Decline, Some(_)) => "Decline",
+            _ => "",
+        };
+        let display = if label.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+        // only touched when it changes, so the layout isn't redone every
+        // frame
+        if style.display != display {
+            style.display = display;
+        }
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                if text.sections[0].value != label {
+                    text.sections[0].value = label.to_string();
                 }
             }
         }
     }
 }
 
-impl ConnectedClient {
-    fn handle_packet(&mut self, packet: &[u8], board: &Board) -> Result<(), NetworkError> {
-        let (key, value) = Self::split_packet(packet)?;
-        match key {
-            b"move" => self.handle_move_packet(value, board),
-            _ => Err(NetworkError),
+/// Type a chat message, and send it with enter
+fn type_chat(
+    mut input: ResMut<ChatInput>,
+    mut characters: EventReader<ReceivedCharacter>,
+    kb_input: Res<Input<KeyCode>>,
+    mut network: ResMut<Network>,
+    game: Res<Game>,
+) {
+    for character in characters.iter() {
+        if !character.char.is_control() {
+            input.0.push(character.char);
         }
     }
-    fn split_packet(packet: &[u8]) -> Result<(&[u8], &[u8]), NetworkError> {
-        let colon_index = match packet
-            .iter()
-            .enumerate()
-            .find(|(_, &b)| b == b':')
-            .map(|(i, _)| i)
-        {
-            Some(i) => i,
-            None => return Err(NetworkError),
-        };
+    if kb_input.just_pressed(KeyCode::Back) {
+        let _ = input.0.pop();
+    }
+    if !kb_input.just_pressed(KeyCode::Return) || input.0.trim().is_empty() {
+        return;
+    }
+    let text = std::mem::take(&mut input.0);
+    match &mut *network {
+        Network::Host(host) => host.chat(&player_name(), text.trim(), &game),
+        Network::Remote(remote) => {
+            if let Err(err) = remote.chat(text.trim()) {
+                println!("Couldn't send the message: {}", err);
+            }
+        }
+        Network::Reconnecting(_) | Network::Offline => (),
+    }
+}
 
-        Ok(packet.split_at(colon_index))
+/// Show the last of the chat and offers, and what's being typed
+fn show_chat(
+    network: Res<Network>,
+    input: Res<ChatInput>,
+    mut query: Query<&mut Text, With<ChatText>>,
+) {
+    let record = match &*network {
+        Network::Host(host) => Some(host.record()),
+        Network::Remote(remote) => Some(remote.record()),
+        Network::Reconnecting(_) | Network::Offline => None,
+    };
+    let value = match record {
+        Some(record) => {
+            let notes = record.notes();
+            let mut lines: Vec<_> = notes[notes.len().saturating_sub(CHAT_LINES)..]
+                .iter()
+                .map(|(_, note)| note.as_str())
+                .collect();
+            let typed = format!("> {}", input.0);
+            lines.push(&typed);
+            lines.join("\n")
+        }
+        None => String::new(),
+    };
+    if let Ok(mut text) = query.single_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
     }
-    fn handle_move_packet(
-        &self,
-        value: &[u8],
-        board: &Board,
-    ) -> Result<Option<NetworkEvent>, NetworkError> {
-        let m: Move = parse_move(str::from_utf8(value)?, board).ok_or(NetworkError)?;
+}
 
-        Ok(Some(NetworkEvent::MoveReceivedEvent(MoveReceivedEvent(m))))
+/// Print the record of a networked game as PGN once it's over
+fn print_record(network: Res<Network>, game: Res<Game>, mut printed: Local<bool>) {
+    let (record, adjudication) = match &*network {
+        Network::Host(host) => (host.record(), host.adjudication()),
+        Network::Remote(remote) => (remote.record(), remote.adjudication()),
+        Network::Reconnecting(_) | Network::Offline => return,
+    };
+    let over = adjudication.is_some()
+        || !matches!(game.board_state(), BoardState::Normal | BoardState::Check);
+    if over && !*printed {
+        print!("{}", record.to_pgn(&game, adjudication));
     }
+    *printed = over;
 }
 
-fn parse_move(s: &str, board: &Board) -> Option<Move> {
-    if s.len() != 5 {
-        return None;
+/// Show the result the host gave the game if it didn't end on the
+/// board, and stop moves from being made until the next game
+fn show_adjudication(
+    network: Res<Network>,
+    mut state: ResMut<UIState>,
+    mut text_query: Query<&mut Text, With<GameEndText>>,
+    mut parent_query: Query<&mut Style, With<GameEndElement>>,
+) {
+    let adjudication = match &*network {
+        Network::Host(host) => host.adjudication(),
+        Network::Remote(remote) => remote.adjudication(),
+        Network::Reconnecting(_) | Network::Offline => None,
+    };
+    match (adjudication, *state) {
+        // a piece being moved is put down first
+        (Some(adjudication), UIState::Default) => {
+            let result = match adjudication.result {
+                GameResult::WhiteWins => "White wins",
+                GameResult::BlackWins => "Black wins",
+                GameResult::Draw => "Draw",
+                GameResult::Unknown => "Game over",
+            };
+            *state = UIState::GameOver;
+            parent_query.single_mut().unwrap().display = Display::Flex;
+            text_query.single_mut().unwrap().sections[0].value =
+                format!("{}, {}!", result, adjudication.reason);
+        }
+        (None, UIState::GameOver) => {
+            *state = UIState::Default;
+            parent_query.single_mut().unwrap().display = Display::None;
+            text_query.single_mut().unwrap().sections[0].value.clear();
+        }
+        _ => (),
     }
+}
 
-    let from = SquareSpec::from_str(&s[0..2]).ok()?;
-    let to = SquareSpec::from_str(&s[2..4]).ok()?;
-    if let Ok(target) = PieceType::from_str(s[4..5].to_ascii_lowercase().as_str()) {
-        Some(Move::Promotion { from, to, target })
-    } else if let Some(piece) = board[from] {
-        Move::new(piece, from, to)
-    } else {
-        None
+/// Let the other end play `color`, or hand it back to a human here
+fn set_remote(players: &mut Players, color: Color, remote: bool) {
+    let player = players.get_mut(color);
+    if remote {
+        *player = Player::Remote;
+    } else if *player == Player::Remote {
+        *player = Player::Human;
     }
 }